@@ -0,0 +1,67 @@
+// Compares the account stores selected by the `accounts-*` features. Run once per store:
+//   cargo bench
+//   cargo bench --features accounts-hashbrown
+//   cargo bench --features accounts-btree
+// ids are widened by the `client-id-*` features, where some of these conversions can't fail
+#![allow(clippy::unnecessary_fallible_conversions)]
+
+use std::any::type_name;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_decimal_macros::dec;
+
+use bank::domain::{transaction::Operation, AccountStore, ClientId, Transaction};
+use bank::engine::Engine;
+
+const SIZES: [u32; 3] = [10_000, 1_000_000, 10_000_000];
+
+fn deposit(client: ClientId, tx: u32) -> Transaction {
+    Transaction {
+        op: Operation::Deposit,
+        client,
+        tx,
+        amount: Some(dec!(1.5)),
+        ..Default::default()
+    }
+}
+
+// Client ids are only 16 bits wide by default, larger stores need one of the `client-id-*`
+// features
+fn populated(accounts: u32) -> Option<Engine> {
+    ClientId::try_from(accounts - 1).ok()?;
+    let mut engine = Engine::new();
+    for tx in 0..accounts {
+        let client = ClientId::try_from(tx).expect("Checked against the largest id");
+        engine.process(deposit(client, tx)).expect("Fresh deposits apply");
+    }
+    Some(engine)
+}
+
+fn account_store(c: &mut Criterion) {
+    let mut group = c.benchmark_group(type_name::<AccountStore>());
+    group.sample_size(10);
+    for size in SIZES {
+        let Some(mut engine) = populated(size) else {
+            eprintln!(
+                "Skipping {size} accounts: client ids are {} bits, enable a wider client-id-* feature",
+                ClientId::BITS
+            );
+            continue;
+        };
+        let mut tx = size;
+        group.bench_with_input(BenchmarkId::new("deposit", size), &size, |b, &size| {
+            b.iter(|| {
+                tx += 1;
+                let client = ClientId::try_from(tx % size).unwrap_or_default();
+                black_box(engine.process(deposit(client, tx)))
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("iterate", size), &engine, |b, engine| {
+            b.iter(|| black_box(engine.accounts().values().filter(|act| act.locked).count()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, account_store);
+criterion_main!(benches);
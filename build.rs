@@ -0,0 +1,33 @@
+// Generates the protobuf messages from `proto/` when the `protobuf` feature is on, and the gRPC
+// services with `grpc`. protoc comes from protoc-bin-vendored, so building doesn't need one
+// installed.
+fn main() {
+    #[cfg(feature = "protobuf")]
+    protobuf();
+}
+
+#[cfg(feature = "protobuf")]
+fn protobuf() {
+    let protoc =
+        protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this platform");
+    std::env::set_var("PROTOC", protoc);
+    println!("cargo:rerun-if-changed=proto");
+    #[cfg(not(feature = "grpc"))]
+    prost_build::Config::new()
+        // like `Transaction::extra`
+        .btree_map(["."])
+        .compile_protos(&["proto/bank.proto"], &["proto"])
+        .expect("Failed to compile proto/bank.proto");
+    #[cfg(feature = "grpc")]
+    tonic_prost_build::configure()
+        .btree_map(".")
+        .compile_protos(
+            &[
+                "proto/bank.proto",
+                "proto/processor.proto",
+                "proto/downstream.proto",
+            ],
+            &["proto"],
+        )
+        .expect("Failed to compile the protos");
+}
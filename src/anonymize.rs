@@ -0,0 +1,119 @@
+use rust_decimal::Decimal;
+
+use crate::domain::transaction::Extra;
+use crate::domain::{ClientId, Transaction};
+
+// Maps client ids and amounts through a keyed permutation so runs can be shared without exposing
+// real customers. Client ids are permuted rather than hashed, which keeps distinct clients distinct.
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    key: u64,
+    perturb_amounts: bool,
+}
+
+const ROUNDS: u64 = 4;
+
+// casts of client ids are no-ops for some of the `client-id-*` widths
+#[allow(clippy::unnecessary_cast)]
+impl Anonymizer {
+    pub fn new(key: &str) -> Self {
+        // FNV-1a, only used to fold the user supplied key into a seed
+        let key = key.bytes().fold(0xcbf29ce484222325, |acc: u64, byte| {
+            (acc ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        Self {
+            key,
+            perturb_amounts: false,
+        }
+    }
+
+    pub fn perturb_amounts(mut self, enabled: bool) -> Self {
+        self.perturb_amounts = enabled;
+        self
+    }
+
+    pub fn client(&self, client: ClientId) -> ClientId {
+        // Feistel network over the two halves of the id, a bijection for any key
+        let half = ClientId::BITS / 2;
+        let mask = (1u128 << half) - 1;
+        let (mut left, mut right) = (client as u128 >> half, client as u128 & mask);
+        for round in 0..ROUNDS {
+            let mixed = self.mix((round << half.min(56)) ^ right as u64) as u128 & mask;
+            (left, right) = (right, left ^ mixed);
+        }
+        ((left << half) | right) as ClientId
+    }
+
+    // Scales every amount of a client by the same keyed factor between 0.5 and 1.5, so the
+    // ordering of that client's deposits and withdrawals is preserved.
+    pub fn amount(&self, client: ClientId, amount: Decimal) -> Decimal {
+        if !self.perturb_amounts {
+            return amount;
+        }
+        let factor = Decimal::new(
+            5000 + (self.mix(u64::MAX - client as u64) % 10000) as i64,
+            4,
+        );
+        (amount * factor).round_dp(4)
+    }
+
+    pub fn transaction(&self, tx: Transaction) -> Transaction {
+        Transaction {
+            client: self.client(tx.client),
+            counterparty: tx.counterparty.map(|client| self.client(client)),
+            amount: tx.amount.map(|amt| self.amount(tx.client, amt)),
+            // partner columns can identify the client as well
+            extra: Extra::new(),
+            ..tx
+        }
+    }
+
+    // splitmix64 finalizer seeded with the key
+    fn mix(&self, value: u64) -> u64 {
+        let mut z = self
+            .key
+            .wrapping_add(value.wrapping_mul(0x9e3779b97f4a7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::collections::HashSet;
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn client_mapping_is_a_permutation() {
+        let anonymizer = Anonymizer::new("secret");
+        let mapped: HashSet<ClientId> = (0..=u16::MAX)
+            .map(|id| anonymizer.client(id as ClientId))
+            .collect();
+
+        assert_eq!(mapped.len(), u16::MAX as usize + 1);
+        assert_eq!(anonymizer.client(42), Anonymizer::new("secret").client(42));
+        assert_ne!(
+            (0..16).map(|id| anonymizer.client(id)).collect::<Vec<_>>(),
+            (0..16)
+                .map(|id| Anonymizer::new("other").client(id))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn amounts_are_scaled_per_client() {
+        let plain = Anonymizer::new("secret");
+        assert_eq!(plain.amount(1, dec!(10.5)), dec!(10.5));
+
+        let perturbed = Anonymizer::new("secret").perturb_amounts(true);
+        let small = perturbed.amount(1, dec!(10));
+        let large = perturbed.amount(1, dec!(20));
+
+        assert!(small >= dec!(5) && small < dec!(15));
+        assert_eq!(large, small * dec!(2));
+    }
+}
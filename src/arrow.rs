@@ -0,0 +1,358 @@
+use std::str::FromStr;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::Decimal128Type;
+use arrow_array::{Array, ArrayRef, RecordBatch, StringArray, UInt64Array};
+use arrow_cast::{can_cast_types, cast};
+use arrow_schema::{ArrowError, DataType, TimeUnit};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::domain::{
+    transaction::{Extra, Operation},
+    Amount, ClientId, Transaction,
+};
+use crate::engine::{Engine, ProcessingReport};
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("Missing column: {0}")]
+    MissingColumn(&'static str),
+    #[error("Column {column} can't be read as {expected}, it holds {data_type}")]
+    UnsupportedType {
+        column: &'static str,
+        expected: &'static str,
+        data_type: DataType,
+    },
+    #[error("Failed to convert column: {0}")]
+    Arrow(#[from] ArrowError),
+    // only fails its row, which is counted as malformed
+    #[error("Invalid {column} in row {row}")]
+    InvalidValue { column: &'static str, row: usize },
+}
+
+// Columns with the names of the CSV header, every other one goes to `Transaction::extra`
+const KNOWN: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "counterparty",
+    "currency",
+    "timestamp",
+];
+
+impl<A: Amount> Engine<A> {
+    // Applies the rows of an Arrow record batch in order and reports on them like `process_all`,
+    // e.g. for batches coming out of DataFusion or Polars. Columns are converted a whole column at
+    // a time before any row is applied, and the ones already of the type they're read as, utf8
+    // operations and uint64 ids, are used in place. A missing column or one of a type that can't
+    // be read fails the batch without applying anything; a row with a null or invalid value is
+    // counted as malformed.
+    pub fn process_record_batch(
+        &mut self,
+        batch: &RecordBatch,
+    ) -> Result<ProcessingReport, BatchError> {
+        let columns = Columns::new(batch)?;
+        Ok(self.process_stream((0..batch.num_rows()).map(|row| columns.transaction(row))))
+    }
+}
+
+// An optional column as given, to tell nulls from values the conversion turned away
+struct Optional<T> {
+    source: ArrayRef,
+    values: T,
+}
+
+impl<T> Optional<T> {
+    fn get<V>(
+        &self,
+        row: usize,
+        column: &'static str,
+        value: impl FnOnce(&T) -> Option<V>,
+    ) -> Result<Option<V>, BatchError> {
+        match self.source.is_null(row) {
+            true => Ok(None),
+            false => value(&self.values)
+                .map(Some)
+                .ok_or(BatchError::InvalidValue { column, row }),
+        }
+    }
+}
+
+struct Columns<A> {
+    ops: StringArray,
+    clients: UInt64Array,
+    txs: UInt64Array,
+    amounts: Option<Optional<Vec<Option<A>>>>,
+    counterparties: Option<Optional<UInt64Array>>,
+    currencies: Option<StringArray>,
+    timestamps: Option<Optional<UInt64Array>>,
+    extra: Vec<(String, StringArray)>,
+}
+
+impl<A: Amount> Columns<A> {
+    fn new(batch: &RecordBatch) -> Result<Self, BatchError> {
+        let required = |name| {
+            batch
+                .column_by_name(name)
+                .ok_or(BatchError::MissingColumn(name))
+        };
+        let optional = |name| batch.column_by_name(name);
+        let extra = batch
+            .schema()
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .filter(|(field, column)| {
+                !KNOWN.contains(&field.name().as_str())
+                    && can_cast_types(column.data_type(), &DataType::Utf8)
+            })
+            .map(|(field, column)| Ok((field.name().clone(), strings(column)?)))
+            .collect::<Result<_, ArrowError>>()?;
+        Ok(Self {
+            ops: text(required("type")?, "type")?,
+            clients: integers(required("client")?, "client")?,
+            txs: integers(required("tx")?, "tx")?,
+            amounts: optional("amount")
+                .map(|source| {
+                    Ok::<_, BatchError>(Optional {
+                        values: amounts(source)?,
+                        source: source.clone(),
+                    })
+                })
+                .transpose()?,
+            counterparties: optional("counterparty")
+                .map(|source| {
+                    Ok::<_, BatchError>(Optional {
+                        values: integers(source, "counterparty")?,
+                        source: source.clone(),
+                    })
+                })
+                .transpose()?,
+            currencies: optional("currency")
+                .map(|source| text(source, "currency"))
+                .transpose()?,
+            timestamps: optional("timestamp")
+                .map(|source| {
+                    Ok::<_, BatchError>(Optional {
+                        values: timestamps(source)?,
+                        source: source.clone(),
+                    })
+                })
+                .transpose()?,
+            extra,
+        })
+    }
+
+    fn transaction(&self, row: usize) -> Result<Transaction<A>, BatchError> {
+        let invalid = |column| BatchError::InvalidValue { column, row };
+        let op = self.ops.is_valid(row).then(|| self.ops.value(row));
+        let client = self.clients.is_valid(row).then(|| self.clients.value(row));
+        let tx = self.txs.is_valid(row).then(|| self.txs.value(row));
+        Ok(Transaction {
+            op: Operation::from_name(op.ok_or_else(|| invalid("type"))?),
+            client: client
+                .and_then(client_id)
+                .ok_or_else(|| invalid("client"))?,
+            tx: tx
+                .and_then(|tx| u32::try_from(tx).ok())
+                .ok_or_else(|| invalid("tx"))?,
+            amount: match &self.amounts {
+                Some(amounts) => amounts.get(row, "amount", |values| values[row])?,
+                None => None,
+            },
+            counterparty: match &self.counterparties {
+                Some(counterparties) => counterparties.get(row, "counterparty", |values| {
+                    values
+                        .is_valid(row)
+                        .then(|| values.value(row))
+                        .and_then(client_id)
+                })?,
+                None => None,
+            },
+            currency: self
+                .currencies
+                .as_ref()
+                .filter(|currencies| currencies.is_valid(row))
+                .map(|currencies| currencies.value(row).to_string()),
+            timestamp: match &self.timestamps {
+                Some(timestamps) => timestamps.get(row, "timestamp", |values| {
+                    values.is_valid(row).then(|| values.value(row))
+                })?,
+                None => None,
+            },
+            extra: self
+                .extra
+                .iter()
+                .filter(|(_, values)| values.is_valid(row))
+                .map(|(name, values)| (name.clone(), values.value(row).to_string()))
+                .collect::<Extra>(),
+        })
+    }
+}
+
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+fn client_id(id: u64) -> Option<ClientId> {
+    ClientId::try_from(u128::from(id)).ok()
+}
+
+fn strings(column: &ArrayRef) -> Result<StringArray, ArrowError> {
+    Ok(cast(column, &DataType::Utf8)?.as_string::<i32>().clone())
+}
+
+fn text(column: &ArrayRef, name: &'static str) -> Result<StringArray, BatchError> {
+    match column.data_type() {
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => Ok(strings(column)?),
+        data_type => Err(unsupported(name, "text", data_type)),
+    }
+}
+
+// Negative values and ones out of range of uint64 become nulls, which makes their rows invalid
+fn integers(column: &ArrayRef, name: &'static str) -> Result<UInt64Array, BatchError> {
+    match column.data_type() {
+        data_type if data_type.is_integer() => {
+            Ok(cast(column, &DataType::UInt64)?.as_primitive().clone())
+        }
+        data_type => Err(unsupported(name, "an integer", data_type)),
+    }
+}
+
+// Seconds since the Unix epoch, from integers or timestamps of any unit
+fn timestamps(column: &ArrayRef) -> Result<UInt64Array, BatchError> {
+    match column.data_type() {
+        DataType::Timestamp(..) => {
+            let seconds = cast(column, &DataType::Timestamp(TimeUnit::Second, None))?;
+            let seconds = cast(&seconds, &DataType::Int64)?;
+            integers(&seconds, "timestamp")
+        }
+        _ => integers(column, "timestamp"),
+    }
+}
+
+// Decimals keep their scale, integers have none and text is parsed like in CSV input. Floats
+// aren't read, they can't hold most amounts exactly. A value that doesn't fit the engine's
+// amounts is None.
+fn amounts<A: Amount>(column: &ArrayRef) -> Result<Vec<Option<A>>, BatchError> {
+    let decimal = |value: Option<Decimal>| value.and_then(A::from_decimal);
+    let scale = match column.data_type() {
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => {
+            let values = strings(column)?;
+            return Ok(values
+                .iter()
+                .map(|value| decimal(value.and_then(|value| Decimal::from_str(value.trim()).ok())))
+                .collect());
+        }
+        DataType::Decimal32(_, scale)
+        | DataType::Decimal64(_, scale)
+        | DataType::Decimal128(_, scale)
+        | DataType::Decimal256(_, scale) => *scale,
+        data_type if data_type.is_integer() => 0,
+        data_type => return Err(unsupported("amount", "a decimal", data_type)),
+    };
+    let unscaled =
+        u32::try_from(scale).map_err(|_| unsupported("amount", "a decimal", column.data_type()))?;
+    let values = cast(column, &DataType::Decimal128(38, scale))?;
+    Ok(values
+        .as_primitive::<Decimal128Type>()
+        .iter()
+        .map(|value| {
+            decimal(value.and_then(|value| Decimal::try_from_i128_with_scale(value, unscaled).ok()))
+        })
+        .collect())
+}
+
+fn unsupported(column: &'static str, expected: &'static str, data_type: &DataType) -> BatchError {
+    BatchError::UnsupportedType {
+        column,
+        expected,
+        data_type: data_type.clone(),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::sync::Arc;
+
+    use arrow_array::{Decimal128Array, Float64Array, Int32Array, UInt32Array};
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn batch(amounts: ArrayRef) -> RecordBatch {
+        RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "deposit",
+                    "withdrawal",
+                    "dispute",
+                    "deposit",
+                ])) as ArrayRef,
+            ),
+            (
+                "client",
+                Arc::new(Int32Array::from(vec![1, 2, 2, 1, -3])) as ArrayRef,
+            ),
+            (
+                "tx",
+                Arc::new(UInt32Array::from(vec![1, 2, 3, 1, 4])) as ArrayRef,
+            ),
+            ("amount", amounts),
+            (
+                "merchant",
+                Arc::new(StringArray::from(vec![Some("m-7"), None, None, None, None])) as ArrayRef,
+            ),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn processes_the_rows_of_a_record_batch() {
+        let amounts = Decimal128Array::from(vec![Some(1050), Some(300), Some(25), None, Some(100)])
+            .with_precision_and_scale(10, 2)
+            .unwrap();
+        let mut engine: Engine = Engine::new();
+        let report = engine
+            .process_record_batch(&batch(Arc::new(amounts)))
+            .unwrap();
+
+        // the last row has a negative client id
+        assert_eq!(report.applied, 4);
+        assert_eq!(report.malformed, 1);
+        let account = &engine.accounts()[&1];
+        assert_eq!(account.available, dec!(0));
+        assert_eq!(account.held, dec!(10.5));
+        assert_eq!(account.total, dec!(10.5));
+        assert_eq!(engine.accounts()[&2].total, dec!(2.75));
+    }
+
+    #[test]
+    fn reads_amounts_from_text_and_turns_away_floats() {
+        let amounts = StringArray::from(vec![Some("10.5"), Some("3"), Some("x"), None, None]);
+        let mut engine: Engine = Engine::new();
+        let report = engine
+            .process_record_batch(&batch(Arc::new(amounts)))
+            .unwrap();
+        // an amount that isn't a number and the negative client id
+        assert_eq!(report.malformed, 2);
+        assert_eq!(engine.accounts()[&1].held, dec!(10.5));
+
+        let floats = Float64Array::from(vec![1.0; 5]);
+        assert!(matches!(
+            engine.process_record_batch(&batch(Arc::new(floats))),
+            Err(BatchError::UnsupportedType {
+                column: "amount",
+                ..
+            })
+        ));
+        let batch = batch(Arc::new(StringArray::from(vec!["1"; 5])))
+            .project(&[0, 2, 3])
+            .unwrap();
+        assert!(matches!(
+            engine.process_record_batch(&batch),
+            Err(BatchError::MissingColumn("client"))
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+use std::future::Future;
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinHandle};
+
+use crate::domain::{errors::TransactionError, AccountRepository, Amount, Transaction};
+use crate::engine::{Engine, Machine, ProcessingReport, Task};
+
+// Async counterpart of `Machine` for tasks run from async services. Applying a transaction never
+// waits on IO, so a run only gives other tasks on the runtime their turn before it starts once
+// the current one has used up its scheduling budget.
+pub trait AsyncMachine {
+    fn run(&mut self) -> impl Future<Output = Result<(), TransactionError>> + Send;
+}
+
+impl<'a, A, S> AsyncMachine for Task<'a, A, S>
+where
+    A: Amount + Send + Sync,
+    S: AccountRepository<A> + Send,
+{
+    async fn run(&mut self) -> Result<(), TransactionError> {
+        task::consume_budget().await;
+        Machine::run(self)
+    }
+}
+
+impl<A: Amount> Engine<A> {
+    // Applies transactions as they're received until every sender is dropped, or the error policy
+    // stops at a rejection, and reports on them like `process_all`. Waiting for input never blocks
+    // the thread, so one runtime can feed many engines.
+    pub async fn ingest(&mut self, mut input: mpsc::Receiver<Transaction<A>>) -> ProcessingReport {
+        let start = self.report();
+        let started = Instant::now();
+        let mut failures = Vec::new();
+        while let Some(transaction) = input.recv().await {
+            if !self.process_in_batch(transaction, &mut failures) {
+                break;
+            }
+        }
+        let mut report = self.report_since(&start);
+        report.failures = failures;
+        report.duration = started.elapsed();
+        report
+    }
+}
+
+impl<A: Amount + Send + Sync + 'static> Engine<A> {
+    // Moves the engine onto a task of the current runtime that ingests what's sent to the
+    // returned channel, holding up to `capacity` transactions before senders wait. The task hands
+    // the engine back with its report once every sender is dropped.
+    pub fn spawn_ingest(
+        self,
+        capacity: usize,
+    ) -> (
+        mpsc::Sender<Transaction<A>>,
+        JoinHandle<(Self, ProcessingReport)>,
+    ) {
+        let (sender, input) = mpsc::channel(capacity.max(1));
+        let handle = task::spawn(async move {
+            let mut engine = self;
+            let report = engine.ingest(input).await;
+            (engine, report)
+        });
+        (sender, handle)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+    use tokio::runtime;
+
+    use super::*;
+    use crate::domain::{transaction::Operation, tx_history::History, AccountStore};
+
+    fn transaction(op: Operation, tx: u32) -> Transaction {
+        Transaction {
+            op,
+            client: 1,
+            tx,
+            amount: Some(dec!(2)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ingests_from_async_senders() {
+        let runtime = runtime::Builder::new_current_thread().build().unwrap();
+        let (engine, report) = runtime.block_on(async {
+            let (sender, handle) = Engine::new().spawn_ingest(2);
+            for tx in 0..10 {
+                let op = match tx % 3 {
+                    2 => Operation::Withdrawal,
+                    _ => Operation::Deposit,
+                };
+                sender.send(transaction(op, tx)).await.unwrap();
+            }
+            // more than the client holds
+            let mut overdraft = transaction(Operation::Withdrawal, 10);
+            overdraft.amount = Some(dec!(100));
+            sender.send(overdraft).await.unwrap();
+            drop(sender);
+            handle.await.unwrap()
+        });
+
+        assert_eq!(report.applied, 10);
+        assert_eq!(report.rejected_total(), 1);
+        assert_eq!(engine.accounts()[&1].total, dec!(8));
+    }
+
+    #[test]
+    fn tasks_run_as_futures() {
+        let (mut history, mut accounts) = (History::new(), AccountStore::new());
+        let runtime = runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let mut task = Task::new(
+                &mut history,
+                &mut accounts,
+                transaction(Operation::Deposit, 1),
+            );
+            AsyncMachine::run(&mut task).await.unwrap();
+        });
+
+        assert_eq!(accounts[&1].available, dec!(2));
+        assert!(history.get(&(1, 1)).is_some());
+    }
+}
@@ -0,0 +1,753 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use avro_schema::file::Compression;
+use avro_schema::read::fallible_streaming_iterator::FallibleStreamingIterator;
+use avro_schema::read::BlockStreamingIterator;
+use avro_schema::schema::{BytesLogical, FixedLogical, LongLogical, Schema};
+use rust_decimal::Decimal;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::domain::{transaction::Operation, Amount, ClientId, Transaction};
+use crate::input::InputError;
+
+// The schema Avro input is resolved against
+pub const READER_SCHEMA: &str = include_str!("../avro/transaction.avsc");
+
+#[derive(Error, Debug)]
+pub enum AvroError {
+    #[error("Invalid schema: {0}")]
+    Schema(String),
+    #[error("The writer's schema has no {0} field")]
+    MissingField(String),
+    #[error("Field {field} can't be read as {expected}")]
+    FieldType {
+        field: String,
+        expected: &'static str,
+    },
+    #[error("Invalid container file: {0}")]
+    File(String),
+    // `Decode` errors only concern the record being read
+    #[error("Invalid record: {0}")]
+    Decode(&'static str),
+}
+
+// Parses a schema as JSON. Field defaults are left out: the `avro-schema` crate can only read
+// null ones, and resolution only needs to know whether a reader field may be null.
+pub fn parse_schema(json: &str) -> Result<Schema, AvroError> {
+    fn strip_defaults(value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                if object.contains_key("name") && object.contains_key("type") {
+                    object.remove("default");
+                }
+                object.values_mut().for_each(strip_defaults);
+            }
+            Value::Array(values) => values.iter_mut().for_each(strip_defaults),
+            _ => {}
+        }
+    }
+    let mut value: Value =
+        serde_json::from_str(json).map_err(|e| AvroError::Schema(e.to_string()))?;
+    strip_defaults(&mut value);
+    serde_json::from_value(value).map_err(|e| AvroError::Schema(e.to_string()))
+}
+
+// What a field of the writer's record is read into
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Op,
+    Client,
+    Tx,
+    Amount(AmountEncoding),
+    Counterparty,
+    Currency,
+    // the divisor that makes the field's unit seconds
+    Timestamp(i64),
+    Extra(String),
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AmountEncoding {
+    Text,
+    Integer,
+    // unscaled big-endian two's complement with this scale
+    Decimal(u32),
+}
+
+// Reads records written with one schema as transactions, resolving the writer's fields against
+// the reader's schema, `READER_SCHEMA` unless given another one. Reader fields match a writer
+// field of the same name or of one of their aliases, so renamed fields can be picked up by
+// listing their old name. A reader field the writer doesn't have is null, which fails the
+// records if it's one of `type`, `client` and `tx`, and writer fields the reader doesn't have are
+// kept in `Transaction::extra` when they're strings, numbers or booleans. Int and long, string
+// and enum and, for amounts, decimals and integers, read as each other.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    fields: Vec<(Schema, Target)>,
+}
+
+impl Decoder {
+    pub fn new(writer: &Schema) -> Result<Self, AvroError> {
+        Self::resolve(writer, &parse_schema(READER_SCHEMA)?)
+    }
+
+    pub fn resolve(writer: &Schema, reader: &Schema) -> Result<Self, AvroError> {
+        let (Schema::Record(writer), Schema::Record(reader)) = (writer, reader) else {
+            return Err(AvroError::Schema("Transactions are records".to_string()));
+        };
+        let mut targets: Vec<Target> = writer
+            .fields
+            .iter()
+            .map(|field| match scalar(&field.schema) {
+                true => Target::Extra(field.name.clone()),
+                false => Target::Skip,
+            })
+            .collect();
+        for field in &reader.fields {
+            let target = match field.name.as_str() {
+                "type" => Target::Op,
+                "client" => Target::Client,
+                "tx" => Target::Tx,
+                "amount" => Target::Amount(AmountEncoding::Text),
+                "counterparty" => Target::Counterparty,
+                "currency" => Target::Currency,
+                "timestamp" => Target::Timestamp(1),
+                name => {
+                    return Err(AvroError::Schema(format!(
+                        "Unknown transaction field {name}"
+                    )))
+                }
+            };
+            let matched = writer.fields.iter().position(|writer| {
+                writer.name == field.name || field.aliases.contains(&writer.name)
+            });
+            match matched {
+                Some(index) => {
+                    let written = &writer.fields[index];
+                    targets[index] = check(target, nullable(&written.schema)).ok_or_else(|| {
+                        AvroError::FieldType {
+                            field: written.name.clone(),
+                            expected: expected(&field.name),
+                        }
+                    })?;
+                }
+                None if optional(&field.schema) => {}
+                None => return Err(AvroError::MissingField(field.name.clone())),
+            }
+        }
+        Ok(Self {
+            fields: writer
+                .fields
+                .iter()
+                .map(|field| field.schema.clone())
+                .zip(targets)
+                .collect(),
+        })
+    }
+
+    // Reads one record from the front of `data`, leaving what follows it
+    pub fn decode<A: Amount>(&self, data: &mut &[u8]) -> Result<Transaction<A>, AvroError> {
+        let (mut op, mut client, mut tx) = (None, None, None);
+        let mut transaction = Transaction::default();
+        for (schema, target) in &self.fields {
+            let value = read(schema, data)?;
+            if value == Datum::Null || *target == Target::Skip {
+                continue;
+            }
+            match (target, value) {
+                (Target::Op, Datum::Text(name)) => op = Some(Operation::from_name(name)),
+                (Target::Client, Datum::Long(id)) => client = Some(client_id(id)?),
+                (Target::Tx, Datum::Long(id)) => {
+                    tx = Some(u32::try_from(id).map_err(|_| AvroError::Decode("tx out of range"))?)
+                }
+                (Target::Amount(encoding), value) => {
+                    transaction.amount = Some(amount(*encoding, value)?);
+                }
+                (Target::Counterparty, Datum::Long(id)) => {
+                    transaction.counterparty = Some(client_id(id)?)
+                }
+                (Target::Currency, Datum::Text(code)) => {
+                    transaction.currency = Some(code.to_string())
+                }
+                (Target::Timestamp(divisor), Datum::Long(time)) => {
+                    let seconds = u64::try_from(time.div_euclid(*divisor))
+                        .map_err(|_| AvroError::Decode("timestamp before the epoch"))?;
+                    transaction.timestamp = Some(seconds);
+                }
+                (Target::Extra(name), value) => {
+                    let value = match value {
+                        Datum::Text(text) => text.to_string(),
+                        Datum::Long(number) => number.to_string(),
+                        Datum::Double(number) => number.to_string(),
+                        Datum::Boolean(flag) => flag.to_string(),
+                        _ => continue,
+                    };
+                    transaction.extra.insert(name.clone(), value);
+                }
+                _ => return Err(AvroError::Decode("value of an unexpected type")),
+            }
+        }
+        transaction.op = op.ok_or(AvroError::Decode("no type"))?;
+        transaction.client = client.ok_or(AvroError::Decode("no client"))?;
+        transaction.tx = tx.ok_or(AvroError::Decode("no tx"))?;
+        Ok(transaction)
+    }
+}
+
+// A union of null and one type reads as that type
+fn nullable(schema: &Schema) -> &Schema {
+    match schema {
+        Schema::Union(branches) => match branches.as_slice() {
+            [Schema::Null, schema] | [schema, Schema::Null] => schema,
+            _ => schema,
+        },
+        schema => schema,
+    }
+}
+
+fn optional(schema: &Schema) -> bool {
+    matches!(schema, Schema::Union(branches) if branches.contains(&Schema::Null))
+}
+
+fn scalar(schema: &Schema) -> bool {
+    matches!(
+        nullable(schema),
+        Schema::Boolean
+            | Schema::Int(_)
+            | Schema::Long(_)
+            | Schema::Float
+            | Schema::Double
+            | Schema::String(_)
+            | Schema::Enum(_)
+    )
+}
+
+fn check(target: Target, written: &Schema) -> Option<Target> {
+    let integer = matches!(written, Schema::Int(None) | Schema::Long(None));
+    let text = matches!(written, Schema::String(_) | Schema::Enum(_));
+    match target {
+        Target::Op | Target::Currency => text.then_some(target),
+        Target::Client | Target::Tx | Target::Counterparty => integer.then_some(target),
+        Target::Timestamp(_) => match written {
+            Schema::Int(None) | Schema::Long(None) => Some(Target::Timestamp(1)),
+            Schema::Long(Some(
+                LongLogical::TimestampMillis | LongLogical::LocalTimestampMillis,
+            )) => Some(Target::Timestamp(1_000)),
+            Schema::Long(Some(
+                LongLogical::TimestampMicros | LongLogical::LocalTimestampMicros,
+            )) => Some(Target::Timestamp(1_000_000)),
+            _ => None,
+        },
+        // floats can't hold most amounts exactly
+        Target::Amount(_) => match written {
+            Schema::String(_) => Some(Target::Amount(AmountEncoding::Text)),
+            Schema::Int(None) | Schema::Long(None) => Some(Target::Amount(AmountEncoding::Integer)),
+            Schema::Bytes(Some(BytesLogical::Decimal(_, scale))) => u32::try_from(*scale)
+                .ok()
+                .map(|scale| Target::Amount(AmountEncoding::Decimal(scale))),
+            Schema::Fixed(fixed) => match fixed.logical {
+                Some(FixedLogical::Decimal(_, scale)) => u32::try_from(scale)
+                    .ok()
+                    .map(|scale| Target::Amount(AmountEncoding::Decimal(scale))),
+                _ => None,
+            },
+            _ => None,
+        },
+        Target::Extra(_) | Target::Skip => Some(target),
+    }
+}
+
+fn expected(field: &str) -> &'static str {
+    match field {
+        "type" | "currency" => "a string",
+        "amount" => "a decimal, a string or an integer",
+        "timestamp" => "an integer or a timestamp",
+        _ => "an integer",
+    }
+}
+
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+fn client_id(id: i64) -> Result<ClientId, AvroError> {
+    u128::try_from(id)
+        .ok()
+        .and_then(|id| ClientId::try_from(id).ok())
+        .ok_or(AvroError::Decode("client id out of range"))
+}
+
+fn amount<A: Amount>(encoding: AmountEncoding, value: Datum) -> Result<A, AvroError> {
+    let decimal = match (encoding, value) {
+        (AmountEncoding::Text, Datum::Text(text)) => Decimal::from_str(text.trim()).ok(),
+        (AmountEncoding::Integer, Datum::Long(number)) => Some(Decimal::from(number)),
+        (AmountEncoding::Decimal(scale), Datum::Bytes(bytes)) if bytes.len() <= 16 => {
+            // sign-extended from the first byte
+            let fill = match bytes.first() {
+                Some(byte) if byte & 0x80 != 0 => 0xff,
+                _ => 0,
+            };
+            let mut unscaled = [fill; 16];
+            unscaled[16 - bytes.len()..].copy_from_slice(bytes);
+            Decimal::try_from_i128_with_scale(i128::from_be_bytes(unscaled), scale).ok()
+        }
+        _ => None,
+    };
+    decimal
+        .and_then(A::from_decimal)
+        .ok_or(AvroError::Decode("invalid amount"))
+}
+
+// A value as it was written, borrowing from the record or the schema. Values transactions are
+// never read from are only skipped over.
+#[derive(Debug, PartialEq)]
+enum Datum<'a> {
+    Null,
+    Boolean(bool),
+    Long(i64),
+    Double(f64),
+    Bytes(&'a [u8]),
+    Text(&'a str),
+    Skipped,
+}
+
+fn read<'a, 'd: 'a>(schema: &'a Schema, data: &mut &'d [u8]) -> Result<Datum<'a>, AvroError> {
+    Ok(match schema {
+        Schema::Null => Datum::Null,
+        Schema::Boolean => Datum::Boolean(take(data, 1)?[0] != 0),
+        Schema::Int(_) | Schema::Long(_) => Datum::Long(long(data)?),
+        Schema::Float => {
+            let bytes = take(data, 4)?.try_into().expect("Took four bytes");
+            Datum::Double(f64::from(f32::from_le_bytes(bytes)))
+        }
+        Schema::Double => {
+            let bytes = take(data, 8)?.try_into().expect("Took eight bytes");
+            Datum::Double(f64::from_le_bytes(bytes))
+        }
+        Schema::Bytes(_) => Datum::Bytes(bytes(data)?),
+        Schema::String(_) => Datum::Text(
+            std::str::from_utf8(bytes(data)?).map_err(|_| AvroError::Decode("invalid utf-8"))?,
+        ),
+        Schema::Fixed(fixed) => Datum::Bytes(take(data, fixed.size)?),
+        Schema::Enum(symbols) => {
+            let index = usize::try_from(long(data)?).ok();
+            let symbol = index.and_then(|index| symbols.symbols.get(index));
+            Datum::Text(symbol.ok_or(AvroError::Decode("enum index out of range"))?)
+        }
+        Schema::Union(branches) => {
+            let index = usize::try_from(long(data)?).ok();
+            let branch = index.and_then(|index| branches.get(index));
+            read(
+                branch.ok_or(AvroError::Decode("union index out of range"))?,
+                data,
+            )?
+        }
+        Schema::Record(record) => {
+            for field in &record.fields {
+                read(&field.schema, data)?;
+            }
+            Datum::Skipped
+        }
+        Schema::Array(items) => {
+            for _ in 0..items_in_blocks(data, |data| read(items, data).map(|_| ()))? {}
+            Datum::Skipped
+        }
+        Schema::Map(values) => {
+            items_in_blocks(data, |data| {
+                bytes(data)?;
+                read(values, data).map(|_| ())
+            })?;
+            Datum::Skipped
+        }
+    })
+}
+
+// Reads the blocks of an array or a map, each a count of items followed by the items, until an
+// empty one, and returns the number of items read
+fn items_in_blocks<'d>(
+    data: &mut &'d [u8],
+    mut item: impl FnMut(&mut &'d [u8]) -> Result<(), AvroError>,
+) -> Result<u64, AvroError> {
+    let mut items = 0;
+    loop {
+        let count = long(data)?;
+        if count == 0 {
+            return Ok(items);
+        }
+        // a negative count is followed by the block's size in bytes
+        if count < 0 {
+            long(data)?;
+        }
+        for _ in 0..count.unsigned_abs() {
+            item(data)?;
+            items += 1;
+        }
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], AvroError> {
+    if data.len() < len {
+        return Err(AvroError::Decode("record cut short"));
+    }
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Ok(taken)
+}
+
+fn bytes<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], AvroError> {
+    let len = usize::try_from(long(data)?).map_err(|_| AvroError::Decode("negative length"))?;
+    take(data, len)
+}
+
+// A zigzag varint
+fn long(data: &mut &[u8]) -> Result<i64, AvroError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(data, 1)?[0];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    Err(AvroError::Decode("varint too long"))
+}
+
+// A zigzag varint read from the container file's header
+fn read_long<R: Read>(input: &mut R) -> Result<i64, AvroError> {
+    let mut encoded = Vec::new();
+    loop {
+        let mut byte = [0];
+        input
+            .read_exact(&mut byte)
+            .map_err(|e| AvroError::File(e.to_string()))?;
+        encoded.push(byte[0]);
+        if byte[0] & 0x80 == 0 || encoded.len() > 10 {
+            return long(&mut encoded.as_slice())
+                .map_err(|_| AvroError::File("invalid header".into()));
+        }
+    }
+}
+
+// The schema, codec and sync marker from the header of an object container file
+fn header<R: Read>(input: &mut R) -> Result<(Schema, Option<Compression>, [u8; 16]), AvroError> {
+    let invalid = |e: io::Error| AvroError::File(e.to_string());
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic).map_err(invalid)?;
+    if magic != *b"Obj\x01" {
+        return Err(AvroError::File("not an Avro container file".to_string()));
+    }
+    let mut metadata = HashMap::new();
+    loop {
+        let count = read_long(input)?;
+        if count == 0 {
+            break;
+        }
+        if count < 0 {
+            read_long(input)?;
+        }
+        for _ in 0..count.unsigned_abs() {
+            let mut entry = || -> Result<Vec<u8>, AvroError> {
+                let len = usize::try_from(read_long(input)?)
+                    .map_err(|_| AvroError::File("invalid header".to_string()))?;
+                let mut value = vec![0; len];
+                input.read_exact(&mut value).map_err(invalid)?;
+                Ok(value)
+            };
+            let key = entry()?;
+            metadata.insert(key, entry()?);
+        }
+    }
+    let mut marker = [0; 16];
+    input.read_exact(&mut marker).map_err(invalid)?;
+
+    let schema = metadata
+        .get(b"avro.schema".as_slice())
+        .ok_or_else(|| AvroError::File("no schema".to_string()))?;
+    let schema = parse_schema(&String::from_utf8_lossy(schema))?;
+    let compression = match metadata.get(b"avro.codec".as_slice()).map(Vec::as_slice) {
+        None | Some(b"null") => None,
+        Some(b"deflate") => Some(Compression::Deflate),
+        Some(b"snappy") => Some(Compression::Snappy),
+        Some(codec) => {
+            let codec = String::from_utf8_lossy(codec);
+            return Err(AvroError::File(format!("unsupported codec {codec}")));
+        }
+    };
+    Ok((schema, compression, marker))
+}
+
+// Counts the bytes read through it for `AvroRecords::bytes`, the reader itself is owned by the
+// block iterator
+struct Counted<R> {
+    input: R,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.input.read(buf)?;
+        self.bytes.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+// Transactions read from an Avro object container file, decoded with a `Decoder` for the schema
+// in its header. `line` counts records. A record that can't be decoded leaves no way to find the
+// next one in its block, so the rest of the block is skipped after it.
+pub struct AvroRecords<R: Read> {
+    blocks: BlockStreamingIterator<Counted<R>>,
+    decoder: Decoder,
+    block: Vec<u8>,
+    offset: usize,
+    // records left in `block`
+    rows: usize,
+    line: u64,
+    bytes: Arc<AtomicU64>,
+    done: bool,
+}
+
+impl<R: Read> AvroRecords<R> {
+    pub fn new(input: R) -> Result<Self, AvroError> {
+        Self::with_reader_schema(input, &parse_schema(READER_SCHEMA)?)
+    }
+
+    pub fn with_reader_schema(input: R, reader: &Schema) -> Result<Self, AvroError> {
+        let bytes = Arc::new(AtomicU64::new(0));
+        let mut input = Counted {
+            input,
+            bytes: bytes.clone(),
+        };
+        let (writer, compression, marker) = header(&mut input)?;
+        Ok(Self {
+            blocks: BlockStreamingIterator::new(input, compression, marker),
+            decoder: Decoder::resolve(&writer, reader)?,
+            block: Vec::new(),
+            offset: 0,
+            rows: 0,
+            line: 0,
+            bytes,
+            done: false,
+        })
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl<R: Read> Iterator for AvroRecords<R> {
+    type Item = Result<Transaction, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.rows == 0 {
+            if self.done {
+                return None;
+            }
+            match self.blocks.next() {
+                Ok(Some(block)) => {
+                    self.block.clear();
+                    self.block.extend_from_slice(&block.data);
+                    self.offset = 0;
+                    self.rows = block.number_of_rows;
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    let error = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                    return Some(Err(InputError::Io(error)));
+                }
+            }
+        }
+        self.rows -= 1;
+        self.line += 1;
+        let mut data = &self.block[self.offset..];
+        let decoded = self.decoder.decode(&mut data);
+        self.offset = self.block.len() - data.len();
+        Some(decoded.map_err(|e| {
+            let line = self.line;
+            self.line += self.rows as u64;
+            self.rows = 0;
+            InputError::Malformed {
+                line,
+                error: e.to_string(),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use avro_schema::file::{Block, CompressedBlock};
+    use avro_schema::schema::{Field, Record};
+    use avro_schema::write;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::input::{InputFormat, Records};
+
+    fn zigzag(value: i64, out: &mut Vec<u8>) {
+        write::encode::zigzag_encode(value, out).unwrap();
+    }
+
+    fn text(value: &str, out: &mut Vec<u8>) {
+        zigzag(value.len() as i64, out);
+        out.extend_from_slice(value.as_bytes());
+    }
+
+    fn container(
+        record: Record,
+        rows: usize,
+        data: Vec<u8>,
+        compression: Option<Compression>,
+    ) -> Vec<u8> {
+        let mut file = Vec::new();
+        write::write_metadata(&mut file, record, compression).unwrap();
+        let mut compressed = CompressedBlock::default();
+        write::compress(&mut Block::new(rows, data), &mut compressed, compression).unwrap();
+        write::write_block(&mut file, &compressed).unwrap();
+        file
+    }
+
+    // A producer's schema that renamed `client` and `type`, added fields of its own, moved the
+    // amount to a decimal and has no counterparty
+    fn evolved() -> Record {
+        Record::new(
+            "Payment",
+            vec![
+                Field::new("client_id", Schema::Int(None)),
+                Field::new("channel", Schema::String(None)),
+                Field::new(
+                    "kind",
+                    Schema::Enum(avro_schema::schema::Enum::new(
+                        "Kind",
+                        vec!["deposit".to_string(), "withdrawal".to_string()],
+                    )),
+                ),
+                Field::new("tx", Schema::Long(None)),
+                Field::new(
+                    "amount",
+                    Schema::Union(vec![
+                        Schema::Null,
+                        Schema::Bytes(Some(BytesLogical::Decimal(10, 2))),
+                    ]),
+                ),
+                Field::new("tags", Schema::Array(Box::new(Schema::String(None)))),
+                Field::new("ts", Schema::Long(Some(LongLogical::TimestampMillis))),
+            ],
+        )
+    }
+
+    fn payment(client: i64, kind: i64, tx: i64, cents: Option<i16>, out: &mut Vec<u8>) {
+        zigzag(client, out);
+        text("app", out);
+        zigzag(kind, out);
+        zigzag(tx, out);
+        match cents {
+            Some(cents) => {
+                zigzag(1, out);
+                let bytes = cents.to_be_bytes();
+                zigzag(bytes.len() as i64, out);
+                out.extend_from_slice(&bytes);
+            }
+            None => zigzag(0, out),
+        }
+        // one block of two tags, then the empty one that ends the array
+        zigzag(2, out);
+        text("a", out);
+        text("b", out);
+        zigzag(0, out);
+        zigzag(1_700_000_000_500, out);
+    }
+
+    #[test]
+    fn resolves_renamed_and_added_fields() {
+        let mut data = Vec::new();
+        payment(1, 0, 1, Some(1050), &mut data);
+        payment(1, 1, 2, Some(-25), &mut data);
+        payment(2, 0, 3, None, &mut data);
+        for compression in [None, Some(Compression::Deflate), Some(Compression::Snappy)] {
+            let file = container(evolved(), 3, data.clone(), compression);
+            let mut records = Records::new(file.as_slice(), InputFormat::Avro).unwrap();
+            let deposit = records.next().unwrap().unwrap();
+            assert_eq!(
+                deposit,
+                Transaction {
+                    op: Operation::Deposit,
+                    client: 1,
+                    tx: 1,
+                    amount: Some(dec!(10.50)),
+                    timestamp: Some(1_700_000_000),
+                    extra: [("channel".to_string(), "app".to_string())].into(),
+                    ..Default::default()
+                }
+            );
+            let withdrawal = records.next().unwrap().unwrap();
+            assert_eq!(withdrawal.op, Operation::Withdrawal);
+            assert_eq!(withdrawal.amount, Some(dec!(-0.25)));
+            assert_eq!(records.next().unwrap().unwrap().amount, None);
+            assert!(records.next().is_none());
+            assert_eq!(records.bytes(), file.len() as u64);
+        }
+
+        // defaults other than null don't get in the way
+        let writer = parse_schema(
+            r#"{"type": "record", "name": "T", "fields": [
+                {"name": "type", "type": "string"},
+                {"name": "client", "type": "int"},
+                {"name": "tx", "type": "int"},
+                {"name": "currency", "type": "string", "default": "USD"}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(Decoder::new(&writer).is_ok());
+    }
+
+    #[test]
+    fn turns_away_writers_it_cant_resolve() {
+        let record = |fields| Schema::Record(Record::new("Transaction", fields));
+        // no tx
+        let writer = record(vec![
+            Field::new("type", Schema::String(None)),
+            Field::new("client", Schema::Long(None)),
+        ]);
+        assert!(matches!(
+            Decoder::new(&writer),
+            Err(AvroError::MissingField(field)) if field == "tx"
+        ));
+        let writer = record(vec![
+            Field::new("type", Schema::String(None)),
+            Field::new("client", Schema::Long(None)),
+            Field::new("tx", Schema::Long(None)),
+            Field::new("amount", Schema::Double),
+        ]);
+        assert!(matches!(
+            Decoder::new(&writer),
+            Err(AvroError::FieldType { field, .. }) if field == "amount"
+        ));
+    }
+
+    #[test]
+    fn skips_the_rest_of_a_block_after_a_bad_record() {
+        let mut data = Vec::new();
+        payment(1, 0, 1, Some(100), &mut data);
+        // an enum index the schema doesn't have
+        payment(1, 7, 2, Some(100), &mut data);
+        payment(1, 0, 3, Some(100), &mut data);
+        let file = container(evolved(), 3, data, None);
+        let mut records = AvroRecords::new(file.as_slice()).unwrap();
+        assert!(records.next().unwrap().is_ok());
+        assert!(matches!(
+            records.next(),
+            Some(Err(InputError::Malformed { line: 2, .. }))
+        ));
+        assert!(records.next().is_none());
+        assert_eq!(records.line(), 3);
+    }
+}
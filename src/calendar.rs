@@ -0,0 +1,145 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CalendarError {
+    #[error("Invalid time {0}, expected HH:MM")]
+    Time(String),
+    #[error("Invalid UTC offset {0}, expected +HH:MM or -HH:MM")]
+    Offset(String),
+}
+
+pub const DAY: u64 = 24 * 60 * 60;
+
+// Seconds after midnight of a HH:MM time of day
+pub fn parse_time(time: &str) -> Result<u64, CalendarError> {
+    let invalid = || CalendarError::Time(time.to_string());
+    let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+    match (hours.parse::<u64>(), minutes.parse::<u64>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 3600 + minutes * 60),
+        _ => Err(invalid()),
+    }
+}
+
+// Seconds east of UTC of a +HH:MM or -HH:MM offset
+pub fn parse_offset(offset: &str) -> Result<i64, CalendarError> {
+    let invalid = || CalendarError::Offset(offset.to_string());
+    let (sign, time) = match offset.split_at_checked(1) {
+        Some(("+", time)) => (1, time),
+        Some(("-", time)) => (-1, time),
+        _ => return Err(invalid()),
+    };
+    let secs = parse_time(time).map_err(|_| invalid())?;
+    Ok(sign * secs as i64)
+}
+
+// YYYY-MM-DD of a UTC time
+pub fn date(time: SystemTime) -> String {
+    date_of_day(unix_secs(time).div_euclid(DAY as i64))
+}
+
+// YYYY-MM-DD of a day counted from 1970-01-01
+fn date_of_day(days: i64) -> String {
+    // counts in 400 year eras starting in March, so leap days fall at the end of a year
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+// How the back office books days: a business day ends at the cutoff, in local time at a fixed
+// offset from UTC, and is named after the calendar day it ends on. Anything at or after the
+// cutoff belongs to the next day.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calendar {
+    // seconds after local midnight
+    cutoff: u64,
+    // seconds east of UTC
+    offset: i64,
+}
+
+impl Calendar {
+    pub fn new(cutoff: u64, offset: i64) -> Self {
+        Self { cutoff, offset }
+    }
+
+    pub fn business_day(&self, time: SystemTime) -> String {
+        // shift so that the cutoff lands on midnight of the day it ends
+        let shift = match self.cutoff {
+            0 => 0,
+            cutoff => (DAY - cutoff) as i64,
+        };
+        let local = unix_secs(time) + self.offset + shift;
+        date_of_day(local.div_euclid(DAY as i64))
+    }
+
+    // First cutoff strictly after `time`
+    pub fn next_close(&self, time: SystemTime) -> SystemTime {
+        let secs = unix_secs(time);
+        let close = (self.cutoff as i64 - self.offset).rem_euclid(DAY as i64);
+        let midnight = secs - secs.rem_euclid(DAY as i64);
+        let mut next = midnight + close;
+        if next <= secs {
+            next += DAY as i64;
+        }
+        UNIX_EPOCH + Duration::from_secs(next as u64)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // 2024-02-29 12:00 UTC
+    const NOON: u64 = 1_709_208_000;
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(date(at(NOON)), "2024-02-29");
+        assert_eq!(date(at(NOON + 12 * 3600)), "2024-03-01");
+        assert_eq!(date(at(0)), "1970-01-01");
+        assert_eq!(parse_offset("-05:30"), Ok(-19_800));
+        assert!(parse_offset("05:00").is_err());
+        assert!(parse_time("24:00").is_err());
+    }
+
+    #[test]
+    fn books_transactions_after_the_cutoff_on_the_next_day() {
+        // 17:00 in New York, 22:00 UTC
+        let calendar = Calendar::new(
+            parse_time("17:00").unwrap(),
+            parse_offset("-05:00").unwrap(),
+        );
+
+        assert_eq!(calendar.business_day(at(NOON)), "2024-02-29");
+        assert_eq!(
+            calendar.business_day(at(NOON + 10 * 3600 - 1)),
+            "2024-02-29"
+        );
+        assert_eq!(calendar.business_day(at(NOON + 10 * 3600)), "2024-03-01");
+        assert_eq!(calendar.next_close(at(NOON)), at(NOON + 10 * 3600));
+        assert_eq!(
+            calendar.next_close(at(NOON + 10 * 3600)),
+            at(NOON + 34 * 3600)
+        );
+        // a midnight cutoff keeps calendar days
+        assert_eq!(Calendar::default().business_day(at(NOON)), "2024-02-29");
+    }
+}
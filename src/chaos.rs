@@ -0,0 +1,199 @@
+use std::io;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ChaosError {
+    #[error("Unknown chaos setting: {0}")]
+    UnknownSetting(String),
+    #[error("Invalid value for chaos setting {0}: {1}")]
+    InvalidValue(String, String),
+}
+
+// Points in the pipeline where a forced abort can be requested
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    // after a record is read from the input
+    Read,
+    // after a record is applied to the engine
+    Apply,
+    // halfway through writing the account output
+    Output,
+}
+
+// Faults to inject, parsed from a comma separated spec such as
+// `seed=7,io-error=0.001,delay=0.05,delay-ms=20,abort=apply:1000`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    // probability that a read or write fails with a simulated I/O error
+    pub io_error_rate: f64,
+    // probability that a record is held back for `delay` before being sent to the engine
+    pub delay_rate: f64,
+    pub delay: Duration,
+    // abort the process the nth time the stage is reached
+    pub abort: Option<(Stage, u64)>,
+}
+
+impl FromStr for ChaosConfig {
+    type Err = ChaosError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut config = ChaosConfig::default();
+        for setting in spec.split(',').filter(|s| !s.is_empty()) {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| ChaosError::UnknownSetting(setting.to_string()))?;
+            let invalid = || ChaosError::InvalidValue(key.to_string(), value.to_string());
+            let rate = || match value.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+                _ => Err(invalid()),
+            };
+            match key {
+                "seed" => config.seed = value.parse().map_err(|_| invalid())?,
+                "io-error" => config.io_error_rate = rate()?,
+                "delay" => config.delay_rate = rate()?,
+                "delay-ms" => {
+                    config.delay = Duration::from_millis(value.parse().map_err(|_| invalid())?)
+                }
+                "abort" => {
+                    let (stage, count) = value.split_once(':').ok_or_else(invalid)?;
+                    let stage = match stage {
+                        "read" => Stage::Read,
+                        "apply" => Stage::Apply,
+                        "output" => Stage::Output,
+                        _ => return Err(invalid()),
+                    };
+                    config.abort = Some((stage, count.parse().map_err(|_| invalid())?));
+                }
+                _ => return Err(ChaosError::UnknownSetting(key.to_string())),
+            }
+        }
+        Ok(config)
+    }
+}
+
+// Injects the configured faults. Decisions come from a seeded generator, so a failing run can be
+// reproduced by reusing its seed. Each thread should own its own fork.
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    config: ChaosConfig,
+    state: u64,
+    reached: u64,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            state: config.seed,
+            config,
+            reached: 0,
+        }
+    }
+
+    // Copy with an independent random stream, for use on another thread
+    pub fn fork(&self, stream: u64) -> Self {
+        Self {
+            config: self.config.clone(),
+            state: self.config.seed ^ stream.wrapping_mul(0x9e3779b97f4a7c15),
+            reached: 0,
+        }
+    }
+
+    // Marks one pass through a stage and aborts if it's the configured point
+    pub fn checkpoint(&mut self, stage: Stage) {
+        let Some((abort_stage, count)) = self.config.abort else {
+            return;
+        };
+        if abort_stage != stage {
+            return;
+        }
+        self.reached += 1;
+        if self.reached == count {
+            error!(stage = ?stage, count = count, "Chaos: aborting process");
+            process::abort();
+        }
+    }
+
+    pub fn io_fault(&mut self) -> io::Result<()> {
+        if self.roll(self.config.io_error_rate) {
+            return Err(io::Error::other("injected I/O error"));
+        }
+        Ok(())
+    }
+
+    pub fn delay(&mut self) {
+        if self.roll(self.config.delay_rate) {
+            thread::sleep(self.config.delay);
+        }
+    }
+
+    fn roll(&mut self, rate: f64) -> bool {
+        let sample = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        rate > 0.0 && sample < rate
+    }
+
+    // splitmix64
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn parses_spec() {
+        let config: ChaosConfig = "seed=7,io-error=0.5,delay=0.25,delay-ms=20,abort=apply:1000"
+            .parse()
+            .expect("Valid spec");
+
+        assert_eq!(
+            config,
+            ChaosConfig {
+                seed: 7,
+                io_error_rate: 0.5,
+                delay_rate: 0.25,
+                delay: Duration::from_millis(20),
+                abort: Some((Stage::Apply, 1000)),
+            }
+        );
+        assert_eq!(
+            "io-error=2".parse::<ChaosConfig>(),
+            Err(ChaosError::InvalidValue("io-error".into(), "2".into()))
+        );
+        assert_eq!(
+            "crash=1".parse::<ChaosConfig>(),
+            Err(ChaosError::UnknownSetting("crash".into()))
+        );
+    }
+
+    #[test]
+    fn faults_are_reproducible() {
+        let config = ChaosConfig {
+            seed: 42,
+            io_error_rate: 0.3,
+            ..Default::default()
+        };
+        let faults = |mut chaos: Chaos| -> Vec<bool> {
+            (0..200).map(|_| chaos.io_fault().is_err()).collect()
+        };
+
+        let first = faults(Chaos::new(config.clone()));
+        assert_eq!(first, faults(Chaos::new(config.clone())));
+        assert!(first.iter().any(|&f| f) && !first.iter().all(|&f| f));
+        assert!(faults(Chaos::new(ChaosConfig::default()))
+            .iter()
+            .all(|&f| !f));
+    }
+}
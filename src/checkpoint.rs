@@ -0,0 +1,371 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use rkyv::{Archive, Deserialize, Serialize};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::domain::transaction::{Extra, Operation};
+use crate::domain::tx_history::Node;
+use crate::domain::{Account, AccountStore, Amount, ClientId, History};
+use crate::mmap::Mapping;
+use crate::output;
+use crate::snapshot::{AccountRecord, HistoryRecord};
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to archive checkpoint: {0}")]
+    Archive(String),
+    #[error("Invalid checkpoint: {0}")]
+    Invalid(String),
+}
+
+// Engine state archived with rkyv. The archived layout is read in place, so a checkpoint can be
+// mapped and queried straight away instead of being deserialized into fresh maps. Both lists
+// are sorted for binary search and amounts use the encoding of `Amount::to_bits`.
+#[derive(Debug, Default, PartialEq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct Checkpoint {
+    accounts: Vec<AccountEntry>,
+    history: Vec<HistoryEntry>,
+    // input records read before the checkpoint was taken, when it was taken at a known point of
+    // an input file
+    position: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+struct AccountEntry {
+    client: ClientId,
+    available: [u8; 16],
+    held: [u8; 16],
+    total: [u8; 16],
+    locked: bool,
+    credit_limit: Option<[u8; 16]>,
+    fees: Option<[u8; 16]>,
+    first_activity: Option<u64>,
+    last_activity: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+struct HistoryEntry {
+    client: ClientId,
+    tx: u32,
+    op: u8,
+    amount: Option<[u8; 16]>,
+    disputes: u8,
+    timestamp: Option<u64>,
+    counterparty: Option<ClientId>,
+    // extra columns as name and value pairs, in name order
+    extra: Vec<(String, String)>,
+}
+
+impl Checkpoint {
+    pub fn new<A: Amount>(history: &History<A>, accounts: &AccountStore<A>) -> Self {
+        let mut accounts: Vec<AccountEntry> = accounts
+            .values()
+            .map(|act| AccountEntry {
+                client: act.client,
+                available: act.available.to_bits(),
+                held: act.held.to_bits(),
+                total: act.total.to_bits(),
+                locked: act.locked,
+                credit_limit: act.credit_limit.map(|limit| limit.to_bits()),
+                fees: act.fees.map(|fees| fees.to_bits()),
+                first_activity: act.first_activity,
+                last_activity: act.last_activity,
+            })
+            .collect();
+        accounts.sort_by_key(|act| act.client);
+
+        let mut history: Vec<HistoryEntry> = history
+            .iter()
+            .map(|((client, tx), node)| HistoryEntry {
+                client,
+                tx,
+                op: node.op.code(),
+                amount: node.amount.map(|amount| amount.to_bits()),
+                disputes: node.disputes,
+                timestamp: node.timestamp,
+                counterparty: node.counterparty,
+                extra: node.extra.into_iter().collect(),
+            })
+            .collect();
+        history.sort_by_key(|entry| (entry.client, entry.tx));
+
+        Self {
+            accounts,
+            history,
+            position: None,
+        }
+    }
+
+    // Records that the state covers the first `position` records of the input
+    pub fn at(mut self, position: u64) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+        let bytes =
+            rkyv::to_bytes::<_, 4096>(self).map_err(|e| CheckpointError::Archive(e.to_string()))?;
+        Ok(output::write_atomic(path, |file| file.write_all(&bytes))?)
+    }
+}
+
+// A checkpoint file mapped into memory. Opening validates the archive once; lookups then read
+// the mapping directly.
+#[derive(Debug)]
+pub struct MappedCheckpoint {
+    map: Mapping,
+}
+
+impl MappedCheckpoint {
+    pub fn open(path: &Path) -> Result<Self, CheckpointError> {
+        let map = Mapping::read_only(File::open(path)?)?;
+        rkyv::check_archived_root::<Checkpoint>(map.bytes())
+            .map_err(|e| CheckpointError::Invalid(e.to_string()))?;
+        Ok(Self { map })
+    }
+
+    fn archived(&self) -> &ArchivedCheckpoint {
+        // SAFETY: the bytes were validated as a `Checkpoint` in `open` and the mapping is
+        // read-only for as long as `self` lives
+        unsafe { rkyv::archived_root::<Checkpoint>(self.map.bytes()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.archived().accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Input records the state covers, the ones a resumed run skips
+    pub fn position(&self) -> Option<u64> {
+        self.archived().position.as_ref().copied()
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<AccountRecord> {
+        let accounts = &self.archived().accounts;
+        let idx = accounts
+            .binary_search_by_key(&client, |act| act.client)
+            .ok()?;
+        let act = &accounts[idx];
+        Some(AccountRecord {
+            client,
+            available: Decimal::from_bits(act.available),
+            held: Decimal::from_bits(act.held),
+            total: Decimal::from_bits(act.total),
+            locked: act.locked,
+        })
+    }
+
+    // History entry of a transaction, what a dispute, resolve or chargeback looks up
+    pub fn transaction(&self, client: ClientId, tx: u32) -> Option<HistoryRecord> {
+        let history = &self.archived().history;
+        let idx = history
+            .binary_search_by_key(&(client, tx), |entry| (entry.client, entry.tx))
+            .ok()?;
+        Some(record(&history[idx]))
+    }
+
+    // Copies the archived state back into an account store and history that an engine can
+    // continue from, e.g. after a crash. Amounts are read back as the type they were archived as.
+    pub fn restore<A: Amount>(&self) -> (History<A>, AccountStore<A>) {
+        let archived = self.archived();
+        let accounts = archived
+            .accounts
+            .iter()
+            .map(|act| {
+                let account = Account {
+                    client: act.client,
+                    currency: None,
+                    available: A::from_bits(act.available),
+                    held: A::from_bits(act.held),
+                    total: A::from_bits(act.total),
+                    locked: act.locked,
+                    credit_limit: act.credit_limit.as_ref().map(|&bits| A::from_bits(bits)),
+                    fees: act.fees.as_ref().map(|&bits| A::from_bits(bits)),
+                    first_activity: act.first_activity.as_ref().copied(),
+                    last_activity: act.last_activity.as_ref().copied(),
+                };
+                (act.client, account)
+            })
+            .collect();
+        let mut history = History::new();
+        for entry in archived.history.iter() {
+            let rec = record(entry);
+            let node = Node {
+                op: rec.op,
+                amount: entry.amount.as_ref().map(|&bits| A::from_bits(bits)),
+                disputes: rec.disputes,
+                timestamp: rec.timestamp,
+                counterparty: rec.counterparty,
+                extra: rec.extra,
+            };
+            history.replace((rec.client, rec.tx), Some(node));
+        }
+        (history, accounts)
+    }
+
+    // Transactions of a client currently under dispute
+    pub fn open_disputes(&self, client: ClientId) -> impl Iterator<Item = HistoryRecord> + '_ {
+        let history = &self.archived().history;
+        let start = history.partition_point(|entry| entry.client < client);
+        history[start..]
+            .iter()
+            .take_while(move |entry| entry.client == client)
+            .filter(|entry| entry.op == Operation::Dispute.code())
+            .map(record)
+    }
+}
+
+fn record(entry: &ArchivedHistoryEntry) -> HistoryRecord {
+    HistoryRecord {
+        client: entry.client,
+        tx: entry.tx,
+        op: Operation::from_code(entry.op).unwrap_or_default(),
+        amount: entry.amount.as_ref().map(|&bits| Decimal::from_bits(bits)),
+        disputes: entry.disputes,
+        timestamp: entry.timestamp.as_ref().copied(),
+        counterparty: entry.counterparty.as_ref().copied(),
+        extra: entry
+            .extra
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Extra>(),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{env, fs};
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::Transaction;
+    use crate::engine::{CreditLimits, Engine, FeeModel};
+    use crate::snapshot::Snapshot;
+
+    #[test]
+    fn mapped_checkpoint_answers_lookups() {
+        let mut engine = Engine::new();
+        for (op, client, tx, amount) in [
+            (Operation::Deposit, 2, 1, Some(dec!(10))),
+            (Operation::Deposit, 2, 2, Some(dec!(2.5))),
+            (Operation::Deposit, 5, 3, Some(dec!(1))),
+            (Operation::Dispute, 2, 2, None),
+        ] {
+            let transaction = Transaction {
+                op,
+                client,
+                tx,
+                amount,
+                ..Default::default()
+            };
+            engine.process(transaction).unwrap();
+        }
+        let path = env::temp_dir().join(format!("bank-checkpoint-{}.rkyv", std::process::id()));
+        Checkpoint::new(engine.history(), engine.accounts())
+            .at(4)
+            .save(&path)
+            .expect("Failed to save checkpoint");
+
+        let checkpoint = MappedCheckpoint::open(&path).expect("Failed to open checkpoint");
+
+        assert_eq!(checkpoint.len(), 2);
+        assert_eq!(checkpoint.position(), Some(4));
+        let act = checkpoint.account(2).expect("Client 2 is archived");
+        assert_eq!((act.available, act.held), (dec!(10), dec!(2.5)));
+        assert!(checkpoint.account(3).is_none());
+        assert_eq!(
+            checkpoint.transaction(5, 3).map(|rec| rec.amount),
+            Some(Some(dec!(1)))
+        );
+        let disputes: Vec<u32> = checkpoint.open_disputes(2).map(|rec| rec.tx).collect();
+        assert_eq!(disputes, vec![2]);
+
+        let mut restored = Engine::restore(&checkpoint);
+        assert_eq!(restored.accounts(), engine.accounts());
+        restored
+            .process(Transaction {
+                op: Operation::Resolve,
+                client: 2,
+                tx: 2,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(restored.accounts()[&2].held, dec!(0));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn resuming_matches_the_uninterrupted_run() {
+        let configure = |engine: Engine| {
+            engine
+                .with_fees(FeeModel::new().deposit("0.5".parse().unwrap()))
+                .with_credit_limits(CreditLimits::new().global(dec!(5)))
+                .with_dispute_limit(2)
+                .with_dispute_window(std::time::Duration::from_secs(100))
+        };
+        let transaction = |op, client, tx, amount, timestamp| {
+            let mut transaction = Transaction {
+                op,
+                client,
+                tx,
+                amount,
+                timestamp: Some(timestamp),
+                ..Default::default()
+            };
+            if tx == 1 {
+                transaction.extra.insert("merchant".into(), "m-1".into());
+            }
+            transaction
+        };
+        let input = vec![
+            transaction(Operation::Deposit, 1, 1, Some(dec!(10)), 10),
+            transaction(Operation::Withdrawal, 1, 2, Some(dec!(3)), 20),
+            transaction(Operation::Dispute, 1, 1, None, 30),
+            transaction(Operation::Resolve, 1, 1, None, 40),
+            // resumed from here
+            transaction(Operation::Dispute, 1, 1, None, 50),
+            transaction(Operation::Resolve, 1, 1, None, 60),
+            // over the dispute limit
+            transaction(Operation::Dispute, 1, 1, None, 70),
+            transaction(Operation::Deposit, 2, 3, Some(dec!(4)), 80),
+            // outside the dispute window
+            transaction(Operation::Dispute, 2, 3, None, 500),
+            // into the overdraft
+            transaction(Operation::Withdrawal, 2, 4, Some(dec!(8)), 510),
+        ];
+
+        let mut full = configure(Engine::new());
+        full.process_all(input.clone());
+
+        let mut first = configure(Engine::new());
+        first.process_all(input[..4].to_vec());
+        let path = env::temp_dir().join(format!("bank-resume-{}.rkyv", std::process::id()));
+        first.checkpoint().at(4).save(&path).unwrap();
+        let checkpoint = MappedCheckpoint::open(&path).unwrap();
+        let mut resumed = configure(Engine::restore(&checkpoint));
+        resumed.process_all(input[checkpoint.position().unwrap() as usize..].to_vec());
+
+        assert_eq!(resumed.accounts(), full.accounts());
+        assert_eq!(
+            Snapshot::new(resumed.history(), resumed.accounts()),
+            Snapshot::new(full.history(), full.accounts())
+        );
+        assert_eq!(full.accounts()[&1].fees, Some(dec!(0.5)));
+        assert_eq!(full.accounts()[&1].held, dec!(0));
+        assert_eq!(full.accounts()[&2].available, dec!(-4.5));
+        assert_eq!(full.accounts()[&2].last_activity, Some(510));
+        fs::remove_file(path).ok();
+    }
+}
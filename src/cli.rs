@@ -0,0 +1,1502 @@
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bank::calendar::{self, Calendar, CalendarError};
+use bank::chaos::{ChaosConfig, ChaosError};
+use bank::cluster::{ClusterError, Shard};
+use bank::currency::{Currencies, Currency, CurrencyError};
+use bank::domain::{transaction::LockPolicy, ClientId};
+use bank::engine::{
+    AmountPrecision, BalanceCaps, CreditLimits, DisputePolicy, FeeModel, Limits, TxOrder,
+};
+use bank::input::{self, InputError, InputFormat, Permissions, UnknownPolicy};
+use bank::journal::Durability;
+use bank::output::OutputFormat;
+use bank::publish::PublishConfig;
+use bank::report::{ReportError, Schedule};
+use bank::settings;
+use bank::statement::StatementFormat;
+use bank::wire::{Endpoint, WireError};
+use rust_decimal::Decimal;
+use thiserror::Error;
+use tonic::codegen::http::uri::InvalidUri;
+use tonic::transport::Uri;
+use tracing::level_filters::LevelFilter;
+
+use crate::logger::LogFormat;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CliError {
+    #[error("Missing argument: {0}")]
+    MissingArgument(&'static str),
+    #[error("Missing value for {0}")]
+    MissingValue(String),
+    #[error("Unrecognized argument: {0}")]
+    UnknownArgument(String),
+    #[error("Invalid value for {0}: {1}")]
+    InvalidValue(String, String),
+    #[error("{0} can't be combined with {1}")]
+    Conflict(&'static str, &'static str),
+    #[error("{0} needs {1}")]
+    Requires(&'static str, String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // bank <transactions.csv> [options]
+    Process(Box<Options>),
+    // bank snapshot-diff <before.json> <after.json>
+    SnapshotDiff {
+        before: PathBuf,
+        after: PathBuf,
+    },
+    // bank query --state <snapshot.json|checkpoint.rkyv|journal.csv> --client <id>
+    Query {
+        state: PathBuf,
+        client: ClientId,
+    },
+    // bank explain <transactions.csv> --tx <id>
+    Explain {
+        input: PathBuf,
+        tx: u32,
+    },
+    // bank send <transactions.csv> <tcp://host:port|unix:///path> [--source <name>]
+    Send {
+        input: PathBuf,
+        to: Endpoint,
+        source: Option<String>,
+    },
+    // bank route <transactions.csv> (--shard <first>-<last>=<address>... | --node <address>...)
+    Route {
+        input: PathBuf,
+        shards: Vec<Shard>,
+        // addresses of the instances clients are hashed onto
+        nodes: Vec<String>,
+    },
+    // bank promote <standby address>
+    Promote {
+        standby: Endpoint,
+    },
+    // bank merge-outputs <part.csv|snapshot.json>... [--sum] [--output <path>], or bank merge
+    Merge {
+        parts: Vec<PathBuf>,
+        output: Option<PathBuf>,
+        // add up the balances of clients in several parts instead of failing
+        sum: bool,
+    },
+    // bank serve [<host:port>] [--serve-http <host:port>] [--workers <n>]
+    Serve {
+        // gRPC
+        listen: Option<SocketAddr>,
+        // REST API
+        http: Option<SocketAddr>,
+        workers: Option<usize>,
+    },
+    // bank statement <journal.csv>... (--client <id> [--output <path>] | --dir <dir> [--client <id>])
+    //     [--format markdown|html]
+    Statement {
+        journals: Vec<PathBuf>,
+        client: Option<ClientId>,
+        format: StatementFormat,
+        output: Option<PathBuf>,
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub input: PathBuf,
+    // further input files, read after `input` in the order given
+    pub inputs: Vec<PathBuf>,
+    // detected from the input's extension when not given
+    pub input_format: Option<InputFormat>,
+    pub snapshot: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    // input records between checkpoints written during the run
+    pub checkpoint_every: Option<u64>,
+    // checkpoint of an earlier run to continue from
+    pub restore: Option<PathBuf>,
+    // like `restore`, skipping the input records the checkpoint covers
+    pub resume: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    // database the accounts and the history are written to instead, from `--output sqlite://<path>`
+    pub sqlite: Option<PathBuf>,
+    // dead letter file of the transactions the engine rejected
+    pub rejects: Option<PathBuf>,
+    // downstream gRPC service account updates and rejections are pushed to
+    pub publish_to: Option<Uri>,
+    pub publish: PublishConfig,
+    // double-entry postings of the applied transactions, written at the end of the run
+    pub ledger: Option<PathBuf>,
+    pub dump_state: Option<PathBuf>,
+    pub merge_into: Option<PathBuf>,
+    pub format: OutputFormat,
+    pub trace_clients: Vec<ClientId>,
+    pub rollback: Option<usize>,
+    pub history: Option<PathBuf>,
+    // sled database the history is kept in, from `--history-backend sled:<path>`
+    pub sled_history: Option<PathBuf>,
+    // history nodes kept in memory in front of the `--history` file or database
+    pub max_history_mem: Option<usize>,
+    pub anonymize: Option<String>,
+    pub perturb_amounts: bool,
+    pub emit_transactions: Option<PathBuf>,
+    pub log_sensitive: bool,
+    pub echo_columns: bool,
+    pub unknown_ops: UnknownPolicy,
+    // fail on the first malformed record instead of skipping it
+    pub strict: bool,
+    pub log_format: LogFormat,
+    // info unless given, trace adds every step the engine takes
+    pub log_level: Option<LevelFilter>,
+    pub max_tps: Option<NonZeroU32>,
+    pub workers: Option<usize>,
+    pub readers: Option<usize>,
+    pub replicate_to: Option<Endpoint>,
+    pub standby: bool,
+    // consumer group of a kafka:// input, `bank` by default
+    pub kafka_group: Option<String>,
+    // keeps consuming a kafka:// input once the end of the topic is reached
+    pub kafka_follow: bool,
+    pub report_at: Option<Schedule>,
+    pub report_dir: Option<PathBuf>,
+    // business days for reports and journal files, from --cutoff and --utc-offset
+    pub calendar: Option<Calendar>,
+    pub verify_determinism: bool,
+    pub chaos: Option<ChaosConfig>,
+    pub journal: Option<PathBuf>,
+    pub journal_durability: Durability,
+    // replay the journal before processing and append to it instead of starting a new one
+    pub recover: bool,
+    pub chargeback_fee: Option<Decimal>,
+    // from --deposit-fee and --withdrawal-fee
+    pub fees: FeeModel,
+    // from --balance-cap and --balance-cap-tier, clients are assigned tiers by --client-tiers
+    pub balance_caps: BalanceCaps,
+    pub client_tiers: Option<PathBuf>,
+    pub dispute_limit: Option<u8>,
+    // in days, for dated transactions
+    pub dispute_window: Option<Duration>,
+    // checks that deposit and withdrawal ids increase per client
+    pub tx_order: Option<TxOrder>,
+    // checks that transaction timestamps don't go back in time per client
+    pub timestamp_order: Option<TxOrder>,
+    pub lock_policy: Option<LockPolicy>,
+    pub dispute_policy: Option<DisputePolicy>,
+    // rejects amounts with more than four decimals rather than rounding them
+    pub amount_precision: Option<AmountPrecision>,
+    // overdrafts for every client and for single clients
+    pub credit_limits: CreditLimits,
+    // tx ids are unique across clients rather than per client
+    pub global_tx_ids: bool,
+    // check account invariants after every transaction and stop at the first violation
+    pub check_invariants: bool,
+    // limits and fees read from a file instead, reloaded on SIGHUP while listening on an address
+    pub settings: Option<PathBuf>,
+    // run configuration file, taken into the options below before the run starts
+    pub config: Option<PathBuf>,
+    // limits and fees from the configuration file, when it sets any
+    pub limits: Option<Limits>,
+    // transactions read ahead of the engine, from the configuration file
+    pub channel_capacity: Option<usize>,
+    // operations each source may issue, from --allow
+    pub permissions: Permissions,
+    // accounts with funds and no transaction in the last `dormant_after` applied transactions
+    pub dormant_report: Option<PathBuf>,
+    pub dormant_after: Option<u64>,
+    // rounds output balances to the currency's minor unit, from --currency and --currency-exponent
+    pub currency: Option<Currency>,
+    // keeps balances per client and currency, from the transactions' currency column
+    pub multi_currency: bool,
+}
+
+// A flag, or another part of a run like a shard directory input, that the rules below refer to
+#[derive(Debug, Clone, Copy)]
+struct Flag {
+    name: &'static str,
+    set: fn(&Options) -> bool,
+}
+
+const SHARD_DIR: Flag = Flag {
+    name: "a shard directory",
+    set: |o| o.input.is_dir(),
+};
+const ADDRESS: Flag = Flag {
+    name: "an address to listen on",
+    set: |o| o.listens(),
+};
+const INPUTS: Flag = Flag {
+    name: "further inputs",
+    set: |o| !o.inputs.is_empty(),
+};
+const WORKERS: Flag = Flag {
+    name: "--workers",
+    set: |o| o.workers.is_some(),
+};
+const KAFKA: Flag = Flag {
+    name: "a kafka:// input",
+    set: |o| o.consumes(),
+};
+const KAFKA_GROUP: Flag = Flag {
+    name: "--kafka-group",
+    set: |o| o.kafka_group.is_some(),
+};
+const KAFKA_FOLLOW: Flag = Flag {
+    name: "--kafka-follow",
+    set: |o| o.kafka_follow,
+};
+const READERS: Flag = Flag {
+    name: "--readers",
+    set: |o| o.readers.is_some(),
+};
+const STANDBY: Flag = Flag {
+    name: "--standby",
+    set: |o| o.standby,
+};
+const REPLICATE_TO: Flag = Flag {
+    name: "--replicate-to",
+    set: |o| o.replicate_to.is_some(),
+};
+const MERGE_INTO: Flag = Flag {
+    name: "--merge-into",
+    set: |o| o.merge_into.is_some(),
+};
+const RESTORE: Flag = Flag {
+    name: "--restore",
+    set: |o| o.restore.is_some(),
+};
+const RESUME: Flag = Flag {
+    name: "--resume",
+    set: |o| o.resume.is_some(),
+};
+const CHECKPOINT: Flag = Flag {
+    name: "--checkpoint",
+    set: |o| o.checkpoint.is_some(),
+};
+const CHECKPOINT_EVERY: Flag = Flag {
+    name: "--checkpoint-every",
+    set: |o| o.checkpoint_every.is_some(),
+};
+const JOURNAL: Flag = Flag {
+    name: "--journal",
+    set: |o| o.journal.is_some(),
+};
+const RECOVER: Flag = Flag {
+    name: "--recover",
+    set: |o| o.recover,
+};
+const HISTORY: Flag = Flag {
+    name: "--history",
+    set: |o| o.history.is_some(),
+};
+const SLED_HISTORY: Flag = Flag {
+    name: "--history-backend",
+    set: |o| o.sled_history.is_some(),
+};
+const MAX_HISTORY_MEM: Flag = Flag {
+    name: "--max-history-mem",
+    set: |o| o.max_history_mem.is_some(),
+};
+const TRACE_CLIENT: Flag = Flag {
+    name: "--trace-client",
+    set: |o| !o.trace_clients.is_empty(),
+};
+const ROLLBACK: Flag = Flag {
+    name: "--rollback",
+    set: |o| o.rollback.is_some(),
+};
+const VERIFY_DETERMINISM: Flag = Flag {
+    name: "--verify-determinism",
+    set: |o| o.verify_determinism,
+};
+const ANONYMIZE: Flag = Flag {
+    name: "--anonymize",
+    set: |o| o.anonymize.is_some(),
+};
+const EMIT_TRANSACTIONS: Flag = Flag {
+    name: "--emit-transactions",
+    set: |o| o.emit_transactions.is_some(),
+};
+const MAX_TPS: Flag = Flag {
+    name: "--max-tps",
+    set: |o| o.max_tps.is_some(),
+};
+const CHAOS: Flag = Flag {
+    name: "--chaos",
+    set: |o| o.chaos.is_some(),
+};
+const INPUT_FORMAT: Flag = Flag {
+    name: "--input-format",
+    set: |o| o.input_format.is_some(),
+};
+const UNKNOWN_OPS: Flag = Flag {
+    name: "--unknown-ops",
+    set: |o| o.unknown_ops != UnknownPolicy::Skip,
+};
+const ALLOW: Flag = Flag {
+    name: "--allow",
+    set: |o| !o.permissions.is_empty(),
+};
+const REJECTS: Flag = Flag {
+    name: "--rejects",
+    set: |o| o.rejects.is_some(),
+};
+const PUBLISH_TO: Flag = Flag {
+    name: "--publish-to",
+    set: |o| o.publish_to.is_some(),
+};
+const PUBLISH: Flag = Flag {
+    name: "--publish-batch or --publish-retries",
+    set: |o| o.publish != PublishConfig::default(),
+};
+const LEDGER: Flag = Flag {
+    name: "--ledger",
+    set: |o| o.ledger.is_some(),
+};
+const DORMANT_REPORT: Flag = Flag {
+    name: "--dormant-report",
+    set: |o| o.dormant_report.is_some(),
+};
+const CHECK_INVARIANTS: Flag = Flag {
+    name: "--check-invariants",
+    set: |o| o.check_invariants,
+};
+const GLOBAL_TX_IDS: Flag = Flag {
+    name: "--global-tx-ids",
+    set: |o| o.global_tx_ids,
+};
+const REPORT_AT: Flag = Flag {
+    name: "--report-at",
+    set: |o| o.report_at.is_some(),
+};
+const REPORT_DIR: Flag = Flag {
+    name: "--report-dir",
+    set: |o| o.report_dir.is_some(),
+};
+const CUTOFF: Flag = Flag {
+    name: "--cutoff",
+    set: |o| o.calendar.is_some(),
+};
+const CHARGEBACK_FEE: Flag = Flag {
+    name: "--chargeback-fee",
+    set: |o| o.chargeback_fee.is_some(),
+};
+const FEES: Flag = Flag {
+    name: "--deposit-fee or --withdrawal-fee",
+    set: |o| !o.fees.is_empty(),
+};
+const BALANCE_CAP: Flag = Flag {
+    name: "--balance-cap or --balance-cap-tier",
+    set: |o| !o.balance_caps.is_empty(),
+};
+const DISPUTE_LIMIT: Flag = Flag {
+    name: "--dispute-limit",
+    set: |o| o.dispute_limit.is_some(),
+};
+const DISPUTE_WINDOW: Flag = Flag {
+    name: "--dispute-window",
+    set: |o| o.dispute_window.is_some(),
+};
+const TX_ORDER: Flag = Flag {
+    name: "--tx-order",
+    set: |o| o.tx_order.is_some(),
+};
+const TIMESTAMP_ORDER: Flag = Flag {
+    name: "--timestamp-order",
+    set: |o| o.timestamp_order.is_some(),
+};
+const LOCK_POLICY: Flag = Flag {
+    name: "--lock-policy",
+    set: |o| o.lock_policy.is_some(),
+};
+const DISPUTE_POLICY: Flag = Flag {
+    name: "--dispute-policy",
+    set: |o| o.dispute_policy.is_some(),
+};
+const AMOUNT_PRECISION: Flag = Flag {
+    name: "--amount-precision",
+    set: |o| o.amount_precision.is_some(),
+};
+const CREDIT_LIMIT: Flag = Flag {
+    name: "--credit-limit or --credit-limit-client",
+    set: |o| !o.credit_limits.is_empty(),
+};
+const SETTINGS: Flag = Flag {
+    name: "--settings",
+    set: |o| o.settings.is_some(),
+};
+const CONFIG_LIMITS: Flag = Flag {
+    name: "limits from --config",
+    set: |o| o.limits.is_some(),
+};
+
+// What settings files and limits from --config replace
+const LIMIT_FLAGS: [Flag; 11] = [
+    BALANCE_CAP,
+    DISPUTE_LIMIT,
+    DISPUTE_WINDOW,
+    TX_ORDER,
+    TIMESTAMP_ORDER,
+    LOCK_POLICY,
+    DISPUTE_POLICY,
+    AMOUNT_PRECISION,
+    CREDIT_LIMIT,
+    CHARGEBACK_FEE,
+    FEES,
+];
+
+// Each flag can't be combined with any of the ones listed with it
+const CONFLICTS: &[(Flag, &[Flag])] = &[
+    // workers apply transactions out of the main loop, each to the engine of its own clients
+    (
+        WORKERS,
+        &[
+            MERGE_INTO,
+            RESTORE,
+            RESUME,
+            RECOVER,
+            TRACE_CLIENT,
+            ROLLBACK,
+            HISTORY,
+            SLED_HISTORY,
+            CHECKPOINT_EVERY,
+            REPLICATE_TO,
+            SETTINGS,
+            CONFIG_LIMITS,
+            CHECK_INVARIANTS,
+            GLOBAL_TX_IDS,
+            REJECTS,
+            PUBLISH_TO,
+            LEDGER,
+            DORMANT_REPORT,
+            REPORT_DIR,
+        ],
+    ),
+    (WORKERS, &LIMIT_FLAGS),
+    // shards are processed one file at a time, by engines of their own
+    (
+        SHARD_DIR,
+        &[
+            INPUTS,
+            RESTORE,
+            RESUME,
+            ANONYMIZE,
+            EMIT_TRANSACTIONS,
+            MAX_TPS,
+            CHAOS,
+            JOURNAL,
+            HISTORY,
+            SLED_HISTORY,
+            CHECKPOINT_EVERY,
+            REPLICATE_TO,
+            UNKNOWN_OPS,
+            GLOBAL_TX_IDS,
+            INPUT_FORMAT,
+            REJECTS,
+            PUBLISH_TO,
+            ALLOW,
+            SETTINGS,
+            CONFIG_LIMITS,
+            CHECK_INVARIANTS,
+            LEDGER,
+            DORMANT_REPORT,
+        ],
+    ),
+    (SHARD_DIR, &LIMIT_FLAGS),
+    (ADDRESS, &[INPUTS, CHAOS, CHECKPOINT_EVERY, RESUME]),
+    // offsets are committed as the main loop applies transactions, in the order they're read
+    (KAFKA, &[INPUTS, WORKERS, CHECKPOINT_EVERY, RESUME]),
+    // the standby mirrors what the engine applies inline, as it's applied, and a rollback would
+    // leave postings of transactions that were undone, or journal records that recovering
+    // applies again
+    (ROLLBACK, &[REPLICATE_TO, LEDGER, JOURNAL]),
+    // a standby receives the fees and limits of its primary along with the transactions
+    (STANDBY, &[CHARGEBACK_FEE, FEES, SETTINGS, CONFIG_LIMITS]),
+    (MERGE_INTO, &[VERIFY_DETERMINISM, RESTORE, RESUME, RECOVER]),
+    (RESTORE, &[RESUME, HISTORY, SLED_HISTORY, RECOVER]),
+    (RESUME, &[HISTORY, SLED_HISTORY, RECOVER]),
+    (HISTORY, &[SLED_HISTORY]),
+    // a cutoff reports at the close of every business day
+    (CUTOFF, &[RECOVER, REPORT_AT]),
+    (SETTINGS, &LIMIT_FLAGS),
+    (CONFIG_LIMITS, &LIMIT_FLAGS),
+];
+
+// Each flag needs one of the ones listed with it, a flag listed more than once needs one of each
+const REQUIRES: &[(Flag, &[Flag])] = &[
+    (CHECKPOINT_EVERY, &[CHECKPOINT]),
+    (RECOVER, &[JOURNAL]),
+    (MAX_HISTORY_MEM, &[HISTORY, SLED_HISTORY]),
+    (READERS, &[ADDRESS]),
+    (STANDBY, &[ADDRESS]),
+    (REPORT_AT, &[ADDRESS]),
+    (REPORT_AT, &[REPORT_DIR]),
+    (REPORT_DIR, &[REPORT_AT, CUTOFF]),
+    (CUTOFF, &[ADDRESS]),
+    (CUTOFF, &[REPORT_DIR, JOURNAL]),
+    (PUBLISH, &[PUBLISH_TO]),
+    (KAFKA_GROUP, &[KAFKA]),
+    (KAFKA_FOLLOW, &[KAFKA]),
+];
+
+impl Options {
+    // Whether the input is an address to receive transactions on rather than a file
+    pub fn listens(&self) -> bool {
+        self.input
+            .to_str()
+            .is_some_and(|input| input.contains("://"))
+            && !self.consumes()
+    }
+
+    // Whether the input is a Kafka topic to consume transactions from
+    pub fn consumes(&self) -> bool {
+        self.input
+            .to_str()
+            .is_some_and(|input| input.starts_with("kafka://"))
+    }
+
+    // Checks the flags of a run against each other, once the configuration file is taken in
+    pub fn check(&self) -> Result<(), CliError> {
+        for (flag, others) in CONFLICTS.iter().filter(|(flag, _)| (flag.set)(self)) {
+            if let Some(other) = others.iter().find(|other| (other.set)(self)) {
+                return Err(CliError::Conflict(other.name, flag.name));
+            }
+        }
+        for (flag, needed) in REQUIRES.iter().filter(|(flag, _)| (flag.set)(self)) {
+            if !needed.iter().any(|needed| (needed.set)(self)) {
+                let names: Vec<&str> = needed.iter().map(|needed| needed.name).collect();
+                return Err(CliError::Requires(flag.name, names.join(" or ")));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, CliError> {
+    // skip the binary name
+    let mut args = args.into_iter().skip(1).peekable();
+    // `--` ends the subcommands, so an input file can have the name of one
+    let escaped = args.next_if(|arg| arg == "--").is_some();
+    // without an input file, transactions are read from stdin
+    let first = match args.peek() {
+        Some(arg) if escaped || !arg.starts_with("--") => args.next().expect("Peeked above"),
+        _ => input::STDIN.to_string(),
+    };
+    if escaped {
+        return parse_process(first, args);
+    }
+
+    if first == "snapshot-diff" {
+        let before = args
+            .next()
+            .ok_or(CliError::MissingArgument("before snapshot"))?;
+        let after = args
+            .next()
+            .ok_or(CliError::MissingArgument("after snapshot"))?;
+        if let Some(extra) = args.next() {
+            return Err(CliError::UnknownArgument(extra));
+        }
+        return Ok(Command::SnapshotDiff {
+            before: before.into(),
+            after: after.into(),
+        });
+    }
+
+    if first == "query" {
+        return parse_query(args);
+    }
+    if first == "explain" {
+        return parse_explain(args);
+    }
+    if first == "route" {
+        return parse_route(args);
+    }
+    if first == "merge" || first == "merge-outputs" {
+        return parse_merge(args);
+    }
+    if first == "statement" {
+        return parse_statement(args);
+    }
+    if first == "serve" {
+        return parse_serve(args);
+    }
+    if first == "promote" {
+        let to = args
+            .next()
+            .ok_or(CliError::MissingArgument("standby address"))?;
+        let standby = to
+            .parse()
+            .map_err(|e: WireError| CliError::InvalidValue(to, e.to_string()))?;
+        if let Some(extra) = args.next() {
+            return Err(CliError::UnknownArgument(extra));
+        }
+        return Ok(Command::Promote { standby });
+    }
+    if first == "send" {
+        let input = args.next().ok_or(CliError::MissingArgument("input file"))?;
+        let to = args
+            .next()
+            .ok_or(CliError::MissingArgument("engine address"))?;
+        let endpoint = to
+            .parse()
+            .map_err(|e: WireError| CliError::InvalidValue(to, e.to_string()))?;
+        let source = match args.next() {
+            Some(arg) if arg == "--source" => {
+                let name = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                if name.is_empty() || name.len() > u8::MAX as usize {
+                    return Err(CliError::InvalidValue(arg, name));
+                }
+                Some(name)
+            }
+            Some(arg) => return Err(CliError::UnknownArgument(arg)),
+            None => None,
+        };
+        if let Some(extra) = args.next() {
+            return Err(CliError::UnknownArgument(extra));
+        }
+        return Ok(Command::Send {
+            input: input.into(),
+            to: endpoint,
+            source,
+        });
+    }
+
+    parse_process(first, args)
+}
+
+fn parse_process(
+    input: String,
+    mut args: impl Iterator<Item = String>,
+) -> Result<Command, CliError> {
+    let mut options = Options {
+        input: input.into(),
+        ..Default::default()
+    };
+    let (mut cutoff, mut utc_offset) = (None, None);
+    let (mut currency, mut currencies) = (None, Currencies::default());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--snapshot" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.snapshot = Some(path.into());
+            }
+            "--checkpoint" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.checkpoint = Some(path.into());
+            }
+            "--checkpoint-every" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let count = value
+                    .parse()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or(CliError::InvalidValue(arg, value))?;
+                options.checkpoint_every = Some(count);
+            }
+            "--output" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                match path.strip_prefix("sqlite://") {
+                    Some(database) => options.sqlite = Some(database.into()),
+                    None => options.output = Some(path.into()),
+                }
+            }
+            "--format" => {
+                let format = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                options.format = format
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, format))?;
+            }
+            "--input-format" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let format = value
+                    .parse()
+                    .map_err(|e: InputError| CliError::InvalidValue(arg, e.to_string()))?;
+                options.input_format = Some(format);
+            }
+            "--rejects" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.rejects = Some(path.into());
+            }
+            "--publish-to" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let address = value
+                    .parse()
+                    .map_err(|e: InvalidUri| CliError::InvalidValue(arg, e.to_string()))?;
+                options.publish_to = Some(address);
+            }
+            "--publish-batch" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.parse() {
+                    Ok(batch) if batch > 0 => options.publish.batch = batch,
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--publish-retries" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                options.publish.retries = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+            }
+            "--ledger" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.ledger = Some(path.into());
+            }
+            "--restore" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.restore = Some(path.into());
+            }
+            "--resume" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.resume = Some(path.into());
+            }
+            "--dump-state" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.dump_state = Some(path.into());
+            }
+            "--merge-into" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.merge_into = Some(path.into());
+            }
+            "--anonymize" => {
+                let key = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.anonymize = Some(key);
+            }
+            "--perturb-amounts" => options.perturb_amounts = true,
+            "--log-sensitive" => options.log_sensitive = true,
+            "--verify-determinism" => options.verify_determinism = true,
+            "--log-format" => {
+                let format = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                options.log_format = match format.as_str() {
+                    "text" => LogFormat::Text,
+                    "json" => LogFormat::Json,
+                    _ => return Err(CliError::InvalidValue(arg, format)),
+                };
+            }
+            "--log-level" => {
+                let level = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let level = level
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, level))?;
+                options.log_level = Some(level);
+            }
+            "--emit-transactions" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.emit_transactions = Some(path.into());
+            }
+            "--max-tps" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let rate = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                options.max_tps = Some(rate);
+            }
+            "--rollback" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let count = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                options.rollback = Some(count);
+            }
+            "--trace-client" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let client = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                options.trace_clients.push(client);
+            }
+            "--workers" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let workers = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                options.workers = Some(workers);
+            }
+            "--readers" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.parse() {
+                    Ok(readers) if readers > 0 => options.readers = Some(readers),
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--replicate-to" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let endpoint = value
+                    .parse()
+                    .map_err(|e: WireError| CliError::InvalidValue(arg, e.to_string()))?;
+                options.replicate_to = Some(endpoint);
+            }
+            "--standby" => options.standby = true,
+            "--kafka-group" => {
+                let group = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.kafka_group = Some(group);
+            }
+            "--kafka-follow" => options.kafka_follow = true,
+            "--recover" => options.recover = true,
+            "--global-tx-ids" => options.global_tx_ids = true,
+            "--check-invariants" => options.check_invariants = true,
+            "--echo-columns" => options.echo_columns = true,
+            "--strict" => options.strict = true,
+            "--unknown-ops" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let policy = value
+                    .parse()
+                    .map_err(|e: InputError| CliError::InvalidValue(arg, e.to_string()))?;
+                options.unknown_ops = policy;
+            }
+            "--report-at" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let schedule = value
+                    .parse()
+                    .map_err(|e: ReportError| CliError::InvalidValue(arg, e.to_string()))?;
+                options.report_at = Some(schedule);
+            }
+            "--report-dir" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.report_dir = Some(path.into());
+            }
+            "--history" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.history = Some(path.into());
+            }
+            "--history-backend" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.split_once(':') {
+                    Some(("mmap", path)) if !path.is_empty() => options.history = Some(path.into()),
+                    Some(("sled", path)) if !path.is_empty() => {
+                        options.sled_history = Some(path.into())
+                    }
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--max-history-mem" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let count = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                options.max_history_mem = Some(count);
+            }
+            "--cutoff" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let time = calendar::parse_time(&value)
+                    .map_err(|e: CalendarError| CliError::InvalidValue(arg, e.to_string()))?;
+                cutoff = Some(time);
+            }
+            "--utc-offset" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let offset = calendar::parse_offset(&value)
+                    .map_err(|e: CalendarError| CliError::InvalidValue(arg, e.to_string()))?;
+                utc_offset = Some(offset);
+            }
+            "--chargeback-fee" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.parse::<Decimal>() {
+                    Ok(fee) if !fee.is_sign_negative() => options.chargeback_fee = Some(fee),
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--deposit-fee" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let fee = value.parse().map_err(|e| CliError::InvalidValue(arg, e))?;
+                options.fees = options.fees.deposit(fee);
+            }
+            "--withdrawal-fee" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let fee = value.parse().map_err(|e| CliError::InvalidValue(arg, e))?;
+                options.fees = options.fees.withdrawal(fee);
+            }
+            "--balance-cap" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.parse::<Decimal>() {
+                    Ok(cap) if !cap.is_sign_negative() => {
+                        options.balance_caps = options.balance_caps.global(cap)
+                    }
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--balance-cap-tier" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let cap = value
+                    .split_once('=')
+                    .and_then(|(tier, cap)| Some((tier, cap.parse::<Decimal>().ok()?)));
+                match cap {
+                    Some((tier, cap)) if !tier.is_empty() && !cap.is_sign_negative() => {
+                        options.balance_caps = options.balance_caps.tier(tier, cap)
+                    }
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--credit-limit" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.parse::<Decimal>() {
+                    Ok(limit) if !limit.is_sign_negative() => {
+                        options.credit_limits = options.credit_limits.global(limit)
+                    }
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--credit-limit-client" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let limit = value.split_once('=').and_then(|(client, limit)| {
+                    Some((client.parse().ok()?, limit.parse::<Decimal>().ok()?))
+                });
+                match limit {
+                    Some((client, limit)) if !limit.is_sign_negative() => {
+                        options.credit_limits = options.credit_limits.client(client, limit)
+                    }
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--client-tiers" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.client_tiers = Some(path.into());
+            }
+            "--settings" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.settings = Some(path.into());
+            }
+            "--config" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.config = Some(path.into());
+            }
+            "--allow" => {
+                let spec = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                options
+                    .permissions
+                    .allow(&spec)
+                    .map_err(|e| CliError::InvalidValue(arg, e.to_string()))?;
+            }
+            "--dispute-limit" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.parse() {
+                    Ok(limit) if limit > 0 => options.dispute_limit = Some(limit),
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--dispute-window" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match settings::days(&value) {
+                    Some(window) => options.dispute_window = Some(window),
+                    None => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            "--tx-order" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let order = value.parse().map_err(|e| CliError::InvalidValue(arg, e))?;
+                options.tx_order = Some(order);
+            }
+            "--timestamp-order" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let order = value.parse().map_err(|e| CliError::InvalidValue(arg, e))?;
+                options.timestamp_order = Some(order);
+            }
+            "--lock-policy" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let policy = value.parse().map_err(|e| CliError::InvalidValue(arg, e))?;
+                options.lock_policy = Some(policy);
+            }
+            "--dispute-policy" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let policy = value.parse().map_err(|e| CliError::InvalidValue(arg, e))?;
+                options.dispute_policy = Some(policy);
+            }
+            "--amount-precision" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let precision = value.parse().map_err(|e| CliError::InvalidValue(arg, e))?;
+                options.amount_precision = Some(precision);
+            }
+            "--dormant-report" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.dormant_report = Some(path.into());
+            }
+            "--dormant-after" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let rows = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                options.dormant_after = Some(rows);
+            }
+            "--multi-currency" => options.multi_currency = true,
+            "--currency" => {
+                let code = args.next().ok_or(CliError::MissingValue(arg))?;
+                currency = Some(code);
+            }
+            "--currency-exponent" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                currencies
+                    .set(&value)
+                    .map_err(|e| CliError::InvalidValue(arg, e.to_string()))?;
+            }
+            "--journal" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                options.journal = Some(path.into());
+            }
+            "--journal-durability" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                options.journal_durability = match value.as_str() {
+                    "record" => Durability::Record,
+                    "group" => Durability::default(),
+                    "os" => Durability::Os,
+                    _ => match value.strip_prefix("group:").map(str::parse) {
+                        Some(Ok(size)) if size > 0 => Durability::Group(size),
+                        _ => return Err(CliError::InvalidValue(arg, value)),
+                    },
+                };
+            }
+            "--chaos" => {
+                let spec = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let config = spec
+                    .parse()
+                    .map_err(|e: ChaosError| CliError::InvalidValue(arg, e.to_string()))?;
+                options.chaos = Some(config);
+            }
+            _ if arg.starts_with("--") => return Err(CliError::UnknownArgument(arg)),
+            _ => options.inputs.push(arg.into()),
+        }
+    }
+    options.calendar = match (cutoff, utc_offset) {
+        (Some(cutoff), offset) => Some(Calendar::new(cutoff, offset.unwrap_or(0))),
+        (None, Some(_)) => return Err(CliError::MissingArgument("--cutoff")),
+        (None, None) => None,
+    };
+    if options.client_tiers.is_some() && options.balance_caps.is_empty() {
+        return Err(CliError::MissingArgument("--balance-cap-tier"));
+    }
+    match (&options.dormant_report, options.dormant_after) {
+        (Some(_), None) => return Err(CliError::MissingArgument("--dormant-after")),
+        (None, Some(_)) => return Err(CliError::MissingArgument("--dormant-report")),
+        _ => {}
+    }
+    if let Some(code) = currency {
+        let currency = currencies.currency(&code).map_err(|e: CurrencyError| {
+            CliError::InvalidValue("--currency".to_string(), e.to_string())
+        })?;
+        options.currency = Some(currency);
+    }
+    Ok(Command::Process(Box::new(options)))
+}
+
+fn parse_query(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let (mut state, mut client) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--state" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                state = Some(PathBuf::from(path));
+            }
+            "--client" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let id = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                client = Some(id);
+            }
+            _ => return Err(CliError::UnknownArgument(arg)),
+        }
+    }
+    Ok(Command::Query {
+        state: state.ok_or(CliError::MissingArgument("--state"))?,
+        client: client.ok_or(CliError::MissingArgument("--client"))?,
+    })
+}
+
+fn parse_serve(args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let address = |arg: String, value: String| {
+        value
+            .parse()
+            .map_err(|_| CliError::InvalidValue(arg, value))
+    };
+    let (mut listen, mut http, mut workers) = (None, None, None);
+    let mut args = args.peekable();
+    if let Some(grpc) = args.next_if(|arg| !arg.starts_with("--")) {
+        listen = Some(address("listen address".into(), grpc)?);
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--serve-http" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                http = Some(address(arg, value)?);
+            }
+            "--workers" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                match value.parse() {
+                    Ok(n) if n > 0 => workers = Some(n),
+                    _ => return Err(CliError::InvalidValue(arg, value)),
+                }
+            }
+            _ => return Err(CliError::UnknownArgument(arg)),
+        }
+    }
+    if listen.is_none() && http.is_none() {
+        return Err(CliError::MissingArgument("listen address or --serve-http"));
+    }
+    Ok(Command::Serve {
+        listen,
+        http,
+        workers,
+    })
+}
+
+fn parse_route(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let input = args.next().ok_or(CliError::MissingArgument("input file"))?;
+    let mut shards = Vec::new();
+    let mut nodes = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shard" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let shard = value
+                    .parse()
+                    .map_err(|e: ClusterError| CliError::InvalidValue(arg, e.to_string()))?;
+                shards.push(shard);
+            }
+            "--node" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                if let Err(e) = value.parse::<Endpoint>() {
+                    return Err(CliError::InvalidValue(arg, e.to_string()));
+                }
+                nodes.push(value);
+            }
+            _ => return Err(CliError::UnknownArgument(arg)),
+        }
+    }
+    match (shards.is_empty(), nodes.is_empty()) {
+        (true, true) => return Err(CliError::MissingArgument("--shard or --node")),
+        (false, false) => {
+            return Err(CliError::InvalidValue(
+                "--node".into(),
+                "can't be combined with --shard".into(),
+            ))
+        }
+        _ => {}
+    }
+    Ok(Command::Route {
+        input: input.into(),
+        shards,
+        nodes,
+    })
+}
+
+fn parse_merge(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let mut parts = Vec::new();
+    let (mut output, mut sum) = (None, false);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                output = Some(path.into());
+            }
+            "--sum" => sum = true,
+            _ if arg.starts_with("--") => return Err(CliError::UnknownArgument(arg)),
+            _ => parts.push(arg.into()),
+        }
+    }
+    if parts.is_empty() {
+        return Err(CliError::MissingArgument("shard output"));
+    }
+    Ok(Command::Merge { parts, output, sum })
+}
+
+fn parse_statement(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let mut journals = Vec::new();
+    let (mut client, mut output, mut dir) = (None, None, None);
+    let mut format = StatementFormat::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--client" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let id = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                client = Some(id);
+            }
+            "--format" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                format = value
+                    .parse()
+                    .map_err(|value| CliError::InvalidValue(arg, value))?;
+            }
+            "--output" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                output = Some(path.into());
+            }
+            "--dir" => {
+                let path = args.next().ok_or(CliError::MissingValue(arg))?;
+                dir = Some(path.into());
+            }
+            _ if arg.starts_with("--") => return Err(CliError::UnknownArgument(arg)),
+            _ => journals.push(arg.into()),
+        }
+    }
+    if journals.is_empty() {
+        return Err(CliError::MissingArgument("journal file"));
+    }
+    match (&dir, &output) {
+        (Some(_), Some(_)) => return Err(CliError::UnknownArgument("--output".to_string())),
+        // a single statement needs to know whose it is
+        (None, _) if client.is_none() => return Err(CliError::MissingArgument("--client")),
+        _ => {}
+    }
+    Ok(Command::Statement {
+        journals,
+        client,
+        format,
+        output,
+        dir,
+    })
+}
+
+fn parse_explain(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let input = args.next().ok_or(CliError::MissingArgument("input file"))?;
+    let mut tx = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tx" => {
+                let value = args.next().ok_or(CliError::MissingValue(arg.clone()))?;
+                let id = value
+                    .parse()
+                    .map_err(|_| CliError::InvalidValue(arg, value))?;
+                tx = Some(id);
+            }
+            _ => return Err(CliError::UnknownArgument(arg)),
+        }
+    }
+    Ok(Command::Explain {
+        input: input.into(),
+        tx: tx.ok_or(CliError::MissingArgument("--tx"))?,
+    })
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        std::iter::once("bank")
+            .chain(line.split_whitespace())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn rejects_a_zero_rate() {
+        assert_eq!(
+            parse(args("in.csv --max-tps 0")),
+            Err(CliError::InvalidValue("--max-tps".into(), "0".into()))
+        );
+        let Ok(Command::Process(options)) = parse(args("in.csv --max-tps 5")) else {
+            panic!("expected a run");
+        };
+        assert_eq!(options.max_tps, NonZeroU32::new(5));
+    }
+
+    fn check(line: &str) -> Result<(), CliError> {
+        match parse(args(line))? {
+            Command::Process(options) => options.check(),
+            command => panic!("expected a run, got {command:?}"),
+        }
+    }
+
+    #[test]
+    fn checks_flags_against_each_other() {
+        assert_eq!(
+            check("in.csv --journal j.csv --recover --workers 2 --rejects r.csv").err(),
+            Some(CliError::Conflict("--recover", "--workers"))
+        );
+        assert_eq!(
+            check("in.csv --settings s.conf --dispute-limit 2"),
+            Err(CliError::Conflict("--dispute-limit", "--settings"))
+        );
+        assert_eq!(
+            check("in.csv --checkpoint-every 10"),
+            Err(CliError::Requires(
+                "--checkpoint-every",
+                "--checkpoint".into()
+            ))
+        );
+        assert_eq!(
+            check("tcp://127.0.0.1:7000 --report-dir reports"),
+            Err(CliError::Requires(
+                "--report-dir",
+                "--report-at or --cutoff".into()
+            ))
+        );
+        assert_eq!(
+            check("in.csv --standby"),
+            Err(CliError::Requires(
+                "--standby",
+                "an address to listen on".into()
+            ))
+        );
+        assert_eq!(
+            check(&format!("{} --journal j.csv", env!("CARGO_MANIFEST_DIR"))),
+            Err(CliError::Conflict("--journal", "a shard directory"))
+        );
+        assert_eq!(
+            check("in.csv --checkpoint c.rkyv --checkpoint-every 10 --rollback 2"),
+            Ok(())
+        );
+        assert_eq!(
+            check("tcp://127.0.0.1:7000 --cutoff 17:00 --journal j.csv --readers 2"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rollbacks_leave_no_journal_to_recover() {
+        assert_eq!(
+            check("in.csv --journal j.csv --rollback 2"),
+            Err(CliError::Conflict("--journal", "--rollback"))
+        );
+        assert_eq!(
+            check("in.csv --journal j.csv --recover --rollback 2"),
+            Err(CliError::Conflict("--journal", "--rollback"))
+        );
+        assert_eq!(check("in.csv --journal j.csv --recover"), Ok(()));
+    }
+
+    #[test]
+    fn parses_publish_options() {
+        let options = match parse(args(
+            "in.csv --publish-to http://core:50052 --publish-batch 500",
+        )) {
+            Ok(Command::Process(options)) => options,
+            command => panic!("expected a run, got {command:?}"),
+        };
+        assert_eq!(
+            options.publish_to,
+            Some("http://core:50052".parse().unwrap())
+        );
+        assert_eq!(options.publish.batch, 500);
+        assert_eq!(options.check(), Ok(()));
+        assert_eq!(
+            check("in.csv --publish-retries 2"),
+            Err(CliError::Requires(
+                "--publish-batch or --publish-retries",
+                "--publish-to".into()
+            ))
+        );
+        assert_eq!(
+            check("in.csv --publish-to http://core:50052 --workers 2"),
+            Err(CliError::Conflict("--publish-to", "--workers"))
+        );
+    }
+
+    #[test]
+    fn parses_sqlite_output() {
+        let options = match parse(args("in.csv --output sqlite://out/accounts.db")) {
+            Ok(Command::Process(options)) => options,
+            command => panic!("expected a run, got {command:?}"),
+        };
+        assert_eq!(options.sqlite, Some("out/accounts.db".into()));
+        assert_eq!(options.output, None);
+    }
+
+    #[test]
+    fn parses_history_backends() {
+        let options = match parse(args("in.csv --history-backend sled:/var/bank/history")) {
+            Ok(Command::Process(options)) => options,
+            command => panic!("expected a run, got {command:?}"),
+        };
+        assert_eq!(options.sled_history, Some("/var/bank/history".into()));
+        assert_eq!(options.history, None);
+        let options = match parse(args("in.csv --history-backend mmap:history.bin")) {
+            Ok(Command::Process(options)) => options,
+            command => panic!("expected a run, got {command:?}"),
+        };
+        assert_eq!(options.history, Some("history.bin".into()));
+        assert_eq!(
+            parse(args("in.csv --history-backend rocksdb:/data")),
+            Err(CliError::InvalidValue(
+                "--history-backend".into(),
+                "rocksdb:/data".into()
+            ))
+        );
+        assert_eq!(
+            check("in.csv --history-backend sled:history --max-history-mem 1000"),
+            Ok(())
+        );
+        assert_eq!(
+            check("in.csv --history-backend sled:history --history history.bin"),
+            Err(CliError::Conflict("--history-backend", "--history"))
+        );
+    }
+
+    #[test]
+    fn parses_kafka_input() {
+        let options = match parse(args(
+            "kafka://k1:9092/payments --kafka-group ledger --kafka-follow",
+        )) {
+            Ok(Command::Process(options)) => options,
+            command => panic!("expected a run, got {command:?}"),
+        };
+        assert!(options.consumes() && !options.listens());
+        assert_eq!(options.kafka_group.as_deref(), Some("ledger"));
+        assert_eq!(options.check(), Ok(()));
+        assert_eq!(
+            check("kafka://k1:9092/payments --workers 2"),
+            Err(CliError::Conflict("--workers", "a kafka:// input"))
+        );
+        assert_eq!(
+            check("in.csv --kafka-group ledger"),
+            Err(CliError::Requires(
+                "--kafka-group",
+                "a kafka:// input".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_serve() {
+        assert_eq!(
+            parse(args("serve 127.0.0.1:50051 --workers 4")),
+            Ok(Command::Serve {
+                listen: Some("127.0.0.1:50051".parse().unwrap()),
+                http: None,
+                workers: Some(4),
+            })
+        );
+        assert_eq!(
+            parse(args("serve --serve-http 0.0.0.0:8080")),
+            Ok(Command::Serve {
+                listen: None,
+                http: Some("0.0.0.0:8080".parse().unwrap()),
+                workers: None,
+            })
+        );
+        assert_eq!(
+            parse(args("serve --workers 2")),
+            Err(CliError::MissingArgument("listen address or --serve-http"))
+        );
+        assert_eq!(
+            parse(args("serve localhost")),
+            Err(CliError::InvalidValue(
+                "listen address".into(),
+                "localhost".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn input_files_can_have_the_name_of_a_subcommand() {
+        assert_eq!(
+            parse(args("query --state s.json --client 1")),
+            Ok(Command::Query {
+                state: "s.json".into(),
+                client: 1,
+            })
+        );
+        let Ok(Command::Process(options)) = parse(args("-- query --rollback 1")) else {
+            panic!("expected a run");
+        };
+        assert_eq!(
+            (options.input, options.rollback),
+            (PathBuf::from("query"), Some(1))
+        );
+        let Ok(Command::Process(options)) = parse(args("--")) else {
+            panic!("expected a run");
+        };
+        assert_eq!(options.input, PathBuf::from(input::STDIN));
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::domain::{AccountStore, ClientId, Transaction};
+use crate::engine::Engine;
+use crate::wire::{Endpoint, Sender, WireError};
+
+#[derive(Error, Debug)]
+pub enum ClusterError {
+    #[error("Connection error: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Wire(#[from] WireError),
+    #[error("Invalid shard {0}, expected <first client>-<last client>=<address>")]
+    Shard(String),
+    #[error("Shards {0} and {1} own overlapping clients")]
+    Overlap(String, String),
+    #[error("No shard owns client {0}")]
+    Unowned(ClientId),
+    #[error("Client {0} appears in more than one shard's output")]
+    Duplicate(ClientId),
+}
+
+// A processor instance and the clients it owns
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    pub clients: RangeInclusive<ClientId>,
+    pub endpoint: Endpoint,
+}
+
+impl FromStr for Shard {
+    type Err = ClusterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ClusterError::Shard(s.to_string());
+        let (range, addr) = s.split_once('=').ok_or_else(invalid)?;
+        let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+        let first: ClientId = first.trim().parse().map_err(|_| invalid())?;
+        let last: ClientId = last.trim().parse().map_err(|_| invalid())?;
+        if first > last {
+            return Err(invalid());
+        }
+        Ok(Shard {
+            clients: first..=last,
+            endpoint: addr.parse()?,
+        })
+    }
+}
+
+// Rejects shard lists where a client would be owned by two instances
+pub fn check_disjoint(shards: &[Shard]) -> Result<(), ClusterError> {
+    let mut sorted: Vec<&Shard> = shards.iter().collect();
+    sorted.sort_by_key(|shard| *shard.clients.start());
+    for pair in sorted.windows(2) {
+        if pair[1].clients.start() <= pair[0].clients.end() {
+            let name = |shard: &Shard| format!("{:?}", shard.clients);
+            return Err(ClusterError::Overlap(name(pair[0]), name(pair[1])));
+        }
+    }
+    Ok(())
+}
+
+// Consistent hashing of clients onto named nodes. Every node is placed on the ring at a number
+// of pseudo random points and owns the clients hashing to just before each of them, so adding or
+// removing a node only moves the clients of the points it gains or loses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HashRing {
+    points: BTreeMap<u64, String>,
+}
+
+// points per node, enough to spread clients within a few percent of even
+const POINTS: u64 = 128;
+
+impl HashRing {
+    pub fn new<S: AsRef<str>>(nodes: impl IntoIterator<Item = S>) -> Self {
+        let mut ring = Self::default();
+        for node in nodes {
+            ring.add(node.as_ref());
+        }
+        ring
+    }
+
+    pub fn add(&mut self, node: &str) {
+        for point in 0..POINTS {
+            self.points
+                .insert(node_point(node, point), node.to_string());
+        }
+    }
+
+    pub fn remove(&mut self, node: &str) {
+        self.points.retain(|_, owner| owner != node);
+    }
+
+    pub fn owner(&self, client: ClientId) -> Option<&str> {
+        let hash = client_point(client);
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+}
+
+// Moves the state of every client whose owner differs between the two rings to the engine of
+// its new owner, adding engines for nodes that don't have one yet. Returns how many clients
+// moved.
+pub fn rebalance(
+    engines: &mut HashMap<String, Engine>,
+    before: &HashRing,
+    after: &HashRing,
+) -> usize {
+    let mut moves: HashMap<(String, String), HashSet<ClientId>> = HashMap::new();
+    for (node, engine) in engines.iter() {
+        for &client in engine.accounts().keys() {
+            let (Some(from), Some(to)) = (before.owner(client), after.owner(client)) else {
+                continue;
+            };
+            if from != to {
+                debug_assert_eq!(from, node);
+                moves
+                    .entry((from.to_string(), to.to_string()))
+                    .or_default()
+                    .insert(client);
+            }
+        }
+    }
+    let mut moved = 0;
+    for ((from, to), clients) in moves {
+        let Some(source) = engines.get_mut(&from) else {
+            continue;
+        };
+        let state = source.split_off(&clients);
+        moved += clients.len();
+        engines
+            .entry(to)
+            .or_default()
+            .merge(state)
+            .expect("Clients are owned by one node at a time");
+    }
+    moved
+}
+
+// Stable across runs and builds, every router has to agree on the owners
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn node_point(node: &str, point: u64) -> u64 {
+    // FNV-1a over the name, then mixed with the point index
+    let name = node.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    mix(name ^ mix(point))
+}
+
+#[allow(clippy::useless_conversion)]
+fn client_point(client: ClientId) -> u64 {
+    let client = u128::from(client);
+    mix(client as u64 ^ mix((client >> 64) as u64))
+}
+
+// How the router picks the instance for a client
+enum Assignment {
+    Ranges(Vec<RangeInclusive<ClientId>>),
+    // node names are the addresses, in the order of `senders`
+    Ring(HashRing, Vec<String>),
+}
+
+// Forwards every transaction to the instance owning its client, over the wire format the
+// instances listen for
+pub struct Router {
+    senders: Vec<Sender>,
+    assignment: Assignment,
+}
+
+impl Router {
+    pub fn connect(shards: &[Shard]) -> Result<Self, ClusterError> {
+        check_disjoint(shards)?;
+        let senders = shards
+            .iter()
+            .map(|shard| shard.endpoint.connect())
+            .collect::<io::Result<_>>()?;
+        let ranges = shards.iter().map(|shard| shard.clients.clone()).collect();
+        Ok(Self {
+            senders,
+            assignment: Assignment::Ranges(ranges),
+        })
+    }
+
+    // Assigns clients to the nodes by consistent hashing, the nodes are named by their address
+    pub fn connect_ring(nodes: &[String]) -> Result<Self, ClusterError> {
+        let senders = nodes
+            .iter()
+            .map(|node| node.parse::<Endpoint>()?.connect().map_err(Into::into))
+            .collect::<Result<_, ClusterError>>()?;
+        Ok(Self {
+            senders,
+            assignment: Assignment::Ring(HashRing::new(nodes), nodes.to_vec()),
+        })
+    }
+
+    pub fn route(&mut self, transaction: &Transaction) -> Result<(), ClusterError> {
+        let client = transaction.client;
+        let idx = match &self.assignment {
+            Assignment::Ranges(ranges) => {
+                ranges.iter().position(|clients| clients.contains(&client))
+            }
+            Assignment::Ring(ring, nodes) => ring
+                .owner(client)
+                .and_then(|owner| nodes.iter().position(|node| node == owner)),
+        };
+        let idx = idx.ok_or(ClusterError::Unowned(client))?;
+        Ok(self.senders[idx].send(transaction)?)
+    }
+
+    // Closes every connection, which lets the instances write their output
+    pub fn finish(self) -> io::Result<()> {
+        for sender in self.senders {
+            sender.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// Combines the outputs of the instances into the output of the whole cluster. A client in more
+// than one output is an error unless `sum` is set, then its balances are added up and it's
+// locked if it's locked in any of them.
+pub fn merge_outputs(
+    parts: impl IntoIterator<Item = AccountStore>,
+    sum: bool,
+) -> Result<AccountStore, ClusterError> {
+    let mut merged = AccountStore::new();
+    for part in parts {
+        for (client, act) in part {
+            match merged.get_mut(&client) {
+                None => {
+                    merged.insert(client, act);
+                }
+                Some(existing) if sum => {
+                    existing.available += act.available;
+                    existing.held += act.held;
+                    existing.total += act.total;
+                    existing.locked |= act.locked;
+                }
+                Some(_) => return Err(ClusterError::Duplicate(client)),
+            }
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::{transaction::Operation, Account};
+
+    #[test]
+    fn parses_shards_and_rejects_overlaps() {
+        let shard: Shard = "0-99=tcp://10.0.0.1:7000".parse().unwrap();
+        assert_eq!(shard.clients, 0..=99);
+        assert_eq!(shard.endpoint, Endpoint::Tcp("10.0.0.1:7000".into()));
+        assert!("99-0=tcp://a:1".parse::<Shard>().is_err());
+        assert!("0-99".parse::<Shard>().is_err());
+
+        let other: Shard = "100-199=unix:///tmp/b.sock".parse().unwrap();
+        assert!(check_disjoint(&[other.clone(), shard.clone()]).is_ok());
+        let overlapping: Shard = "50-150=tcp://c:1".parse().unwrap();
+        assert!(matches!(
+            check_disjoint(&[shard, other, overlapping]),
+            Err(ClusterError::Overlap(..))
+        ));
+    }
+
+    #[test]
+    fn adding_a_node_only_moves_clients_to_it() {
+        let before = HashRing::new(["a", "b", "c"]);
+        let mut after = before.clone();
+        after.add("d");
+
+        let mut moved = 0;
+        for client in 0..=1000 {
+            let (from, to) = (before.owner(client).unwrap(), after.owner(client).unwrap());
+            if from != to {
+                assert_eq!(to, "d");
+                moved += 1;
+            }
+        }
+        // roughly a quarter of the clients
+        assert!((150..350).contains(&moved), "moved {moved}");
+
+        after.remove("d");
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn rebalance_migrates_client_state() {
+        let before = HashRing::new(["a", "b"]);
+        let mut engines: HashMap<String, Engine> = HashMap::new();
+        for id in 0..200u8 {
+            let (client, tx) = (ClientId::from(id), u32::from(id));
+            let owner = before.owner(client).unwrap().to_string();
+            let engine = engines.entry(owner).or_default();
+            for (op, amount) in [
+                (Operation::Deposit, Some(dec!(5))),
+                (Operation::Dispute, None),
+            ] {
+                let transaction = Transaction {
+                    op,
+                    client,
+                    tx,
+                    amount,
+                    ..Default::default()
+                };
+                engine.process(transaction).unwrap();
+            }
+        }
+        let mut after = before.clone();
+        after.add("c");
+
+        let moved = rebalance(&mut engines, &before, &after);
+
+        assert!(moved > 0);
+        for (node, engine) in engines.iter() {
+            for (&client, act) in engine.accounts() {
+                assert_eq!(after.owner(client), Some(node.as_str()));
+                assert_eq!(act.held, dec!(5));
+                // the disputed deposit came along, so it can still be resolved
+                assert!(engine
+                    .history()
+                    .iter()
+                    .any(|((owner, _), node)| owner == client && node.op == Operation::Dispute));
+            }
+        }
+        let total: usize = engines.values().map(|engine| engine.accounts().len()).sum();
+        assert_eq!(total, 200);
+    }
+
+    #[test]
+    fn merges_disjoint_outputs() {
+        let part = |clients: &[ClientId]| -> AccountStore {
+            clients.iter().map(|&c| (c, Account::new(c))).collect()
+        };
+
+        let merged = merge_outputs([part(&[1, 2]), part(&[3])], false).unwrap();
+
+        assert_eq!(merged.len(), 3);
+        assert!(matches!(
+            merge_outputs([part(&[1, 2]), part(&[2])], false),
+            Err(ClusterError::Duplicate(2))
+        ));
+    }
+
+    #[test]
+    fn sums_overlapping_outputs_when_allowed() {
+        let part = |available, locked| -> AccountStore {
+            let mut act = Account::new(7);
+            act.available = available;
+            act.total = available;
+            act.locked = locked;
+            [(7, act)].into_iter().collect()
+        };
+
+        let merged = merge_outputs([part(dec!(2.5), false), part(dec!(4), true)], true).unwrap();
+
+        assert_eq!(merged[&7].available, dec!(6.5));
+        assert_eq!(merged[&7].total, dec!(6.5));
+        assert!(merged[&7].locked);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "mmap")]
+use crate::domain::History;
+use crate::engine::{Engine, ErrorPolicy, Limits};
+use crate::output::OutputFormat;
+use crate::settings::{self, Settings, SettingsError};
+
+// Transactions read ahead of the engine before the reader waits for it
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+// Prefix of the environment variables that override a configuration file
+pub const ENV_PREFIX: &str = "BANK_";
+
+// Everything a run is configured with, so batch deployments can keep it in a file rather than in
+// long flag strings. The file has the format of settings files and takes all of their keys for
+// the limits and fees, plus:
+//   error-policy = strict       lenient, strict or collect
+//   channel-capacity = 4096     transactions read ahead of the engine
+//   format = json               csv, table, json or json-by-client
+//   history = history.bin       keep the history in a memory-mapped file
+//   max-history-mem = 100000    history entries kept in memory in front of that file
+// Values may be quoted, so a flat TOML file of these keys reads the same. An environment
+// variable named after a key, `BANK_` and the key in upper case with underscores, e.g.
+// `BANK_DISPUTE_POLICY=deposits-only`, overrides the file; variables that don't name a key are
+// left alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub limits: Limits,
+    pub error_policy: ErrorPolicy,
+    pub channel_capacity: usize,
+    pub format: OutputFormat,
+    pub history: Option<PathBuf>,
+    pub max_history_mem: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            limits: Limits::default(),
+            error_policy: ErrorPolicy::default(),
+            channel_capacity: CHANNEL_CAPACITY,
+            format: OutputFormat::default(),
+            history: None,
+            max_history_mem: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+
+    pub fn with_channel_capacity(mut self, capacity: usize) -> Self {
+        self.channel_capacity = capacity;
+        self
+    }
+
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_history(mut self, path: impl Into<PathBuf>) -> Self {
+        self.history = Some(path.into());
+        self
+    }
+
+    pub fn with_max_history_mem(mut self, entries: usize) -> Self {
+        self.max_history_mem = Some(entries);
+        self
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SettingsError> {
+        Self::load_with_env(path, [])
+    }
+
+    // Reads the file at `path` and applies the overrides among `vars`, usually `std::env::vars()`
+    pub fn load_with_env(
+        path: &Path,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, SettingsError> {
+        let contents = fs::read_to_string(path).map_err(|e| SettingsError::Io(path.into(), e))?;
+        Self::parse(&contents, vars)
+    }
+
+    // Like settings, the file and its overrides are checked in full before anything is returned
+    pub fn parse(
+        contents: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, SettingsError> {
+        let mut config = Config::new();
+        let mut settings = Settings::default();
+        for entry in settings::entries(contents) {
+            let (line, key, value) = entry?;
+            match config.set(&mut settings, key, value) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(SettingsError::Invalid {
+                        line,
+                        reason: format!("unknown key {key}"),
+                    })
+                }
+                Err(reason) => return Err(SettingsError::Invalid { line, reason }),
+            }
+        }
+        for (var, value) in vars {
+            let Some(key) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase().replace('_', "-");
+            config
+                .set(&mut settings, &key, value.trim())
+                .map_err(|reason| SettingsError::Env { var, reason })?;
+        }
+        config.limits = settings.finish()?;
+        Ok(config)
+    }
+
+    // Sets what `key` names, false when it names nothing
+    fn set(&mut self, settings: &mut Settings, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "error-policy" => self.error_policy = value.parse()?,
+            "channel-capacity" => {
+                self.channel_capacity = value
+                    .parse()
+                    .ok()
+                    .filter(|capacity| *capacity > 0)
+                    .ok_or("expected a positive number")?
+            }
+            "format" => self.format = value.parse()?,
+            "history" => self.history = Some(value.into()),
+            "max-history-mem" => {
+                let entries = value.parse().map_err(|_| "expected a number")?;
+                self.max_history_mem = Some(entries)
+            }
+            _ => return settings.set(key, value),
+        }
+        Ok(true)
+    }
+
+    // An engine with the configured limits, error policy and history
+    pub fn engine(&self) -> io::Result<Engine> {
+        let mut engine = Engine::new().with_error_policy(self.error_policy);
+        engine.set_limits(self.limits.clone());
+        match &self.history {
+            #[cfg(feature = "mmap")]
+            Some(path) => {
+                let history = match self.max_history_mem {
+                    Some(capacity) => History::tiered(path, capacity)?,
+                    None => History::mapped(path)?,
+                };
+                Ok(engine.with_history(history))
+            }
+            #[cfg(not(feature = "mmap"))]
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Keeping the history in a file needs the mmap feature",
+            )),
+            None => Ok(engine),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::engine::DisputePolicy;
+
+    #[test]
+    fn reads_file_and_environment_overrides() {
+        let vars = [
+            ("BANK_FORMAT", "table"),
+            ("BANK_CHANNEL_CAPACITY", "64"),
+            ("BANK_HOME", "/srv/bank"),
+            ("PATH", "/usr/bin"),
+        ]
+        .map(|(var, value)| (var.to_string(), value.to_string()));
+        let config = Config::parse(
+            "# nightly batch\n\
+             error-policy = \"strict\"\n\
+             format = \"json\"\n\
+             dispute-policy = deposits-only\n\
+             chargeback-fee = 15\n\
+             max-history-mem = 1000\n",
+            vars,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config::new()
+                .with_limits(Limits {
+                    dispute_policy: DisputePolicy::DepositsOnly,
+                    chargeback_fee: Some(dec!(15)),
+                    ..Default::default()
+                })
+                .with_error_policy(ErrorPolicy::Strict)
+                .with_channel_capacity(64)
+                .with_format(OutputFormat::Table)
+                .with_max_history_mem(1000)
+        );
+        assert!(matches!(
+            Config::parse("format = xml\n", []),
+            Err(SettingsError::Invalid { line: 1, .. })
+        ));
+        assert!(matches!(
+            Config::parse("", [("BANK_ERROR_POLICY".into(), "loud".into())]),
+            Err(SettingsError::Env { .. })
+        ));
+    }
+}
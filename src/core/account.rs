@@ -0,0 +1,215 @@
+use super::{errors::TransactionError, Amount, ClientId};
+use alloc::string::String;
+use rust_decimal::Decimal;
+#[cfg(feature = "serde")]
+use serde::Serializer;
+
+#[derive(Debug, Default, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(bound = "A: Amount"))]
+pub struct Account<A = Decimal> {
+    pub client: ClientId,
+    // Set on the accounts of multi-currency runs, which keep one per client and currency
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub currency: Option<String>,
+    // Total - held
+    #[cfg_attr(feature = "serde", serde(serialize_with = "four_decimal_precision"))]
+    pub available: A,
+    // total - available
+    #[cfg_attr(feature = "serde", serde(serialize_with = "four_decimal_precision"))]
+    pub held: A,
+    // available + held
+    #[cfg_attr(feature = "serde", serde(serialize_with = "four_decimal_precision"))]
+    pub total: A,
+    pub locked: bool,
+    // How far withdrawals may take available funds below zero, if the account has an overdraft
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "optional_four_decimal_precision"
+        )
+    )]
+    pub credit_limit: Option<A>,
+    // Sum of the fees charged to the account, once it's been charged any
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            serialize_with = "optional_four_decimal_precision"
+        )
+    )]
+    pub fees: Option<A>,
+    // Earliest and latest timestamp of the transactions applied to the account, once it's seen a
+    // dated one
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub first_activity: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub last_activity: Option<u64>,
+}
+
+#[cfg(feature = "serde")]
+pub fn four_decimal_precision<A, S>(amount: &A, s: S) -> Result<S::Ok, S::Error>
+where
+    A: Amount,
+    S: Serializer,
+{
+    s.serialize_str(&amount.to_output())
+}
+
+#[cfg(feature = "serde")]
+pub fn optional_four_decimal_precision<A, S>(amount: &Option<A>, s: S) -> Result<S::Ok, S::Error>
+where
+    A: Amount,
+    S: Serializer,
+{
+    match amount {
+        Some(amount) => four_decimal_precision(amount, s),
+        None => s.serialize_none(),
+    }
+}
+
+impl<A: Amount> Account<A> {
+    pub fn new(client: ClientId) -> Self {
+        Self {
+            client,
+            currency: None,
+            available: A::zero(),
+            held: A::zero(),
+            total: A::zero(),
+            locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
+        }
+    }
+
+    pub fn withdraw(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        // an overdraft lets available funds go as far below zero as the credit limit
+        let spendable = add(self.available, self.credit_limit.unwrap_or_default())?;
+        match amt {
+            Some(val) if val > spendable && self.credit_limit.is_some() => {
+                Err(TransactionError::CreditLimitExceeded)
+            }
+            Some(val) if val > spendable => Err(TransactionError::InsufficientFunds),
+            Some(val) if val <= spendable => {
+                let total = sub(self.total, val)?;
+                let available = sub(total, self.held)?;
+                self.held = sub(total, available)?;
+                self.total = total;
+                self.available = available;
+                Ok(())
+            }
+            None => Ok(()),
+            _ => Err(TransactionError::UnspecifiedBehavior),
+        }
+    }
+
+    // Holds funds for an authorization, checked like a withdrawal. They stay part of the total
+    // until the authorization is captured.
+    pub fn authorize(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        let val = amt.unwrap_or_default();
+        let spendable = add(self.available, self.credit_limit.unwrap_or_default())?;
+        if val > spendable {
+            return match self.credit_limit {
+                Some(_) => Err(TransactionError::CreditLimitExceeded),
+                None => Err(TransactionError::InsufficientFunds),
+            };
+        }
+        self.shift(neg(val)?, val, A::zero())
+    }
+
+    // Takes the captured part of an authorization out of the held funds
+    pub fn capture(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        let val = neg(amt.unwrap_or_default())?;
+        self.shift(A::zero(), val, val)
+    }
+
+    // Returns held funds to available, e.g. what a capture left of its authorization
+    pub fn release(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        let val = amt.unwrap_or_default();
+        self.shift(val, neg(val)?, A::zero())
+    }
+
+    pub fn deposit(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        // Deposits should always have an amount, if missing default to 0.0
+        let total = add(self.total, amt.unwrap_or_default())?;
+        let available = sub(total, self.held)?;
+        self.held = sub(total, available)?;
+        self.total = total;
+        self.available = available;
+        Ok(())
+    }
+
+    pub fn resolve(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        let val = amt.unwrap_or_default();
+        // if resolving deposit dispute
+        if val < A::default() {
+            self.shift(A::zero(), val, val)
+        // if resolving withdrawal dispute
+        } else {
+            self.shift(val, neg(val)?, A::zero())
+        }
+    }
+
+    pub fn chargeback(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        let val = amt.unwrap_or_default();
+        // if charging back deposit dispute
+        if val < A::default() {
+            self.shift(neg(val)?, val, A::zero())?;
+        // if charging back withdrawal dispute
+        } else {
+            let val = neg(val)?;
+            self.shift(A::zero(), val, val)?;
+        }
+        self.locked = true;
+        Ok(())
+    }
+
+    // Fees may take the account negative, the client owes them either way
+    pub fn charge_fee(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        let val = amt.unwrap_or_default();
+        let fees = add(self.fees.unwrap_or_default(), val)?;
+        let val = neg(val)?;
+        self.shift(val, A::zero(), val)?;
+        self.fees = Some(fees);
+        Ok(())
+    }
+
+    pub fn dispute(&mut self, amt: Option<A>) -> Result<(), TransactionError> {
+        let val = amt.unwrap_or_default();
+        // if disputing deposit
+        if val < A::default() {
+            self.shift(val, neg(val)?, A::zero())
+        } else {
+            // if disputing withdrawal
+            self.shift(A::zero(), val, val)
+        }
+    }
+
+    // Adds to the balances all at once, leaving them as they were if any of them overflows
+    fn shift(&mut self, available: A, held: A, total: A) -> Result<(), TransactionError> {
+        let available = add(self.available, available)?;
+        let held = add(self.held, held)?;
+        let total = add(self.total, total)?;
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        Ok(())
+    }
+}
+
+fn add<A: Amount>(lhs: A, rhs: A) -> Result<A, TransactionError> {
+    lhs.checked_add(rhs).ok_or(TransactionError::AmountOverflow)
+}
+
+fn sub<A: Amount>(lhs: A, rhs: A) -> Result<A, TransactionError> {
+    lhs.checked_sub(rhs).ok_or(TransactionError::AmountOverflow)
+}
+
+fn neg<A: Amount>(amount: A) -> Result<A, TransactionError> {
+    amount.checked_neg().ok_or(TransactionError::AmountOverflow)
+}
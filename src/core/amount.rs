@@ -0,0 +1,275 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::{self, Debug, Display};
+use core::str::FromStr;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+// Numeric type the domain types, state machine and engine compute balances with. `Decimal` is
+// the default; `MinorUnits` trades arbitrary precision for plain integer arithmetic. Arithmetic
+// is checked, a result the type can't hold rejects the transaction rather than panicking.
+pub trait Amount: Copy + Debug + Default + PartialOrd + Serde {
+    // Starting balance of a new account
+    fn zero() -> Self;
+
+    // None when the result doesn't fit the type
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+    fn checked_sub(self, rhs: Self) -> Option<Self>;
+    fn checked_neg(self) -> Option<Self>;
+
+    // Rounded half to even to at most `dp` decimals
+    fn round_dp(self, dp: u32) -> Self;
+
+    // Conversions for what's computed in decimals whatever the amount type, e.g. percentage
+    // fees and the decimal columns of snapshots. None when the value has more decimals than the
+    // type keeps or is out of its range.
+    fn to_decimal(&self) -> Decimal;
+    fn from_decimal(value: Decimal) -> Option<Self>;
+
+    // Renders the amount with four decimal places for the account output
+    fn to_output(&self) -> String;
+
+    // Fixed width encoding for archived layouts such as the memory-mapped history
+    fn to_bits(&self) -> [u8; 16];
+    fn from_bits(bits: [u8; 16]) -> Self;
+}
+
+// Serialization bounds of `Amount`, only required with the `serde` feature
+#[cfg(feature = "serde")]
+pub trait Serde: Serialize + DeserializeOwned {}
+#[cfg(feature = "serde")]
+impl<T: Serialize + DeserializeOwned> Serde for T {}
+#[cfg(not(feature = "serde"))]
+pub trait Serde {}
+#[cfg(not(feature = "serde"))]
+impl<T> Serde for T {}
+
+impl Amount for Decimal {
+    fn zero() -> Self {
+        dec!(0.0)
+    }
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        Decimal::checked_add(self, rhs)
+    }
+
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        Decimal::checked_sub(self, rhs)
+    }
+
+    // flips the sign bit, which can't overflow
+    fn checked_neg(self) -> Option<Self> {
+        Some(-self)
+    }
+
+    fn round_dp(self, dp: u32) -> Self {
+        Decimal::round_dp(&self, dp)
+    }
+
+    fn to_decimal(&self) -> Decimal {
+        *self
+    }
+
+    fn from_decimal(value: Decimal) -> Option<Self> {
+        Some(value)
+    }
+
+    fn to_output(&self) -> String {
+        self.round_dp(4).to_string()
+    }
+
+    fn to_bits(&self) -> [u8; 16] {
+        self.serialize()
+    }
+
+    fn from_bits(bits: [u8; 16]) -> Self {
+        Decimal::deserialize(bits)
+    }
+}
+
+// Fixed point amount counted in ten-thousandths, the precision of the input format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MinorUnits(pub i64);
+
+const SCALE: u32 = 4;
+const UNIT: i64 = 10i64.pow(SCALE);
+
+#[derive(Debug, PartialEq)]
+pub struct ParseMinorUnitsError(String);
+
+impl Display for ParseMinorUnitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid amount: {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseMinorUnitsError {}
+
+impl FromStr for MinorUnits {
+    type Err = ParseMinorUnitsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseMinorUnitsError(s.to_string());
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if (whole.is_empty() && fraction.is_empty())
+            || fraction.len() > SCALE as usize
+            || !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let whole: i64 = match whole {
+            "" => 0,
+            _ => whole.parse().map_err(|_| invalid())?,
+        };
+        let fraction: i64 = match fraction {
+            "" => 0,
+            _ => format!("{fraction:0<4}").parse().map_err(|_| invalid())?,
+        };
+        let units = whole
+            .checked_mul(UNIT)
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or_else(invalid)?;
+        Ok(MinorUnits(if negative { -units } else { units }))
+    }
+}
+
+impl Display for MinorUnits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let (whole, fraction) = (units / UNIT as u64, units % UNIT as u64);
+        write!(f, "{sign}{whole}.{fraction:04}")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for MinorUnits {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for MinorUnits {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(d)?;
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Amount for MinorUnits {
+    fn zero() -> Self {
+        MinorUnits(0)
+    }
+
+    fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(MinorUnits)
+    }
+
+    fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(MinorUnits)
+    }
+
+    fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(MinorUnits)
+    }
+
+    fn round_dp(self, dp: u32) -> Self {
+        if dp >= SCALE {
+            return self;
+        }
+        let step = 10i64.pow(SCALE - dp);
+        let (quotient, rest) = (self.0 / step, self.0 % step);
+        let twice = rest.abs() * 2;
+        let up = twice > step || (twice == step && quotient % 2 != 0);
+        let quotient = if up {
+            quotient + rest.signum()
+        } else {
+            quotient
+        };
+        MinorUnits(quotient.saturating_mul(step))
+    }
+
+    fn to_decimal(&self) -> Decimal {
+        Decimal::new(self.0, SCALE)
+    }
+
+    fn from_decimal(value: Decimal) -> Option<Self> {
+        let units = value.checked_mul(Decimal::from(UNIT))?;
+        if !units.fract().is_zero() {
+            return None;
+        }
+        i64::try_from(units).ok().map(MinorUnits)
+    }
+
+    fn to_output(&self) -> String {
+        self.to_string()
+    }
+
+    fn to_bits(&self) -> [u8; 16] {
+        let mut bits = [0; 16];
+        bits[..8].copy_from_slice(&self.0.to_le_bytes());
+        bits
+    }
+
+    fn from_bits(bits: [u8; 16]) -> Self {
+        let mut units = [0; 8];
+        units.copy_from_slice(&bits[..8]);
+        MinorUnits(i64::from_le_bytes(units))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::core::{transaction::Operation, Account, Transaction, TryUpdate};
+
+    #[test]
+    fn parses_and_renders_minor_units() {
+        assert_eq!("12.5".parse(), Ok(MinorUnits(125_000)));
+        assert_eq!("-0.0001".parse(), Ok(MinorUnits(-1)));
+        assert_eq!(".25".parse(), Ok(MinorUnits(2_500)));
+        assert_eq!("+1".parse(), Ok(MinorUnits(10_000)));
+        assert!("1.00001".parse::<MinorUnits>().is_err());
+        assert!("abc".parse::<MinorUnits>().is_err());
+        // only one leading sign and plain digits on both sides of the point
+        for malformed in ["1.-5", "--1", "1.+5", "+-1", "-+1", "1. 5", "1.5-"] {
+            assert!(malformed.parse::<MinorUnits>().is_err(), "{malformed}");
+        }
+        assert_eq!(MinorUnits(-125_000).to_string(), "-12.5000");
+    }
+
+    #[test]
+    fn accounts_work_with_minor_units() {
+        let mut act = Account::<MinorUnits>::new(1);
+        let deposit = Transaction {
+            op: Operation::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(MinorUnits(100_000)),
+            ..Default::default()
+        };
+        let withdrawal = Transaction {
+            op: Operation::Withdrawal,
+            client: 1,
+            tx: 2,
+            amount: Some(MinorUnits(25_000)),
+            ..Default::default()
+        };
+
+        deposit.try_update(&mut act).unwrap();
+        withdrawal.try_update(&mut act).unwrap();
+
+        assert_eq!(act.available, MinorUnits(75_000));
+        assert_eq!(act.total.to_output(), "7.5000");
+    }
+}
@@ -0,0 +1,97 @@
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransactionError {
+    InsufficientFunds,
+    TransactionNotFound,
+    UnspecifiedBehavior,
+    LockedAccount,
+    BalanceCapExceeded,
+    DisputeLimitReached,
+    OutOfOrder,
+    AlreadyDisputed,
+    NotUnderDispute,
+    AlreadyChargedBack,
+    OperationNotDisputable,
+    ClientMismatch,
+    InvariantViolation,
+    NegativeAmount,
+    ExcessPrecision,
+    CreditLimitExceeded,
+    MissingCurrency,
+    CurrencyMismatch,
+    TimestampOutOfOrder,
+    DisputeWindowExpired,
+    AlreadyCaptured,
+    CaptureExceedsAuthorization,
+    AmountOverflow,
+    FeeIdTaken,
+}
+
+impl TransactionError {
+    // Stable identifier for counting and exporting rejections, unlike the display message
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::InsufficientFunds => "insufficient_funds",
+            TransactionError::TransactionNotFound => "transaction_not_found",
+            TransactionError::UnspecifiedBehavior => "unspecified_behavior",
+            TransactionError::LockedAccount => "locked_account",
+            TransactionError::BalanceCapExceeded => "balance_cap_exceeded",
+            TransactionError::DisputeLimitReached => "dispute_limit_reached",
+            TransactionError::OutOfOrder => "out_of_order",
+            TransactionError::AlreadyDisputed => "already_disputed",
+            TransactionError::NotUnderDispute => "not_under_dispute",
+            TransactionError::AlreadyChargedBack => "already_charged_back",
+            TransactionError::OperationNotDisputable => "operation_not_disputable",
+            TransactionError::ClientMismatch => "client_mismatch",
+            TransactionError::InvariantViolation => "invariant_violation",
+            TransactionError::NegativeAmount => "negative_amount",
+            TransactionError::ExcessPrecision => "excess_precision",
+            TransactionError::CreditLimitExceeded => "credit_limit_exceeded",
+            TransactionError::MissingCurrency => "missing_currency",
+            TransactionError::CurrencyMismatch => "currency_mismatch",
+            TransactionError::TimestampOutOfOrder => "timestamp_out_of_order",
+            TransactionError::DisputeWindowExpired => "dispute_window_expired",
+            TransactionError::AlreadyCaptured => "already_captured",
+            TransactionError::CaptureExceedsAuthorization => "capture_exceeds_authorization",
+            TransactionError::AmountOverflow => "amount_overflow",
+            TransactionError::FeeIdTaken => "fee_id_taken",
+        }
+    }
+}
+
+// Written out by hand rather than derived, thiserror needs std
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            TransactionError::InsufficientFunds => "Insufficient funds in account",
+            TransactionError::TransactionNotFound => "Cannot find transaction",
+            TransactionError::UnspecifiedBehavior => "Unexpected behavior",
+            TransactionError::LockedAccount => "Account Frozen",
+            TransactionError::BalanceCapExceeded => "Balance cap exceeded",
+            TransactionError::DisputeLimitReached => "Dispute limit reached",
+            TransactionError::OutOfOrder => "Transaction id out of order",
+            TransactionError::AlreadyDisputed => "Transaction already disputed",
+            TransactionError::NotUnderDispute => "Transaction not under dispute",
+            TransactionError::AlreadyChargedBack => "Transaction already charged back",
+            TransactionError::OperationNotDisputable => "Operation can't be disputed",
+            TransactionError::ClientMismatch => "Transaction belongs to another client",
+            TransactionError::InvariantViolation => "Account invariant violated",
+            TransactionError::NegativeAmount => "Amount can't be negative",
+            TransactionError::ExcessPrecision => "Amount has more than four decimals",
+            TransactionError::CreditLimitExceeded => "Credit limit exceeded",
+            TransactionError::MissingCurrency => "Transaction has no currency",
+            TransactionError::CurrencyMismatch => "Transaction is in another currency",
+            TransactionError::TimestampOutOfOrder => "Timestamp before the client's last activity",
+            TransactionError::DisputeWindowExpired => "Transaction too old to dispute",
+            TransactionError::AlreadyCaptured => "Authorization already captured",
+            TransactionError::CaptureExceedsAuthorization => "Capture exceeds the authorized amount",
+            TransactionError::AmountOverflow => "Amount out of range",
+            TransactionError::FeeIdTaken => "Transaction id already holds a fee",
+        };
+        f.write_str(msg)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TransactionError {}
@@ -0,0 +1,35 @@
+// Pure settlement logic: account math, dispute transitions and their errors. Only depends on
+// `core` and `alloc` so it builds with `--no-default-features` for targets without std.
+pub mod account;
+pub mod amount;
+pub mod errors;
+pub mod transaction;
+
+// Width of client ids. u16 matches the CLI's input format; the `client-id-*` features widen it
+// for upstreams with larger ids, e.g. u128 for UUIDs.
+#[cfg(not(any(
+    feature = "client-id-u32",
+    feature = "client-id-u64",
+    feature = "client-id-u128"
+)))]
+pub type ClientId = u16;
+#[cfg(all(
+    feature = "client-id-u32",
+    not(any(feature = "client-id-u64", feature = "client-id-u128"))
+))]
+pub type ClientId = u32;
+#[cfg(all(feature = "client-id-u64", not(feature = "client-id-u128")))]
+pub type ClientId = u64;
+#[cfg(feature = "client-id-u128")]
+pub type ClientId = u128;
+
+pub use account::Account;
+pub use amount::Amount;
+pub use transaction::Transaction;
+
+pub trait TryUpdate<Rhs> {
+    type Output;
+    type Error;
+    // Required method
+    fn try_update(self, rhs: Rhs) -> Result<(), Self::Error>;
+}
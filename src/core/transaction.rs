@@ -0,0 +1,391 @@
+use super::{errors::TransactionError, Account, Amount, ClientId, TryUpdate};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::str::FromStr;
+use rust_decimal::Decimal;
+
+// Input columns outside the transaction format, e.g. a partner's merchant or reference id, by
+// header name
+pub type Extra = BTreeMap<String, String>;
+
+#[derive(Debug, Default, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(bound = "A: Amount"))]
+pub struct Transaction<A = Decimal> {
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub op: Operation,
+    pub client: ClientId,
+    pub tx: u32,
+    pub amount: Option<A>,
+    // client receiving a transfer, empty for every other operation
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub counterparty: Option<ClientId>,
+    // ISO 4217 code of the amount, only read by multi-currency runs. Disputes, resolves and
+    // chargebacks may leave it out, they're in the currency of the transaction they refer to.
+    // Always written, empty when unset, so every CSV row has the same columns.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub currency: Option<String>,
+    // Seconds since the Unix epoch from the optional `timestamp` column, written like `currency`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timestamp: Option<u64>,
+    // filled in by readers that know the input's headers, the CSV format itself has no room for it
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub extra: Extra,
+}
+
+impl<A> Transaction<A> {
+    // The client a transfer credits, None for every other operation
+    pub fn recipient(&self) -> Option<ClientId> {
+        match self.op {
+            Operation::Transfer => self.counterparty,
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub enum Operation {
+    #[default]
+    Deposit,
+    Withdrawal,
+    Resolve,
+    Chargeback,
+    Dispute,
+    // charged by the processor itself, e.g. for a chargeback
+    Fee,
+    // moves funds from the client's account to the counterparty's
+    Transfer,
+    // holds funds for a card payment until it's captured, the total stays the same
+    Authorize,
+    // settles an authorization under the same tx id, taking the amount given or the whole
+    // authorized amount and releasing the rest
+    Capture,
+    // a type this version doesn't know, e.g. a new upstream `refund`. The engine rejects it, so
+    // readers decide whether it's skipped, quarantined or fails the run.
+    Unknown(String),
+}
+
+impl Operation {
+    // Single byte code used by the fixed width archived layouts. Unknown operations are never
+    // applied, so they never end up in an archive.
+    pub fn code(&self) -> u8 {
+        match self {
+            Operation::Deposit => 0,
+            Operation::Withdrawal => 1,
+            Operation::Resolve => 2,
+            Operation::Chargeback => 3,
+            Operation::Dispute => 4,
+            Operation::Fee => 5,
+            Operation::Transfer => 6,
+            Operation::Authorize => 7,
+            Operation::Capture => 8,
+            Operation::Unknown(_) => u8::MAX,
+        }
+    }
+
+    // Name used in the `type` column
+    pub fn name(&self) -> &str {
+        match self {
+            Operation::Deposit => "deposit",
+            Operation::Withdrawal => "withdrawal",
+            Operation::Resolve => "resolve",
+            Operation::Chargeback => "chargeback",
+            Operation::Dispute => "dispute",
+            Operation::Fee => "fee",
+            Operation::Transfer => "transfer",
+            Operation::Authorize => "authorize",
+            Operation::Capture => "capture",
+            Operation::Unknown(name) => name,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "deposit" => Operation::Deposit,
+            "withdrawal" => Operation::Withdrawal,
+            "resolve" => Operation::Resolve,
+            "chargeback" => Operation::Chargeback,
+            "dispute" => Operation::Dispute,
+            "fee" => Operation::Fee,
+            "transfer" => Operation::Transfer,
+            "authorize" => Operation::Authorize,
+            "capture" => Operation::Capture,
+            name => Operation::Unknown(name.to_string()),
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Operation::Deposit),
+            1 => Some(Operation::Withdrawal),
+            2 => Some(Operation::Resolve),
+            3 => Some(Operation::Chargeback),
+            4 => Some(Operation::Dispute),
+            5 => Some(Operation::Fee),
+            6 => Some(Operation::Transfer),
+            7 => Some(Operation::Authorize),
+            8 => Some(Operation::Capture),
+            _ => None,
+        }
+    }
+}
+
+// Which operations a locked account still accepts. Fees are always charged, the chargeback that
+// incurs one locks the account.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LockPolicy {
+    // rejects everything else with `LockedAccount`
+    #[default]
+    Freeze,
+    // still lets the account's disputes be opened, resolved and charged back, while deposits,
+    // withdrawals and transfers stay blocked
+    Settle,
+}
+
+impl LockPolicy {
+    pub fn allows(self, op: &Operation) -> bool {
+        match op {
+            Operation::Fee => true,
+            Operation::Dispute | Operation::Resolve | Operation::Chargeback => {
+                self == LockPolicy::Settle
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for LockPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "freeze" => Ok(LockPolicy::Freeze),
+            "settle" => Ok(LockPolicy::Settle),
+            _ => Err(format!(
+                "unknown lock policy {s}, expected freeze or settle"
+            )),
+        }
+    }
+}
+
+impl<A: Amount> Transaction<A> {
+    // Applies the transaction to the account, letting it through a lock only if `policy` allows
+    // its operation
+    pub fn apply(&self, rhs: &mut Account<A>, policy: LockPolicy) -> Result<(), TransactionError> {
+        if rhs.locked && !policy.allows(&self.op) {
+            return Err(TransactionError::LockedAccount);
+        }
+
+        match self.op {
+            Operation::Deposit => rhs.deposit(self.amount),
+            Operation::Withdrawal => rhs.withdraw(self.amount),
+            // the sending side, the engine credits the counterparty
+            Operation::Transfer => rhs.withdraw(self.amount),
+            // need to retrieve the disputed transaction to properly
+            // update held / available balances, assuming that this transaction
+            // is populated with the appropriate amount
+            Operation::Resolve => rhs.resolve(self.amount),
+            Operation::Chargeback => rhs.chargeback(self.amount),
+            Operation::Dispute => rhs.dispute(self.amount),
+            // fees are owed whether or not the account is frozen
+            Operation::Fee => rhs.charge_fee(self.amount),
+            Operation::Authorize => rhs.authorize(self.amount),
+            // the engine sets the captured amount from the authorization and releases the rest
+            Operation::Capture => rhs.capture(self.amount),
+            Operation::Unknown(_) => Err(TransactionError::UnspecifiedBehavior),
+        }
+    }
+}
+
+// Written out by hand so that any name deserializes, unknown ones into `Operation::Unknown`
+#[cfg(feature = "serde")]
+impl serde::Serialize for Operation {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Operation {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Operation::from_name(&name))
+    }
+}
+
+impl<A: Amount> TryUpdate<&mut Account<A>> for &Transaction<A> {
+    type Output = ();
+    type Error = TransactionError;
+
+    fn try_update(self, rhs: &mut Account<A>) -> Result<Self::Output, Self::Error> {
+        self.apply(rhs, LockPolicy::Freeze)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use crate::core::errors::TransactionError;
+
+    use super::*;
+
+    #[test]
+    fn successful_deposit() {
+        let tx: Transaction = Transaction {
+            op: Operation::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(dec!(42)),
+            ..Default::default()
+        };
+
+        let mut act = Account {
+            client: 1,
+            currency: None,
+            available: dec!(0.0),
+            held: dec!(0.0),
+            total: dec!(0.0),
+            locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
+        };
+
+        let out = Account {
+            client: 1,
+            currency: None,
+            available: dec!(42),
+            held: dec!(0.0),
+            total: dec!(42),
+            locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
+        };
+
+        tx.try_update(&mut act).expect("Failed to update Account");
+
+        assert_eq!(act, out);
+    }
+
+    #[test]
+    fn insufficient_funds_for_withdrawal() {
+        let tx: Transaction = Transaction {
+            op: Operation::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: Some(dec!(42)),
+            ..Default::default()
+        };
+
+        let mut act = Account {
+            client: 1,
+            currency: None,
+            available: dec!(0.0),
+            held: dec!(0.0),
+            total: dec!(0.0),
+            locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
+        };
+
+        let res = tx.try_update(&mut act);
+
+        match res {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => assert_eq!(e, TransactionError::InsufficientFunds),
+        }
+    }
+
+    #[test]
+    fn successful_withdrawal() {
+        let tx: Transaction = Transaction {
+            op: Operation::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: Some(dec!(42)),
+            ..Default::default()
+        };
+
+        let mut act = Account {
+            client: 1,
+            currency: None,
+            available: dec!(42),
+            held: dec!(0.0),
+            total: dec!(42),
+            locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
+        };
+
+        let out = Account {
+            client: 1,
+            currency: None,
+            available: dec!(0.0),
+            held: dec!(0.0),
+            total: dec!(0.0),
+            locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
+        };
+
+        let res = tx.try_update(&mut act);
+
+        match res {
+            Ok(_) => assert_eq!(act, out),
+            Err(_) => panic!("expected a successful withdrawal"),
+        }
+    }
+
+    #[test]
+    fn settles_disputes_on_locked_accounts_by_policy() {
+        let resolve: Transaction = Transaction {
+            op: Operation::Resolve,
+            client: 1,
+            tx: 1,
+            amount: Some(dec!(5)),
+            ..Default::default()
+        };
+        let deposit: Transaction = Transaction {
+            op: Operation::Deposit,
+            ..resolve.clone()
+        };
+
+        let mut act = Account {
+            client: 1,
+            currency: None,
+            available: dec!(0.0),
+            held: dec!(5),
+            total: dec!(5),
+            locked: true,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
+        };
+
+        assert_eq!(
+            resolve.apply(&mut act, LockPolicy::Freeze),
+            Err(TransactionError::LockedAccount)
+        );
+        assert_eq!(
+            deposit.apply(&mut act, LockPolicy::Settle),
+            Err(TransactionError::LockedAccount)
+        );
+        resolve
+            .apply(&mut act, LockPolicy::Settle)
+            .expect("Failed to settle on a locked account");
+        assert!(act.locked);
+        assert_eq!(act.held, dec!(0));
+    }
+}
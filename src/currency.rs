@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::domain::AccountStore;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum CurrencyError {
+    #[error("Unknown currency {0}, add its exponent with --currency-exponent")]
+    Unknown(String),
+    #[error("Invalid currency exponent {0}, expected <code>=<digits>")]
+    Exponent(String),
+}
+
+// Minor unit exponents of ISO 4217 currencies that don't use two decimals, plus the common two
+// decimal ones. Anything else has to be configured.
+const EXPONENTS: &[(&str, u32)] = &[
+    ("AED", 2),
+    ("AUD", 2),
+    ("BHD", 3),
+    ("BIF", 0),
+    ("BRL", 2),
+    ("CAD", 2),
+    ("CHF", 2),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("CNY", 2),
+    ("CZK", 2),
+    ("DJF", 0),
+    ("DKK", 2),
+    ("EUR", 2),
+    ("GBP", 2),
+    ("GNF", 0),
+    ("HKD", 2),
+    ("HUF", 2),
+    ("IDR", 2),
+    ("INR", 2),
+    ("IQD", 3),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KMF", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("LYD", 3),
+    ("MXN", 2),
+    ("NOK", 2),
+    ("NZD", 2),
+    ("OMR", 3),
+    ("PLN", 2),
+    ("PYG", 0),
+    ("RWF", 0),
+    ("SEK", 2),
+    ("SGD", 2),
+    ("TND", 3),
+    ("UGX", 0),
+    ("USD", 2),
+    ("UYI", 0),
+    ("UYW", 4),
+    ("VND", 0),
+    ("VUV", 0),
+    ("XAF", 0),
+    ("XOF", 0),
+    ("XPF", 0),
+    ("ZAR", 2),
+];
+
+// The built-in table with configured additions and overrides
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Currencies {
+    overrides: HashMap<String, u32>,
+}
+
+impl Currencies {
+    // Adds or overrides the exponent of a currency from a `<code>=<digits>` setting
+    pub fn set(&mut self, setting: &str) -> Result<(), CurrencyError> {
+        let invalid = || CurrencyError::Exponent(setting.to_string());
+        let (code, digits) = setting.split_once('=').ok_or_else(invalid)?;
+        // Decimal can't hold more than 28 digits after the point
+        let exponent = digits
+            .parse()
+            .ok()
+            .filter(|&e| e <= 28)
+            .ok_or_else(invalid)?;
+        self.overrides.insert(code.trim().to_uppercase(), exponent);
+        Ok(())
+    }
+
+    pub fn exponent(&self, code: &str) -> Result<u32, CurrencyError> {
+        let code = code.to_uppercase();
+        self.overrides
+            .get(&code)
+            .copied()
+            .or_else(|| {
+                EXPONENTS
+                    .binary_search_by_key(&code.as_str(), |&(code, _)| code)
+                    .ok()
+                    .map(|idx| EXPONENTS[idx].1)
+            })
+            .ok_or(CurrencyError::Unknown(code))
+    }
+
+    pub fn currency(&self, code: &str) -> Result<Currency, CurrencyError> {
+        Ok(Currency {
+            code: code.to_uppercase(),
+            exponent: self.exponent(code)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Currency {
+    pub code: String,
+    pub exponent: u32,
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Currencies::default().currency(code)
+    }
+}
+
+impl Currency {
+    // Rounds to the currency's minor unit, half to even like the four decimal output, and pads
+    // to exactly that many decimals so amounts serialize the way the currency is written
+    pub fn round(&self, amount: Decimal) -> Decimal {
+        let mut rounded = amount.round_dp(self.exponent);
+        rounded.rescale(self.exponent);
+        rounded
+    }
+
+    // Copy of the accounts with every balance rounded to the currency
+    pub fn round_accounts(&self, accounts: &AccountStore) -> AccountStore {
+        accounts
+            .iter()
+            .map(|(&client, act)| {
+                let mut act = act.clone();
+                act.available = self.round(act.available);
+                act.held = self.round(act.held);
+                act.total = self.round(act.total);
+                (client, act)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn rounds_to_each_currencys_minor_unit() {
+        let mut currencies = Currencies::default();
+        assert!(EXPONENTS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let yen = currencies.currency("jpy").unwrap();
+        assert_eq!(yen.round(dec!(1234.5)).to_string(), "1234");
+        assert_eq!(yen.round(dec!(1235.5)).to_string(), "1236");
+        let dinar: Currency = "BHD".parse().unwrap();
+        assert_eq!(dinar.round(dec!(1.23456)).to_string(), "1.235");
+        let dollar: Currency = "USD".parse().unwrap();
+        assert_eq!(dollar.round(dec!(2.5)).to_string(), "2.50");
+
+        assert_eq!(
+            currencies.exponent("XTS"),
+            Err(CurrencyError::Unknown("XTS".into()))
+        );
+        currencies.set("xts=1").unwrap();
+        currencies.set("USD=4").unwrap();
+        assert_eq!(currencies.exponent("XTS"), Ok(1));
+        assert_eq!(currencies.exponent("usd"), Ok(4));
+        assert!(currencies.set("USD").is_err());
+    }
+}
@@ -1,13 +0,0 @@
-use thiserror::Error;
-
-#[derive(Error, Debug, PartialEq)]
-pub enum TransactionError {
-    #[error("Insufficient funds in account")]
-    InsufficientFunds,
-    #[error("Cannot find transaction")]
-    TransactionNotFound,
-    #[error("Unexpected behavior")]
-    UnspecifiedBehavior,
-    #[error("Account Frozen")]
-    LockedAccount
-}
\ No newline at end of file
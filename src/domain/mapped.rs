@@ -0,0 +1,353 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{transaction::Operation, ClientId};
+use crate::mmap::Mapping;
+
+// Open addressing table of history entries kept in a memory-mapped file. Entries are fixed width
+// slots that lookups read straight out of the mapping, so nothing is loaded up front and the
+// table survives restarts. Amounts are stored in the 16 byte encoding of `Amount::to_bits`.
+//
+// Layout: a 64 byte header (magic, capacity, live entries, used slots) followed by `capacity`
+// slots of `SLOT` bytes, all little endian:
+//   client u128 | tx u32 | state u8 | op u8 | fields u8 | disputes u8 | amount [u8; 16]
+//   | timestamp u64 | counterparty u128
+// `fields` has a bit each for the amount, the timestamp and the counterparty. Tables written
+// before disputes were counted hold a zero there, tables from before timestamps were kept have
+// 40 byte slots without one, and tables from before transfers were linked 48 byte slots without
+// a counterparty; they are rewritten in the current layout when opened.
+#[derive(Debug)]
+pub struct MappedTable {
+    path: PathBuf,
+    map: Mapping,
+    // width of the table's slots, `SLOT` unless it's a table of an older layout being upgraded
+    slot: usize,
+}
+
+const MAGIC: &[u8; 8] = b"BANKHIS3";
+const MAGIC_V2: &[u8; 8] = b"BANKHIS2";
+const MAGIC_V1: &[u8; 8] = b"BANKHIS1";
+const HEADER: usize = 64;
+const SLOT: usize = 64;
+const SLOT_V2: usize = 48;
+const SLOT_V1: usize = 40;
+
+const HAS_AMOUNT: u8 = 1;
+const HAS_TIMESTAMP: u8 = 2;
+const HAS_COUNTERPARTY: u8 = 4;
+const MIN_CAPACITY: u64 = 1024;
+
+const EMPTY: u8 = 0;
+const OCCUPIED: u8 = 1;
+const REMOVED: u8 = 2;
+
+// An entry as stored in a slot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub op: Operation,
+    pub amount: Option<[u8; 16]>,
+    pub disputes: u8,
+    pub timestamp: Option<u64>,
+    pub counterparty: Option<ClientId>,
+}
+
+impl MappedTable {
+    // Opens the table at `path`, creating an empty one if the file doesn't exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Self::create(path, MIN_CAPACITY);
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Not a history file");
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        if file.metadata()?.len() < HEADER as u64 {
+            return Err(invalid());
+        }
+        let mut table = Self {
+            path: path.to_path_buf(),
+            map: Mapping::read_write(file)?,
+            slot: SLOT,
+        };
+        table.slot = match &table.map.bytes()[..8] {
+            magic if magic == MAGIC => SLOT,
+            magic if magic == MAGIC_V2 => SLOT_V2,
+            magic if magic == MAGIC_V1 => SLOT_V1,
+            _ => return Err(invalid()),
+        };
+        let size = HEADER as u64 + table.capacity().saturating_mul(table.slot as u64);
+        if table.map.bytes().len() as u64 != size || !table.capacity().is_power_of_two() {
+            return Err(invalid());
+        }
+        if table.slot != SLOT {
+            table.rebuild(table.capacity())?;
+        }
+        Ok(table)
+    }
+
+    fn create(path: &Path, capacity: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(HEADER as u64 + capacity * SLOT as u64)?;
+        let mut table = Self {
+            path: path.to_path_buf(),
+            map: Mapping::read_write(file)?,
+            slot: SLOT,
+        };
+        let header = &mut table.map.bytes_mut()[..HEADER];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..16].copy_from_slice(&capacity.to_le_bytes());
+        Ok(table)
+    }
+
+    pub fn len(&self) -> usize {
+        self.header(16) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, key: &(ClientId, u32)) -> Option<Entry> {
+        self.find(key).ok().map(|idx| self.entry(idx))
+    }
+
+    pub fn insert(&mut self, key: (ClientId, u32), entry: Entry) -> Option<Entry> {
+        let idx = match self.find(&key) {
+            Ok(idx) => {
+                let previous = self.entry(idx);
+                self.write(idx, &key, &entry);
+                return Some(previous);
+            }
+            Err(idx) => idx,
+        };
+        // keep a third of the slots free so probe sequences stay short
+        if (self.header(24) + 1) * 3 > self.capacity() * 2 {
+            self.rebuild(self.capacity() * 2)
+                .expect("Failed to grow the history file");
+            return self.insert(key, entry);
+        }
+        if self.slot(idx)[20] == EMPTY {
+            self.set_header(24, self.header(24) + 1);
+        }
+        self.write(idx, &key, &entry);
+        self.set_header(16, self.header(16) + 1);
+        None
+    }
+
+    pub fn remove(&mut self, key: &(ClientId, u32)) -> Option<Entry> {
+        let idx = self.find(key).ok()?;
+        let previous = self.entry(idx);
+        self.slot_mut(idx)[20] = REMOVED;
+        self.set_header(16, self.header(16) - 1);
+        Some(previous)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((ClientId, u32), Entry)> + '_ {
+        (0..self.capacity() as usize)
+            .filter(|&idx| self.slot(idx)[20] == OCCUPIED)
+            .map(|idx| (self.key(idx), self.entry(idx)))
+    }
+
+    // Writes the mapped pages back to the file
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+
+    // Rebuilds the table in the current layout with `capacity` slots in a new file, dropping
+    // removed entries, and renames it over the current one
+    fn rebuild(&mut self, capacity: u64) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".grow");
+        let tmp = PathBuf::from(tmp);
+        let mut grown = Self::create(&tmp, capacity)?;
+        for (key, entry) in self.iter() {
+            grown.insert(key, entry);
+        }
+        grown.flush()?;
+        fs::rename(&tmp, &self.path)?;
+        grown.path.clone_from(&self.path);
+        *self = grown;
+        Ok(())
+    }
+
+    // Slot holding `key`, or the slot it would be inserted into
+    fn find(&self, key: &(ClientId, u32)) -> Result<usize, usize> {
+        let mask = self.capacity() - 1;
+        let mut idx = hash(key) & mask;
+        let mut free = None;
+        loop {
+            let slot = self.slot(idx as usize);
+            match slot[20] {
+                EMPTY => return Err(free.unwrap_or(idx as usize)),
+                REMOVED => {
+                    free.get_or_insert(idx as usize);
+                }
+                _ if self.key(idx as usize) == *key => return Ok(idx as usize),
+                _ => {}
+            }
+            idx = (idx + 1) & mask;
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        self.header(8)
+    }
+
+    fn header(&self, offset: usize) -> u64 {
+        let bytes = &self.map.bytes()[offset..offset + 8];
+        u64::from_le_bytes(bytes.try_into().expect("Header fields are 8 bytes"))
+    }
+
+    fn set_header(&mut self, offset: usize, value: u64) {
+        self.map.bytes_mut()[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn slot(&self, idx: usize) -> &[u8] {
+        let start = HEADER + idx * self.slot;
+        &self.map.bytes()[start..start + self.slot]
+    }
+
+    fn slot_mut(&mut self, idx: usize) -> &mut [u8] {
+        let start = HEADER + idx * self.slot;
+        let end = start + self.slot;
+        &mut self.map.bytes_mut()[start..end]
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    fn key(&self, idx: usize) -> (ClientId, u32) {
+        let slot = self.slot(idx);
+        let client = u128::from_le_bytes(slot[..16].try_into().expect("Client is 16 bytes"));
+        let tx = u32::from_le_bytes(slot[16..20].try_into().expect("Tx is 4 bytes"));
+        (client as ClientId, tx)
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    fn entry(&self, idx: usize) -> Entry {
+        let slot = self.slot(idx);
+        let op = Operation::from_code(slot[21]).unwrap_or_default();
+        let amount = (slot[22] & HAS_AMOUNT != 0)
+            .then(|| slot[24..40].try_into().expect("Amount is 16 bytes"));
+        let timestamp = (slot[22] & HAS_TIMESTAMP != 0 && slot.len() >= SLOT_V2)
+            .then(|| u64::from_le_bytes(slot[40..48].try_into().expect("Timestamp is 8 bytes")));
+        let counterparty = (slot[22] & HAS_COUNTERPARTY != 0 && slot.len() == SLOT).then(|| {
+            u128::from_le_bytes(slot[48..64].try_into().expect("Counterparty is 16 bytes"))
+                as ClientId
+        });
+        Entry {
+            op,
+            amount,
+            disputes: slot[23],
+            timestamp,
+            counterparty,
+        }
+    }
+
+    // Only called on tables of the current layout
+    #[allow(clippy::useless_conversion)]
+    fn write(&mut self, idx: usize, key: &(ClientId, u32), entry: &Entry) {
+        let slot = self.slot_mut(idx);
+        slot[..16].copy_from_slice(&u128::from(key.0).to_le_bytes());
+        slot[16..20].copy_from_slice(&key.1.to_le_bytes());
+        slot[20] = OCCUPIED;
+        slot[21] = entry.op.code();
+        slot[22] = entry.amount.map_or(0, |_| HAS_AMOUNT)
+            | entry.timestamp.map_or(0, |_| HAS_TIMESTAMP)
+            | entry.counterparty.map_or(0, |_| HAS_COUNTERPARTY);
+        slot[23] = entry.disputes;
+        slot[24..40].copy_from_slice(&entry.amount.unwrap_or_default());
+        slot[40..48].copy_from_slice(&entry.timestamp.unwrap_or_default().to_le_bytes());
+        let counterparty = entry.counterparty.map_or(0, u128::from);
+        slot[48..64].copy_from_slice(&counterparty.to_le_bytes());
+    }
+}
+
+// Stable across builds and runs, unlike std's hashers, since slots are persisted
+#[allow(clippy::useless_conversion)]
+fn hash(key: &(ClientId, u32)) -> u64 {
+    let client = u128::from(key.0);
+    let mut z = (client as u64) ^ ((client >> 64) as u64).rotate_left(32) ^ u64::from(key.1) << 16;
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::env;
+
+    use super::*;
+
+    fn entry(op: Operation, amount: u8) -> Entry {
+        Entry {
+            op,
+            amount: Some([amount; 16]),
+            disputes: 0,
+            timestamp: Some(1_709_164_800 + u64::from(amount)),
+            counterparty: None,
+        }
+    }
+
+    #[test]
+    fn entries_survive_reopening_and_growth() {
+        let path = env::temp_dir().join(format!("bank-history-{}.bin", std::process::id()));
+        fs::remove_file(&path).ok();
+
+        let mut table = MappedTable::open(&path).expect("Failed to create table");
+        for tx in 0..2_000 {
+            assert_eq!(table.insert((7, tx), entry(Operation::Deposit, 1)), None);
+        }
+        // the dispute of a transfer keeps the other client of it
+        let dispute = Entry {
+            counterparty: Some(9),
+            ..entry(Operation::Dispute, 2)
+        };
+        let previous = table.insert((7, 5), dispute.clone());
+        assert_eq!(previous, Some(entry(Operation::Deposit, 1)));
+        assert_eq!(table.remove(&(7, 6)), Some(entry(Operation::Deposit, 1)));
+        table.flush().expect("Failed to flush");
+        drop(table);
+
+        let table = MappedTable::open(&path).expect("Failed to reopen table");
+        assert_eq!(table.len(), 1_999);
+        assert_eq!(table.get(&(7, 5)), Some(dispute));
+        assert_eq!(table.get(&(7, 6)), None);
+        assert_eq!(table.get(&(8, 5)), None);
+        assert_eq!(table.iter().count(), 1_999);
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn upgrades_tables_without_timestamps() {
+        let path = env::temp_dir().join(format!("bank-history-v1-{}.bin", std::process::id()));
+        // a table of the first layout holding a single deposit of 5 by client 7
+        let mut bytes = vec![0; HEADER + 1024 * SLOT_V1];
+        bytes[..8].copy_from_slice(MAGIC_V1);
+        bytes[8..16].copy_from_slice(&1024u64.to_le_bytes());
+        bytes[16..24].copy_from_slice(&1u64.to_le_bytes());
+        bytes[24..32].copy_from_slice(&1u64.to_le_bytes());
+        let key = (7, 3);
+        let idx = (hash(&key) & 1023) as usize;
+        let slot = &mut bytes[HEADER + idx * SLOT_V1..][..SLOT_V1];
+        slot[..16].copy_from_slice(&7u128.to_le_bytes());
+        slot[16..20].copy_from_slice(&3u32.to_le_bytes());
+        slot[20] = OCCUPIED;
+        slot[21] = Operation::Deposit.code();
+        slot[22] = HAS_AMOUNT;
+        slot[24..40].copy_from_slice(&[5; 16]);
+        fs::write(&path, bytes).unwrap();
+
+        let mut table = MappedTable::open(&path).expect("Failed to upgrade table");
+        let mut deposit = entry(Operation::Deposit, 5);
+        deposit.timestamp = None;
+        assert_eq!(table.get(&key), Some(deposit));
+        table.insert((7, 4), entry(Operation::Deposit, 6));
+        assert_eq!(table.get(&(7, 4)), Some(entry(Operation::Deposit, 6)));
+        assert_eq!(&fs::read(&path).unwrap()[..8], MAGIC);
+        fs::remove_file(&path).ok();
+    }
+}
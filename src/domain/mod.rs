@@ -1,15 +1,52 @@
-pub mod account;
-pub mod transaction;
-pub mod errors;
+#[cfg(feature = "mmap")]
+pub mod mapped;
+#[cfg(feature = "sled")]
+pub mod sled_store;
+#[cfg(feature = "std")]
+pub mod tiered;
+#[cfg(feature = "std")]
 pub mod tx_history;
 
-pub use account::Account;
-pub use transaction::Transaction;
+// The settlement types live in `core` so they build without std, re-exported here for the
+// std side of the crate
+pub use crate::core::{
+    account, amount, errors, transaction, Account, Amount, ClientId, Transaction, TryUpdate,
+};
+#[cfg(feature = "std")]
 pub use tx_history::History;
 
-pub trait TryUpdate<Rhs> {
-    type Output;
-    type Error;
-    // Required method
-    fn try_update(self, rhs: Rhs) -> Result<(), Self::Error> ;
-}
\ No newline at end of file
+// Map the engine keeps accounts in. std's HashMap by default; the `accounts-*` features swap in
+// hashbrown's map with its faster hasher, or a BTreeMap that iterates in client order.
+#[cfg(all(
+    feature = "std",
+    not(any(feature = "accounts-hashbrown", feature = "accounts-btree"))
+))]
+pub type AccountStore<A = rust_decimal::Decimal> = std::collections::HashMap<ClientId, Account<A>>;
+#[cfg(all(
+    feature = "std",
+    feature = "accounts-hashbrown",
+    not(feature = "accounts-btree")
+))]
+pub type AccountStore<A = rust_decimal::Decimal> = hashbrown::HashMap<ClientId, Account<A>>;
+#[cfg(all(feature = "std", feature = "accounts-btree"))]
+pub type AccountStore<A = rust_decimal::Decimal> = std::collections::BTreeMap<ClientId, Account<A>>;
+
+// Account storage a `Task` applies transactions against. `AccountStore` is the in-memory one the
+// engine uses; a store on disk or in a database implements this to run tasks against it. Tasks
+// read an account, update their copy and write it back, so stores don't have to hand out
+// references into their storage.
+#[cfg(feature = "std")]
+pub trait AccountRepository<A = rust_decimal::Decimal> {
+    fn get(&self, client: &ClientId) -> Option<Account<A>>;
+    fn insert(&mut self, account: Account<A>);
+}
+
+#[cfg(feature = "std")]
+impl<A: Amount> AccountRepository<A> for AccountStore<A> {
+    fn get(&self, client: &ClientId) -> Option<Account<A>> {
+        AccountStore::get(self, client).cloned()
+    }
+    fn insert(&mut self, account: Account<A>) {
+        AccountStore::insert(self, account.client, account);
+    }
+}
@@ -0,0 +1,195 @@
+use std::io;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::{Db, Transactional, Tree};
+
+use super::tx_history::{DisputeState, Node, TxStore};
+use super::{Amount, ClientId};
+
+// History store backed by a sled database, for histories that don't fit in memory and need to
+// outlive the run. Nodes live in the `transactions` tree as JSON, so unlike a mapped table they
+// keep their extra columns. The `disputes` tree, the counterpart of a RocksDB column family,
+// holds the dispute state and count of every transaction that has been disputed, written in the
+// same sled transaction as its node, so open disputes are listed without reading the history.
+//
+// Keys of both trees are the client id and tx id, big endian, so the trees iterate in client
+// and then tx order. The width of the client id is part of the key, a database has to be opened
+// by builds with the same client id width.
+#[derive(Debug)]
+pub struct SledStore<A = Decimal> {
+    db: Db,
+    transactions: Tree,
+    disputes: Tree,
+    amounts: PhantomData<A>,
+}
+
+const KEY: usize = size_of::<ClientId>() + size_of::<u32>();
+
+impl<A: Amount> SledStore<A> {
+    // Opens the database at `path`, creating it if it doesn't exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            transactions: db.open_tree("transactions")?,
+            disputes: db.open_tree("disputes")?,
+            db,
+            amounts: PhantomData,
+        })
+    }
+
+    // Transactions that have been disputed, with their dispute state and how often they were
+    // disputed, in client and tx order
+    pub fn disputes(&self) -> impl Iterator<Item = ((ClientId, u32), DisputeState, u8)> + '_ {
+        self.disputes.iter().map(|entry| {
+            let (key, value) = entry.expect("Failed to read the dispute states");
+            let state = match value[0] {
+                DISPUTED => DisputeState::Disputed,
+                RESOLVED => DisputeState::Resolved,
+                _ => DisputeState::ChargedBack,
+            };
+            (decode_key(&key), state, value[1])
+        })
+    }
+
+    // Writes the node and its dispute state together, returning the node it replaced
+    fn write(&self, key: &(ClientId, u32), node: Option<&Node<A>>) -> Option<Node<A>> {
+        let key = encode_key(key);
+        let value = node.map(|node| serde_json::to_vec(node).expect("Failed to encode a node"));
+        let state = node.and_then(|node| {
+            let state = match node.dispute_state() {
+                DisputeState::Disputed => DISPUTED,
+                DisputeState::Resolved => RESOLVED,
+                DisputeState::ChargedBack => CHARGED_BACK,
+                DisputeState::None => return None,
+            };
+            Some([state, node.disputes])
+        });
+        let previous = (&self.transactions, &self.disputes)
+            .transaction(|(transactions, disputes)| {
+                let previous = match &value {
+                    Some(value) => transactions.insert(&key[..], value.as_slice())?,
+                    None => transactions.remove(&key[..])?,
+                };
+                match state {
+                    Some(state) => disputes.insert(&key[..], &state[..])?,
+                    None => disputes.remove(&key[..])?,
+                };
+                Ok::<_, ConflictableTransactionError<()>>(previous)
+            })
+            .map_err(|e: TransactionError<()>| match e {
+                TransactionError::Storage(e) => e,
+                TransactionError::Abort(()) => unreachable!("Writes don't abort"),
+            })
+            .expect("Failed to write to the history database");
+        previous.map(|value| decode_node(&value))
+    }
+}
+
+// Dispute states in the `disputes` tree, next to the dispute count
+const DISPUTED: u8 = 1;
+const RESOLVED: u8 = 2;
+const CHARGED_BACK: u8 = 3;
+
+fn encode_key((client, tx): &(ClientId, u32)) -> [u8; KEY] {
+    let mut key = [0; KEY];
+    key[..size_of::<ClientId>()].copy_from_slice(&client.to_be_bytes());
+    key[size_of::<ClientId>()..].copy_from_slice(&tx.to_be_bytes());
+    key
+}
+
+fn decode_key(key: &[u8]) -> (ClientId, u32) {
+    let (client, tx) = key.split_at(size_of::<ClientId>());
+    (
+        ClientId::from_be_bytes(client.try_into().expect("Client id of the build's width")),
+        u32::from_be_bytes(tx.try_into().expect("Tx is 4 bytes")),
+    )
+}
+
+fn decode_node<A: Amount>(value: &[u8]) -> Node<A> {
+    serde_json::from_slice(value).expect("Failed to decode a node")
+}
+
+impl<A: Amount + Send + Sync> TxStore<A> for SledStore<A> {
+    fn get(&self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        let value = self
+            .transactions
+            .get(encode_key(key))
+            .expect("Failed to read from the history database")?;
+        Some(decode_node(&value))
+    }
+    fn insert(&mut self, key: (ClientId, u32), node: Node<A>) -> Option<Node<A>> {
+        self.write(&key, Some(&node))
+    }
+    fn remove(&mut self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        self.write(key, None)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = ((ClientId, u32), Node<A>)> + '_> {
+        Box::new(self.transactions.iter().map(|entry| {
+            let (key, value) = entry.expect("Failed to read the history database");
+            (decode_key(&key), decode_node(&value))
+        }))
+    }
+    fn flush(&self) -> io::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::{Extra, Operation};
+
+    #[test]
+    fn keeps_nodes_and_their_dispute_state_across_restarts() {
+        let path = std::env::temp_dir().join(format!("bank-sled-{}", std::process::id()));
+        let deposit = Node {
+            op: Operation::Deposit,
+            amount: Some(dec!(10.5)),
+            disputes: 0,
+            timestamp: Some(1_700_000_000),
+            counterparty: None,
+            extra: Extra::from([("merchant".to_string(), "m-7".to_string())]),
+        };
+        {
+            let mut store: SledStore = SledStore::open(&path).unwrap();
+            assert_eq!(store.insert((1, 1), deposit.clone()), None);
+            store.insert((2, 2), deposit.clone());
+            let disputed = Node {
+                op: Operation::Dispute,
+                amount: Some(dec!(-10.5)),
+                disputes: 1,
+                ..deposit.clone()
+            };
+            assert_eq!(store.insert((1, 1), disputed), Some(deposit.clone()));
+            assert_eq!(store.remove(&(2, 2)), Some(deposit.clone()));
+            store.flush().unwrap();
+        }
+
+        // sled's flusher thread lets go of the database shortly after the store is dropped
+        let store: SledStore = (0..100)
+            .find_map(|_| {
+                SledStore::open(&path)
+                    .inspect_err(|_| std::thread::sleep(std::time::Duration::from_millis(10)))
+                    .ok()
+            })
+            .unwrap();
+        let node = store.get(&(1, 1)).unwrap();
+        assert_eq!(node.op, Operation::Dispute);
+        assert_eq!(node.extra, deposit.extra);
+        assert_eq!(store.get(&(2, 2)), None);
+        assert_eq!(
+            store.disputes().collect::<Vec<_>>(),
+            vec![((1, 1), DisputeState::Disputed, 1)]
+        );
+        assert_eq!(store.iter().count(), 1);
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+
+use rust_decimal::Decimal;
+
+use super::tx_history::{Node, TxStore};
+use super::{Amount, ClientId};
+
+// History store that keeps the `capacity` most recently used nodes in memory in front of a store
+// on disk, so memory use stays bounded however long the input is. Writes go through to the disk
+// store, which always holds the complete history: evicting a node only drops it from memory, and
+// a crash loses nothing the disk store alone would have kept. Lookups are answered from memory
+// when the node is there and fetched from disk otherwise, and both reads and writes make a node
+// the last to be evicted. Memory holds nodes as the disk store keeps them, e.g. without the extra
+// columns a mapped table has no room for, so a lookup answers the same whether or not the node
+// was evicted.
+#[derive(Debug)]
+pub struct Tiered<A = Decimal> {
+    // behind a lock since lookups refresh a node's recency
+    hot: Mutex<Hot<A>>,
+    capacity: usize,
+    cold: Box<dyn TxStore<A>>,
+}
+
+#[derive(Debug)]
+struct Hot<A> {
+    nodes: HashMap<(ClientId, u32), (Node<A>, u64)>,
+    // use stamp of every node in `nodes`, the least recently used is evicted first
+    order: BTreeMap<u64, (ClientId, u32)>,
+    uses: u64,
+}
+
+impl<A: Clone> Hot<A> {
+    fn get(&mut self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        let (node, stamp) = self.nodes.get_mut(key)?;
+        self.order.remove(stamp);
+        self.uses += 1;
+        *stamp = self.uses;
+        self.order.insert(self.uses, *key);
+        Some(node.clone())
+    }
+
+    fn insert(&mut self, key: (ClientId, u32), node: Node<A>, capacity: usize) -> Option<Node<A>> {
+        self.uses += 1;
+        self.order.insert(self.uses, key);
+        let replaced = self.nodes.insert(key, (node, self.uses));
+        let previous = self.remove_stamped(replaced);
+        while self.nodes.len() > capacity {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            self.nodes.remove(&key);
+        }
+        previous
+    }
+
+    fn remove(&mut self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        let removed = self.nodes.remove(key);
+        self.remove_stamped(removed)
+    }
+
+    fn remove_stamped(&mut self, entry: Option<(Node<A>, u64)>) -> Option<Node<A>> {
+        let (node, stamp) = entry?;
+        self.order.remove(&stamp);
+        Some(node)
+    }
+}
+
+impl<A: Amount> Tiered<A> {
+    pub fn new(cold: Box<dyn TxStore<A>>, capacity: usize) -> Self {
+        Self {
+            hot: Mutex::new(Hot {
+                nodes: HashMap::new(),
+                order: BTreeMap::new(),
+                uses: 0,
+            }),
+            capacity,
+            cold,
+        }
+    }
+
+    // Nodes currently held in memory
+    pub fn hot_len(&self) -> usize {
+        self.hot().nodes.len()
+    }
+
+    fn hot(&self) -> MutexGuard<'_, Hot<A>> {
+        self.hot.lock().expect("Hot nodes lock poisoned")
+    }
+}
+
+impl<A: Amount + Send + Sync + 'static> TxStore<A> for Tiered<A> {
+    fn get(&self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        let mut hot = self.hot();
+        if let Some(node) = hot.get(key) {
+            return Some(node);
+        }
+        let node = self.cold.get(key)?;
+        hot.insert(*key, node.clone(), self.capacity);
+        Some(node)
+    }
+    fn insert(&mut self, key: (ClientId, u32), node: Node<A>) -> Option<Node<A>> {
+        let previous = self.cold.insert(key, node.clone());
+        // cache what the disk store kept of the node
+        let kept = self.cold.get(&key).unwrap_or(node);
+        let capacity = self.capacity;
+        self.hot().insert(key, kept, capacity).or(previous)
+    }
+    fn remove(&mut self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        let previous = self.cold.remove(key);
+        self.hot().remove(key).or(previous)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = ((ClientId, u32), Node<A>)> + '_> {
+        // nodes in memory are the same as on disk
+        self.cold.iter()
+    }
+    fn flush(&self) -> io::Result<()> {
+        self.cold.flush()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::Operation;
+
+    // Stands in for the disk store
+    #[derive(Default)]
+    struct Memory(HashMap<(ClientId, u32), Node>);
+
+    impl TxStore for Memory {
+        fn get(&self, key: &(ClientId, u32)) -> Option<Node> {
+            self.0.get(key).cloned()
+        }
+        fn insert(&mut self, key: (ClientId, u32), node: Node) -> Option<Node> {
+            self.0.insert(key, node)
+        }
+        fn remove(&mut self, key: &(ClientId, u32)) -> Option<Node> {
+            self.0.remove(key)
+        }
+        fn iter(&self) -> Box<dyn Iterator<Item = ((ClientId, u32), Node)> + '_> {
+            Box::new(self.0.iter().map(|(key, node)| (*key, node.clone())))
+        }
+    }
+
+    fn node(op: Operation) -> Node {
+        Node {
+            op,
+            amount: Some(dec!(5)),
+            disputes: 0,
+            timestamp: None,
+            counterparty: None,
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn keeps_recent_nodes_in_memory_and_fetches_the_rest() {
+        let mut store = Tiered::new(Box::<Memory>::default(), 2);
+        for tx in 1..=4 {
+            assert_eq!(store.insert((1, tx), node(Operation::Deposit)), None);
+        }
+        assert_eq!(store.hot_len(), 2);
+        // evicted nodes are still there, fetched from the cold store
+        assert_eq!(store.get(&(1, 1)), Some(node(Operation::Deposit)));
+        assert_eq!(store.iter().count(), 4);
+
+        // rewriting an evicted node brings it back, evicting the least recently used one
+        let previous = store.insert((1, 2), node(Operation::Dispute));
+        assert_eq!(previous, Some(node(Operation::Deposit)));
+        let hot = |store: &Tiered, tx| store.hot().nodes.contains_key(&(1, tx));
+        assert!(hot(&store, 1) && hot(&store, 2));
+        assert!(!hot(&store, 4));
+        assert_eq!(store.get(&(1, 2)), Some(node(Operation::Dispute)));
+
+        // reading a node keeps it in memory ahead of ones written since
+        store.get(&(1, 1));
+        store.insert((1, 3), node(Operation::Dispute));
+        assert!(hot(&store, 1) && hot(&store, 3));
+        assert!(!hot(&store, 2));
+
+        assert_eq!(store.remove(&(1, 4)), Some(node(Operation::Deposit)));
+        assert_eq!(store.get(&(1, 4)), None);
+        assert_eq!(store.iter().count(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn answers_the_same_after_eviction() {
+        let dir = std::env::temp_dir();
+        let mut deposit = node(Operation::Deposit);
+        deposit.timestamp = Some(1_709_164_800);
+        deposit.extra.insert("merchant".into(), "m-42".into());
+
+        let mut lookups = vec![];
+        for capacity in [1, 10] {
+            let path = dir.join(format!("bank-tiered-{capacity}-{}.bin", std::process::id()));
+            std::fs::remove_file(&path).ok();
+            let cold = Box::new(crate::domain::mapped::MappedTable::open(&path).unwrap());
+            let mut store = Tiered::new(cold, capacity);
+            for tx in 1..=3 {
+                store.insert((1, tx), deposit.clone());
+            }
+            lookups.push(store.get(&(1, 1)).unwrap());
+            std::fs::remove_file(&path).ok();
+        }
+        assert_eq!(lookups[0], lookups[1]);
+        assert_eq!(lookups[0].timestamp, Some(1_709_164_800));
+    }
+}
@@ -1,41 +1,303 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
+#[cfg(any(feature = "mmap", feature = "sled"))]
+use std::path::Path;
 
 use rust_decimal::Decimal;
 
-use super::{transaction::Operation, Transaction};
+use super::errors::TransactionError;
+#[cfg(feature = "mmap")]
+use super::mapped::{Entry, MappedTable};
+#[cfg(feature = "sled")]
+use super::sled_store::SledStore;
+#[cfg(any(feature = "mmap", feature = "sled"))]
+use super::tiered::Tiered;
+use super::transaction::{Extra, Operation};
+use super::{Amount, ClientId, Transaction};
+
+// Storage for the history that takes the place of its in-memory map, e.g. when the history
+// outgrows memory. Updating a transaction's dispute state inserts its new node over the old one.
+// `MappedTable` keeps the history in a memory-mapped file; other stores, say one backed by a
+// database, plug in with `History::with_store`.
+pub trait TxStore<A = Decimal>: Send + Sync {
+    fn get(&self, key: &(ClientId, u32)) -> Option<Node<A>>;
+    // Sets the node of a transaction, returning the previous one
+    fn insert(&mut self, key: (ClientId, u32), node: Node<A>) -> Option<Node<A>>;
+    fn remove(&mut self, key: &(ClientId, u32)) -> Option<Node<A>>;
+    fn iter(&self) -> Box<dyn Iterator<Item = ((ClientId, u32), Node<A>)> + '_>;
+    // Writes anything buffered back to storage
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<A> fmt::Debug for dyn TxStore<A> + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TxStore")
+    }
+}
 
 #[derive(Debug, Default)]
-pub struct History {
+pub struct History<A = Decimal> {
     // K = tuple of client, tx mapped to Node
-    history: HashMap<(u16, u32), Node>,
+    history: HashMap<(ClientId, u32), Node<A>>,
+    // takes the place of `history` when the history is kept outside of memory
+    store: Option<Box<dyn TxStore<A>>>,
 }
 
-impl History {
+impl<A: Amount> History<A> {
     pub fn new() -> Self {
         Self {
-            history: HashMap::<(u16, u32), Node>::new(),
+            history: HashMap::<(ClientId, u32), Node<A>>::new(),
+            store: None,
         }
     }
-    pub fn insert(&mut self, tx: &Transaction) -> Option<Node> {
-        let node = Node::from(tx);
-        self.history.insert((tx.client, tx.tx), node)
+    // Keeps the history in `store`, continuing from the entries already in it
+    pub fn with_store(store: Box<dyn TxStore<A>>) -> Self {
+        Self {
+            store: Some(store),
+            ..Self::new()
+        }
     }
-    pub fn get(&self, key: &(u16, u32)) -> Option<&Node> {
-        self.history.get(key)
+    // Keeps the history in the file at `path`, continuing from its entries if it exists
+    #[cfg(feature = "mmap")]
+    pub fn mapped(path: &Path) -> io::Result<Self> {
+        Ok(Self::with_store(Box::new(MappedTable::open(path)?)))
+    }
+    // Keeps the history in the file at `path` like `mapped`, with the `capacity` most recently
+    // written nodes also kept in memory
+    #[cfg(feature = "mmap")]
+    pub fn tiered(path: &Path, capacity: usize) -> io::Result<Self>
+    where
+        A: Send + Sync + 'static,
+    {
+        let cold = Box::new(MappedTable::open(path)?);
+        Ok(Self::with_store(Box::new(Tiered::new(cold, capacity))))
+    }
+    // Keeps the history in the sled database at `path`, continuing from its entries if it exists,
+    // with the `capacity` most recently written nodes also kept in memory if given
+    #[cfg(feature = "sled")]
+    pub fn sled(path: &Path, capacity: Option<usize>) -> io::Result<Self>
+    where
+        A: Send + Sync + 'static,
+    {
+        let store = Box::new(SledStore::open(path)?);
+        Ok(match capacity {
+            Some(capacity) => Self::with_store(Box::new(Tiered::new(store, capacity))),
+            None => Self::with_store(store),
+        })
+    }
+    // Writes a history kept outside of memory back to its storage
+    pub fn flush(&self) -> io::Result<()> {
+        self.store.as_ref().map_or(Ok(()), |store| store.flush())
+    }
+    pub fn insert(&mut self, tx: &Transaction<A>) -> Option<Node<A>> {
+        let key = (tx.client, tx.tx);
+        let mut node = Node::from(tx);
+        if let Some(previous) = self.get(&key) {
+            // a dispute or its settlement keeps the columns of the transaction it refers to
+            // unless it brings its own
+            for (name, value) in previous.extra.into_iter() {
+                node.extra.entry(name).or_insert(value);
+            }
+            node.disputes = previous.disputes;
+            // the other side of a transfer stays linked through its disputes
+            node.counterparty = node.counterparty.or(previous.counterparty);
+            // and the time of the transaction itself rather than the dispute's
+            node.timestamp = previous.timestamp.or(node.timestamp);
+        }
+        if tx.op == Operation::Dispute {
+            node.disputes = node.disputes.saturating_add(1);
+        }
+        self.replace(key, Some(node))
+    }
+    pub fn get(&self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        if let Some(store) = &self.store {
+            return store.get(key);
+        }
+        self.history.get(key).cloned()
+    }
+    // Sets or removes the node of a transaction, returning the previous one
+    pub fn replace(&mut self, key: (ClientId, u32), node: Option<Node<A>>) -> Option<Node<A>> {
+        if let Some(store) = &mut self.store {
+            return match node {
+                Some(node) => store.insert(key, node),
+                None => store.remove(&key),
+            };
+        }
+        match node {
+            Some(node) => self.history.insert(key, node),
+            None => self.history.remove(&key),
+        }
+    }
+    pub fn retain(&mut self, mut keep: impl FnMut(&(ClientId, u32), &Node<A>) -> bool) {
+        if self.store.is_some() {
+            let dropped: Vec<(ClientId, u32)> = self
+                .iter()
+                .filter(|(key, node)| !keep(key, node))
+                .map(|(key, _)| key)
+                .collect();
+            for key in dropped {
+                self.replace(key, None);
+            }
+            return;
+        }
+        self.history.retain(|key, node| keep(key, node))
+    }
+    pub fn extend(&mut self, other: History<A>) {
+        if self.store.is_some() || other.store.is_some() {
+            for (key, node) in other.iter() {
+                self.replace(key, Some(node));
+            }
+            return;
+        }
+        self.history.extend(other.history)
+    }
+    pub fn iter(&self) -> Box<dyn Iterator<Item = ((ClientId, u32), Node<A>)> + '_> {
+        if let Some(store) = &self.store {
+            return store.iter();
+        }
+        Box::new(self.history.iter().map(|(key, node)| (*key, node.clone())))
     }
 }
 
-#[derive(Debug)]
-pub struct Node {
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(bound = "A: Amount"))]
+pub struct Node<A = Decimal> {
     pub op: Operation,
-    pub amount: Option<Decimal>,
+    pub amount: Option<A>,
+    // times the transaction has been disputed, including an open dispute
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+    pub disputes: u8,
+    // when the transaction was made, if the input dates it
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub timestamp: Option<u64>,
+    // the other client of a transfer, the recipient on the sender's node and the sender on the
+    // recipient's, so a dispute of either side moves both accounts
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub counterparty: Option<ClientId>,
+    // not kept by mapped histories, their entries have a fixed width
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Extra::is_empty")
+    )]
+    pub extra: Extra,
 }
 
-impl From<&Transaction> for Node {
-    fn from(value: &Transaction) -> Self {
+// Where a transaction is in the dispute lifecycle. The node's op is the latest step the
+// transaction went through, so the state follows from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeState {
+    None,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    // Whether `op` may move a transaction on from this state. A resolved transaction may be
+    // disputed again, how often is up to the engine's dispute limit.
+    pub fn check(self, op: &Operation) -> Result<(), TransactionError> {
+        match (op, self) {
+            (Operation::Dispute, DisputeState::Disputed) => Err(TransactionError::AlreadyDisputed),
+            (Operation::Dispute, DisputeState::ChargedBack) => {
+                Err(TransactionError::AlreadyChargedBack)
+            }
+            (Operation::Resolve | Operation::Chargeback, DisputeState::Disputed) => Ok(()),
+            (Operation::Resolve | Operation::Chargeback, _) => {
+                Err(TransactionError::NotUnderDispute)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<A> Node<A> {
+    pub fn dispute_state(&self) -> DisputeState {
+        match self.op {
+            Operation::Dispute => DisputeState::Disputed,
+            Operation::Resolve => DisputeState::Resolved,
+            Operation::Chargeback => DisputeState::ChargedBack,
+            _ => DisputeState::None,
+        }
+    }
+
+    // Whether the transaction raised its client's balance, i.e. it's a deposit or the receiving
+    // side of a transfer, which is recorded with the amount negated
+    pub fn is_credit(&self) -> bool
+    where
+        A: Amount,
+    {
+        match self.op {
+            Operation::Deposit => true,
+            Operation::Transfer => self.amount.is_some_and(|amt| amt < A::default()),
+            _ => false,
+        }
+    }
+}
+
+impl<A: Amount> From<&Transaction<A>> for Node<A> {
+    fn from(value: &Transaction<A>) -> Self {
         Self {
             op: value.op.clone(),
             amount: value.amount,
+            disputes: 0,
+            timestamp: value.timestamp,
+            counterparty: value.recipient(),
+            extra: value.extra.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn is_zero(n: &u8) -> bool {
+    *n == 0
+}
+
+#[cfg(feature = "mmap")]
+impl<A: Amount> TxStore<A> for MappedTable {
+    fn get(&self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        MappedTable::get(self, key).map(Node::from)
+    }
+    fn insert(&mut self, key: (ClientId, u32), node: Node<A>) -> Option<Node<A>> {
+        MappedTable::insert(self, key, Entry::from(&node)).map(Node::from)
+    }
+    fn remove(&mut self, key: &(ClientId, u32)) -> Option<Node<A>> {
+        MappedTable::remove(self, key).map(Node::from)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = ((ClientId, u32), Node<A>)> + '_> {
+        Box::new(MappedTable::iter(self).map(|(key, entry)| (key, Node::from(entry))))
+    }
+    fn flush(&self) -> io::Result<()> {
+        MappedTable::flush(self)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<A: Amount> From<&Node<A>> for Entry {
+    fn from(node: &Node<A>) -> Self {
+        Self {
+            op: node.op.clone(),
+            amount: node.amount.map(|amount| amount.to_bits()),
+            disputes: node.disputes,
+            timestamp: node.timestamp,
+            counterparty: node.counterparty,
+        }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<A: Amount> From<Entry> for Node<A> {
+    fn from(entry: Entry) -> Self {
+        Self {
+            op: entry.op,
+            amount: entry.amount.map(A::from_bits),
+            disputes: entry.disputes,
+            timestamp: entry.timestamp,
+            counterparty: entry.counterparty,
+            extra: Extra::new(),
         }
     }
 }
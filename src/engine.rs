@@ -1,11 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use rust_decimal_macros::dec;
+use rust_decimal::Decimal;
+use tracing::{field, trace, trace_span};
 
+#[cfg(feature = "archive")]
+use crate::checkpoint::{Checkpoint, MappedCheckpoint};
 use crate::domain::{
-    errors::TransactionError, transaction::Operation, tx_history::History, Account, Transaction,
-    TryUpdate,
+    errors::TransactionError,
+    transaction::{LockPolicy, Operation},
+    tx_history::{History, Node},
+    Account, AccountRepository, AccountStore, Amount, ClientId, Transaction,
 };
+use crate::invariants::{Check, InvariantViolation};
+#[cfg(feature = "csv")]
+use crate::snapshot::{Snapshot, StateDump};
 
 #[derive(Debug)]
 pub enum State {
@@ -16,44 +28,92 @@ pub enum State {
     Done,
 }
 
-pub struct Task<'a> {
-    history: &'a mut History,
-    accounts: &'a mut HashMap<u16, Account>,
-    transaction: Transaction,
+// Applies a transaction to the accounts in `S`, the engine's `AccountStore` unless a task is run
+// against another `AccountRepository`
+pub struct Task<'a, A = Decimal, S = AccountStore<A>> {
+    history: &'a mut History<A>,
+    accounts: &'a mut S,
+    transaction: Transaction<A>,
     state: State,
+    lock_policy: LockPolicy,
+    credit_limit: Option<A>,
+    dispute_window: Option<Duration>,
+    // what a capture leaves of its authorization, released once the capture is applied
+    release: Option<A>,
+    // the other client of a disputed transfer, whose account and history move along
+    linked: Option<ClientId>,
+    // whether the task's span records the client id
+    log_client: bool,
 }
 
-impl<'a> Task<'a> {
+impl<'a, A: Amount, S: AccountRepository<A>> Task<'a, A, S> {
     pub fn new(
-        history: &'a mut History,
-        accounts: &'a mut HashMap<u16, Account>,
-        transaction: Transaction,
+        history: &'a mut History<A>,
+        accounts: &'a mut S,
+        transaction: Transaction<A>,
     ) -> Self {
         Self {
             history,
             accounts,
             transaction,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         }
     }
-}
 
-impl<'a> Machine for Task<'a> {
-    fn run(&mut self) -> Result<(), TransactionError> {
+    // Lets disputes through a locked account if the policy allows them
+    pub fn with_lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = policy;
+        self
+    }
+
+    // Gives the client's account an overdraft of `limit` before the transaction is applied
+    pub fn with_credit_limit(mut self, limit: Option<A>) -> Self {
+        self.credit_limit = limit;
+        self
+    }
+
+    // Rejects disputes dated more than `window` after the transaction they refer to
+    pub fn with_dispute_window(mut self, window: Option<Duration>) -> Self {
+        self.dispute_window = window;
+        self
+    }
+
+    // Records the client id on the task's span, which leaves it out unless the caller's logs may
+    // show client ids
+    pub fn with_logged_client(mut self, log: bool) -> Self {
+        self.log_client = log;
+        self
+    }
+
+    fn steps(&mut self) -> Result<(), TransactionError> {
         loop {
             match self.state {
                 State::Idle => match self.transaction.op {
-                    // if the transaction is a deposit or a withdrawal, attempt to apply transaction to account
-                    Operation::Deposit | Operation::Withdrawal => {
+                    // if the transaction is a deposit, a withdrawal or a fee, attempt to apply transaction to account
+                    Operation::Deposit
+                    | Operation::Withdrawal
+                    | Operation::Fee
+                    | Operation::Transfer
+                    | Operation::Authorize => {
                         self.state = State::Updating;
                         self.next_state()?;
                     }
                     // if the transaction is from the family of dispute operations, fetch the associated
                     // transaction from the transaction history.
-                    Operation::Resolve | Operation::Chargeback | Operation::Dispute => {
+                    Operation::Resolve
+                    | Operation::Chargeback
+                    | Operation::Dispute
+                    | Operation::Capture => {
                         self.state = State::Fetching;
                         self.next_state()?;
                     }
+                    Operation::Unknown(_) => return Err(TransactionError::UnspecifiedBehavior),
                 },
                 State::Fetching | State::Updating | State::Logging => {
                     self.next_state()?;
@@ -62,52 +122,1612 @@ impl<'a> Machine for Task<'a> {
             }
         }
     }
+}
+
+impl<'a, A: Amount, S: AccountRepository<A>> Machine for Task<'a, A, S> {
+    fn run(&mut self) -> Result<(), TransactionError> {
+        // every step of the task and its outcome are logged within a span of the transaction
+        let span = trace_span!(
+            "task",
+            tx = self.transaction.tx,
+            op = self.transaction.op.name(),
+            client = field::Empty,
+        );
+        if self.log_client {
+            span.record("client", self.transaction.client);
+        }
+        let _entered = span.enter();
+        let result = self.steps();
+        match &result {
+            Ok(()) => trace!("Task applied"),
+            Err(e) => trace!(error = %e, "Task rejected"),
+        }
+        result
+    }
 
     fn next_state(&mut self) -> Result<&mut Self, TransactionError> {
+        // the span carries the transaction, client ids stay out unless it was asked to log them
+        trace!(state = ?self.state, "Task step");
         match self.state {
             State::Idle => Ok(self),
+            State::Fetching if self.transaction.op == Operation::Capture => {
+                // a capture settles the authorization recorded under its tx id
+                let node = self
+                    .history
+                    .get(&(self.transaction.client, self.transaction.tx))
+                    .filter(|node| matches!(node.op, Operation::Authorize | Operation::Capture))
+                    .ok_or(TransactionError::TransactionNotFound)?;
+                if node.op == Operation::Capture {
+                    return Err(TransactionError::AlreadyCaptured);
+                }
+                let authorized = node.amount.unwrap_or_default();
+                let captured = self.transaction.amount.unwrap_or(authorized);
+                if captured > authorized {
+                    return Err(TransactionError::CaptureExceedsAuthorization);
+                }
+                self.transaction.amount = Some(captured);
+                self.release = Some(overflowing(authorized.checked_sub(captured))?)
+                    .filter(|rest| *rest > A::zero());
+                self.state = State::Updating;
+                Ok(self)
+            }
             State::Fetching => {
                 // For disputes, fetch the disputed transaction from the history
+                // fees are charged by the processor and can't be disputed, and authorizations
+                // aren't payments until they're captured
                 let maybe_node = self
                     .history
-                    .get(&(self.transaction.client, self.transaction.tx));
+                    .get(&(self.transaction.client, self.transaction.tx))
+                    .filter(|node| !matches!(node.op, Operation::Fee | Operation::Authorize));
                 if let Some(node) = maybe_node {
+                    node.dispute_state().check(&self.transaction.op)?;
+                    // only dated disputes of dated transactions can be checked against the window
+                    if let (Operation::Dispute, Some(window), Some(made), Some(disputed)) = (
+                        &self.transaction.op,
+                        self.dispute_window,
+                        node.timestamp,
+                        self.transaction.timestamp,
+                    ) {
+                        if disputed.saturating_sub(made) > window.as_secs() {
+                            return Err(TransactionError::DisputeWindowExpired);
+                        }
+                    }
                     // set the disputed amount on the dispute transaction, reversing deposits should be
                     // negative and reversing withdrawals should be positive.
                     match node.op {
                         Operation::Deposit => {
-                            self.transaction.amount = node.amount.map(|el| dec!(-1) * el)
+                            self.transaction.amount = node.amount.map(negated).transpose()?
                         }
                         Operation::Withdrawal => self.transaction.amount = node.amount,
                         _ => self.transaction.amount = node.amount,
                     };
+                    // both sides of a transfer keep its amount, negated for the recipient
+                    self.linked = node.counterparty;
                     self.state = State::Updating;
                     Ok(self)
                 } else {
                     Err(TransactionError::TransactionNotFound)
                 }
             }
-            State::Updating => {
-                let maybe_account = self.accounts.get_mut(&self.transaction.client);
-                if let Some(act) = maybe_account {
-                    self.transaction.try_update(act)?;
-                } else {
-                    let mut new_act = Account::new(self.transaction.client);
-                    self.transaction.try_update(&mut new_act)?;
-                    self.accounts.insert(self.transaction.client, new_act);
-                }
-                self.state = State::Logging;
-                Ok(self)
+            State::Updating if self.linked.is_some() => {
+                self.settle_transfer()?;
+                self.state = State::Logging;
+                Ok(self)
+            }
+            State::Updating => {
+                // the recipient is checked before the sender is debited, so a rejected transfer
+                // leaves both accounts untouched
+                let recipient = self.transaction.recipient();
+                match recipient {
+                    Some(to) if to == self.transaction.client => {
+                        return Err(TransactionError::UnspecifiedBehavior)
+                    }
+                    None if self.transaction.op == Operation::Transfer => {
+                        return Err(TransactionError::UnspecifiedBehavior)
+                    }
+                    Some(to) if self.accounts.get(&to).is_some_and(|act| act.locked) => {
+                        return Err(TransactionError::LockedAccount)
+                    }
+                    _ => {}
+                }
+                let client = self.transaction.client;
+                let mut act = self
+                    .accounts
+                    .get(&client)
+                    .unwrap_or_else(|| Account::new(client));
+                if let Some(limit) = self.credit_limit {
+                    act.credit_limit = Some(limit);
+                }
+                self.transaction.apply(&mut act, self.lock_policy)?;
+                if self.release.is_some() {
+                    act.release(self.release)?;
+                }
+                self.accounts.insert(act);
+                if let Some(to) = recipient {
+                    let mut act = self.accounts.get(&to).unwrap_or_else(|| Account::new(to));
+                    act.deposit(self.transaction.amount)?;
+                    self.accounts.insert(act);
+                }
+                self.state = State::Logging;
+                Ok(self)
+            }
+            State::Logging => {
+                // Mutates tx node to reflect most recent op (ie Deposit, Dispute, Chargeback...)
+                // or inserts a new history Node
+                self.history.insert(&self.transaction);
+                // the receiving side of a transfer is recorded under the recipient with the
+                // amount negated, so disputing it holds the funds like disputing a deposit
+                if let Some(to) = self.transaction.recipient() {
+                    self.history.insert(&Transaction {
+                        client: to,
+                        amount: self.transaction.amount.map(negated).transpose()?,
+                        counterparty: Some(self.transaction.client),
+                        ..self.transaction.clone()
+                    });
+                }
+                // the other side of a disputed transfer follows it through the dispute
+                if let Some(other) = self.linked {
+                    self.history.insert(&Transaction {
+                        client: other,
+                        amount: self.transaction.amount.map(negated).transpose()?,
+                        ..self.transaction.clone()
+                    });
+                }
+                self.state = State::Done;
+                Ok(self)
+            }
+            State::Done => Ok(self),
+        }
+    }
+}
+
+impl<'a, A: Amount, S: AccountRepository<A>> Task<'a, A, S> {
+    // Disputes of a transfer hold its amount on the recipient's account, whichever side opened
+    // them. A resolve releases it to the recipient again, a chargeback returns it to the sender
+    // and locks the recipient's account. Both accounts are checked before either is changed.
+    fn settle_transfer(&mut self) -> Result<(), TransactionError> {
+        let (client, other) = (self.transaction.client, self.linked.unwrap_or_default());
+        let amount = self.transaction.amount.unwrap_or_default();
+        // the recipient's side is recorded with the amount negated
+        let (sender, recipient, amount) = match amount < A::zero() {
+            true => (other, client, negated(amount)?),
+            false => (client, other, amount),
+        };
+        let account = |accounts: &S, id| accounts.get(&id).unwrap_or_else(|| Account::new(id));
+        let (mut from, mut to) = (
+            account(self.accounts, sender),
+            account(self.accounts, recipient),
+        );
+        if [&from, &to]
+            .iter()
+            .any(|act| act.locked && !self.lock_policy.allows(&self.transaction.op))
+        {
+            return Err(TransactionError::LockedAccount);
+        }
+        match self.transaction.op {
+            // held like a disputed deposit
+            Operation::Dispute => to.dispute(Some(negated(amount)?))?,
+            // released like a resolved withdrawal
+            Operation::Resolve => to.resolve(Some(amount))?,
+            // taken out of held like a charged back withdrawal, and credited to the sender
+            Operation::Chargeback => {
+                to.chargeback(Some(amount))?;
+                from.deposit(Some(amount))?;
+            }
+            _ => return Err(TransactionError::UnspecifiedBehavior),
+        }
+        self.accounts.insert(from);
+        self.accounts.insert(to);
+        Ok(())
+    }
+}
+
+// A result the amount type can't hold rejects the transaction
+fn overflowing<A>(amount: Option<A>) -> Result<A, TransactionError> {
+    amount.ok_or(TransactionError::AmountOverflow)
+}
+
+fn negated<A: Amount>(amount: A) -> Result<A, TransactionError> {
+    overflowing(amount.checked_neg())
+}
+
+// Effect a transaction would have on its client's account
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDelta<A = Decimal> {
+    pub client: ClientId,
+    pub available: A,
+    pub held: A,
+    pub total: A,
+    // lock state after the transaction
+    pub locked: bool,
+}
+
+impl<A: Amount> AccountDelta<A> {
+    fn between(before: &Account<A>, after: &Account<A>) -> Result<Self, TransactionError> {
+        Ok(Self {
+            client: after.client,
+            available: overflowing(after.available.checked_sub(before.available))?,
+            held: overflowing(after.held.checked_sub(before.held))?,
+            total: overflowing(after.total.checked_sub(before.total))?,
+            locked: after.locked,
+        })
+    }
+}
+
+// Owns the account and history state that tasks run against, with balances in `A`
+#[derive(Debug, Default)]
+pub struct Engine<A = Decimal> {
+    history: History<A>,
+    accounts: AccountStore<A>,
+    // state overwritten by the most recent transactions, newest last
+    undo: VecDeque<Undo<A>>,
+    undo_depth: usize,
+    // number of transactions applied and not rolled back
+    applied: u64,
+    // positions of the open savepoints, oldest first
+    savepoints: Vec<u64>,
+    // value of `applied` after each client's most recent transaction
+    last_seen: HashMap<ClientId, u64>,
+    limits: Limits<A>,
+    // fee charged by the most recent call to `process`
+    last_fee: Option<Transaction<A>>,
+    // lowest tx id each client's fees were recorded under, where the search for a free one starts.
+    // Follows the client's history, through rollbacks, splits, merges and dormant collection.
+    fee_ids: HashMap<ClientId, u32>,
+    // transactions applied and rejected over the engine's lifetime, summed by `merge`. Rolled
+    // back transactions no longer count as applied.
+    total_applied: u64,
+    rejected: BTreeMap<&'static str, u64>,
+    // highest deposit or withdrawal id applied for each client, kept while ids are checked
+    last_tx: HashMap<ClientId, u32>,
+    // whether the most recent call to `process` applied a flagged out of order transaction
+    out_of_order: bool,
+    total_flagged: u64,
+    // client that applied each deposit, withdrawal and transfer id, kept while ids are global
+    tx_owners: Option<HashMap<u32, ClientId>>,
+    check_invariants: bool,
+    // whether task spans record client ids
+    log_clients: bool,
+    // the first invariant a transaction violated, the engine's state can't be trusted after it
+    violation: Option<InvariantViolation<A>>,
+    error_policy: ErrorPolicy,
+}
+
+// Outcome of running transactions through an engine, for embedding code to check or export
+// rather than reading it from logs. The engine counts what it applied and rejected; readers add
+// the records that never reached it, the bytes read and the time taken.
+#[derive(Clone, Default, PartialEq)]
+pub struct ProcessingReport {
+    // records read, including malformed and skipped ones
+    pub rows: u64,
+    pub applied: u64,
+    // rejected transactions by `TransactionError::code`
+    pub rejected: BTreeMap<&'static str, u64>,
+    // records that couldn't be read as transactions
+    pub malformed: u64,
+    // records of unknown operations
+    pub skipped: u64,
+    // records their source wasn't allowed to issue
+    pub denied: u64,
+    // transactions applied although their id didn't increase on the client's previous one
+    pub flagged: u64,
+    // clients with at least one applied transaction
+    pub accounts_touched: usize,
+    pub bytes: u64,
+    pub duration: Duration,
+    // the rejected transactions themselves, kept by `ErrorPolicy::Strict` and `Collect`
+    pub failures: Vec<Failure>,
+    // value of the engine's `applied` when the report was taken
+    position: u64,
+}
+
+impl ProcessingReport {
+    pub fn rejected_total(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+}
+
+// Leaves out the engine position, which only means something to `report_since`
+impl fmt::Debug for ProcessingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessingReport")
+            .field("rows", &self.rows)
+            .field("applied", &self.applied)
+            .field("rejected", &self.rejected)
+            .field("malformed", &self.malformed)
+            .field("skipped", &self.skipped)
+            .field("denied", &self.denied)
+            .field("flagged", &self.flagged)
+            .field("accounts_touched", &self.accounts_touched)
+            .field("bytes", &self.bytes)
+            .field("duration", &self.duration)
+            .field("failures", &self.failures)
+            .finish_non_exhaustive()
+    }
+}
+
+// A transaction the engine rejected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub client: ClientId,
+    pub tx: u32,
+    pub error: TransactionError,
+}
+
+// What `process_all` and `process_stream` do about rejected transactions and unreadable records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    // counts them and carries on
+    #[default]
+    Lenient,
+    // stops at the first one, keeping the rejection in the report
+    Strict,
+    // carries on and keeps every rejection in the report
+    Collect,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lenient" => Ok(ErrorPolicy::Lenient),
+            "strict" => Ok(ErrorPolicy::Strict),
+            "collect" => Ok(ErrorPolicy::Collect),
+            _ => Err(format!(
+                "unknown error policy {s}, expected lenient, strict or collect"
+            )),
+        }
+    }
+}
+
+// Limits and fees the engine enforces. They can be swapped between transactions with
+// `set_limits`, e.g. when a long running engine reloads its settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits<A = Decimal> {
+    pub balance_caps: BalanceCaps<A>,
+    // most times a transaction may be disputed, counting disputes that were settled
+    pub dispute_limit: Option<u8>,
+    // longest time after a transaction that it may still be disputed
+    pub dispute_window: Option<Duration>,
+    // charged to the account after every chargeback
+    pub chargeback_fee: Option<A>,
+    // what to do with deposits and withdrawals whose id isn't above the client's previous one
+    pub tx_order: Option<TxOrder>,
+    // what to do with transactions dated before their client's last activity
+    pub timestamp_order: Option<TxOrder>,
+    // operations a locked account still accepts
+    pub lock_policy: LockPolicy,
+    pub dispute_policy: DisputePolicy,
+    // what to do with amounts that have more decimals than the engine keeps
+    pub amount_precision: AmountPrecision,
+    pub credit_limits: CreditLimits<A>,
+    // charged to the account after every deposit and withdrawal
+    pub fees: FeeModel<A>,
+}
+
+// Which transactions may be disputed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputePolicy {
+    #[default]
+    DepositsAndWithdrawals,
+    // only deposits and received transfers, as many payment specs have it. Disputes of anything
+    // else are rejected with `OperationNotDisputable`.
+    DepositsOnly,
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposits-and-withdrawals" => Ok(DisputePolicy::DepositsAndWithdrawals),
+            "deposits-only" => Ok(DisputePolicy::DepositsOnly),
+            _ => Err(format!(
+                "unknown dispute policy {s}, expected deposits-only or deposits-and-withdrawals"
+            )),
+        }
+    }
+}
+
+// Most decimals an amount may have, as many as the output is written with
+pub const AMOUNT_SCALE: u32 = 4;
+
+// Handling of amounts with more than `AMOUNT_SCALE` decimals
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AmountPrecision {
+    // rejects them with `ExcessPrecision`
+    Reject,
+    // rounds them half to even before they're applied, as the output would have
+    #[default]
+    Round,
+}
+
+impl FromStr for AmountPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(AmountPrecision::Reject),
+            "round" => Ok(AmountPrecision::Round),
+            _ => Err(format!(
+                "unknown amount precision {s}, expected reject or round"
+            )),
+        }
+    }
+}
+
+// Handling of transaction ids that don't strictly increase per client, or of timestamps that go
+// back in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxOrder {
+    // rejects them with `OutOfOrder` or `TimestampOutOfOrder`
+    Reject,
+    // applies them and reports them through `out_of_order`
+    Flag,
+}
+
+impl FromStr for TxOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(TxOrder::Reject),
+            "flag" => Ok(TxOrder::Flag),
+            _ => Err(format!(
+                "unknown tx order handling {s}, expected reject or flag"
+            )),
+        }
+    }
+}
+
+// Most a client's total balance may reach. Clients assigned to a tier with a cap get that cap,
+// everyone else the global one, if any.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceCaps<A = Decimal> {
+    global: Option<A>,
+    tiers: HashMap<String, A>,
+    clients: HashMap<ClientId, String>,
+}
+
+impl<A: Amount> BalanceCaps<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn global(mut self, cap: A) -> Self {
+        self.global = Some(cap);
+        self
+    }
+
+    pub fn tier(mut self, tier: impl Into<String>, cap: A) -> Self {
+        self.tiers.insert(tier.into(), cap);
+        self
+    }
+
+    pub fn assign(&mut self, client: ClientId, tier: impl Into<String>) {
+        self.clients.insert(client, tier.into());
+    }
+
+    pub fn cap(&self, client: ClientId) -> Option<A> {
+        self.clients
+            .get(&client)
+            .and_then(|tier| self.tiers.get(tier))
+            .copied()
+            .or(self.global)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.global.is_none() && self.tiers.is_empty()
+    }
+}
+
+// How far withdrawals and transfers may overdraw a client's available funds. Clients with a
+// limit of their own get it, everyone else the global one, if any.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CreditLimits<A = Decimal> {
+    global: Option<A>,
+    clients: HashMap<ClientId, A>,
+}
+
+impl<A: Amount> CreditLimits<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn global(mut self, limit: A) -> Self {
+        self.global = Some(limit);
+        self
+    }
+
+    pub fn client(mut self, client: ClientId, limit: A) -> Self {
+        self.clients.insert(client, limit);
+        self
+    }
+
+    pub fn limit(&self, client: ClientId) -> Option<A> {
+        self.clients.get(&client).copied().or(self.global)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.global.is_none() && self.clients.is_empty()
+    }
+}
+
+// A flat amount plus a percentage of the transaction's amount, written `0.5`, `1%` or `0.5+1%`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fee<A = Decimal> {
+    pub flat: A,
+    pub percent: Decimal,
+}
+
+impl<A: Amount> Fee<A> {
+    // Rounded to the amount scale, so the fee is an amount like any other. The percentage is
+    // taken in decimals whatever the amount type.
+    pub fn on(&self, amount: A) -> Result<A, TransactionError> {
+        let share = amount
+            .to_decimal()
+            .checked_mul(self.percent)
+            .map(|share| (share / Decimal::ONE_HUNDRED).round_dp(AMOUNT_SCALE))
+            .and_then(A::from_decimal);
+        overflowing(share.and_then(|share| self.flat.checked_add(share)))
+    }
+}
+
+impl<A: Amount> FromStr for Fee<A> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid fee {s}, expected e.g. 0.5, 1% or 0.5+1%");
+        let (mut flat, mut percent) = (Decimal::ZERO, Decimal::ZERO);
+        for part in s.split('+').map(str::trim) {
+            let (value, is_percent) = match part.strip_suffix('%') {
+                Some(value) => (value.trim(), true),
+                None => (part, false),
+            };
+            let value: Decimal = value.parse().map_err(|_| invalid())?;
+            if value.is_sign_negative() {
+                return Err(invalid());
+            }
+            let sum = match is_percent {
+                true => &mut percent,
+                false => &mut flat,
+            };
+            *sum = sum.checked_add(value).ok_or_else(invalid)?;
+        }
+        Ok(Fee {
+            flat: A::from_decimal(flat).ok_or_else(invalid)?,
+            percent,
+        })
+    }
+}
+
+// Fees charged on deposits and withdrawals, e.g. to model acquiring fees
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeeModel<A = Decimal> {
+    deposit: Option<Fee<A>>,
+    withdrawal: Option<Fee<A>>,
+}
+
+impl<A: Amount> FeeModel<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn deposit(mut self, fee: Fee<A>) -> Self {
+        self.deposit = Some(fee);
+        self
+    }
+
+    pub fn withdrawal(mut self, fee: Fee<A>) -> Self {
+        self.withdrawal = Some(fee);
+        self
+    }
+
+    // What a transaction is charged, None if it isn't
+    pub fn fee(&self, transaction: &Transaction<A>) -> Result<Option<A>, TransactionError> {
+        let fee = match transaction.op {
+            Operation::Deposit => self.deposit,
+            Operation::Withdrawal => self.withdrawal,
+            _ => None,
+        };
+        let Some(fee) = fee else {
+            return Ok(None);
+        };
+        Ok(Some(fee.on(transaction.amount.unwrap_or_default())?).filter(|fee| *fee != A::zero()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deposit.is_none() && self.withdrawal.is_none()
+    }
+}
+
+// An account that hasn't seen a transaction in a while, with its position in the run
+#[derive(Debug, Clone, PartialEq)]
+pub struct DormantAccount<A = Decimal> {
+    pub account: Account<A>,
+    // applied transactions up to and including the client's last one, 0 for seeded accounts
+    pub last_seen: u64,
+    // applied transactions since
+    pub idle: u64,
+}
+
+// Marks a point in a run that the engine can be rolled back to. Savepoints are handed back to
+// the engine with either `rollback_to` or `release`; until then every transaction applied after
+// the oldest open savepoint is kept in the undo log.
+#[derive(Debug, PartialEq)]
+#[must_use]
+pub struct Savepoint(u64);
+
+// What a single applied transaction replaced
+#[derive(Debug)]
+struct Undo<A> {
+    tx: u32,
+    client: Replaced<A>,
+    // the other side of a transfer, or of a dispute of one
+    counterparty: Option<Replaced<A>>,
+    // the client's highest ordered id before the transaction
+    last_tx: Option<u32>,
+    // history entry of the fee the transaction incurred, and the client's lowest fee id before it
+    fee: Option<(ClientId, u32)>,
+    fee_id: Option<u32>,
+    // whether the transaction was counted as applied and as flagged in the lifetime totals
+    counted: bool,
+    flagged: bool,
+}
+
+// A client's account, history entry and position before a transaction
+#[derive(Debug)]
+struct Replaced<A> {
+    client: ClientId,
+    account: Option<Account<A>>,
+    node: Option<Node<A>>,
+    last_seen: Option<u64>,
+}
+
+impl<A: Amount> Engine<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts from previously computed balances. Disputes can't reach transactions from before the
+    // seed since their history isn't carried over.
+    pub fn with_accounts(accounts: AccountStore<A>) -> Self {
+        Self {
+            accounts,
+            ..Default::default()
+        }
+    }
+
+    // Remembers enough to reverse the last `depth` applied transactions with `rollback`
+    pub fn keep_undo(mut self, depth: usize) -> Self {
+        self.undo_depth = depth;
+        self
+    }
+
+    // Applies transactions against `history` instead of an empty in-memory one, e.g. a history
+    // kept in a memory-mapped file from an earlier run
+    pub fn with_history(mut self, history: History<A>) -> Self {
+        self.history = history;
+        self
+    }
+
+    // Charges `fee` to the account after every successful chargeback. The fee is recorded in the
+    // history under the highest tx id the client hasn't used and is returned by `assessed_fee`.
+    // Transactions arriving later under that id are rejected with `FeeIdTaken`.
+    pub fn with_chargeback_fee(mut self, fee: A) -> Self {
+        self.limits.chargeback_fee = Some(fee);
+        self
+    }
+
+    // Charges a fee after every deposit and withdrawal the model has one for, recorded in the
+    // history and returned by `assessed_fee` like a chargeback fee
+    pub fn with_fees(mut self, fees: FeeModel<A>) -> Self {
+        self.limits.fees = fees;
+        self
+    }
+
+    // Rejects deposits that would take a client's total balance over its cap with
+    // `BalanceCapExceeded`
+    pub fn with_balance_caps(mut self, caps: BalanceCaps<A>) -> Self {
+        self.limits.balance_caps = caps;
+        self
+    }
+
+    // Lets withdrawals and transfers overdraw accounts up to their credit limit, beyond it
+    // they're rejected with `CreditLimitExceeded`. Accounts that are already open get their
+    // limit right away, others when they're first touched.
+    pub fn with_credit_limits(mut self, limits: CreditLimits<A>) -> Self {
+        self.limits.credit_limits = limits;
+        self.assign_credit_limits();
+        self
+    }
+
+    // Allows a transaction to be disputed again after a resolve until it has been disputed
+    // `limit` times, further disputes are rejected with `DisputeLimitReached`
+    pub fn with_dispute_limit(mut self, limit: u8) -> Self {
+        self.limits.dispute_limit = Some(limit);
+        self
+    }
+
+    // Rejects disputes dated more than `window` after the transaction they refer to with
+    // `DisputeWindowExpired`. Only transactions and disputes that both have a timestamp are
+    // checked.
+    pub fn with_dispute_window(mut self, window: Duration) -> Self {
+        self.limits.dispute_window = Some(window);
+        self
+    }
+
+    // Checks that deposit and withdrawal ids strictly increase for each client. Ids are only
+    // tracked from here on, transactions applied before aren't taken into account.
+    pub fn with_tx_order(mut self, order: TxOrder) -> Self {
+        self.limits.tx_order = Some(order);
+        self
+    }
+
+    // Checks that the timestamps of each client's transactions don't go back in time. Equal
+    // timestamps are in order, and transactions without one aren't checked.
+    pub fn with_timestamp_order(mut self, order: TxOrder) -> Self {
+        self.limits.timestamp_order = Some(order);
+        self
+    }
+
+    // Lets a locked account's disputes still be settled with `LockPolicy::Settle`
+    pub fn with_lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.limits.lock_policy = policy;
+        self
+    }
+
+    pub fn with_dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.limits.dispute_policy = policy;
+        self
+    }
+
+    pub fn with_amount_precision(mut self, precision: AmountPrecision) -> Self {
+        self.limits.amount_precision = precision;
+        self
+    }
+
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+
+    // Treats tx ids as unique across clients instead of per client: a deposit, withdrawal or
+    // transfer reusing another client's id, and a dispute, resolve or chargeback of another
+    // client's transaction, are rejected with `ClientMismatch` rather than passing as a new
+    // transaction or failing with `TransactionNotFound`. Owners of ids already in the history are
+    // taken from it.
+    pub fn with_global_tx_ids(mut self) -> Self {
+        let mut owners = HashMap::new();
+        for ((client, tx), _) in self.history.iter() {
+            owners.entry(tx).or_insert(client);
+        }
+        self.tx_owners = Some(owners);
+        self
+    }
+
+    // Records client ids on the tracing spans of tasks. They're left out by default, since callers
+    // redact client ids in their own logs.
+    pub fn with_logged_clients(mut self) -> Self {
+        self.log_clients = true;
+        self
+    }
+
+    // Checks the accounts a transaction touched after applying it, see `invariants::Check`. A
+    // transaction that breaks an invariant stays applied but is reported as rejected with
+    // `InvariantViolation`, and `invariant_violation` tells what broke.
+    pub fn with_invariant_checks(mut self) -> Self {
+        self.check_invariants = true;
+        self
+    }
+
+    pub fn invariant_violation(&self) -> Option<&InvariantViolation<A>> {
+        self.violation.as_ref()
+    }
+
+    pub fn limits(&self) -> &Limits<A> {
+        &self.limits
+    }
+
+    // Replaces every limit and fee at once, transactions applied from now on see the new ones
+    pub fn set_limits(&mut self, limits: Limits<A>) {
+        self.limits = limits;
+        self.assign_credit_limits();
+    }
+
+    // Accounts keep the limit they were given when no credit limits are configured
+    fn assign_credit_limits(&mut self) {
+        if self.limits.credit_limits.is_empty() {
+            return;
+        }
+        for act in self.accounts.values_mut() {
+            if let Some(limit) = self.limits.credit_limits.limit(act.client) {
+                act.credit_limit = Some(limit);
+            }
+        }
+    }
+
+    // The fee charged by the most recent call to `process`, if any, as the transaction that
+    // applies it. Journals and standbys record it so that replaying them charges it again.
+    pub fn assessed_fee(&self) -> Option<&Transaction<A>> {
+        self.last_fee.as_ref()
+    }
+
+    // Whether the most recent call to `process` applied a transaction out of order, only ever
+    // set with `TxOrder::Flag`
+    pub fn out_of_order(&self) -> bool {
+        self.out_of_order
+    }
+
+    pub fn process(&mut self, transaction: Transaction<A>) -> Result<(), TransactionError> {
+        let ordered = self.limits.tx_order.is_some()
+            && matches!(
+                transaction.op,
+                Operation::Deposit
+                    | Operation::Withdrawal
+                    | Operation::Transfer
+                    | Operation::Authorize
+            );
+        let (client, tx) = (transaction.client, transaction.tx);
+        let in_order = !ordered || self.in_order(client, tx);
+        let timely = self.limits.timestamp_order.is_none() || self.timely(&transaction);
+        let owned = matches!(
+            transaction.op,
+            Operation::Deposit | Operation::Withdrawal | Operation::Transfer | Operation::Authorize
+        );
+        self.out_of_order = false;
+        let tracked = self.keeps_undo();
+        let transaction = self.validate(transaction);
+        let check = match &transaction {
+            Ok(transaction) if self.check_invariants => Some(Check::new(self, transaction)),
+            _ => None,
+        };
+        let mut result = transaction.and_then(|transaction| self.apply(transaction));
+        if let (Some(check), Ok(())) = (check, &result) {
+            if let Some(violation) = check.verify(self) {
+                self.violation.get_or_insert(violation);
+                result = Err(TransactionError::InvariantViolation);
+            }
+        }
+        if let (Some(owners), true, Ok(())) = (&mut self.tx_owners, owned, &result) {
+            owners.entry(tx).or_insert(client);
+        }
+        match &result {
+            Ok(()) => self.total_applied += 1,
+            Err(e) => *self.rejected.entry(e.code()).or_default() += 1,
+        }
+        if result.is_ok() {
+            // out of order transactions only get this far when they're flagged
+            self.out_of_order = !in_order || !timely;
+            self.total_flagged += u64::from(self.out_of_order);
+            if let Some(undo) = self.undo.back_mut().filter(|_| tracked) {
+                undo.counted = true;
+                undo.flagged = self.out_of_order;
+            }
+        }
+        if ordered && result.is_ok() {
+            let last = self.last_tx.entry(client).or_insert(tx);
+            *last = (*last).max(tx);
+        }
+        result
+    }
+
+    fn in_order(&self, client: ClientId, tx: u32) -> bool {
+        self.last_tx.get(&client).is_none_or(|&last| tx > last)
+    }
+
+    fn timely(&self, transaction: &Transaction<A>) -> bool {
+        let last = self
+            .accounts
+            .get(&transaction.client)
+            .and_then(|act| act.last_activity);
+        match (transaction.timestamp, last) {
+            (Some(timestamp), Some(last)) => timestamp >= last,
+            _ => true,
+        }
+    }
+
+    // Widens the activity span of the accounts a dated transaction was applied to
+    fn record_activity(
+        &mut self,
+        client: ClientId,
+        counterparty: Option<ClientId>,
+        timestamp: Option<u64>,
+    ) {
+        let Some(timestamp) = timestamp else {
+            return;
+        };
+        for id in std::iter::once(client).chain(counterparty) {
+            if let Some(act) = self.accounts.get_mut(&id) {
+                act.first_activity =
+                    Some(act.first_activity.map_or(timestamp, |t| t.min(timestamp)));
+                act.last_activity = Some(act.last_activity.map_or(timestamp, |t| t.max(timestamp)));
+            }
+        }
+    }
+
+    // Processes every transaction and reports what happened to them, stopping early or keeping
+    // the rejections as the error policy says
+    pub fn process_all(
+        &mut self,
+        transactions: impl IntoIterator<Item = Transaction<A>>,
+    ) -> ProcessingReport {
+        self.process_stream(transactions.into_iter().map(Ok::<_, Infallible>))
+    }
+
+    // Processes transactions from any source that can fail to produce them, e.g. a reader or a
+    // socket. Records that couldn't be read are counted as malformed and only stop the stream
+    // under `ErrorPolicy::Strict`.
+    pub fn process_stream<E>(
+        &mut self,
+        transactions: impl IntoIterator<Item = Result<Transaction<A>, E>>,
+    ) -> ProcessingReport {
+        let start = self.report();
+        let started = Instant::now();
+        let (mut malformed, mut failures) = (0, Vec::new());
+        for record in transactions {
+            let Ok(transaction) = record else {
+                malformed += 1;
+                match self.error_policy {
+                    ErrorPolicy::Strict => break,
+                    _ => continue,
+                }
+            };
+            if !self.process_in_batch(transaction, &mut failures) {
+                break;
+            }
+        }
+        let mut report = self.report_since(&start);
+        report.rows += malformed;
+        report.malformed = malformed;
+        report.failures = failures;
+        report.duration = started.elapsed();
+        report
+    }
+
+    // Applies one transaction of a batch, keeping its rejection in `failures` if the error policy
+    // collects them. False once the policy says the batch stops.
+    pub(crate) fn process_in_batch(
+        &mut self,
+        transaction: Transaction<A>,
+        failures: &mut Vec<Failure>,
+    ) -> bool {
+        let (client, tx) = (transaction.client, transaction.tx);
+        // rejections are counted in the report either way
+        let Err(error) = self.process(transaction) else {
+            return true;
+        };
+        if self.error_policy != ErrorPolicy::Lenient {
+            failures.push(Failure { client, tx, error });
+        }
+        self.error_policy != ErrorPolicy::Strict
+    }
+
+    // Everything the engine applied and rejected since it was created
+    pub fn report(&self) -> ProcessingReport {
+        ProcessingReport {
+            rows: self.total_applied + self.rejected.values().sum::<u64>(),
+            applied: self.total_applied,
+            rejected: self.rejected.clone(),
+            flagged: self.total_flagged,
+            accounts_touched: self.last_seen.len(),
+            position: self.applied,
+            ..Default::default()
+        }
+    }
+
+    // What the engine applied and rejected after `start` was taken with `report`
+    pub fn report_since(&self, start: &ProcessingReport) -> ProcessingReport {
+        let mut report = self.report();
+        // transactions rolled back since can take the totals below where they started
+        report.rows = report.rows.saturating_sub(start.rows);
+        report.applied = report.applied.saturating_sub(start.applied);
+        report.flagged = report.flagged.saturating_sub(start.flagged);
+        for (code, count) in start.rejected.iter() {
+            if let Some(total) = report.rejected.get_mut(code) {
+                *total -= count;
+            }
+        }
+        report.rejected.retain(|_, count| *count > 0);
+        report.accounts_touched = self
+            .last_seen
+            .values()
+            .filter(|&&seen| seen > start.position)
+            .count();
+        report
+    }
+
+    fn apply(&mut self, transaction: Transaction<A>) -> Result<(), TransactionError> {
+        self.check_limits(&transaction)?;
+        let client = transaction.client;
+        let recipient = transaction.recipient();
+        let counterparty = self.counterparty(&transaction);
+        let timestamp = transaction.timestamp;
+        let fee = match transaction.op {
+            Operation::Chargeback => self.limits.chargeback_fee,
+            _ => self.limits.fees.fee(&transaction)?,
+        };
+        self.last_fee = None;
+        let credit_limit = self.limits.credit_limits.limit(client);
+        let tracked = self.keeps_undo();
+        // without a fee to charge, nothing can fail once the task ran
+        if !tracked && fee.is_none() {
+            Task::new(&mut self.history, &mut self.accounts, transaction)
+                .with_lock_policy(self.limits.lock_policy)
+                .with_credit_limit(credit_limit)
+                .with_dispute_window(self.limits.dispute_window)
+                .with_logged_client(self.log_clients)
+                .run()?;
+            self.assign_recipient_credit_limit(recipient);
+            self.record_activity(client, counterparty, timestamp);
+            self.applied += 1;
+            self.last_seen.insert(client, self.applied);
+            if let Some(other) = counterparty {
+                self.last_seen.insert(other, self.applied);
+            }
+            return Ok(());
+        }
+        let tx = transaction.tx;
+        let mut undo = Undo {
+            tx,
+            client: self.replaced(client, tx),
+            counterparty: counterparty.map(|other| self.replaced(other, tx)),
+            last_tx: self.last_tx.get(&client).copied(),
+            fee: None,
+            fee_id: self.fee_ids.get(&client).copied(),
+            counted: false,
+            flagged: false,
+        };
+        Task::new(&mut self.history, &mut self.accounts, transaction)
+            .with_lock_policy(self.limits.lock_policy)
+            .with_credit_limit(credit_limit)
+            .with_dispute_window(self.limits.dispute_window)
+            .with_logged_client(self.log_clients)
+            .run()?;
+        self.assign_recipient_credit_limit(recipient);
+        self.record_activity(client, counterparty, timestamp);
+        // a transaction whose fee can't be charged isn't applied either
+        if let Err(e) = self.assess_fee(client, fee) {
+            self.undo(undo);
+            return Err(e);
+        }
+        undo.fee = self.last_fee.as_ref().map(|fee| (fee.client, fee.tx));
+        self.applied += 1;
+        self.last_seen.insert(client, self.applied);
+        if let Some(other) = counterparty {
+            self.last_seen.insert(other, self.applied);
+        }
+        if !tracked {
+            return Ok(());
+        }
+        self.undo.push_back(undo);
+        let keep = match self.savepoints.first() {
+            Some(&oldest) => self.undo_depth.max((self.applied - oldest) as usize),
+            None => self.undo_depth,
+        };
+        while self.undo.len() > keep {
+            self.undo.pop_front();
+        }
+        Ok(())
+    }
+
+    // The other client a transaction moves funds of: a transfer's recipient, or the other side of
+    // the transfer a dispute, resolve or chargeback refers to
+    pub(crate) fn counterparty(&self, transaction: &Transaction<A>) -> Option<ClientId> {
+        match transaction.op {
+            Operation::Dispute | Operation::Resolve | Operation::Chargeback => self
+                .history
+                .get(&(transaction.client, transaction.tx))
+                .and_then(|node| node.counterparty),
+            _ => transaction.recipient(),
+        }
+    }
+
+    // The receiving side of a transfer gets its limit along with the funds
+    fn assign_recipient_credit_limit(&mut self, recipient: Option<ClientId>) {
+        let Some(to) = recipient else {
+            return;
+        };
+        if let (Some(limit), Some(act)) = (
+            self.limits.credit_limits.limit(to),
+            self.accounts.get_mut(&to),
+        ) {
+            act.credit_limit = Some(limit);
+        }
+    }
+
+    // Rejects amounts no transaction should carry before anything looks at them: negative ones,
+    // which would flip a deposit into a withdrawal, and ones with more decimals than the engine
+    // keeps, unless they're to be rounded. Amounts that aren't numbers never get this far, they
+    // fail to parse and are counted as malformed records.
+    fn validate(
+        &self,
+        mut transaction: Transaction<A>,
+    ) -> Result<Transaction<A>, TransactionError> {
+        let moves_funds = matches!(
+            transaction.op,
+            Operation::Deposit
+                | Operation::Withdrawal
+                | Operation::Transfer
+                | Operation::Fee
+                | Operation::Authorize
+                | Operation::Capture
+        );
+        let Some(amount) = transaction.amount.as_mut().filter(|_| moves_funds) else {
+            return Ok(transaction);
+        };
+        if *amount < A::zero() {
+            return Err(TransactionError::NegativeAmount);
+        }
+        let rounded = amount.round_dp(AMOUNT_SCALE);
+        if rounded != *amount {
+            match self.limits.amount_precision {
+                AmountPrecision::Reject => return Err(TransactionError::ExcessPrecision),
+                AmountPrecision::Round => *amount = rounded,
+            }
+        }
+        Ok(transaction)
+    }
+
+    fn check_limits(&self, transaction: &Transaction<A>) -> Result<(), TransactionError> {
+        if self.limits.tx_order == Some(TxOrder::Reject)
+            && matches!(
+                transaction.op,
+                Operation::Deposit
+                    | Operation::Withdrawal
+                    | Operation::Transfer
+                    | Operation::Authorize
+            )
+            && !self.in_order(transaction.client, transaction.tx)
+        {
+            return Err(TransactionError::OutOfOrder);
+        }
+        if self.limits.timestamp_order == Some(TxOrder::Reject) && !self.timely(transaction) {
+            return Err(TransactionError::TimestampOutOfOrder);
+        }
+        if matches!(
+            transaction.op,
+            Operation::Deposit | Operation::Withdrawal | Operation::Transfer | Operation::Authorize
+        ) {
+            // fees take ids from the top of the client's range, a new transaction mustn't
+            // replace one, on either side of a transfer
+            let fee_held = std::iter::once(transaction.client)
+                .chain(transaction.recipient())
+                .filter_map(|client| self.history.get(&(client, transaction.tx)))
+                .any(|node| node.op == Operation::Fee);
+            if fee_held {
+                return Err(TransactionError::FeeIdTaken);
+            }
+        }
+        if let Some(owners) = &self.tx_owners {
+            let key = (transaction.client, transaction.tx);
+            let other = owners
+                .get(&key.1)
+                .is_some_and(|&owner| owner != transaction.client);
+            // the recipient of a transfer holds its id as well
+            let mismatch = match transaction.op {
+                Operation::Deposit
+                | Operation::Withdrawal
+                | Operation::Transfer
+                | Operation::Authorize => other,
+                Operation::Dispute
+                | Operation::Resolve
+                | Operation::Chargeback
+                | Operation::Capture => other && self.history.get(&key).is_none(),
+                _ => false,
+            };
+            if mismatch {
+                return Err(TransactionError::ClientMismatch);
+            }
+        }
+        if transaction.op == Operation::Dispute
+            && self.limits.dispute_policy == DisputePolicy::DepositsOnly
+        {
+            // fees aren't found by disputes at all
+            let key = (transaction.client, transaction.tx);
+            if let Some(node) = self.history.get(&key) {
+                if node.op != Operation::Fee && !node.is_credit() {
+                    return Err(TransactionError::OperationNotDisputable);
+                }
+            }
+        }
+        if let (Operation::Dispute, Some(limit)) = (&transaction.op, self.limits.dispute_limit) {
+            let key = (transaction.client, transaction.tx);
+            if let Some(node) = self.history.get(&key) {
+                if node.disputes >= limit {
+                    return Err(TransactionError::DisputeLimitReached);
+                }
+            }
+        }
+        // the client whose balance the transaction raises
+        let credited = match transaction.op {
+            Operation::Deposit => Some(transaction.client),
+            _ => transaction.recipient(),
+        };
+        let (Some(credited), Some(amount)) = (credited, transaction.amount) else {
+            return Ok(());
+        };
+        let Some(cap) = self.limits.balance_caps.cap(credited) else {
+            return Ok(());
+        };
+        let total = self
+            .accounts
+            .get(&credited)
+            .map(|act| act.total)
+            .unwrap_or_default();
+        // a total beyond what the amount type holds is beyond any cap too
+        match total.checked_add(amount).is_none_or(|total| total > cap) {
+            true => Err(TransactionError::BalanceCapExceeded),
+            false => Ok(()),
+        }
+    }
+
+    fn assess_fee(&mut self, client: ClientId, fee: Option<A>) -> Result<(), TransactionError> {
+        let Some(fee) = fee else {
+            return Ok(());
+        };
+        let below = self.fee_ids.get(&client).copied().unwrap_or(u32::MAX);
+        let Some(tx) = (0..=below)
+            .rev()
+            .find(|&tx| self.history.get(&(client, tx)).is_none())
+        else {
+            return Ok(());
+        };
+        self.fee_ids.insert(client, tx);
+        let transaction = Transaction {
+            op: Operation::Fee,
+            client,
+            tx,
+            amount: Some(fee),
+            ..Default::default()
+        };
+        Task::new(&mut self.history, &mut self.accounts, transaction.clone())
+            .with_lock_policy(self.limits.lock_policy)
+            .run()?;
+        self.last_fee = Some(transaction);
+        Ok(())
+    }
+
+    fn replaced(&self, client: ClientId, tx: u32) -> Replaced<A> {
+        Replaced {
+            client,
+            account: self.accounts.get(&client).cloned(),
+            node: self.history.get(&(client, tx)),
+            last_seen: self.last_seen.get(&client).copied(),
+        }
+    }
+
+    // Whether applied transactions are remembered for `rollback`
+    fn keeps_undo(&self) -> bool {
+        self.undo_depth > 0 || !self.savepoints.is_empty()
+    }
+
+    // Reverses up to `n` of the most recently applied transactions, restoring balances, lock
+    // state, history, the ids checked for order and the lifetime totals, so a rolled back
+    // transaction can be applied again. Returns how many were reversed, which is less than `n`
+    // when fewer are remembered.
+    pub fn rollback(&mut self, n: usize) -> usize {
+        let n = n.min(self.undo.len());
+        let undone: Vec<Undo<A>> = self.undo.drain(self.undo.len() - n..).rev().collect();
+        for undo in undone {
+            self.undo(undo);
+        }
+        self.applied -= n as u64;
+        // savepoints taken after the new position can't be returned to anymore
+        let applied = self.applied;
+        self.savepoints.retain(|&savepoint| savepoint <= applied);
+        n
+    }
+
+    fn undo(&mut self, undo: Undo<A>) {
+        let (client, tx) = (undo.client.client, undo.tx);
+        // a transaction that was new to the history gives up its id
+        if let (Some(owners), None) = (&mut self.tx_owners, &undo.client.node) {
+            if owners.get(&tx) == Some(&client) {
+                owners.remove(&tx);
+            }
+        }
+        if let Some(fee) = undo.fee {
+            self.history.replace(fee, None);
+        }
+        match undo.fee_id {
+            Some(id) => self.fee_ids.insert(client, id),
+            None => self.fee_ids.remove(&client),
+        };
+        match undo.last_tx {
+            Some(last) => self.last_tx.insert(client, last),
+            None => self.last_tx.remove(&client),
+        };
+        for replaced in std::iter::once(undo.client).chain(undo.counterparty) {
+            match replaced.account {
+                Some(act) => self.accounts.insert(replaced.client, act),
+                None => self.accounts.remove(&replaced.client),
+            };
+            self.history.replace((replaced.client, tx), replaced.node);
+            match replaced.last_seen {
+                Some(seen) => self.last_seen.insert(replaced.client, seen),
+                None => self.last_seen.remove(&replaced.client),
+            };
+        }
+        self.total_applied -= u64::from(undo.counted);
+        self.total_flagged -= u64::from(undo.flagged);
+    }
+
+    // Drops accounts that hold nothing, aren't locked and haven't seen a transaction in the last
+    // `window` applied transactions, along with their history. Transactions of a dropped client
+    // can no longer be disputed. The dropped accounts are returned, ordered by client id.
+    pub fn collect_dormant(&mut self, window: u64) -> Vec<Account<A>> {
+        let applied = self.applied;
+        let last_seen = &self.last_seen;
+        let dormant: Vec<ClientId> = self
+            .accounts
+            .values()
+            .filter(|act| {
+                act.available == A::zero()
+                    && act.held == A::zero()
+                    && act.total == A::zero()
+                    && !act.locked
+                    && applied - last_seen.get(&act.client).copied().unwrap_or(0) >= window
+            })
+            .map(|act| act.client)
+            .collect();
+        if dormant.is_empty() {
+            return Vec::new();
+        }
+
+        let dormant_set: HashSet<ClientId> = dormant.iter().copied().collect();
+        self.history
+            .retain(|(client, _), _| !dormant_set.contains(client));
+        if let Some(owners) = &mut self.tx_owners {
+            owners.retain(|_, client| !dormant_set.contains(client));
+        }
+        let mut removed: Vec<Account<A>> = dormant
+            .iter()
+            .filter_map(|client| {
+                self.last_seen.remove(client);
+                self.last_tx.remove(client);
+                // the fees went with the history, their ids are free again
+                self.fee_ids.remove(client);
+                self.accounts.remove(client)
+            })
+            .collect();
+        removed.sort_by_key(|act| act.client);
+        removed
+    }
+
+    // Accounts still holding funds, or owing them, that haven't seen a transaction in the last
+    // `window` applied transactions, ordered by client id. Unlike `collect_dormant` nothing is
+    // dropped.
+    pub fn dormant(&self, window: u64) -> Vec<DormantAccount<A>> {
+        let mut dormant: Vec<DormantAccount<A>> = self
+            .accounts
+            .values()
+            .filter(|act| act.total != A::zero() || act.held != A::zero())
+            .filter_map(|act| {
+                let last_seen = self.last_seen.get(&act.client).copied().unwrap_or(0);
+                let idle = self.applied.saturating_sub(last_seen);
+                (idle >= window).then(|| DormantAccount {
+                    account: act.clone(),
+                    last_seen,
+                    idle,
+                })
+            })
+            .collect();
+        dormant.sort_by_key(|dormant| dormant.account.client);
+        dormant
+    }
+
+    pub fn savepoint(&mut self) -> Savepoint {
+        self.savepoints.push(self.applied);
+        Savepoint(self.applied)
+    }
+
+    // Discards everything applied since the savepoint was taken, including later savepoints.
+    // Returns false when the savepoint was already invalidated by an earlier rollback.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> bool {
+        let Some(index) = self.savepoints.iter().rposition(|&sp| sp == savepoint.0) else {
+            return false;
+        };
+        self.rollback((self.applied - savepoint.0) as usize);
+        self.savepoints.truncate(index);
+        true
+    }
+
+    // Keeps everything applied since the savepoint and stops tracking it
+    pub fn release(&mut self, savepoint: Savepoint) {
+        if let Some(index) = self.savepoints.iter().rposition(|&sp| sp == savepoint.0) {
+            self.savepoints.remove(index);
+        }
+    }
+
+    // Runs a transaction against a scratch copy of the state it touches, leaving this engine as is
+    pub fn preview(
+        &self,
+        transaction: &Transaction<A>,
+    ) -> Result<AccountDelta<A>, TransactionError> {
+        let transaction = &self.validate(transaction.clone())?;
+        self.check_limits(transaction)?;
+        let key = (transaction.client, transaction.tx);
+        let mut scratch = Engine {
+            limits: Limits {
+                chargeback_fee: self.limits.chargeback_fee,
+                fees: self.limits.fees.clone(),
+                lock_policy: self.limits.lock_policy,
+                amount_precision: self.limits.amount_precision,
+                credit_limits: self.limits.credit_limits.clone(),
+                dispute_window: self.limits.dispute_window,
+                ..Default::default()
+            },
+            ..Engine::new()
+        };
+        scratch.history.replace(key, self.history.get(&key));
+        let before = self.accounts.get(&transaction.client).cloned();
+        if let Some(act) = before.clone() {
+            scratch.accounts.insert(act.client, act);
+        }
+        if let Some(other) = self.counterparty(transaction) {
+            scratch
+                .history
+                .replace((other, key.1), self.history.get(&(other, key.1)));
+            if let Some(act) = self.accounts.get(&other) {
+                scratch.accounts.insert(other, act.clone());
+            }
+        }
+        scratch.process(transaction.clone())?;
+
+        let before = before.unwrap_or_else(|| Account::new(transaction.client));
+        AccountDelta::between(&before, &scratch.accounts[&transaction.client])
+    }
+
+    // Applies each transaction as it is pulled and yields it with its effect on the client's
+    // account, or why it was rejected
+    pub fn outcomes<'a>(
+        &'a mut self,
+        input: impl IntoIterator<Item = Transaction<A>> + 'a,
+    ) -> impl Iterator<Item = (Transaction<A>, Result<AccountDelta<A>, TransactionError>)> + 'a
+    {
+        input
+            .into_iter()
+            .map(move |transaction| self.outcome(transaction))
+    }
+
+    // Applies one transaction, pairing it with its effect on the client's account
+    pub(crate) fn outcome(
+        &mut self,
+        transaction: Transaction<A>,
+    ) -> (Transaction<A>, Result<AccountDelta<A>, TransactionError>) {
+        let before = self.accounts.get(&transaction.client).cloned();
+        let outcome = self.process(transaction.clone()).and_then(|()| {
+            let before = before.unwrap_or_else(|| Account::new(transaction.client));
+            AccountDelta::between(&before, &self.accounts[&transaction.client])
+        });
+        (transaction, outcome)
+    }
+
+    pub fn accounts(&self) -> &AccountStore<A> {
+        &self.accounts
+    }
+
+    pub fn history(&self) -> &History<A> {
+        &self.history
+    }
+
+    // Archives the accounts and history in rkyv's binary layout, see `Checkpoint`
+    #[cfg(feature = "archive")]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint::new(&self.history, &self.accounts)
+    }
+
+    // Continues from a checkpoint written by an earlier engine, without its counters, limits or
+    // undo log
+    #[cfg(feature = "archive")]
+    pub fn restore(checkpoint: &MappedCheckpoint) -> Self {
+        let (history, accounts) = checkpoint.restore();
+        Self::with_accounts(accounts).with_history(history)
+    }
+
+    #[cfg(feature = "csv")]
+    pub fn dump_state(&self) -> StateDump {
+        Snapshot::new(&self.history, &self.accounts).into()
+    }
+
+    // Moves the accounts and history of `clients` into a new engine, e.g. to hand them to the
+    // instance that owns them after a rebalance. Their undo entries are dropped with them.
+    pub fn split_off(&mut self, clients: &HashSet<ClientId>) -> Engine<A> {
+        let mut moved = Engine::new();
+        for client in clients {
+            if let Some(act) = self.accounts.remove(client) {
+                moved.accounts.insert(*client, act);
+            }
+            if let Some(seen) = self.last_seen.remove(client) {
+                moved.last_seen.insert(*client, seen);
             }
-            State::Logging => {
-                // Mutates tx node to reflect most recent op (ie Deposit, Dispute, Chargeback...)
-                // or inserts a new history Node
-                self.history.insert(&self.transaction);
-                self.state = State::Done;
-                Ok(self)
+            if let Some(tx) = self.last_tx.remove(client) {
+                moved.last_tx.insert(*client, tx);
             }
-            State::Done => Ok(self),
+            if let Some(tx) = self.fee_ids.remove(client) {
+                moved.fee_ids.insert(*client, tx);
+            }
+        }
+        for (key, node) in self.history.iter() {
+            if clients.contains(&key.0) {
+                moved.history.replace(key, Some(node));
+            }
+        }
+        if let Some(owners) = &mut self.tx_owners {
+            let (gone, kept) = owners
+                .drain()
+                .partition(|(_, client)| clients.contains(client));
+            *owners = kept;
+            moved.tx_owners = Some(gone);
+        }
+        self.history.retain(|key, _| !clients.contains(&key.0));
+        self.undo
+            .retain(|undo| !clients.contains(&undo.client.client));
+        moved
+    }
+
+    // Applies a transaction touching `counterparty`, a client `other` holds, e.g. a transfer
+    // between two shards. The client's state is moved over for the time it takes and back after.
+    pub(crate) fn process_with(
+        &mut self,
+        other: &mut Engine<A>,
+        counterparty: ClientId,
+        transaction: Transaction<A>,
+    ) -> Result<(), TransactionError> {
+        let key = (counterparty, transaction.tx);
+        Self::hand_over(other, self, key);
+        let result = self.process(transaction);
+        Self::hand_over(self, other, key);
+        result
+    }
+
+    // Moves a client's account and positions, and its history entry under the tx id of `key`,
+    // to another engine. Unlike `split_off` it leaves the rest of the history alone.
+    fn hand_over(from: &mut Engine<A>, to: &mut Engine<A>, key: (ClientId, u32)) {
+        let client = key.0;
+        if let Some(act) = from.accounts.remove(&client) {
+            to.accounts.insert(client, act);
+        }
+        if let Some(seen) = from.last_seen.remove(&client) {
+            to.last_seen.insert(client, seen);
+        }
+        if let Some(tx) = from.last_tx.remove(&client) {
+            to.last_tx.insert(client, tx);
+        }
+        if let Some(tx) = from.fee_ids.remove(&client) {
+            to.fee_ids.insert(client, tx);
         }
+        if let Some(node) = from.history.replace(key, None) {
+            to.history.replace(key, Some(node));
+        }
+    }
+
+    // Folds another engine's state into this one. Both engines must own disjoint clients,
+    // otherwise the conflicting client id is returned and nothing is merged.
+    pub fn merge(&mut self, other: Engine<A>) -> Result<(), ClientId> {
+        if let Some(client) = other
+            .accounts
+            .keys()
+            .find(|c| self.accounts.contains_key(c))
+        {
+            return Err(*client);
+        }
+        self.accounts.extend(other.accounts);
+        self.history.extend(other.history);
+        self.last_seen.extend(other.last_seen);
+        self.last_tx.extend(other.last_tx);
+        self.fee_ids.extend(other.fee_ids);
+        if let (Some(owners), Some(other)) = (&mut self.tx_owners, other.tx_owners) {
+            owners.extend(other);
+        }
+        self.total_applied += other.total_applied;
+        self.total_flagged += other.total_flagged;
+        for (code, count) in other.rejected {
+            *self.rejected.entry(code).or_default() += count;
+        }
+        Ok(())
     }
 }
 
@@ -118,7 +1738,7 @@ pub trait Machine {
 
 #[cfg(test)]
 pub mod test {
-    use crate::domain::tx_history::History;
+    use crate::domain::tx_history::{DisputeState, History, Node, TxStore};
     use rust_decimal_macros::dec;
 
     use super::*;
@@ -126,18 +1746,25 @@ pub mod test {
     #[test]
     fn handles_deposit() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let transaction = Transaction {
             op: Operation::Deposit,
             client: 1,
             tx: 1,
             amount: Some(dec!(10)),
+            ..Default::default()
         };
         let mut task = Task {
             history: &mut history,
             accounts: &mut accounts,
             transaction,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
@@ -145,10 +1772,15 @@ pub mod test {
 
         let expected = Account {
             client: 1,
+            currency: None,
             available: dec!(10),
             held: dec!(0.0),
             total: dec!(10),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
 
         let output = accounts.get(&1);
@@ -159,13 +1791,18 @@ pub mod test {
     #[test]
     fn handles_successful_withdrawal() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(40),
             held: dec!(0.0),
             total: dec!(40),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
 
@@ -174,12 +1811,19 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: Some(dec!(20)),
+            ..Default::default()
         };
         let mut task = Task {
             history: &mut history,
             accounts: &mut accounts,
             transaction,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
@@ -187,10 +1831,15 @@ pub mod test {
 
         let expected = Account {
             client: 1,
+            currency: None,
             available: dec!(20),
             held: dec!(0.0),
             total: dec!(20),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
 
         let output = accounts.get(&1);
@@ -201,13 +1850,18 @@ pub mod test {
     #[test]
     fn handles_failed_withdrawal() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(40),
             held: dec!(0.0),
             total: dec!(40),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
 
@@ -216,18 +1870,25 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: Some(dec!(50)),
+            ..Default::default()
         };
         let mut task = Task {
             history: &mut history,
             accounts: &mut accounts,
             transaction,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
         assert!(result.is_err());
         match result {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!("expected an error"),
             Err(e) => assert_eq!(e, TransactionError::InsufficientFunds),
         }
     }
@@ -235,13 +1896,18 @@ pub mod test {
     #[test]
     fn handles_dispute() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
         let tx0 = Transaction {
@@ -249,6 +1915,7 @@ pub mod test {
             tx: 1,
             op: Operation::Withdrawal,
             amount: Some(dec!(50)),
+            ..Default::default()
         };
 
         let mut task0 = Task::new(&mut history, &mut accounts, tx0);
@@ -259,6 +1926,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task = Task {
@@ -266,6 +1934,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx1,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
@@ -273,10 +1947,15 @@ pub mod test {
 
         let expected = Account {
             client: 1,
+            currency: None,
             available: dec!(100),
             held: dec!(50),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         let output = accounts.get(&1);
         assert!(output.is_some());
@@ -286,13 +1965,18 @@ pub mod test {
     #[test]
     fn handles_dispute_and_chargeback() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
         let tx0 = Transaction {
@@ -300,6 +1984,7 @@ pub mod test {
             tx: 1,
             op: Operation::Withdrawal,
             amount: Some(dec!(50)),
+            ..Default::default()
         };
 
         let mut task0 = Task::new(&mut history, &mut accounts, tx0);
@@ -310,6 +1995,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task = Task {
@@ -317,6 +2003,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx1,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
@@ -324,10 +2016,15 @@ pub mod test {
 
         let expected = Account {
             client: 1,
+            currency: None,
             available: dec!(100),
             held: dec!(50),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         {
             let output = accounts.get(&1);
@@ -340,6 +2037,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task2 = Task {
@@ -347,6 +2045,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx2,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let res2 = task2.run();
@@ -354,10 +2058,15 @@ pub mod test {
 
         let final_expected = Account {
             client: 1,
+            currency: None,
             available: dec!(100),
             held: dec!(0),
             total: dec!(100),
             locked: true,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
 
         let output = accounts.get(&1);
@@ -368,13 +2077,18 @@ pub mod test {
     #[test]
     fn handles_dispute_and_resolve() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
         let tx0 = Transaction {
@@ -382,6 +2096,7 @@ pub mod test {
             tx: 1,
             op: Operation::Withdrawal,
             amount: Some(dec!(50)),
+            ..Default::default()
         };
 
         let mut task0 = Task::new(&mut history, &mut accounts, tx0);
@@ -394,6 +2109,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task = Task {
@@ -401,6 +2117,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx1,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
@@ -408,10 +2130,15 @@ pub mod test {
 
         let expected = Account {
             client: 1,
+            currency: None,
             available: dec!(100),
             held: dec!(50),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         {
             let output = accounts.get(&1);
@@ -424,6 +2151,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task2 = Task {
@@ -431,6 +2159,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx2,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let res2 = task2.run();
@@ -438,10 +2172,15 @@ pub mod test {
 
         let final_expected = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
 
         let output = accounts.get(&1);
@@ -452,13 +2191,18 @@ pub mod test {
     #[test]
     fn handles_dispute_and_resolve_deposit() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
         let tx0 = Transaction {
@@ -466,6 +2210,7 @@ pub mod test {
             tx: 1,
             op: Operation::Deposit,
             amount: Some(dec!(50)),
+            ..Default::default()
         };
 
         let mut task0 = Task::new(&mut history, &mut accounts, tx0);
@@ -476,6 +2221,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task = Task {
@@ -483,6 +2229,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx1,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
@@ -490,10 +2242,15 @@ pub mod test {
 
         let expected = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(50),
             total: dec!(200),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         {
             let output = accounts.get(&1);
@@ -506,6 +2263,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task2 = Task {
@@ -513,6 +2271,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx2,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let res2 = task2.run();
@@ -520,10 +2284,15 @@ pub mod test {
 
         let final_expected = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
 
         let output = accounts.get(&1);
@@ -534,13 +2303,18 @@ pub mod test {
     #[test]
     fn handles_dispute_and_chargeback_deposit() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
         let tx0 = Transaction {
@@ -548,6 +2322,7 @@ pub mod test {
             tx: 1,
             op: Operation::Deposit,
             amount: Some(dec!(50)),
+            ..Default::default()
         };
 
         let mut task0 = Task::new(&mut history, &mut accounts, tx0);
@@ -558,6 +2333,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task = Task {
@@ -565,6 +2341,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx1,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let result = task.run();
@@ -572,10 +2354,15 @@ pub mod test {
 
         let expected = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(50),
             total: dec!(200),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         {
             let output = accounts.get(&1);
@@ -588,6 +2375,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task2 = Task {
@@ -595,6 +2383,12 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx2,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let res2 = task2.run();
@@ -602,10 +2396,15 @@ pub mod test {
 
         let final_expected = Account {
             client: 1,
+            currency: None,
             available: dec!(200),
             held: dec!(0),
             total: dec!(200),
             locked: true,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
 
         let output = accounts.get(&1);
@@ -616,13 +2415,18 @@ pub mod test {
     #[test]
     fn no_active_dispute() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: false,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
 
@@ -631,6 +2435,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task = Task {
@@ -638,13 +2443,19 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx1,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let res = task.run();
 
         assert!(res.is_err());
         match res {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!("expected an error"),
             Err(ref e) => assert_eq!(*e, TransactionError::TransactionNotFound),
         };
 
@@ -653,6 +2464,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: None,
+            ..Default::default()
         };
 
         let mut task2 = Task {
@@ -660,13 +2472,19 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx2,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let res2 = task2.run();
 
         assert!(res2.is_err());
         match res {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!("expected an error"),
             Err(e) => assert_eq!(e, TransactionError::TransactionNotFound),
         }
     }
@@ -674,13 +2492,18 @@ pub mod test {
     #[test]
     fn locked_account() {
         let mut history = History::new();
-        let mut accounts = HashMap::<u16, Account>::new();
+        let mut accounts = AccountStore::new();
         let start = Account {
             client: 1,
+            currency: None,
             available: dec!(150),
             held: dec!(0),
             total: dec!(150),
             locked: true,
+            credit_limit: None,
+            fees: None,
+            first_activity: None,
+            last_activity: None,
         };
         accounts.insert(1, start);
 
@@ -689,6 +2512,7 @@ pub mod test {
             client: 1,
             tx: 1,
             amount: Some(dec!(100)),
+            ..Default::default()
         };
 
         let mut task = Task {
@@ -696,14 +2520,1394 @@ pub mod test {
             accounts: &mut accounts,
             transaction: tx1,
             state: State::Idle,
+            lock_policy: LockPolicy::Freeze,
+            credit_limit: None,
+            dispute_window: None,
+            release: None,
+            linked: None,
+            log_client: false,
         };
 
         let res = task.run();
 
         assert!(res.is_err());
         match res {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!("expected an error"),
             Err(e) => assert_eq!(e, TransactionError::LockedAccount),
         }
     }
+
+    #[test]
+    fn preview_leaves_state_untouched() {
+        let mut engine = Engine::new();
+        engine
+            .process(Transaction {
+                op: Operation::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(dec!(40)),
+                ..Default::default()
+            })
+            .unwrap();
+        let dispute = Transaction {
+            op: Operation::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            ..Default::default()
+        };
+
+        let delta = engine.preview(&dispute).unwrap();
+
+        assert_eq!(
+            delta,
+            AccountDelta {
+                client: 1,
+                available: dec!(-40),
+                held: dec!(40),
+                total: dec!(0),
+                locked: false,
+            }
+        );
+        assert_eq!(engine.accounts()[&1].held, dec!(0));
+        assert_eq!(
+            engine.history().get(&(1, 1)).unwrap().op,
+            Operation::Deposit
+        );
+        assert_eq!(
+            engine.preview(&Transaction {
+                op: Operation::Withdrawal,
+                client: 2,
+                tx: 2,
+                amount: Some(dec!(1)),
+                ..Default::default()
+            }),
+            Err(TransactionError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn outcomes_pair_transactions_with_their_effect() {
+        let mut engine = Engine::new();
+        let input = [
+            (Operation::Deposit, Some(dec!(10))),
+            (Operation::Withdrawal, Some(dec!(25))),
+            (Operation::Withdrawal, Some(dec!(4))),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(tx, (op, amount))| Transaction {
+            op,
+            client: 1,
+            tx: tx as u32,
+            amount,
+            ..Default::default()
+        });
+
+        let outcomes: Vec<_> = engine.outcomes(input).collect();
+
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes[0].1.as_ref().unwrap().total, dec!(10));
+        assert_eq!(outcomes[1].1, Err(TransactionError::InsufficientFunds));
+        assert_eq!(outcomes[2].0.tx, 2);
+        assert_eq!(outcomes[2].1.as_ref().unwrap().available, dec!(-4));
+        assert_eq!(engine.accounts()[&1].total, dec!(6));
+    }
+
+    #[test]
+    fn deposits_and_withdrawals_incur_the_modelled_fees() {
+        let transaction = |op, tx, amount| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            ..Default::default()
+        };
+        let fees = FeeModel::new()
+            .deposit("0.25+1%".parse().unwrap())
+            .withdrawal("2".parse().unwrap());
+        let mut engine = Engine::new().with_fees(fees).keep_undo(1);
+        engine
+            .process(transaction(Operation::Deposit, 1, dec!(100)))
+            .unwrap();
+        assert_eq!(
+            engine.assessed_fee().and_then(|fee| fee.amount),
+            Some(dec!(1.25))
+        );
+        engine
+            .process(transaction(Operation::Withdrawal, 2, dec!(10)))
+            .unwrap();
+
+        // every fee is a history entry of its own
+        let fee = engine.assessed_fee().cloned().expect("Fee charged");
+        assert_eq!((fee.op.clone(), fee.tx), (Operation::Fee, u32::MAX - 1));
+        assert_eq!(
+            engine.history().get(&(1, u32::MAX)).map(|node| node.op),
+            Some(Operation::Fee)
+        );
+        let act = &engine.accounts()[&1];
+        assert_eq!((act.total, act.fees), (dec!(86.75), Some(dec!(3.25))));
+
+        // rolling back a withdrawal takes its fee with it
+        engine.rollback(1);
+        let act = &engine.accounts()[&1];
+        assert_eq!((act.total, act.fees), (dec!(98.75), Some(dec!(1.25))));
+        assert_eq!(engine.history().get(&(1, u32::MAX - 1)), None);
+        assert!("1%+x".parse::<Fee>().is_err());
+    }
+
+    #[test]
+    fn chargebacks_incur_the_configured_fee() {
+        let mut engine = Engine::new().with_chargeback_fee(dec!(15)).keep_undo(1);
+        for (op, tx, amount) in [
+            (Operation::Deposit, 1, Some(dec!(100))),
+            (Operation::Deposit, 2, Some(dec!(30))),
+            (Operation::Dispute, 2, None),
+        ] {
+            engine
+                .process(Transaction {
+                    op,
+                    client: 1,
+                    tx,
+                    amount,
+                    ..Default::default()
+                })
+                .unwrap();
+            assert_eq!(engine.assessed_fee(), None);
+        }
+        let chargeback = Transaction {
+            op: Operation::Chargeback,
+            client: 1,
+            tx: 2,
+            amount: None,
+            ..Default::default()
+        };
+
+        engine.process(chargeback).unwrap();
+
+        let fee = engine.assessed_fee().cloned().expect("Fee charged");
+        assert_eq!((fee.op.clone(), fee.tx), (Operation::Fee, u32::MAX));
+        let act = &engine.accounts()[&1];
+        assert_eq!(
+            (act.available, act.total, act.locked),
+            (dec!(115), dec!(115), true)
+        );
+        assert_eq!(
+            engine.history().get(&(1, u32::MAX)).unwrap().amount,
+            Some(dec!(15))
+        );
+        // fees aren't disputable
+        let dispute = Transaction {
+            op: Operation::Dispute,
+            client: 1,
+            tx: u32::MAX,
+            amount: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            engine.preview(&dispute),
+            Err(TransactionError::TransactionNotFound)
+        );
+
+        // replaying the chargeback and its fee without a configured fee gives the same state
+        let mut replay = Engine::new();
+        for (op, tx, amount) in [
+            (Operation::Deposit, 1, Some(dec!(100))),
+            (Operation::Deposit, 2, Some(dec!(30))),
+            (Operation::Dispute, 2, None),
+            (Operation::Chargeback, 2, None),
+        ] {
+            let transaction = Transaction {
+                op,
+                client: 1,
+                tx,
+                amount,
+                ..Default::default()
+            };
+            replay.process(transaction).unwrap();
+        }
+        replay.process(fee).unwrap();
+        assert_eq!(replay.accounts()[&1], engine.accounts()[&1]);
+
+        engine.rollback(1);
+        assert_eq!(engine.accounts()[&1].total, dec!(130));
+        assert!(!engine.accounts()[&1].locked);
+        assert!(engine.history().get(&(1, u32::MAX)).is_none());
+    }
+
+    #[test]
+    fn fees_keep_their_ids_and_charge_or_reject_the_transaction() {
+        use crate::domain::amount::MinorUnits;
+
+        let transaction = |op: Operation, client, tx, amount| Transaction {
+            counterparty: (op == Operation::Transfer).then_some(2),
+            op,
+            client,
+            tx,
+            amount: Some(MinorUnits(amount)),
+            ..Default::default()
+        };
+        let fees = FeeModel::new().deposit(Fee {
+            flat: MinorUnits(i64::MAX),
+            percent: Decimal::ZERO,
+        });
+        let mut engine = Engine::<MinorUnits>::new().with_fees(fees);
+        engine
+            .process(transaction(Operation::Deposit, 1, 1, 1))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Deposit, 2, 1, 1))
+            .unwrap();
+
+        // neither a transaction of the client nor a transfer to it replaces the fee
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 1, u32::MAX, 1)),
+            Err(TransactionError::FeeIdTaken)
+        );
+        assert_eq!(
+            engine.process(transaction(Operation::Transfer, 3, u32::MAX, 0)),
+            Err(TransactionError::FeeIdTaken)
+        );
+        assert_eq!(
+            engine.history().get(&(1, u32::MAX)).map(|node| node.op),
+            Some(Operation::Fee)
+        );
+
+        // a second fee doesn't fit the account, so the deposit isn't applied either
+        let before = engine.accounts()[&1].clone();
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 1, 2, 1)),
+            Err(TransactionError::AmountOverflow)
+        );
+        assert_eq!(engine.accounts()[&1], before);
+        assert!(engine.history().get(&(1, 2)).is_none());
+        assert!(engine.history().get(&(1, u32::MAX - 1)).is_none());
+    }
+
+    #[test]
+    fn client_ids_colliding_with_fee_ids() {
+        let transaction = |op, tx, amount| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        let charged_back = [
+            (Operation::Deposit, 1, Some(dec!(5))),
+            (Operation::Dispute, 1, None),
+            (Operation::Chargeback, 1, None),
+        ];
+        let mut engine = Engine::new().with_chargeback_fee(dec!(1)).keep_undo(4);
+        // the client already uses the id the first fee would take
+        engine
+            .process(transaction(Operation::Deposit, u32::MAX, Some(dec!(10))))
+            .unwrap();
+        for (op, tx, amount) in charged_back.clone() {
+            engine.process(transaction(op, tx, amount)).unwrap();
+        }
+        assert_eq!(engine.assessed_fee().map(|fee| fee.tx), Some(u32::MAX - 1));
+        assert_eq!(
+            engine.history().get(&(1, u32::MAX)).map(|node| node.op),
+            Some(Operation::Deposit)
+        );
+        assert_eq!(
+            engine.process(transaction(
+                Operation::Withdrawal,
+                u32::MAX - 1,
+                Some(dec!(1))
+            )),
+            Err(TransactionError::FeeIdTaken)
+        );
+
+        // rolling everything back frees both ids, the next fee takes the highest again
+        assert_eq!(engine.rollback(4), 4);
+        assert!(engine.history().get(&(1, u32::MAX - 1)).is_none());
+        for (op, tx, amount) in charged_back {
+            engine.process(transaction(op, tx, amount)).unwrap();
+        }
+        assert_eq!(engine.assessed_fee().map(|fee| fee.tx), Some(u32::MAX));
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, u32::MAX, Some(dec!(10)))),
+            Err(TransactionError::FeeIdTaken)
+        );
+        assert_eq!(engine.accounts()[&1].total, dec!(4));
+    }
+
+    #[test]
+    fn rollback_restores_previous_state() {
+        let mut engine = Engine::new().keep_undo(2);
+        let transactions = [
+            (Operation::Deposit, 1, 1, Some(dec!(10))),
+            (Operation::Deposit, 2, 2, Some(dec!(5))),
+            (Operation::Dispute, 1, 1, None),
+            (Operation::Chargeback, 1, 1, None),
+        ];
+        for (op, client, tx, amount) in transactions {
+            engine
+                .process(Transaction {
+                    op,
+                    client,
+                    tx,
+                    amount,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        assert!(engine.accounts()[&1].locked);
+
+        assert_eq!(engine.rollback(5), 2);
+
+        let act = &engine.accounts()[&1];
+        assert_eq!(
+            (act.available, act.held, act.locked),
+            (dec!(10), dec!(0), false)
+        );
+        assert_eq!(
+            engine.history().get(&(1, 1)).unwrap().op,
+            Operation::Deposit
+        );
+        assert_eq!(engine.accounts()[&2].total, dec!(5));
+        assert_eq!(engine.rollback(1), 0);
+    }
+
+    #[test]
+    fn rollback_to_savepoint_discards_sub_batch() {
+        let deposit = |client, tx, amount| Transaction {
+            op: Operation::Deposit,
+            client,
+            tx,
+            amount: Some(amount),
+            ..Default::default()
+        };
+        let mut engine = Engine::new();
+        engine.process(deposit(1, 1, dec!(10))).unwrap();
+
+        let kept = engine.savepoint();
+        engine.process(deposit(1, 2, dec!(5))).unwrap();
+        engine.release(kept);
+
+        let risky = engine.savepoint();
+        engine.process(deposit(2, 3, dec!(7))).unwrap();
+        let nested = engine.savepoint();
+        engine.process(deposit(1, 4, dec!(1))).unwrap();
+        engine.release(nested);
+        assert!(engine.rollback_to(risky));
+
+        assert_eq!(engine.accounts()[&1].total, dec!(15));
+        assert!(!engine.accounts().contains_key(&2));
+        assert!(engine.history().get(&(1, 4)).is_none());
+
+        let outer = engine.savepoint();
+        engine.process(deposit(3, 5, dec!(2))).unwrap();
+        let inner = engine.savepoint();
+        assert!(engine.rollback_to(outer));
+        assert!(!engine.rollback_to(inner));
+    }
+
+    #[test]
+    fn rolled_back_ids_can_be_applied_again() {
+        let deposit = |tx, amount| Transaction {
+            op: Operation::Deposit,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            ..Default::default()
+        };
+        let mut engine = Engine::new()
+            .keep_undo(4)
+            .with_tx_order(TxOrder::Reject)
+            .with_chargeback_fee(dec!(1));
+        engine.process(deposit(1, dec!(10))).unwrap();
+        let start = engine.report();
+
+        engine.process(deposit(2, dec!(5))).unwrap();
+        engine
+            .process(Transaction {
+                op: Operation::Dispute,
+                ..deposit(2, dec!(5))
+            })
+            .unwrap();
+        engine
+            .process(Transaction {
+                op: Operation::Chargeback,
+                ..deposit(2, dec!(5))
+            })
+            .unwrap();
+        assert_eq!(engine.rollback(3), 3);
+
+        let report = engine.report();
+        assert_eq!((report.applied, report.position), (1, 1));
+        assert_eq!(engine.report_since(&start).applied, 0);
+        engine.process(deposit(2, dec!(5))).unwrap();
+        assert_eq!(
+            engine.process(deposit(2, dec!(5))),
+            Err(TransactionError::OutOfOrder)
+        );
+
+        // the same through a savepoint, for a client the rolled back transaction was the first of
+        let savepoint = engine.savepoint();
+        engine
+            .process(Transaction {
+                client: 2,
+                ..deposit(7, dec!(1))
+            })
+            .unwrap();
+        assert!(engine.rollback_to(savepoint));
+        assert_eq!(engine.report().accounts_touched, 1);
+        engine
+            .process(Transaction {
+                client: 2,
+                ..deposit(7, dec!(1))
+            })
+            .unwrap();
+        assert_eq!(engine.report().applied, 3);
+    }
+
+    #[test]
+    fn collects_dormant_empty_accounts() {
+        let transaction = |op, client, tx, amount| Transaction {
+            op,
+            client,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        let mut engine = Engine::new();
+        for tx in [
+            transaction(Operation::Deposit, 1, 1, Some(dec!(5))),
+            transaction(Operation::Withdrawal, 1, 2, Some(dec!(5))),
+            transaction(Operation::Deposit, 2, 3, Some(dec!(5))),
+            transaction(Operation::Withdrawal, 2, 4, Some(dec!(5))),
+            transaction(Operation::Deposit, 3, 5, Some(dec!(1))),
+        ] {
+            engine.process(tx).unwrap();
+        }
+
+        // client 2 emptied its account too recently, client 3 still has funds
+        let removed = engine.collect_dormant(3);
+
+        assert_eq!(
+            removed.iter().map(|act| act.client).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert!(!engine.accounts().contains_key(&1));
+        assert!(engine.history().get(&(1, 1)).is_none());
+        assert!(engine.history().get(&(2, 3)).is_some());
+        assert_eq!(engine.collect_dormant(0).len(), 1);
+    }
+
+    #[test]
+    fn process_all_reports_outcomes() {
+        let transaction = |op, client, tx, amount| Transaction {
+            op,
+            client,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        let mut engine = Engine::new();
+        engine
+            .process(transaction(Operation::Deposit, 1, 1, Some(dec!(5))))
+            .unwrap();
+
+        let report = engine.process_all([
+            transaction(Operation::Deposit, 2, 2, Some(dec!(5))),
+            transaction(Operation::Withdrawal, 2, 3, Some(dec!(9))),
+            transaction(Operation::Dispute, 2, 9, None),
+            transaction(Operation::Withdrawal, 3, 4, Some(dec!(1))),
+            transaction(Operation::Withdrawal, 2, 5, Some(dec!(1))),
+        ]);
+
+        assert_eq!((report.rows, report.applied), (5, 2));
+        assert_eq!(report.rejected["insufficient_funds"], 2);
+        assert_eq!(report.rejected["transaction_not_found"], 1);
+        assert_eq!(report.rejected_total(), 3);
+        // client 1 was only touched before the batch
+        assert_eq!(report.accounts_touched, 1);
+
+        let total = engine.report();
+        assert_eq!((total.rows, total.applied), (6, 3));
+        assert_eq!(total.accounts_touched, 2);
+    }
+
+    #[test]
+    fn rejects_deposits_over_the_balance_cap() {
+        let deposit = |client, tx, amount| Transaction {
+            op: Operation::Deposit,
+            client,
+            tx,
+            amount: Some(amount),
+            ..Default::default()
+        };
+        let mut caps = BalanceCaps::new().global(dec!(100)).tier("basic", dec!(10));
+        caps.assign(2, "basic");
+        let mut engine = Engine::new().with_balance_caps(caps);
+
+        engine.process(deposit(1, 1, dec!(60))).unwrap();
+        engine.process(deposit(1, 2, dec!(40))).unwrap();
+        assert_eq!(
+            engine.process(deposit(1, 3, dec!(0.01))),
+            Err(TransactionError::BalanceCapExceeded)
+        );
+        assert_eq!(
+            engine.preview(&deposit(2, 4, dec!(11))),
+            Err(TransactionError::BalanceCapExceeded)
+        );
+        engine.process(deposit(2, 5, dec!(10))).unwrap();
+
+        assert_eq!(engine.accounts()[&1].total, dec!(100));
+        assert!(engine.history().get(&(1, 3)).is_none());
+        assert_eq!(engine.report().rejected["balance_cap_exceeded"], 1);
+    }
+
+    #[test]
+    fn finds_dormant_accounts_with_balances() {
+        let transaction = |op, client, tx, amount| Transaction {
+            op,
+            client,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        let mut engine = Engine::new();
+        for tx in [
+            transaction(Operation::Deposit, 1, 1, Some(dec!(5))),
+            transaction(Operation::Deposit, 2, 2, Some(dec!(5))),
+            transaction(Operation::Withdrawal, 2, 3, Some(dec!(5))),
+            transaction(Operation::Deposit, 3, 4, Some(dec!(1))),
+            transaction(Operation::Deposit, 4, 5, Some(dec!(1))),
+        ] {
+            engine.process(tx).unwrap();
+        }
+
+        // client 2 is empty, clients 3 and 4 were active too recently
+        let dormant = engine.dormant(2);
+
+        assert_eq!(dormant.len(), 1);
+        assert_eq!(dormant[0].account.client, 1);
+        assert_eq!((dormant[0].last_seen, dormant[0].idle), (1, 4));
+        assert_eq!(engine.dormant(0).len(), 3);
+        assert_eq!(engine.accounts().len(), 4);
+    }
+
+    #[test]
+    fn limits_disputes_per_transaction() {
+        let transaction = |op, tx, amount| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_dispute_limit(2);
+        engine
+            .process(transaction(Operation::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        for op in [Operation::Dispute, Operation::Resolve, Operation::Dispute] {
+            engine.process(transaction(op, 1, None)).unwrap();
+        }
+        engine
+            .process(transaction(Operation::Resolve, 1, None))
+            .unwrap();
+
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 1, None)),
+            Err(TransactionError::DisputeLimitReached)
+        );
+        assert_eq!(engine.history().get(&(1, 1)).unwrap().disputes, 2);
+        assert_eq!(engine.accounts()[&1].held, dec!(0));
+    }
+
+    #[test]
+    fn rejects_invalid_dispute_transitions() {
+        let transaction = |op, tx, amount| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        let mut engine = Engine::new();
+        for tx in [1, 2] {
+            engine
+                .process(transaction(Operation::Deposit, tx, Some(dec!(10))))
+                .unwrap();
+        }
+
+        assert_eq!(
+            engine.process(transaction(Operation::Resolve, 1, None)),
+            Err(TransactionError::NotUnderDispute)
+        );
+        engine
+            .process(transaction(Operation::Dispute, 1, None))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 1, None)),
+            Err(TransactionError::AlreadyDisputed)
+        );
+        assert_eq!(engine.accounts()[&1].held, dec!(10));
+        engine
+            .process(transaction(Operation::Dispute, 2, None))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Chargeback, 2, None))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Chargeback, 2, None)),
+            Err(TransactionError::NotUnderDispute)
+        );
+        assert_eq!(
+            engine.history().get(&(1, 2)).unwrap().dispute_state(),
+            DisputeState::ChargedBack
+        );
+    }
+
+    #[test]
+    fn transfers_between_clients() {
+        let transfer = |tx, amount| Transaction {
+            op: Operation::Transfer,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            counterparty: Some(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::new().keep_undo(1);
+        engine
+            .process(Transaction {
+                op: Operation::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(dec!(10)),
+                ..Default::default()
+            })
+            .unwrap();
+        engine.process(transfer(2, dec!(4))).unwrap();
+
+        assert_eq!(
+            engine.process(transfer(3, dec!(7))),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(engine.accounts()[&1].total, dec!(6));
+        assert_eq!(engine.accounts()[&2].available, dec!(4));
+        // a dispute holds the transferred funds on the recipient's account
+        engine
+            .process(Transaction {
+                op: Operation::Dispute,
+                client: 2,
+                tx: 2,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            (engine.accounts()[&2].available, engine.accounts()[&2].held),
+            (dec!(0), dec!(4))
+        );
+        engine.rollback(1);
+        engine.process(transfer(4, dec!(6))).unwrap();
+        assert_eq!(engine.accounts()[&1].total, dec!(0));
+        assert_eq!(engine.accounts()[&2].available, dec!(10));
+    }
+
+    #[test]
+    fn disputes_of_transfers_move_both_accounts() {
+        let transaction = |op, client, amount| Transaction {
+            op,
+            client,
+            tx: 2,
+            amount,
+            counterparty: Some(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_invariant_checks().keep_undo(2);
+        engine
+            .process(Transaction {
+                tx: 1,
+                ..transaction(Operation::Deposit, 1, Some(dec!(10)))
+            })
+            .unwrap();
+        engine
+            .process(transaction(Operation::Transfer, 1, Some(dec!(4))))
+            .unwrap();
+        let balances = |engine: &Engine, client| {
+            let act = &engine.accounts()[&client];
+            (act.available, act.held, act.total, act.locked)
+        };
+        let states = |engine: &Engine| {
+            [1, 2].map(|client| engine.history().get(&(client, 2)).unwrap().dispute_state())
+        };
+
+        // the sender opens the dispute, the recipient's funds are held
+        engine
+            .process(transaction(Operation::Dispute, 1, None))
+            .unwrap();
+        assert_eq!(balances(&engine, 1), (dec!(6), dec!(0), dec!(6), false));
+        assert_eq!(balances(&engine, 2), (dec!(0), dec!(4), dec!(4), false));
+        assert_eq!(states(&engine), [DisputeState::Disputed; 2]);
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 2, None)),
+            Err(TransactionError::AlreadyDisputed)
+        );
+
+        // either side settles it, a resolve releases the funds to the recipient
+        engine
+            .process(transaction(Operation::Resolve, 2, None))
+            .unwrap();
+        assert_eq!(balances(&engine, 2), (dec!(4), dec!(0), dec!(4), false));
+        assert_eq!(states(&engine), [DisputeState::Resolved; 2]);
+
+        // a chargeback returns them to the sender
+        engine
+            .process(transaction(Operation::Dispute, 2, None))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Chargeback, 1, None))
+            .unwrap();
+        assert_eq!(balances(&engine, 1), (dec!(10), dec!(0), dec!(10), false));
+        assert_eq!(balances(&engine, 2), (dec!(0), dec!(0), dec!(0), true));
+        assert_eq!(states(&engine), [DisputeState::ChargedBack; 2]);
+        assert_eq!(engine.history().get(&(2, 2)).unwrap().disputes, 2);
+        assert_eq!(engine.invariant_violation(), None);
+
+        // rolling back restores both sides
+        engine.rollback(2);
+        assert_eq!(balances(&engine, 1), (dec!(6), dec!(0), dec!(6), false));
+        assert_eq!(balances(&engine, 2), (dec!(4), dec!(0), dec!(4), false));
+        assert_eq!(states(&engine), [DisputeState::Resolved; 2]);
+    }
+
+    #[test]
+    fn stops_or_collects_as_the_error_policy_says() {
+        let withdrawal = |tx| Transaction {
+            op: Operation::Withdrawal,
+            client: 1,
+            tx,
+            amount: Some(dec!(5)),
+            ..Default::default()
+        };
+        let run = |policy| {
+            let records = vec![Ok(withdrawal(1)), Err("bad row"), Ok(withdrawal(2))];
+            Engine::new()
+                .with_error_policy(policy)
+                .process_stream(records)
+        };
+
+        let lenient = run(ErrorPolicy::Lenient);
+        assert_eq!((lenient.rows, lenient.rejected_total()), (3, 2));
+        assert!(lenient.failures.is_empty());
+        let strict = run(ErrorPolicy::Strict);
+        assert_eq!(strict.rows, 1);
+        assert_eq!(
+            strict.failures,
+            vec![Failure {
+                client: 1,
+                tx: 1,
+                error: TransactionError::InsufficientFunds
+            }]
+        );
+        let collect = run(ErrorPolicy::Collect);
+        assert_eq!(collect.malformed, 1);
+        assert_eq!(
+            collect.failures.iter().map(|f| f.tx).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn processes_streams_with_unreadable_records() {
+        let deposit = |tx| Transaction {
+            op: Operation::Deposit,
+            client: 1,
+            tx,
+            amount: Some(dec!(5)),
+            ..Default::default()
+        };
+        let withdrawal = Transaction {
+            op: Operation::Withdrawal,
+            amount: Some(dec!(20)),
+            ..deposit(3)
+        };
+        let records = vec![
+            Ok(deposit(1)),
+            Err("bad row"),
+            Ok(deposit(2)),
+            Ok(withdrawal),
+        ];
+        let report = Engine::new().process_stream(records);
+
+        assert_eq!((report.rows, report.applied, report.malformed), (4, 2, 1));
+        assert_eq!(report.rejected_total(), 1);
+    }
+
+    #[test]
+    fn checks_tx_ids_increase_per_client() {
+        let deposit = |client, tx| Transaction {
+            op: Operation::Deposit,
+            client,
+            tx,
+            amount: Some(dec!(1)),
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_tx_order(TxOrder::Reject);
+        engine.process(deposit(1, 5)).unwrap();
+        engine.process(deposit(2, 3)).unwrap();
+        assert_eq!(
+            engine.process(deposit(1, 4)),
+            Err(TransactionError::OutOfOrder)
+        );
+        engine.process(deposit(1, 6)).unwrap();
+        assert_eq!(engine.accounts()[&1].total, dec!(2));
+
+        let mut engine = Engine::new().with_tx_order(TxOrder::Flag);
+        engine.process(deposit(1, 5)).unwrap();
+        assert!(!engine.out_of_order());
+        engine.process(deposit(1, 4)).unwrap();
+        assert!(engine.out_of_order());
+        engine.process(deposit(1, 6)).unwrap();
+        assert!(!engine.out_of_order());
+        assert_eq!(engine.report().flagged, 1);
+    }
+
+    #[test]
+    fn checks_timestamps_and_tracks_activity() {
+        let deposit = |tx, timestamp| Transaction {
+            op: Operation::Deposit,
+            client: 1,
+            tx,
+            amount: Some(dec!(1)),
+            timestamp,
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_timestamp_order(TxOrder::Reject);
+        engine.process(deposit(1, Some(200))).unwrap();
+        engine.process(deposit(2, Some(200))).unwrap();
+        assert_eq!(
+            engine.process(deposit(3, Some(100))),
+            Err(TransactionError::TimestampOutOfOrder)
+        );
+        engine.process(deposit(4, None)).unwrap();
+        engine.process(deposit(5, Some(300))).unwrap();
+        let account = &engine.accounts()[&1];
+        assert_eq!(account.total, dec!(4));
+        assert_eq!(
+            (account.first_activity, account.last_activity),
+            (Some(200), Some(300))
+        );
+        // disputes keep the timestamp of the transaction they refer to
+        let dispute = Transaction {
+            op: Operation::Dispute,
+            amount: None,
+            ..deposit(1, Some(400))
+        };
+        engine.process(dispute).unwrap();
+        assert_eq!(engine.history.get(&(1, 1)).unwrap().timestamp, Some(200));
+
+        let mut engine = Engine::new().with_timestamp_order(TxOrder::Flag);
+        engine.process(deposit(1, Some(200))).unwrap();
+        engine.process(deposit(2, Some(100))).unwrap();
+        assert!(engine.out_of_order());
+        let account = &engine.accounts()[&1];
+        assert_eq!(
+            (account.first_activity, account.last_activity),
+            (Some(100), Some(200))
+        );
+        assert_eq!(engine.report().flagged, 1);
+    }
+
+    #[test]
+    fn rejects_disputes_outside_the_window() {
+        const DAY: u64 = 24 * 60 * 60;
+        let transaction = |op, tx, timestamp| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount: Some(dec!(10)),
+            timestamp,
+            ..Default::default()
+        };
+        let dispute = |tx, timestamp| Transaction {
+            amount: None,
+            ..transaction(Operation::Dispute, tx, timestamp)
+        };
+        let mut engine = Engine::new().with_dispute_window(Duration::from_secs(90 * DAY));
+        engine
+            .process(transaction(Operation::Deposit, 1, Some(0)))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Deposit, 2, Some(20 * DAY)))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Deposit, 3, None))
+            .unwrap();
+
+        assert_eq!(
+            engine.process(dispute(1, Some(91 * DAY))),
+            Err(TransactionError::DisputeWindowExpired)
+        );
+        engine.process(dispute(2, Some(91 * DAY))).unwrap();
+        // undated transactions can always be disputed
+        engine.process(dispute(3, Some(91 * DAY))).unwrap();
+        assert_eq!(engine.accounts()[&1].held, dec!(20));
+
+        // previews check it too
+        assert_eq!(
+            engine.preview(&dispute(1, Some(91 * DAY))),
+            Err(TransactionError::DisputeWindowExpired)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn checks_the_window_against_mapped_histories() {
+        const DAY: u64 = 24 * 60 * 60;
+        let path = std::env::temp_dir().join(format!("bank-window-{}.bin", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let mut engine = Engine::new()
+            .with_history(History::mapped(&path).unwrap())
+            .with_dispute_window(Duration::from_secs(90 * DAY));
+        engine
+            .process(Transaction {
+                op: Operation::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(dec!(10)),
+                timestamp: Some(0),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine.process(Transaction {
+                op: Operation::Dispute,
+                client: 1,
+                tx: 1,
+                timestamp: Some(91 * DAY),
+                ..Default::default()
+            }),
+            Err(TransactionError::DisputeWindowExpired)
+        );
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn captures_authorizations() {
+        let transaction = |op, tx, amount| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount,
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_invariant_checks();
+        engine
+            .process(transaction(Operation::Deposit, 1, Some(dec!(100))))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Authorize, 2, Some(dec!(60))))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Authorize, 3, Some(dec!(30))))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Authorize, 4, Some(dec!(20)))),
+            Err(TransactionError::InsufficientFunds)
+        );
+        let account = &engine.accounts()[&1];
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (dec!(10), dec!(90), dec!(100))
+        );
+
+        // a partial capture releases the rest of the authorization
+        assert_eq!(
+            engine.process(transaction(Operation::Capture, 2, Some(dec!(70)))),
+            Err(TransactionError::CaptureExceedsAuthorization)
+        );
+        engine
+            .process(transaction(Operation::Capture, 2, Some(dec!(45))))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Capture, 2, None)),
+            Err(TransactionError::AlreadyCaptured)
+        );
+        engine
+            .process(transaction(Operation::Capture, 3, None))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Capture, 1, None)),
+            Err(TransactionError::TransactionNotFound)
+        );
+        let account = &engine.accounts()[&1];
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (dec!(25), dec!(0), dec!(25))
+        );
+
+        // open authorizations can't be disputed, captures can like withdrawals
+        engine
+            .process(transaction(Operation::Authorize, 5, Some(dec!(5))))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 5, None)),
+            Err(TransactionError::TransactionNotFound)
+        );
+        engine
+            .process(transaction(Operation::Dispute, 2, None))
+            .unwrap();
+        assert_eq!(engine.accounts()[&1].held, dec!(50));
+        assert!(engine.invariant_violation().is_none());
+    }
+
+    // a store that keeps transactions in client and tx order, as a database index would
+    struct OrderedStore(std::collections::BTreeMap<(ClientId, u32), Node>);
+
+    impl TxStore for OrderedStore {
+        fn get(&self, key: &(ClientId, u32)) -> Option<Node> {
+            self.0.get(key).cloned()
+        }
+        fn insert(&mut self, key: (ClientId, u32), node: Node) -> Option<Node> {
+            self.0.insert(key, node)
+        }
+        fn remove(&mut self, key: &(ClientId, u32)) -> Option<Node> {
+            self.0.remove(key)
+        }
+        fn iter(&self) -> Box<dyn Iterator<Item = ((ClientId, u32), Node)> + '_> {
+            Box::new(self.0.iter().map(|(key, node)| (*key, node.clone())))
+        }
+    }
+
+    #[test]
+    fn keeps_history_in_a_pluggable_store() {
+        let store = OrderedStore(Default::default());
+        let mut engine = Engine::new().with_history(History::with_store(Box::new(store)));
+        for (op, tx) in [
+            (Operation::Deposit, 2),
+            (Operation::Deposit, 1),
+            (Operation::Dispute, 2),
+        ] {
+            let amount = (op == Operation::Deposit).then_some(dec!(3));
+            let transaction = Transaction {
+                op,
+                client: 1,
+                tx,
+                amount,
+                ..Default::default()
+            };
+            engine.process(transaction).unwrap();
+        }
+
+        let keys: Vec<_> = engine.history().iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![(1, 1), (1, 2)]);
+        let disputed = engine.history().get(&(1, 2)).unwrap();
+        assert_eq!(disputed.dispute_state(), DisputeState::Disputed);
+        assert_eq!(engine.accounts()[&1].held, dec!(3));
+    }
+
+    // a repository that only keeps a few accounts, as a cache in front of a database would
+    #[derive(Default)]
+    struct Slots(Vec<Account>);
+
+    impl AccountRepository for Slots {
+        fn get(&self, client: &ClientId) -> Option<Account> {
+            self.0.iter().find(|act| act.client == *client).cloned()
+        }
+        fn insert(&mut self, account: Account) {
+            self.0.retain(|act| act.client != account.client);
+            self.0.push(account);
+        }
+    }
+
+    #[test]
+    fn runs_tasks_against_an_account_repository() {
+        let mut history = History::new();
+        let mut accounts = Slots::default();
+        let transfer = Transaction {
+            op: Operation::Transfer,
+            client: 1,
+            tx: 2,
+            amount: Some(dec!(4)),
+            counterparty: Some(2),
+            ..Default::default()
+        };
+        let deposit = Transaction {
+            op: Operation::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(dec!(10)),
+            ..Default::default()
+        };
+        Task::new(&mut history, &mut accounts, deposit)
+            .run()
+            .unwrap();
+        Task::new(&mut history, &mut accounts, transfer.clone())
+            .run()
+            .unwrap();
+        let overdrawn = Transaction {
+            tx: 3,
+            amount: Some(dec!(7)),
+            ..transfer
+        };
+        assert_eq!(
+            Task::new(&mut history, &mut accounts, overdrawn).run(),
+            Err(TransactionError::InsufficientFunds)
+        );
+
+        assert_eq!(accounts.get(&1).unwrap().available, dec!(6));
+        assert_eq!(accounts.get(&2).unwrap().available, dec!(4));
+    }
+
+    #[test]
+    fn disputes_only_deposits_under_the_deposits_only_policy() {
+        let transaction = |op, client, tx, amount| Transaction {
+            op,
+            client,
+            tx,
+            amount,
+            counterparty: Some(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_dispute_policy(DisputePolicy::DepositsOnly);
+        for tx in [
+            transaction(Operation::Deposit, 1, 1, Some(dec!(10))),
+            transaction(Operation::Withdrawal, 1, 2, Some(dec!(2))),
+            transaction(Operation::Transfer, 1, 3, Some(dec!(3))),
+        ] {
+            engine.process(tx).unwrap();
+        }
+
+        for (client, tx) in [(1, 2), (1, 3)] {
+            assert_eq!(
+                engine.process(transaction(Operation::Dispute, client, tx, None)),
+                Err(TransactionError::OperationNotDisputable)
+            );
+        }
+        // the deposit and the receiving side of the transfer
+        for (client, tx) in [(1, 1), (2, 3)] {
+            engine
+                .process(transaction(Operation::Dispute, client, tx, None))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn validates_amounts_before_applying_them() {
+        let transaction = |op, tx, amount| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            counterparty: Some(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_amount_precision(AmountPrecision::Reject);
+        for op in [
+            Operation::Deposit,
+            Operation::Withdrawal,
+            Operation::Transfer,
+        ] {
+            assert_eq!(
+                engine.process(transaction(op, 1, dec!(-5))),
+                Err(TransactionError::NegativeAmount)
+            );
+        }
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 1, dec!(1.23456))),
+            Err(TransactionError::ExcessPrecision)
+        );
+        // trailing zeros aren't decimals
+        engine
+            .process(transaction(Operation::Deposit, 1, dec!(1.500000)))
+            .unwrap();
+        assert_eq!(engine.accounts().get(&1).unwrap().total, dec!(1.5));
+
+        // amounts are rounded unless they're to be rejected
+        let mut engine = Engine::new();
+        engine
+            .process(transaction(Operation::Deposit, 1, dec!(1.23455)))
+            .unwrap();
+        assert_eq!(engine.accounts().get(&1).unwrap().total, dec!(1.2346));
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 2, dec!(-1.23455))),
+            Err(TransactionError::NegativeAmount)
+        );
+    }
+
+    #[test]
+    fn overdraws_accounts_up_to_their_credit_limit() {
+        let transaction = |op, client, tx, amount| Transaction {
+            op,
+            client,
+            tx,
+            amount: Some(amount),
+            counterparty: Some(3),
+            ..Default::default()
+        };
+        let mut accounts = AccountStore::new();
+        accounts.insert(2, Account::new(2));
+        let limits = CreditLimits::new().global(dec!(50)).client(2, dec!(100));
+        let mut engine = Engine::with_accounts(accounts).with_credit_limits(limits);
+        assert_eq!(engine.accounts()[&2].credit_limit, Some(dec!(100)));
+
+        engine
+            .process(transaction(Operation::Deposit, 1, 1, dec!(10)))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Withdrawal, 1, 2, dec!(55)))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Withdrawal, 1, 3, dec!(5.01))),
+            Err(TransactionError::CreditLimitExceeded)
+        );
+        engine
+            .process(transaction(Operation::Transfer, 2, 4, dec!(100)))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Transfer, 2, 5, dec!(1))),
+            Err(TransactionError::CreditLimitExceeded)
+        );
+
+        let accounts = engine.accounts();
+        assert_eq!(accounts[&1].available, dec!(-45));
+        assert_eq!(accounts[&1].credit_limit, Some(dec!(50)));
+        assert_eq!(accounts[&2].total, dec!(-100));
+        // the recipient of the transfer gets the global limit
+        assert_eq!(accounts[&3].credit_limit, Some(dec!(50)));
+        // without a limit withdrawals stop at the available funds as before
+        assert_eq!(
+            Engine::new().process(transaction(Operation::Withdrawal, 1, 1, dec!(1))),
+            Err(TransactionError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn rejects_other_clients_transactions_with_global_ids() {
+        let transaction = |op, client, tx| Transaction {
+            op,
+            client,
+            tx,
+            amount: Some(dec!(5)),
+            ..Default::default()
+        };
+        let mut engine = Engine::new().keep_undo(1).with_global_tx_ids();
+        engine
+            .process(transaction(Operation::Deposit, 1, 1))
+            .unwrap();
+
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 2, 1)),
+            Err(TransactionError::ClientMismatch)
+        );
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 2, 1)),
+            Err(TransactionError::ClientMismatch)
+        );
+        engine
+            .process(transaction(Operation::Dispute, 1, 1))
+            .unwrap();
+
+        // a rolled back transaction frees its id
+        engine
+            .process(transaction(Operation::Deposit, 1, 2))
+            .unwrap();
+        engine.rollback(1);
+        engine
+            .process(transaction(Operation::Deposit, 2, 2))
+            .unwrap();
+
+        // without global ids the dispute just doesn't find the transaction
+        let mut engine = Engine::new();
+        engine
+            .process(transaction(Operation::Deposit, 1, 1))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 2, 1)),
+            Err(TransactionError::TransactionNotFound)
+        );
+    }
+
+    #[test]
+    fn runs_over_minor_units() {
+        use crate::domain::amount::MinorUnits;
+
+        let transaction = |op, client, tx, amount: Option<i64>| Transaction {
+            op,
+            client,
+            tx,
+            amount: amount.map(MinorUnits),
+            ..Default::default()
+        };
+        let mut engine = Engine::<MinorUnits>::new()
+            .with_fees(FeeModel::new().deposit("0.1+1%".parse().unwrap()))
+            .with_chargeback_fee(MinorUnits(5_000))
+            .with_balance_caps(BalanceCaps::new().global(MinorUnits(1_000_000)))
+            .with_invariant_checks();
+
+        engine
+            .process(transaction(Operation::Deposit, 1, 1, Some(100_000)))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Deposit, 1, 2, Some(50_000)))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Dispute, 1, 2, None))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Chargeback, 1, 2, None))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 2, 3, Some(1_000_001))),
+            Err(TransactionError::BalanceCapExceeded)
+        );
+
+        // 10 and 5 deposited with 0.1 + 1% fees, and a 0.5 fee for the chargeback
+        let act = &engine.accounts()[&1];
+        assert_eq!(act.total, MinorUnits(150_000 - 2_000 - 1_500 - 5_000));
+        assert_eq!(act.fees, Some(MinorUnits(2_000 + 1_500 + 5_000)));
+        assert!(act.locked);
+        assert_eq!(engine.invariant_violation(), None);
+    }
+
+    #[test]
+    fn rejects_amounts_beyond_the_amount_type() {
+        use crate::domain::amount::MinorUnits;
+
+        let transaction = |op, tx, amount: Option<i64>| Transaction {
+            op,
+            client: 1,
+            tx,
+            amount: amount.map(MinorUnits),
+            ..Default::default()
+        };
+        let mut engine = Engine::<MinorUnits>::new().keep_undo(4);
+        engine
+            .process(transaction(Operation::Deposit, 1, Some(i64::MAX)))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 2, Some(1))),
+            Err(TransactionError::AmountOverflow)
+        );
+        engine
+            .process(transaction(Operation::Withdrawal, 3, Some(i64::MAX)))
+            .unwrap();
+        // disputing the withdrawal holds its amount on top of the new deposit
+        engine
+            .process(transaction(Operation::Deposit, 4, Some(1)))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 3, None)),
+            Err(TransactionError::AmountOverflow)
+        );
+
+        let act = &engine.accounts()[&1];
+        assert_eq!(
+            (act.available, act.held, act.total),
+            (MinorUnits(1), MinorUnits(0), MinorUnits(1))
+        );
+        assert_eq!(engine.history().get(&(1, 2)), None);
+        assert_eq!(
+            engine.history().get(&(1, 3)).unwrap().op,
+            Operation::Withdrawal
+        );
+    }
 }
@@ -0,0 +1,228 @@
+use std::future::Future;
+
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{self, Server};
+use tonic::{Request, Response, Status};
+
+use crate::domain::{errors::TransactionError, Transaction};
+use crate::proto::{self, pb, ProtoError};
+use crate::shared::SharedEngine;
+
+pub use pb::processor_client::ProcessorClient;
+pub use pb::processor_server::ProcessorServer;
+
+// The `bank.Processor` service of proto/processor.proto over a `SharedEngine`, so every
+// transaction runs through the same tasks as a batch run. Applying one only waits for its
+// shards' locks, which are never held across IO, so calls apply it on the runtime's threads.
+#[derive(Debug, Clone)]
+pub struct ProcessorService {
+    engine: SharedEngine,
+}
+
+impl ProcessorService {
+    pub fn new(engine: SharedEngine) -> Self {
+        Self { engine }
+    }
+}
+
+#[tonic::async_trait]
+impl pb::processor_server::Processor for ProcessorService {
+    async fn submit_transaction(
+        &self,
+        request: Request<pb::Transaction>,
+    ) -> Result<Response<pb::Account>, Status> {
+        let transaction = Transaction::try_from(request.into_inner()).map_err(invalid)?;
+        let client = transaction.client;
+        self.engine.process(transaction).map_err(status)?;
+        let account = self
+            .engine
+            .account(client)
+            .expect("Applied to the client's account");
+        Ok(Response::new((&account).try_into().map_err(invalid)?))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<pb::GetAccountRequest>,
+    ) -> Result<Response<pb::Account>, Status> {
+        let client = proto::client_id(request.into_inner().client).map_err(invalid)?;
+        let account = self
+            .engine
+            .account(client)
+            .ok_or_else(|| Status::not_found("No account for the client"))?;
+        Ok(Response::new((&account).try_into().map_err(invalid)?))
+    }
+
+    async fn list_accounts(
+        &self,
+        _: Request<pb::ListAccountsRequest>,
+    ) -> Result<Response<pb::ListAccountsResponse>, Status> {
+        let accounts = self.engine.accounts();
+        let mut sorted: Vec<_> = accounts.values().collect();
+        sorted.sort_by_key(|act| act.client);
+        let accounts = sorted
+            .into_iter()
+            .map(pb::Account::try_from)
+            .collect::<Result<_, _>>()
+            .map_err(invalid)?;
+        Ok(Response::new(pb::ListAccountsResponse { accounts }))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<pb::GetTransactionRequest>,
+    ) -> Result<Response<pb::HistoryEntry>, Status> {
+        let request = request.into_inner();
+        let client = proto::client_id(request.client).map_err(invalid)?;
+        let node = self
+            .engine
+            .transaction(client, request.tx)
+            .ok_or_else(|| status(TransactionError::TransactionNotFound))?;
+        Ok(Response::new(pb::HistoryEntry {
+            client: request.client,
+            tx: request.tx,
+            r#type: node.op.name().to_string(),
+            amount: node.amount.map(|amount| amount.to_string()),
+            disputes: u32::from(node.disputes),
+            counterparty: node
+                .counterparty
+                .map(proto::message_id)
+                .transpose()
+                .map_err(invalid)?,
+            timestamp: node.timestamp,
+        }))
+    }
+}
+
+// Serves the engine on `incoming` until `shutdown` completes, finishing the calls in flight
+pub async fn serve(
+    incoming: TcpIncoming,
+    engine: SharedEngine,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), transport::Error> {
+    Server::builder()
+        .add_service(ProcessorServer::new(ProcessorService::new(engine)))
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
+
+fn invalid(error: ProtoError) -> Status {
+    Status::invalid_argument(error.to_string())
+}
+
+// Rejections keep their `TransactionError` code at the start of the message, under the gRPC code
+// closest to why they were rejected
+pub fn status(error: TransactionError) -> Status {
+    let message = format!("{}: {error}", error.code());
+    match error {
+        TransactionError::TransactionNotFound => Status::not_found(message),
+        TransactionError::OutOfOrder | TransactionError::FeeIdTaken => {
+            Status::already_exists(message)
+        }
+        TransactionError::InsufficientFunds
+        | TransactionError::LockedAccount
+        | TransactionError::BalanceCapExceeded
+        | TransactionError::DisputeLimitReached
+        | TransactionError::AlreadyDisputed
+        | TransactionError::NotUnderDispute
+        | TransactionError::AlreadyChargedBack
+        | TransactionError::CreditLimitExceeded
+        | TransactionError::TimestampOutOfOrder
+        | TransactionError::DisputeWindowExpired
+        | TransactionError::AlreadyCaptured
+        | TransactionError::CaptureExceedsAuthorization => Status::failed_precondition(message),
+        TransactionError::UnspecifiedBehavior
+        | TransactionError::OperationNotDisputable
+        | TransactionError::ClientMismatch
+        | TransactionError::NegativeAmount
+        | TransactionError::ExcessPrecision
+        | TransactionError::MissingCurrency
+        | TransactionError::CurrencyMismatch
+        | TransactionError::AmountOverflow => Status::invalid_argument(message),
+        TransactionError::InvariantViolation => Status::internal(message),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use tokio::runtime;
+    use tokio::sync::oneshot;
+    use tonic::Code;
+
+    use super::*;
+
+    fn transaction(op: &str, tx: u32, amount: Option<&str>) -> pb::Transaction {
+        pb::Transaction {
+            r#type: op.to_string(),
+            client: 1,
+            tx,
+            amount: amount.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn serves_the_engine_over_grpc() {
+        let runtime = runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            let address = incoming.local_addr().unwrap();
+            let (stop, stopped) = oneshot::channel::<()>();
+            let engine = SharedEngine::new(2);
+            let server = tokio::spawn(serve(incoming, engine.clone(), async {
+                stopped.await.ok();
+            }));
+
+            let mut client = ProcessorClient::connect(format!("http://{address}"))
+                .await
+                .unwrap();
+            let account = client
+                .submit_transaction(transaction("deposit", 1, Some("10")))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(account.total, "10");
+            let rejected = client
+                .submit_transaction(transaction("withdrawal", 2, Some("25")))
+                .await
+                .unwrap_err();
+            assert_eq!(rejected.code(), Code::FailedPrecondition);
+            assert!(rejected.message().starts_with("insufficient_funds"));
+            client
+                .submit_transaction(transaction("dispute", 1, None))
+                .await
+                .unwrap();
+
+            let account = client
+                .get_account(pb::GetAccountRequest { client: 1 })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                (account.available.as_str(), account.held.as_str()),
+                ("0", "10")
+            );
+            let missing = client
+                .get_account(pb::GetAccountRequest { client: 2 })
+                .await
+                .unwrap_err();
+            assert_eq!(missing.code(), Code::NotFound);
+            let listed = client
+                .list_accounts(pb::ListAccountsRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(listed.accounts, vec![account]);
+            let entry = client
+                .get_transaction(pb::GetTransactionRequest { client: 1, tx: 1 })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!((entry.r#type.as_str(), entry.disputes), ("dispute", 1));
+
+            stop.send(()).unwrap();
+            server.await.unwrap().unwrap();
+            assert_eq!(engine.accounts().len(), 1);
+        });
+    }
+}
@@ -0,0 +1,177 @@
+use std::future::Future;
+use std::io;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+
+use crate::domain::{errors::TransactionError, Account, ClientId};
+use crate::input;
+use crate::shared::SharedEngine;
+
+// Body of every error response. `code` is the `TransactionError` code of a rejection, or one of
+// `malformed` and `account_not_found`.
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    error: String,
+}
+
+fn error(status: StatusCode, code: &'static str, error: String) -> Response {
+    (status, Json(ErrorBody { code, error })).into_response()
+}
+
+// Rejections are answered with the HTTP status closest to why they were rejected
+fn rejection(e: TransactionError) -> Response {
+    let status = match e {
+        TransactionError::TransactionNotFound => StatusCode::NOT_FOUND,
+        TransactionError::OutOfOrder | TransactionError::FeeIdTaken => StatusCode::CONFLICT,
+        TransactionError::InsufficientFunds
+        | TransactionError::LockedAccount
+        | TransactionError::BalanceCapExceeded
+        | TransactionError::DisputeLimitReached
+        | TransactionError::AlreadyDisputed
+        | TransactionError::NotUnderDispute
+        | TransactionError::AlreadyChargedBack
+        | TransactionError::CreditLimitExceeded
+        | TransactionError::TimestampOutOfOrder
+        | TransactionError::DisputeWindowExpired
+        | TransactionError::AlreadyCaptured
+        | TransactionError::CaptureExceedsAuthorization => StatusCode::UNPROCESSABLE_ENTITY,
+        TransactionError::UnspecifiedBehavior
+        | TransactionError::OperationNotDisputable
+        | TransactionError::ClientMismatch
+        | TransactionError::NegativeAmount
+        | TransactionError::ExcessPrecision
+        | TransactionError::MissingCurrency
+        | TransactionError::CurrencyMismatch
+        | TransactionError::AmountOverflow => StatusCode::BAD_REQUEST,
+        TransactionError::InvariantViolation => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error(status, e.code(), e.to_string())
+}
+
+// POST /transactions takes a transaction in the JSON lines format and answers with the client's
+// account after it
+async fn submit(State(engine): State<SharedEngine>, body: String) -> Response {
+    let transaction = match input::parse_json(&body) {
+        Ok(transaction) => transaction,
+        // serde's message can echo raw field values, only its position is kept
+        Err(e) => {
+            let at = format!("Malformed transaction at column {}", e.column());
+            return error(StatusCode::BAD_REQUEST, "malformed", at);
+        }
+    };
+    let client = transaction.client;
+    match engine.process(transaction) {
+        Ok(()) => Json(
+            engine
+                .account(client)
+                .expect("Applied to the client's account"),
+        )
+        .into_response(),
+        Err(e) => rejection(e),
+    }
+}
+
+async fn account(State(engine): State<SharedEngine>, Path(client): Path<ClientId>) -> Response {
+    match engine.account(client) {
+        Some(account) => Json(account).into_response(),
+        None => error(
+            StatusCode::NOT_FOUND,
+            "account_not_found",
+            format!("No account for client {client}"),
+        ),
+    }
+}
+
+// Ordered by client id like the JSON output
+async fn accounts(State(engine): State<SharedEngine>) -> Json<Vec<Account>> {
+    let mut accounts: Vec<Account> = engine.accounts().into_values().collect();
+    accounts.sort_by_key(|act| act.client);
+    Json(accounts)
+}
+
+// The REST API over a `SharedEngine`, every transaction runs through the same tasks as a batch run
+pub fn router(engine: SharedEngine) -> Router {
+    Router::new()
+        .route("/transactions", post(submit))
+        .route("/accounts", get(accounts))
+        .route("/accounts/{client}", get(account))
+        .with_state(engine)
+}
+
+// Serves the API on `listener` until `shutdown` completes, finishing the requests in flight
+pub async fn serve(
+    listener: TcpListener,
+    engine: SharedEngine,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    axum::serve(listener, router(engine))
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+#[cfg(test)]
+pub mod test {
+    use axum::body::{self, Body};
+    use axum::http::Request;
+    use serde_json::Value;
+    use tokio::runtime;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn call(router: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn serves_transactions_and_accounts_over_http() {
+        let router = router(SharedEngine::new(2));
+        let runtime = runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let deposit = r#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}"#;
+            let (status, account) = call(&router, "POST", "/transactions", deposit).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(account["total"], "10.5");
+
+            let overdraft = r#"{"type":"withdrawal","client":1,"tx":2,"amount":20}"#;
+            let (status, body) = call(&router, "POST", "/transactions", overdraft).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["code"], "insufficient_funds");
+
+            let (status, body) = call(&router, "POST", "/transactions", "{\"type\":").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["code"], "malformed");
+
+            let dispute = r#"{"type":"dispute","client":1,"tx":9}"#;
+            let (status, body) = call(&router, "POST", "/transactions", dispute).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body["code"], "transaction_not_found");
+
+            let (status, account) = call(&router, "GET", "/accounts/1", "").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(account["available"], "10.5");
+            let (status, body) = call(&router, "GET", "/accounts/2", "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body["code"], "account_not_found");
+            let (_, accounts) = call(&router, "GET", "/accounts", "").await;
+            assert_eq!(accounts.as_array().unwrap().len(), 1);
+        });
+    }
+}
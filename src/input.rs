@@ -0,0 +1,613 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use csv::StringRecord;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tracing::warn;
+
+#[cfg(feature = "avro")]
+use crate::avro::{AvroError, AvroRecords};
+use crate::domain::transaction::Operation;
+use crate::domain::Transaction;
+#[cfg(feature = "protobuf")]
+use crate::proto::Messages;
+
+#[derive(Error, Debug)]
+pub enum InputError {
+    // `error` can echo raw field values, the message only shows the line
+    #[error("Malformed record at line {line}")]
+    Malformed { line: u64, error: String },
+    #[error("Unknown operation {op} in tx {tx}")]
+    UnknownOperation { op: String, tx: u32 },
+    #[error("Invalid unknown operation policy {0}, expected skip, quarantine:<path> or error")]
+    Policy(String),
+    #[error("Failed to read input: {0}")]
+    Read(csv::Error),
+    #[error("Failed to read input: {0}")]
+    Io(std::io::Error),
+    #[error("Invalid input format {0}, expected one of {}", FORMATS.join(", "))]
+    Format(String),
+    #[error("Failed to quarantine record: {0}")]
+    Quarantine(csv::Error),
+    #[error("Invalid permission {0}, expected <source>=<operation>[+<operation>...]")]
+    Permission(String),
+    #[cfg(feature = "avro")]
+    #[error("Failed to read Avro input: {0}")]
+    Avro(#[from] AvroError),
+    #[error("Source {origin} may not issue {op} in tx {tx}")]
+    Denied { origin: String, op: String, tx: u32 },
+}
+
+// A record that couldn't be read as a transaction, or an error that ends the input
+impl From<csv::Error> for InputError {
+    fn from(e: csv::Error) -> Self {
+        match e.position() {
+            Some(pos) => InputError::Malformed {
+                line: pos.line(),
+                error: e.to_string(),
+            },
+            None => InputError::Read(e),
+        }
+    }
+}
+
+// Columns of the input format, anything else ends up in `Transaction::extra`
+const COLUMNS: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "counterparty",
+    "currency",
+    "timestamp",
+];
+
+// Reads transactions from a CSV file with headers, keeping the values of columns outside the
+// input format by header name. Empty values are left out.
+pub struct Transactions<R> {
+    reader: csv::Reader<R>,
+    headers: StringRecord,
+    // index and name of every column outside the format
+    extra: Vec<(usize, String)>,
+    record: StringRecord,
+}
+
+impl<R: Read> Transactions<R> {
+    pub fn new(mut reader: csv::Reader<R>) -> Result<Self, csv::Error> {
+        let headers = reader.headers()?.clone();
+        let extra = headers
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| (idx, name.trim()))
+            .filter(|(_, name)| !COLUMNS.contains(name))
+            .map(|(idx, name)| (idx, name.to_string()))
+            .collect();
+        Ok(Self {
+            reader,
+            headers,
+            extra,
+            record: StringRecord::new(),
+        })
+    }
+
+    // Bytes read so far, headers included
+    pub fn bytes(&self) -> u64 {
+        self.reader.position().byte()
+    }
+
+    // Line the last record started on
+    pub fn line(&self) -> u64 {
+        self.record.position().map_or(0, |pos| pos.line())
+    }
+}
+
+impl<R: Read> Iterator for Transactions<R> {
+    type Item = Result<Transaction, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+        let mut transaction: Transaction = match self.record.deserialize(Some(&self.headers)) {
+            Ok(transaction) => transaction,
+            Err(e) => return Some(Err(e)),
+        };
+        for (idx, name) in self.extra.iter() {
+            match self.record.get(*idx).map(str::trim) {
+                Some(value) if !value.is_empty() => {
+                    transaction.extra.insert(name.clone(), value.to_string());
+                }
+                _ => {}
+            }
+        }
+        Some(Ok(transaction))
+    }
+}
+
+// Reads transactions from newline-delimited JSON objects with the fields of the CSV format, e.g.
+// `{"type":"deposit","client":1,"tx":1,"amount":"10.5"}`. Amounts may be strings or numbers,
+// other fields with a value end up in `Transaction::extra` like extra CSV columns. Blank lines
+// are skipped.
+pub struct JsonLines<R> {
+    reader: BufReader<R>,
+    line: u64,
+    bytes: u64,
+    buf: String,
+}
+
+impl<R: Read> JsonLines<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            reader: BufReader::new(input),
+            line: 0,
+            bytes: 0,
+            buf: String::new(),
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl<R: Read> Iterator for JsonLines<R> {
+    type Item = Result<Transaction, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(read) => self.bytes += read as u64,
+                Err(e) => return Some(Err(InputError::Io(e))),
+            }
+            self.line += 1;
+            let record = self.buf.trim();
+            if record.is_empty() {
+                continue;
+            }
+            return Some(parse_json(record).map_err(|e| InputError::Malformed {
+                line: self.line,
+                error: e.to_string(),
+            }));
+        }
+    }
+}
+
+// One JSON lines record, which is also the body `http` takes transactions in
+pub(crate) fn parse_json(record: &str) -> Result<Transaction, serde_json::Error> {
+    let mut fields: Map<String, Value> = serde_json::from_str(record)?;
+    let mut extra = Vec::new();
+    fields.retain(|name, value| {
+        if COLUMNS.contains(&name.as_str()) {
+            return true;
+        }
+        match value {
+            Value::Null => {}
+            Value::String(value) if value.trim().is_empty() => {}
+            Value::String(value) => extra.push((name.clone(), value.trim().to_string())),
+            value => extra.push((name.clone(), value.to_string())),
+        }
+        false
+    });
+    let mut transaction = Transaction::deserialize(Value::Object(fields))?;
+    transaction.extra.extend(extra);
+    Ok(transaction)
+}
+
+// Input path that reads transactions from stdin, e.g. at the end of a shell pipeline
+pub const STDIN: &str = "-";
+
+// Opens an input file, or stdin for `STDIN`. Stdin is opened as a file of its own rather than
+// through std's buffered handle, so callers can wait on it like any other file.
+pub fn open(path: &Path) -> std::io::Result<File> {
+    if path == Path::new(STDIN) {
+        return Ok(File::from(std::io::stdin().as_fd().try_clone_to_owned()?));
+    }
+    File::open(path)
+}
+
+// Names of the formats `InputFormat::from_str` takes
+const FORMATS: &[&str] = &[
+    "csv",
+    "jsonl",
+    #[cfg(feature = "protobuf")]
+    "protobuf",
+    #[cfg(feature = "avro")]
+    "avro",
+];
+
+// Encoding of an input file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    JsonLines,
+    // length-delimited `bank.Transaction` messages, see `proto`
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+    // an object container file, see `avro`
+    #[cfg(feature = "avro")]
+    Avro,
+}
+
+impl InputFormat {
+    // Guessed from the extension: `.jsonl` and `.ndjson` files are JSON lines, anything else CSV
+    pub fn detect(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "ndjson") => InputFormat::JsonLines,
+            #[cfg(feature = "protobuf")]
+            Some("pb") => InputFormat::Protobuf,
+            #[cfg(feature = "avro")]
+            Some("avro") => InputFormat::Avro,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = InputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" => Ok(InputFormat::JsonLines),
+            #[cfg(feature = "protobuf")]
+            "protobuf" => Ok(InputFormat::Protobuf),
+            #[cfg(feature = "avro")]
+            "avro" => Ok(InputFormat::Avro),
+            _ => Err(InputError::Format(s.to_string())),
+        }
+    }
+}
+
+// Transactions read from an input in either format
+pub enum Records<R: Read> {
+    Csv(Transactions<R>),
+    JsonLines(JsonLines<R>),
+    #[cfg(feature = "protobuf")]
+    Protobuf(Messages<R>),
+    #[cfg(feature = "avro")]
+    Avro(AvroRecords<R>),
+}
+
+impl<R: Read> Records<R> {
+    pub fn new(input: R, format: InputFormat) -> Result<Self, InputError> {
+        Ok(match format {
+            InputFormat::Csv => Records::Csv(Transactions::new(csv::Reader::from_reader(input))?),
+            InputFormat::JsonLines => Records::JsonLines(JsonLines::new(input)),
+            #[cfg(feature = "protobuf")]
+            InputFormat::Protobuf => Records::Protobuf(Messages::new(input)),
+            #[cfg(feature = "avro")]
+            InputFormat::Avro => Records::Avro(AvroRecords::new(input)?),
+        })
+    }
+
+    pub fn bytes(&self) -> u64 {
+        match self {
+            Records::Csv(reader) => reader.bytes(),
+            Records::JsonLines(reader) => reader.bytes(),
+            #[cfg(feature = "protobuf")]
+            Records::Protobuf(reader) => reader.bytes(),
+            #[cfg(feature = "avro")]
+            Records::Avro(reader) => reader.bytes(),
+        }
+    }
+
+    pub fn line(&self) -> u64 {
+        match self {
+            Records::Csv(reader) => reader.line(),
+            Records::JsonLines(reader) => reader.line(),
+            #[cfg(feature = "protobuf")]
+            Records::Protobuf(reader) => reader.line(),
+            #[cfg(feature = "avro")]
+            Records::Avro(reader) => reader.line(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = Result<Transaction, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Records::Csv(reader) => reader.next().map(|record| record.map_err(InputError::from)),
+            Records::JsonLines(reader) => reader.next(),
+            #[cfg(feature = "protobuf")]
+            Records::Protobuf(reader) => reader.next(),
+            #[cfg(feature = "avro")]
+            Records::Avro(reader) => reader.next(),
+        }
+    }
+}
+
+// Which operations each source of transactions may issue, e.g. that only the admin socket's
+// reader may send chargebacks. Without any entries every source may issue anything; once a
+// source has an entry, sources without one may issue nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Permissions {
+    allowed: HashMap<String, Vec<Operation>>,
+}
+
+impl Permissions {
+    // Adds the operations of a `<source>=<operation>[+<operation>...]` entry
+    pub fn allow(&mut self, spec: &str) -> Result<(), InputError> {
+        let invalid = || InputError::Permission(spec.to_string());
+        let (source, ops) = spec.split_once('=').ok_or_else(invalid)?;
+        if source.is_empty() {
+            return Err(invalid());
+        }
+        let ops = ops
+            .split('+')
+            .map(|name| match Operation::from_name(name) {
+                Operation::Unknown(_) => Err(invalid()),
+                op => Ok(op),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.allowed
+            .entry(source.to_string())
+            .or_default()
+            .extend(ops);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty()
+    }
+
+    pub fn check(&self, source: &str, transaction: &Transaction) -> Result<(), InputError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        match self.allowed.get(source) {
+            Some(ops) if ops.contains(&transaction.op) => Ok(()),
+            _ => Err(InputError::Denied {
+                origin: source.to_string(),
+                op: transaction.op.name().to_string(),
+                tx: transaction.tx,
+            }),
+        }
+    }
+}
+
+// What happens to records whose type isn't an operation this version knows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum UnknownPolicy {
+    // logged as a warning and dropped
+    #[default]
+    Skip,
+    // written to a CSV file in the input format, e.g. to replay once the type is supported
+    Quarantine(PathBuf),
+    // fails the run before any output is written
+    Error,
+}
+
+impl FromStr for UnknownPolicy {
+    type Err = InputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "skip" => Ok(UnknownPolicy::Skip),
+            None if s == "error" => Ok(UnknownPolicy::Error),
+            Some(("quarantine", path)) if !path.is_empty() => {
+                Ok(UnknownPolicy::Quarantine(path.into()))
+            }
+            _ => Err(InputError::Policy(s.to_string())),
+        }
+    }
+}
+
+// Counts the operations read and keeps unknown ones away from the engine according to the policy
+pub struct OperationFilter {
+    policy: UnknownPolicy,
+    quarantine: Option<csv::Writer<File>>,
+    // records read by type name
+    counts: BTreeMap<String, u64>,
+}
+
+impl OperationFilter {
+    pub fn new(policy: UnknownPolicy) -> Result<Self, InputError> {
+        let quarantine = match &policy {
+            UnknownPolicy::Quarantine(path) => {
+                Some(csv::Writer::from_path(path).map_err(InputError::Quarantine)?)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            policy,
+            quarantine,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    // Whether the transaction goes on to the engine
+    pub fn admit(&mut self, transaction: &Transaction) -> Result<bool, InputError> {
+        let name = transaction.op.name();
+        match self.counts.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                self.counts.insert(name.to_string(), 1);
+            }
+        }
+        let Operation::Unknown(op) = &transaction.op else {
+            return Ok(true);
+        };
+        match (&self.policy, &mut self.quarantine) {
+            (UnknownPolicy::Error, _) => {
+                return Err(InputError::UnknownOperation {
+                    op: op.clone(),
+                    tx: transaction.tx,
+                })
+            }
+            (_, Some(writer)) => writer
+                .serialize(transaction)
+                .map_err(InputError::Quarantine)?,
+            _ => {
+                warn!(op = %op, tx = transaction.tx, "Skipping transaction with unknown operation")
+            }
+        }
+        Ok(false)
+    }
+
+    // Flushes the quarantine and returns the number of records read of every type, unknown ones
+    // included
+    pub fn finish(self) -> Result<BTreeMap<String, u64>, InputError> {
+        if let Some(mut writer) = self.quarantine {
+            writer
+                .flush()
+                .map_err(|e| InputError::Quarantine(e.into()))?;
+        }
+        Ok(self.counts)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::{Extra, Operation};
+
+    #[test]
+    fn keeps_columns_outside_the_format() {
+        let input = "type,client,merchant,tx,amount,reference\n\
+                     deposit,1,acme,1,2.5,r-1\n\
+                     withdrawal,1,,2,1,\n";
+        let reader = csv::Reader::from_reader(input.as_bytes());
+
+        let transactions: Vec<Transaction> = Transactions::new(reader)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(transactions[0].op, Operation::Deposit);
+        assert_eq!(transactions[0].amount, Some(dec!(2.5)));
+        assert_eq!(
+            transactions[0].extra,
+            [("merchant", "acme"), ("reference", "r-1")]
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .into_iter()
+                .collect::<Extra>()
+        );
+        assert_eq!(transactions[1].tx, 2);
+        assert!(transactions[1].extra.is_empty());
+    }
+
+    #[test]
+    fn reads_json_lines() {
+        let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.5\",\"ref\":7}\n\
+                     \n\
+                     {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n\
+                     {\"type\":\"deposit\",\"client\":\"one\"}\n\
+                     {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":2.25}\n";
+        let mut reader = Records::new(input.as_bytes(), InputFormat::JsonLines).unwrap();
+        let records: Vec<_> = reader.by_ref().collect();
+
+        let deposit = records[0].as_ref().unwrap();
+        assert_eq!(deposit.amount, Some(dec!(10.5)));
+        assert_eq!(deposit.extra.get("ref").map(String::as_str), Some("7"));
+        assert_eq!(records[1].as_ref().unwrap().amount, None);
+        assert!(matches!(
+            records[2],
+            Err(InputError::Malformed { line: 4, .. })
+        ));
+        assert_eq!(records[3].as_ref().unwrap().amount, Some(dec!(2.25)));
+        assert_eq!(reader.bytes(), input.len() as u64);
+        assert_eq!(
+            InputFormat::detect(Path::new("in/day.ndjson")),
+            InputFormat::JsonLines
+        );
+    }
+
+    #[test]
+    fn applies_the_unknown_operation_policy() {
+        let input = "type,client,tx,amount,currency,timestamp\n\
+                     deposit,1,1,2.5,USD,1700000001\n\
+                     refund,1,2,1,EUR,1700000002\n\
+                     deposit,1,3,1,USD,1700000003\n";
+        let read = || {
+            Transactions::new(csv::Reader::from_reader(input.as_bytes()))
+                .unwrap()
+                .map(Result::unwrap)
+        };
+        let path = std::env::temp_dir().join(format!("bank-quarantine-{}.csv", std::process::id()));
+
+        let mut filter = OperationFilter::new(UnknownPolicy::Quarantine(path.clone())).unwrap();
+        let admitted: Vec<u32> = read()
+            .filter(|transaction| filter.admit(transaction).unwrap())
+            .map(|transaction| transaction.tx)
+            .collect();
+        let counts = filter.finish().unwrap();
+
+        assert_eq!(admitted, vec![1, 3]);
+        assert_eq!(counts["deposit"], 2);
+        assert_eq!(counts["refund"], 1);
+        let quarantined = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            quarantined,
+            "type,client,tx,amount,counterparty,currency,timestamp\nrefund,1,2,1,,EUR,1700000002\n"
+        );
+        // quarantined records replay as input, currency and timestamp included
+        let replayed: Vec<Transaction> =
+            Transactions::new(csv::Reader::from_reader(quarantined.as_bytes()))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(replayed[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(replayed[0].timestamp, Some(1_700_000_002));
+        std::fs::remove_file(path).ok();
+
+        let mut filter = OperationFilter::new("error".parse().unwrap()).unwrap();
+        let errors: Vec<_> = read().filter_map(|tx| filter.admit(&tx).err()).collect();
+        assert!(matches!(
+            errors[..],
+            [InputError::UnknownOperation { tx: 2, .. }]
+        ));
+        assert!("quarantine:".parse::<UnknownPolicy>().is_err());
+    }
+
+    #[test]
+    fn checks_operations_per_source() {
+        let transaction = |op| Transaction {
+            op,
+            client: 1,
+            tx: 7,
+            ..Default::default()
+        };
+        let mut permissions = Permissions::default();
+        assert!(permissions
+            .check("file", &transaction(Operation::Chargeback))
+            .is_ok());
+
+        permissions.allow("file=deposit+withdrawal").unwrap();
+        permissions.allow("admin=chargeback").unwrap();
+
+        assert!(permissions
+            .check("file", &transaction(Operation::Deposit))
+            .is_ok());
+        assert!(matches!(
+            permissions.check("file", &transaction(Operation::Chargeback)),
+            Err(InputError::Denied { tx: 7, .. })
+        ));
+        assert!(permissions
+            .check("admin", &transaction(Operation::Chargeback))
+            .is_ok());
+        assert!(permissions
+            .check("partner", &transaction(Operation::Deposit))
+            .is_err());
+        assert!(permissions.allow("file=refund").is_err());
+        assert!(permissions.allow("=deposit").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+use crate::domain::{transaction::Operation, Account, Amount, Transaction};
+use crate::engine::Engine;
+
+// An account invariant that didn't hold after a transaction was applied, with the accounts the
+// transaction touched as they were before and after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvariantViolation<A = Decimal> {
+    pub rule: &'static str,
+    pub transaction: Transaction<A>,
+    pub before: Vec<Account<A>>,
+    pub after: Vec<Account<A>>,
+}
+
+impl<A> fmt::Display for InvariantViolation<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invariant violated by {} tx {}: {}",
+            self.transaction.op.name(),
+            self.transaction.tx,
+            self.rule
+        )
+    }
+}
+
+impl<A: fmt::Debug> std::error::Error for InvariantViolation<A> {}
+
+// What a transaction is expected to do to the accounts it touches, taken before it's applied.
+// Checked afterwards:
+//  - total == available + held for every touched account
+//  - held never goes negative
+//  - the touched accounts' totals change by exactly what the transaction moves: a deposit adds
+//    its amount, a withdrawal and a fee take it away, a transfer and its disputes move it between
+//    the same two accounts, and other disputes shift funds the way the engine books them, so the
+//    sum of all totals reconciles with the net of the applied transactions
+//  - available funds stay >= 0, or >= -credit_limit for an account with an overdraft, when the
+//    transaction lowered them. Disputes, chargebacks and fees are exempt: disputing a deposit
+//    that was already spent and charging fees legitimately take available funds negative.
+// Sums beyond the range of the amount type count as not reconciling.
+pub(crate) struct Check<A> {
+    transaction: Transaction<A>,
+    before: Vec<Account<A>>,
+    expected: Option<A>,
+}
+
+impl<A: Amount> Check<A> {
+    pub(crate) fn new(engine: &Engine<A>, transaction: &Transaction<A>) -> Self {
+        let counterparty = engine.counterparty(transaction);
+        let clients = std::iter::once(transaction.client).chain(counterparty);
+        let before = clients
+            .map(|client| {
+                engine
+                    .accounts()
+                    .get(&client)
+                    .cloned()
+                    .unwrap_or_else(|| Account::new(client))
+            })
+            .collect();
+        let amount = transaction.amount.unwrap_or_default();
+        // a dispute, resolve or chargeback applies the amount of the transaction it refers to,
+        // negated for deposits
+        let disputed = || {
+            let node = engine.history().get(&(transaction.client, transaction.tx));
+            let amount = node
+                .as_ref()
+                .and_then(|node| node.amount)
+                .unwrap_or_default();
+            match node.map(|node| node.op) {
+                Some(Operation::Deposit) => amount.checked_neg(),
+                _ => Some(amount),
+            }
+        };
+        let zero = A::zero();
+        let expected = match transaction.op {
+            // a dispute of a transfer moves funds between its two accounts at most
+            Operation::Dispute | Operation::Resolve | Operation::Chargeback
+                if counterparty.is_some() =>
+            {
+                Some(zero)
+            }
+            Operation::Deposit => Some(amount),
+            Operation::Withdrawal | Operation::Fee => amount.checked_neg(),
+            Operation::Transfer | Operation::Unknown(_) => Some(zero),
+            Operation::Dispute => disputed().map(|amount| max(amount, zero)),
+            Operation::Resolve => disputed().map(|amount| min(amount, zero)),
+            Operation::Chargeback => disputed().and_then(|amount| max(amount, zero).checked_neg()),
+            // an authorization only holds funds, its capture takes them
+            Operation::Authorize => Some(zero),
+            Operation::Capture => transaction
+                .amount
+                .or_else(disputed)
+                .and_then(A::checked_neg),
+        };
+        Self {
+            transaction: transaction.clone(),
+            before,
+            expected,
+        }
+    }
+
+    // The invariant the applied transaction broke, if any
+    pub(crate) fn verify(self, engine: &Engine<A>) -> Option<InvariantViolation<A>> {
+        let after: Vec<Account<A>> = self
+            .before
+            .iter()
+            .map(|act| {
+                engine
+                    .accounts()
+                    .get(&act.client)
+                    .cloned()
+                    .unwrap_or_else(|| Account::new(act.client))
+            })
+            .collect();
+        // a chargeback may have incurred a fee
+        let fee = engine
+            .assessed_fee()
+            .and_then(|fee| fee.amount)
+            .unwrap_or_default();
+        let moved = self
+            .before
+            .iter()
+            .zip(&after)
+            .try_fold(A::zero(), |moved, (before, act)| {
+                moved.checked_add(act.total.checked_sub(before.total)?)
+            });
+        let expected = self.expected.and_then(|expected| expected.checked_sub(fee));
+        let rule = if after
+            .iter()
+            .any(|act| act.available.checked_add(act.held) != Some(act.total))
+        {
+            "total == available + held"
+        } else if after.iter().any(|act| act.held < A::zero()) {
+            "held >= 0"
+        } else if moved.is_none() || moved != expected {
+            "totals change by the amount the transaction moves"
+        } else if self.overdrawn(&after) {
+            "available >= 0, or >= -credit_limit with an overdraft"
+        } else {
+            return None;
+        };
+        Some(InvariantViolation {
+            rule,
+            transaction: self.transaction,
+            before: self.before,
+            after,
+        })
+    }
+
+    // Whether the transaction took an account's available funds below what its overdraft allows
+    fn overdrawn(&self, after: &[Account<A>]) -> bool {
+        if matches!(
+            self.transaction.op,
+            Operation::Dispute | Operation::Chargeback | Operation::Fee
+        ) {
+            return false;
+        }
+        self.before.iter().zip(after).any(|(before, act)| {
+            let floor = act.credit_limit.unwrap_or_default().checked_neg();
+            act.available < before.available && floor.is_none_or(|floor| act.available < floor)
+        })
+    }
+}
+
+// `Ord::max` and `min` for amounts, which are only `PartialOrd`
+fn max<A: Amount>(lhs: A, rhs: A) -> A {
+    if lhs < rhs {
+        rhs
+    } else {
+        lhs
+    }
+}
+
+fn min<A: Amount>(lhs: A, rhs: A) -> A {
+    if rhs < lhs {
+        rhs
+    } else {
+        lhs
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::{errors::TransactionError, AccountStore};
+    use crate::engine::ErrorPolicy;
+
+    #[test]
+    fn checks_every_applied_transaction() {
+        let transaction = |op, client, tx, amount| Transaction {
+            op,
+            client,
+            tx,
+            amount,
+            counterparty: Some(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::new()
+            .with_chargeback_fee(dec!(1.5))
+            .with_error_policy(ErrorPolicy::Strict)
+            .with_invariant_checks();
+        let report = engine.process_all(vec![
+            transaction(Operation::Deposit, 1, 1, Some(dec!(10))),
+            transaction(Operation::Withdrawal, 1, 2, Some(dec!(4))),
+            transaction(Operation::Transfer, 1, 3, Some(dec!(3))),
+            transaction(Operation::Dispute, 1, 2, None),
+            transaction(Operation::Resolve, 1, 2, None),
+            transaction(Operation::Dispute, 2, 3, None),
+            transaction(Operation::Chargeback, 2, 3, None),
+        ]);
+        assert_eq!(report.applied, 7);
+        assert_eq!(engine.invariant_violation(), None);
+
+        // balances that were already off before the engine started
+        let mut accounts = AccountStore::new();
+        let broken = Account {
+            client: 1,
+            available: dec!(12),
+            held: dec!(-2),
+            total: dec!(10),
+            ..Account::new(1)
+        };
+        accounts.insert(1, broken);
+        let mut engine = Engine::with_accounts(accounts).with_invariant_checks();
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 1, 1, Some(dec!(1)))),
+            Err(TransactionError::InvariantViolation)
+        );
+        let violation = engine.invariant_violation().unwrap();
+        assert_eq!(violation.rule, "held >= 0");
+        assert_eq!(violation.before[0].total, dec!(10));
+        assert_eq!(violation.after[0].total, dec!(11));
+    }
+
+    #[test]
+    fn catches_overdrawn_available_funds() {
+        let withdrawal = |tx, amount| Transaction {
+            op: Operation::Withdrawal,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            ..Default::default()
+        };
+        // available funds that don't match the total, so a withdrawal the account seems to cover
+        // takes it negative
+        let broken = |credit_limit| {
+            let mut accounts = AccountStore::new();
+            let act = Account {
+                available: dec!(10),
+                total: dec!(3),
+                credit_limit,
+                ..Account::new(1)
+            };
+            accounts.insert(1, act);
+            Engine::with_accounts(accounts).with_invariant_checks()
+        };
+
+        let mut engine = broken(None);
+        assert_eq!(
+            engine.process(withdrawal(1, dec!(8))),
+            Err(TransactionError::InvariantViolation)
+        );
+        let violation = engine.invariant_violation().unwrap();
+        assert_eq!(
+            violation.rule,
+            "available >= 0, or >= -credit_limit with an overdraft"
+        );
+        assert_eq!(violation.after[0].available, dec!(-5));
+
+        // within the overdraft
+        let mut engine = broken(Some(dec!(5)));
+        assert_eq!(engine.process(withdrawal(1, dec!(8))), Ok(()));
+        assert_eq!(engine.invariant_violation(), None);
+        assert_eq!(
+            engine.process(withdrawal(2, dec!(1))),
+            Err(TransactionError::CreditLimitExceeded)
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use thiserror::Error;
+
+use crate::calendar::Calendar;
+use crate::domain::Transaction;
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("Failed to write journal: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to encode journal record: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Can't rewind a journal to a mark from an earlier day's file")]
+    Rewind,
+}
+
+// How hard the journal works to keep records across a crash. From slowest to fastest:
+//  - Record fsyncs every record, so nothing acknowledged is lost even on power failure. Each
+//    record costs a disk flush, typically a few thousand records per second at most.
+//  - Group fsyncs once every n records. A power failure loses at most the last n - 1 records,
+//    a process crash loses the ones still buffered. Throughput approaches Os as n grows.
+//  - Os hands every record to the OS but never fsyncs. Survives a process crash, but a power
+//    failure loses whatever the OS hasn't written back yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Durability {
+    Record,
+    Group(usize),
+    Os,
+}
+
+impl Default for Durability {
+    fn default() -> Self {
+        Durability::Group(1024)
+    }
+}
+
+// Append-only log of the transactions handed to the engine, written before they're applied.
+// Records use the input CSV format, so a journal can be fed back in as input to rebuild state.
+pub struct Journal {
+    writer: csv::Writer<File>,
+    durability: Durability,
+    unsynced: usize,
+    daily: Option<Daily>,
+    // files started by a daily journal, so marks in earlier ones can be told apart
+    files: u64,
+}
+
+// Position in a journal taken with `Journal::mark`, e.g. next to an engine savepoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JournalMark {
+    file: u64,
+    len: u64,
+}
+
+// Splits a journal into one file per business day
+struct Daily {
+    path: PathBuf,
+    calendar: Calendar,
+    day: String,
+}
+
+impl Journal {
+    pub fn create(path: &Path, durability: Durability) -> Result<Self, JournalError> {
+        Ok(Self {
+            writer: csv::Writer::from_path(path)?,
+            durability,
+            unsynced: 0,
+            daily: None,
+            files: 0,
+        })
+    }
+
+    // Continues a journal left by an earlier run, appending after the records already in it
+    pub fn reopen(path: &Path, durability: Durability) -> Result<Self, JournalError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        Ok(Self {
+            writer: csv::WriterBuilder::new()
+                .has_headers(empty)
+                .from_writer(file),
+            durability,
+            unsynced: 0,
+            daily: None,
+            files: 0,
+        })
+    }
+
+    // Writes each business day's records to its own file, named by inserting the day before the
+    // extension of `path`, e.g. journal-2024-02-29.csv. Records go to the day they are appended
+    // on and a new file is started with the first record after a cutoff.
+    pub fn create_daily(
+        path: &Path,
+        durability: Durability,
+        calendar: Calendar,
+    ) -> Result<Self, JournalError> {
+        let day = calendar.business_day(SystemTime::now());
+        let mut journal = Self::create(&day_path(path, &day), durability)?;
+        journal.daily = Some(Daily {
+            path: path.to_path_buf(),
+            calendar,
+            day,
+        });
+        Ok(journal)
+    }
+
+    pub fn append(&mut self, transaction: &Transaction) -> Result<(), JournalError> {
+        let next = self.daily.as_ref().and_then(|daily| {
+            let day = daily.calendar.business_day(SystemTime::now());
+            (day != daily.day).then(|| (day_path(&daily.path, &day), day))
+        });
+        if let Some((path, day)) = next {
+            self.sync()?;
+            self.writer = csv::Writer::from_path(path)?;
+            self.files += 1;
+            self.daily
+                .as_mut()
+                .expect("Only set for daily journals")
+                .day = day;
+        }
+        self.writer.serialize(transaction)?;
+        self.unsynced += 1;
+        match self.durability {
+            Durability::Record => self.sync(),
+            Durability::Group(size) if self.unsynced >= size => self.sync(),
+            Durability::Group(_) => Ok(()),
+            Durability::Os => Ok(self.writer.flush()?),
+        }
+    }
+
+    // The position after the records appended so far
+    pub fn mark(&mut self) -> Result<JournalMark, JournalError> {
+        self.writer.flush()?;
+        Ok(JournalMark {
+            file: self.files,
+            len: self.writer.get_ref().metadata()?.len(),
+        })
+    }
+
+    // Cuts off every record appended after `mark`, for transactions the engine rolled back.
+    // A daily journal can only be rewound within the day's file.
+    pub fn rewind(&mut self, mark: JournalMark) -> Result<(), JournalError> {
+        if mark.file != self.files {
+            return Err(JournalError::Rewind);
+        }
+        self.writer.flush()?;
+        let mut file = self.writer.get_ref();
+        file.set_len(mark.len)?;
+        file.seek(SeekFrom::Start(mark.len))?;
+        // the header goes with the records when the journal was empty
+        if mark.len == 0 {
+            self.writer = csv::Writer::from_writer(file.try_clone()?);
+        }
+        self.sync()
+    }
+
+    // Forces everything appended so far to disk
+    pub fn sync(&mut self) -> Result<(), JournalError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+// Reads back the records of a journal, e.g. after a crash, to replay them before processing
+// anything new. A crash can leave the last record partly written, which could still parse as a
+// different transaction, so a last record without its line ending is dropped and cut off the
+// file for `Journal::reopen` to append after the complete ones. The reader is flexible since a
+// partly written record can have fewer fields than the header. A missing journal is empty.
+//
+// Every record is taken to have been applied for good, the journal has no record of rollbacks.
+// An engine rolled back after writing to its journal has to `rewind` the journal to the mark
+// taken with the savepoint as well, otherwise recovering reapplies what was undone.
+pub fn recover(path: &Path) -> Result<Vec<Transaction>, JournalError> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(data.as_slice());
+    let headers = reader.headers()?.clone();
+    let mut transactions = vec![];
+    let mut end = reader.position().byte();
+    let mut record = csv::StringRecord::new();
+    while reader.read_record(&mut record)? {
+        let next = reader.position().byte();
+        if data[next as usize - 1] != b'\n' {
+            break;
+        }
+        transactions.push(record.deserialize(Some(&headers))?);
+        end = next;
+    }
+    if end < data.len() as u64 {
+        OpenOptions::new().write(true).open(path)?.set_len(end)?;
+    }
+    Ok(transactions)
+}
+
+pub fn day_path(path: &Path, day: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{day}"));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{env, fs};
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::Operation;
+    use crate::engine::Engine;
+
+    #[test]
+    fn journal_replays_as_input() {
+        let path = env::temp_dir().join(format!("bank-journal-{}.csv", std::process::id()));
+        let transactions = vec![
+            Transaction {
+                op: Operation::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(dec!(2.5)),
+                timestamp: Some(1_709_164_800),
+                ..Default::default()
+            },
+            Transaction {
+                op: Operation::Dispute,
+                client: 1,
+                tx: 1,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+
+        let mut journal = Journal::create(&path, Durability::Group(8)).expect("Journal created");
+        for transaction in transactions.iter() {
+            journal.append(transaction).expect("Record appended");
+        }
+        journal.sync().expect("Journal synced");
+
+        let replayed: Vec<Transaction> = csv::Reader::from_path(&path)
+            .expect("Journal readable")
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .expect("Journal parses as input");
+        assert_eq!(replayed, transactions);
+        fs::remove_file(path).ok();
+    }
+    #[test]
+    fn recovers_complete_records_and_appends_after_them() {
+        let path = env::temp_dir().join(format!("bank-recover-{}.csv", std::process::id()));
+        let deposit = |tx| Transaction {
+            op: Operation::Deposit,
+            client: 1,
+            tx,
+            amount: Some(dec!(10)),
+            timestamp: Some(1_709_164_800 + u64::from(tx)),
+            ..Default::default()
+        };
+        assert!(recover(&path).expect("Missing journal is empty").is_empty());
+
+        let mut journal = Journal::reopen(&path, Durability::Record).expect("Journal created");
+        journal.append(&deposit(1)).expect("Record appended");
+        drop(journal);
+        // a crash in the middle of the second record
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        std::io::Write::write_all(&mut file, b"deposit,1,2,1").unwrap();
+
+        assert_eq!(recover(&path).expect("Journal recovered"), vec![deposit(1)]);
+        let mut journal = Journal::reopen(&path, Durability::Record).expect("Journal reopened");
+        journal.append(&deposit(3)).expect("Record appended");
+        assert_eq!(
+            recover(&path).expect("Journal recovered"),
+            vec![deposit(1), deposit(3)]
+        );
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn recovers_what_stood_after_a_rollback() {
+        let path = env::temp_dir().join(format!("bank-rewind-{}.csv", std::process::id()));
+        let deposit = |client, tx| Transaction {
+            op: Operation::Deposit,
+            client,
+            tx,
+            amount: Some(dec!(10)),
+            ..Default::default()
+        };
+        let mut engine = Engine::new();
+        let mut journal = Journal::create(&path, Durability::Os).expect("Journal created");
+        let run = |engine: &mut Engine, journal: &mut Journal, transaction| {
+            journal.append(&transaction).expect("Record appended");
+            engine.process(transaction).ok();
+        };
+
+        // rolled back from the very start, header included
+        let (savepoint, mark) = (engine.savepoint(), journal.mark().unwrap());
+        run(&mut engine, &mut journal, deposit(1, 1));
+        assert!(engine.rollback_to(savepoint));
+        journal.rewind(mark).expect("Journal rewound");
+
+        run(&mut engine, &mut journal, deposit(2, 2));
+        let (savepoint, mark) = (engine.savepoint(), journal.mark().unwrap());
+        run(&mut engine, &mut journal, deposit(3, 3));
+        run(&mut engine, &mut journal, deposit(3, 3));
+        assert!(engine.rollback_to(savepoint));
+        journal.rewind(mark).expect("Journal rewound");
+        run(&mut engine, &mut journal, deposit(4, 4));
+        journal.sync().unwrap();
+
+        let recovered = recover(&path).expect("Journal recovered");
+        assert_eq!(recovered, vec![deposit(2, 2), deposit(4, 4)]);
+        let mut replay = Engine::new();
+        replay.process_all(recovered);
+        assert_eq!(replay.accounts(), engine.accounts());
+        fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::KafkaError as ConsumerError;
+use rdkafka::types::RDKafkaErrorCode;
+use rdkafka::Message as _;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::domain::Transaction;
+use crate::input::{InputError, InputFormat, Records};
+
+#[derive(Error, Debug)]
+pub enum KafkaError {
+    #[error("Invalid Kafka address {0}, expected kafka://<broker>[,<broker>...]/<topic>")]
+    Address(String),
+    #[error("Kafka consumer failed: {0}")]
+    Consumer(#[from] ConsumerError),
+}
+
+// Where transactions are consumed from and how, the address parsed from
+// `kafka://<broker>[,<broker>...]/<topic>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KafkaConfig {
+    // comma separated host:port pairs
+    pub brokers: String,
+    pub topic: String,
+    // consumer group the offsets are committed for, a run continues where the last run of the
+    // same group stopped
+    pub group: String,
+    // encoding of the message payloads, each read like an input file of its own
+    pub format: InputFormat,
+    // keeps waiting for messages once the end of the topic is reached
+    pub follow: bool,
+}
+
+impl FromStr for KafkaConfig {
+    type Err = KafkaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || KafkaError::Address(s.to_string());
+        let (brokers, topic) = s
+            .strip_prefix("kafka://")
+            .and_then(|rest| rest.split_once('/'))
+            .ok_or_else(invalid)?;
+        if brokers.is_empty() || topic.is_empty() || topic.contains('/') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            brokers: brokers.to_string(),
+            topic: topic.to_string(),
+            group: "bank".to_string(),
+            format: InputFormat::JsonLines,
+            follow: false,
+        })
+    }
+}
+
+// A message's place in its topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+// The transactions of one message, with the payload's own line numbers in malformed records
+#[derive(Debug)]
+pub struct Message {
+    pub records: Vec<Result<Transaction, InputError>>,
+    pub position: Position,
+}
+
+#[derive(Debug)]
+pub enum Poll {
+    Message(Message),
+    // nothing arrived in time
+    Idle,
+    // every partition assigned to the consumer was read to its end
+    End,
+}
+
+// Consumes transactions from a Kafka topic as a member of a consumer group. Offsets aren't
+// committed as messages are read: the code applying the transactions hands their positions to
+// `Offsets::applied`, and only those are committed, so a consumer that stops or crashes before
+// applying a message has it delivered again to the next one.
+pub struct KafkaInput {
+    consumer: Arc<BaseConsumer>,
+    config: KafkaConfig,
+    // partitions read to their end since their last message
+    ended: HashSet<i32>,
+    // the error logged last, repeats of it aren't logged until a message arrives
+    failing: Option<ConsumerError>,
+}
+
+impl KafkaInput {
+    pub fn subscribe(config: KafkaConfig) -> Result<Self, KafkaError> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("group.id", &config.group)
+            // a group without committed offsets starts from the beginning of the topic
+            .set("auto.offset.reset", "earliest")
+            // commits in the background are of the offsets stored after applying only
+            .set("enable.auto.offset.store", "false")
+            .set("enable.partition.eof", "true")
+            .create()?;
+        consumer.subscribe(&[&config.topic])?;
+        Ok(Self {
+            consumer: Arc::new(consumer),
+            config,
+            ended: HashSet::new(),
+            failing: None,
+        })
+    }
+
+    // Handle to commit applied messages with, which can be sent to the thread applying them
+    pub fn offsets(&self) -> Offsets {
+        Offsets {
+            consumer: self.consumer.clone(),
+            topic: self.config.topic.clone(),
+            read: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Waits up to `timeout` for the next message. Partitions are only ever at their end when not
+    // following the topic.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Poll, KafkaError> {
+        let message = match self.consumer.poll(timeout) {
+            None => return Ok(Poll::Idle),
+            Some(Err(ConsumerError::PartitionEOF(partition))) => {
+                self.ended.insert(partition);
+                return Ok(match self.at_end()? {
+                    true => Poll::End,
+                    false => Poll::Idle,
+                });
+            }
+            // librdkafka recovers from anything but a fatal error by itself, e.g. reconnects to
+            // a broker that went away
+            Some(Err(e)) if self.consumer.client().fatal_error().is_none() => {
+                if self.failing.as_ref() != Some(&e) {
+                    warn!(error = %e, "Kafka consumer error, retrying");
+                    self.failing = Some(e);
+                }
+                return Ok(Poll::Idle);
+            }
+            Some(Err(e)) => return Err(e.into()),
+            Some(Ok(message)) => message,
+        };
+        self.failing = None;
+        self.ended.remove(&message.partition());
+        Ok(Poll::Message(Message {
+            records: decode(message.payload().unwrap_or_default(), self.config.format),
+            position: Position {
+                partition: message.partition(),
+                offset: message.offset(),
+            },
+        }))
+    }
+
+    fn at_end(&self) -> Result<bool, KafkaError> {
+        if self.config.follow {
+            return Ok(false);
+        }
+        let assignment = self.consumer.assignment()?;
+        let partitions = assignment.elements_for_topic(&self.config.topic);
+        Ok(!partitions.is_empty()
+            && partitions
+                .iter()
+                .all(|partition| self.ended.contains(&partition.partition())))
+    }
+}
+
+// Reads a payload like an input file in `format`, a malformed payload is a malformed record
+pub fn decode(payload: &[u8], format: InputFormat) -> Vec<Result<Transaction, InputError>> {
+    match Records::new(payload, format) {
+        Ok(records) => records.collect(),
+        Err(e) => vec![Err(InputError::Malformed {
+            line: 0,
+            error: e.to_string(),
+        })],
+    }
+}
+
+// Commits the offsets of messages once their transactions were applied. Messages are recorded
+// with `read` in the order their transactions are applied in, along with how many transactions
+// had been read through the end of the message; `applied` then stores the offset of every
+// message whose transactions are all applied, and the consumer commits stored offsets in the
+// background and on `commit`.
+pub struct Offsets {
+    consumer: Arc<BaseConsumer>,
+    topic: String,
+    read: Mutex<VecDeque<(u64, Position)>>,
+}
+
+impl Offsets {
+    pub fn read(&self, through: u64, position: Position) {
+        self.queue().push_back((through, position));
+    }
+
+    // Stores the offsets of the messages within the first `applied` transactions
+    pub fn applied(&self, applied: u64) -> Result<(), KafkaError> {
+        let mut read = self.queue();
+        while let Some((_, position)) = read.front().filter(|(through, _)| *through <= applied) {
+            self.consumer
+                .store_offset(&self.topic, position.partition, position.offset)?;
+            read.pop_front();
+        }
+        Ok(())
+    }
+
+    // Commits the stored offsets and waits for the brokers to take them
+    pub fn commit(&self) -> Result<(), KafkaError> {
+        match self.consumer.commit_consumer_state(CommitMode::Sync) {
+            Err(e) if e.rdkafka_error_code() == Some(RDKafkaErrorCode::NoOffset) => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    fn queue(&self) -> std::sync::MutexGuard<'_, VecDeque<(u64, Position)>> {
+        self.read.lock().expect("Read messages lock poisoned")
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        let config: KafkaConfig = "kafka://k1:9092,k2:9092/payments".parse().unwrap();
+        assert_eq!(config.brokers, "k1:9092,k2:9092");
+        assert_eq!(config.topic, "payments");
+        assert_eq!(config.group, "bank");
+        for address in [
+            "kafka://k1:9092",
+            "kafka:///payments",
+            "tcp://k1:9092/payments",
+        ] {
+            assert!(matches!(
+                address.parse::<KafkaConfig>(),
+                Err(KafkaError::Address(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn decodes_payloads_like_input_files() {
+        let records = decode(
+            br#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}"#,
+            InputFormat::JsonLines,
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].as_ref().unwrap().amount, Some(dec!(10.5)));
+
+        let records = decode(
+            b"type,client,tx,amount\ndeposit,1,2,3\nwithdrawal,1,x,1\n",
+            InputFormat::Csv,
+        );
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].as_ref().unwrap().tx, 2);
+        assert!(matches!(
+            records[1],
+            Err(InputError::Malformed { line: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use rust_decimal::Decimal;
+
+use crate::domain::{transaction::Operation, Account, ClientId, Transaction};
+use crate::engine::Engine;
+
+// Account of the processor's books a posting goes to. Client balances are what the processor
+// owes its clients, so crediting them raises what's owed; the omnibus account holds the funds
+// backing them, and fees are the processor's income.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerAccount {
+    Available(ClientId),
+    Held(ClientId),
+    Omnibus,
+    FeeIncome,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::Available(client) => write!(f, "client:{client}:available"),
+            LedgerAccount::Held(client) => write!(f, "client:{client}:held"),
+            LedgerAccount::Omnibus => f.write_str("omnibus"),
+            LedgerAccount::FeeIncome => f.write_str("fee_income"),
+        }
+    }
+}
+
+// One side of a journal entry, either `debit` or `credit` is zero
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    // entries are numbered from 1 in the order they were posted
+    pub entry: u64,
+    pub tx: u32,
+    pub op: Operation,
+    pub account: LedgerAccount,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+// Balances of the accounts a transaction may touch, taken before it's applied
+pub struct Pending {
+    transaction: Transaction,
+    before: Vec<Account>,
+}
+
+impl Pending {
+    pub fn new(engine: &Engine, transaction: &Transaction) -> Self {
+        let clients = std::iter::once(transaction.client).chain(engine.counterparty(transaction));
+        let before = clients.map(|client| account(engine, client)).collect();
+        Self {
+            transaction: transaction.clone(),
+            before,
+        }
+    }
+}
+
+fn account(engine: &Engine, client: ClientId) -> Account {
+    engine
+        .accounts()
+        .get(&client)
+        .cloned()
+        .unwrap_or_else(|| Account::new(client))
+}
+
+// Records every applied transaction as balanced debit and credit postings, for reconciliation
+// with accounting systems. The postings follow from what the transaction did to the balances:
+// every change to a client's available or held funds is posted to that client's account, and
+// whatever moved in or out of the clients' funds as a whole is balanced against the omnibus
+// account. A fee the transaction incurred is an entry of its own against fee income.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    postings: Vec<Posting>,
+    entries: u64,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Posts the entries of a transaction the engine has just applied
+    pub fn post(&mut self, pending: Pending, engine: &Engine) {
+        let fee = engine
+            .assessed_fee()
+            .map(|fee| (fee.client, fee.tx, fee.amount.unwrap_or_default()));
+        let mut lines = vec![];
+        let mut moved = Decimal::ZERO;
+        for before in pending.before.iter() {
+            let after = account(engine, before.client);
+            let mut available = after.available - before.available;
+            // the fee is posted on its own
+            if let Some((_, _, amount)) = fee.filter(|fee| fee.0 == before.client) {
+                available += amount;
+            }
+            let held = after.held - before.held;
+            lines.push((LedgerAccount::Available(before.client), available));
+            lines.push((LedgerAccount::Held(before.client), held));
+            moved += available + held;
+        }
+        lines.push((LedgerAccount::Omnibus, -moved));
+        self.entry(pending.transaction.tx, &pending.transaction.op, lines);
+
+        if let Some((client, tx, amount)) = fee {
+            let lines = vec![
+                (LedgerAccount::Available(client), -amount),
+                (LedgerAccount::FeeIncome, amount),
+            ];
+            self.entry(tx, &Operation::Fee, lines);
+        }
+    }
+
+    // A positive amount credits the account, a negative one debits it. Accounts the entry
+    // didn't change are left out, and so are entries that didn't change any.
+    fn entry(&mut self, tx: u32, op: &Operation, mut lines: Vec<(LedgerAccount, Decimal)>) {
+        lines.retain(|(_, amount)| !amount.is_zero());
+        if lines.is_empty() {
+            return;
+        }
+        self.entries += 1;
+        for (account, amount) in lines {
+            self.postings.push(Posting {
+                entry: self.entries,
+                tx,
+                op: op.clone(),
+                account,
+                debit: (-amount).max(Decimal::ZERO),
+                credit: amount.max(Decimal::ZERO),
+            });
+        }
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    // Writes the journal as CSV, a row per posting
+    pub fn write_csv<W: Write>(&self, out: W) -> Result<W, csv::Error> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(["entry", "tx", "type", "account", "debit", "credit"])?;
+        for posting in self.postings.iter() {
+            writer.write_record([
+                posting.entry.to_string(),
+                posting.tx.to_string(),
+                posting.op.name().to_string(),
+                posting.account.to_string(),
+                posting.debit.round_dp(4).to_string(),
+                posting.credit.round_dp(4).to_string(),
+            ])?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| io::Error::new(e.error().kind(), e.to_string()).into())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn posts_balanced_entries() {
+        let transaction = |op, client, tx, amount| Transaction {
+            op,
+            client,
+            tx,
+            amount,
+            counterparty: Some(2),
+            ..Default::default()
+        };
+        let mut engine = Engine::new().with_chargeback_fee(dec!(1));
+        let mut ledger = Ledger::new();
+        for tx in [
+            transaction(Operation::Deposit, 1, 1, Some(dec!(10))),
+            transaction(Operation::Transfer, 1, 2, Some(dec!(4))),
+            transaction(Operation::Dispute, 1, 1, None),
+            transaction(Operation::Chargeback, 1, 1, None),
+        ] {
+            let pending = Pending::new(&engine, &tx);
+            engine.process(tx).unwrap();
+            ledger.post(pending, &engine);
+        }
+
+        for entry in 1..=5 {
+            let (debits, credits) = ledger
+                .postings()
+                .iter()
+                .filter(|posting| posting.entry == entry)
+                .fold((Decimal::ZERO, Decimal::ZERO), |(d, c), posting| {
+                    (d + posting.debit, c + posting.credit)
+                });
+            assert_eq!(debits, credits, "entry {entry} is balanced");
+        }
+        let lines = |entry| -> Vec<(String, Decimal, Decimal)> {
+            ledger
+                .postings()
+                .iter()
+                .filter(|posting| posting.entry == entry)
+                .map(|posting| (posting.account.to_string(), posting.debit, posting.credit))
+                .collect()
+        };
+        assert_eq!(
+            lines(1),
+            vec![
+                ("client:1:available".into(), dec!(0), dec!(10)),
+                ("omnibus".into(), dec!(10), dec!(0)),
+            ]
+        );
+        // a transfer moves funds between clients without touching the omnibus account
+        assert_eq!(
+            lines(2),
+            vec![
+                ("client:1:available".into(), dec!(4), dec!(0)),
+                ("client:2:available".into(), dec!(0), dec!(4)),
+            ]
+        );
+        assert_eq!(
+            lines(5),
+            vec![
+                ("client:1:available".into(), dec!(1), dec!(0)),
+                ("fee_income".into(), dec!(0), dec!(1)),
+            ]
+        );
+
+        let out = ledger.write_csv(vec![]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("entry,tx,type,account,debit,credit\n1,1,deposit,"));
+    }
+}
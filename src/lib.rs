@@ -1,2 +1,89 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod core;
 pub mod domain;
-pub mod engine;
\ No newline at end of file
+
+// What embedding code needs to drive the engine without going through the CLI: a `Processor`
+// applies transactions one at a time with `process` and exposes the resulting accounts and history
+pub use crate::core::{errors::TransactionError, Account, Transaction};
+#[cfg(feature = "std")]
+pub use engine::{Engine as Processor, ProcessingReport};
+
+#[cfg(feature = "std")]
+pub mod anonymize;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "tokio")]
+pub mod async_engine;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod invariants;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "std")]
+pub mod multi_currency;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod throttle;
+
+// The CSV pipeline: file IO, sharding, worker threads and logging
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "csv")]
+pub mod calendar;
+#[cfg(feature = "csv")]
+pub mod chaos;
+#[cfg(feature = "archive")]
+pub mod checkpoint;
+#[cfg(feature = "csv")]
+pub mod config;
+#[cfg(feature = "csv")]
+pub mod cluster;
+#[cfg(feature = "csv")]
+pub mod currency;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "csv")]
+pub mod input;
+#[cfg(feature = "csv")]
+pub mod journal;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "csv")]
+pub mod ledger;
+#[cfg(feature = "csv")]
+pub mod output;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+#[cfg(feature = "grpc")]
+pub mod publish;
+#[cfg(feature = "csv")]
+pub mod redact;
+#[cfg(feature = "csv")]
+pub mod rejects;
+#[cfg(feature = "csv")]
+pub mod report;
+#[cfg(feature = "csv")]
+pub mod scheduler;
+#[cfg(feature = "csv")]
+pub mod settings;
+#[cfg(feature = "csv")]
+pub mod shard;
+#[cfg(feature = "csv")]
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "csv")]
+pub mod statement;
+#[cfg(feature = "csv")]
+pub mod trace;
+#[cfg(feature = "csv")]
+pub mod wire;
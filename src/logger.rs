@@ -0,0 +1,119 @@
+use tracing::level_filters::LevelFilter;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    // one JSON object per line, for log pipelines
+    Json,
+}
+
+// Writes log events to stderr so they never mix with the account output on stdout
+pub fn init(level: LevelFilter, format: LogFormat) {
+    let _ = tracing::subscriber::set_global_default(subscriber(level, format, std::io::stderr));
+}
+
+// JSON lines carry the event's fields next to its timestamp, level, target and message, and the
+// fields of the spans it happened in, e.g. the transaction of an engine task
+fn subscriber<W>(
+    level: LevelFilter,
+    format: LogFormat,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_max_level(level)
+        .with_ansi(false);
+    match format {
+        LogFormat::Text => Box::new(builder.without_time().with_target(false).finish()),
+        LogFormat::Json => Box::new(
+            builder
+                .json()
+                .flatten_event(true)
+                .with_current_span(false)
+                .with_span_list(true)
+                .finish(),
+        ),
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    use bank::domain::{transaction::Operation, Transaction};
+    use bank::engine::Engine;
+    use rust_decimal_macros::dec;
+    use serde_json::Value;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn lines(engine: Engine) -> Vec<Value> {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = subscriber(LevelFilter::TRACE, LogFormat::Json, move || writer.clone());
+        let mut engine = engine;
+        tracing::subscriber::with_default(subscriber, || {
+            let _ = engine.process(Transaction {
+                op: Operation::Withdrawal,
+                client: 7,
+                tx: 3,
+                amount: Some(dec!(5)),
+                ..Default::default()
+            });
+        });
+        let output = captured.0.lock().unwrap().clone();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn task_steps_are_logged_in_a_span_of_the_transaction() {
+        let logged = lines(Engine::new());
+
+        let messages: Vec<&str> = logged
+            .iter()
+            .map(|line| line["message"].as_str().unwrap())
+            .collect();
+        assert_eq!(messages, ["Task step", "Task rejected"]);
+        assert_eq!(logged[0]["state"], "Updating");
+        assert_eq!(logged[1]["error"], "Insufficient funds in account");
+        for line in logged.iter() {
+            let span = &line["spans"][0];
+            assert_eq!(span["name"], "task");
+            assert_eq!(
+                (&span["tx"], &span["op"]),
+                (&3.into(), &"withdrawal".into())
+            );
+            assert!(span.get("client").is_none());
+        }
+
+        // the client id only shows up when the engine was asked to log it
+        let logged = lines(Engine::new().with_logged_clients());
+        // ids wider than 64 bits are written as strings
+        let client = logged[0]["spans"][0]["client"].to_string();
+        assert_eq!(client.trim_matches('"'), "7");
+    }
+}
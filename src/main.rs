@@ -1,53 +1,1245 @@
-use bank::domain::Transaction;
-use bank::domain::{History, Account};
-use bank::engine::{Machine, Task};
-use log::error;
-use std::collections::HashMap;
+mod cli;
+mod logger;
+mod signals;
+
+use bank::anonymize::Anonymizer;
+use bank::chaos::{Chaos, Stage};
+use bank::checkpoint::MappedCheckpoint;
+use bank::cluster::{self, ClusterError, Router, Shard};
+use bank::config::{Config, CHANNEL_CAPACITY};
+use bank::domain::{Account, ClientId, History, Transaction};
+use bank::engine::{Engine, ErrorPolicy, FeeModel, Limits};
+use bank::grpc;
+use bank::input::{
+    self, InputError, InputFormat, OperationFilter, Permissions, Records, UnknownPolicy,
+};
+use bank::journal::{self, Journal};
+use bank::kafka::{KafkaConfig, KafkaError, KafkaInput, Offsets, Poll};
+use bank::ledger::{Ledger, Pending};
+use bank::multi_currency::MultiCurrencyEngine;
+use bank::output::{self, OutputFormat};
+use bank::publish::Publisher;
+use bank::redact::Redactor;
+use bank::rejects::Rejects;
+use bank::report::{self, Reporter};
+use bank::scheduler::Scheduler;
+use bank::settings;
+use bank::shard;
+use bank::shared::SharedEngine;
+use bank::snapshot::{HistoryRecord, Snapshot};
+use bank::sqlite;
+use bank::statement::{self, StatementFormat};
+use bank::throttle::Throttle;
+use bank::trace::Tracer;
+use bank::wire::{self, Endpoint, Stream, WireError};
+use cli::{CliError, Command, Options};
+use logger::LogFormat;
 use std::fs::File;
+use std::path::{Path, PathBuf};
+use tokio::signal::unix::{self, SignalKind};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tonic::transport::server::TcpIncoming;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info, warn};
 
 use std::env::args;
-use std::io::Write;
-use std::sync::mpsc::channel;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+// Parallelism used by --verify-determinism when --workers isn't given and the core count is unknown
+const DEFAULT_WORKERS: usize = 4;
+// Added to the number of the signal that interrupted a run for its exit code, as shells do
+const INTERRUPTED: i32 = 128;
+// Sources --allow checks transactions of an input file, of a Kafka topic and of readers that
+// didn't name theirs
+const FILE_SOURCE: &str = "file";
+const KAFKA_SOURCE: &str = "kafka";
+const ANONYMOUS_SOURCE: &str = "anonymous";
+
+// A record handed to the engine with the input line it was read from, None for connections
+type Received = (Option<u64>, Result<Transaction, InputError>);
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = args().collect();
-    let mut history = History::new();
-    let mut accounts = HashMap::<u16, Account>::new();
-
-    let (tx, rx) = channel();
-    let handle = thread::spawn(move || {
-        let tx_file = &args[1];
-        let file = File::open(tx_file).expect("Failed to open file");
-        let mut reader = csv::Reader::from_reader(file);
-        for record in reader.deserialize::<Transaction>() {
-            match record {
-                Ok(out) => tx.send(out).expect("Failed to send record"),
-                Err(e) => error!("Failed to deserialize record: {e}"),
+    let command = cli::parse(args())?;
+    let (log_level, log_format) = match &command {
+        Command::Process(options) => (
+            options.log_level.unwrap_or(LevelFilter::INFO),
+            options.log_format,
+        ),
+        _ => (LevelFilter::INFO, LogFormat::Text),
+    };
+    logger::init(log_level, log_format);
+
+    match command {
+        Command::Process(options) => process(*options),
+        Command::SnapshotDiff { before, after } => snapshot_diff(&before, &after),
+        Command::Query { state, client } => query(&state, client),
+        Command::Explain { input, tx } => explain(&input, tx),
+        Command::Send { input, to, source } => send(&input, &to, source.as_deref()),
+        Command::Route {
+            input,
+            shards,
+            nodes,
+        } => route(&input, &shards, &nodes),
+        Command::Merge { parts, output, sum } => merge(&parts, output.as_deref(), sum),
+        Command::Statement {
+            journals,
+            client,
+            format,
+            output,
+            dir,
+        } => statement(&journals, client, format, output.as_deref(), dir.as_deref()),
+        Command::Serve {
+            listen,
+            http,
+            workers,
+        } => serve(listen, http, workers),
+        Command::Promote { standby } => Ok(standby.connect_as(Stream::Promote)?.finish()?),
+    }
+}
+
+fn process(mut options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = options.config.clone() {
+        configure(
+            &mut options,
+            Config::load_with_env(&path, std::env::vars())?,
+        )?;
+    }
+    let redactor = Redactor::new(options.log_sensitive).echo_columns(options.echo_columns);
+    if options.multi_currency {
+        return process_currencies(&options, &redactor);
+    }
+    options.check().map_err(|e| e.to_string())?;
+
+    let (mut engine, position) = if options.input.is_dir() {
+        let started = Instant::now();
+        let paths = shard::shard_files(&options.input)?;
+        let engine = shard::process_shards(&paths, &redactor)?;
+        let mut report = engine.report();
+        report.duration = started.elapsed();
+        info!(report = ?report, "Processed input");
+        if options.verify_determinism {
+            let mut serial = Engine::new();
+            for path in paths.iter() {
+                shard::apply_file(&mut serial, path, &redactor)?;
+            }
+            verify_determinism(&serial, &engine)?;
+        }
+        (engine, None)
+    } else {
+        process_stream(&options, &redactor)?
+    };
+
+    if let Some(count) = options.rollback {
+        let reversed = engine.rollback(count);
+        info!(
+            requested = count,
+            reversed = reversed,
+            "Rolled back the last applied transactions"
+        );
+    }
+    engine.history().flush()?;
+
+    if let Some(path) = &options.snapshot {
+        Snapshot::new(engine.history(), engine.accounts()).save(path)?;
+    }
+    if let Some(path) = &options.checkpoint {
+        // rolled back transactions were read but aren't in the state anymore
+        match position.filter(|_| options.rollback.is_none()) {
+            Some(position) => engine.checkpoint().at(position).save(path)?,
+            None => engine.checkpoint().save(path)?,
+        }
+    }
+    if let Some(path) = &options.dump_state {
+        engine.dump_state().save(path)?;
+    }
+    if let (Some(path), Some(window)) = (&options.dormant_report, options.dormant_after) {
+        let count = report::write_dormant(&engine, path, window)?;
+        info!(
+            accounts = count,
+            window = window,
+            "Wrote dormant account report"
+        );
+    }
+
+    // a merge rewrites the file it was seeded from unless told otherwise
+    let destination = options.output.as_ref().or(options.merge_into.as_ref());
+    let rounded = options
+        .currency
+        .as_ref()
+        .map(|currency| currency.round_accounts(engine.accounts()));
+    let accounts = rounded.as_ref().unwrap_or(engine.accounts());
+    if let Some(path) = &options.sqlite {
+        let history = Snapshot::new(engine.history(), engine.accounts()).history;
+        sqlite::write(path, accounts.values(), &history)?;
+        info!(
+            accounts = accounts.len(),
+            transactions = history.len(),
+            "Wrote SQLite database"
+        );
+    } else {
+        let inner = match options.format {
+            OutputFormat::Csv => output::write_csv(accounts, vec![])?,
+            OutputFormat::Table => {
+                let color = destination.is_none()
+                    && std::env::var_os("NO_COLOR").is_none()
+                    && std::io::stdout().is_terminal();
+                output::write_table(accounts, vec![], color)?
+            }
+            OutputFormat::Json => output::write_json(accounts, vec![], false)?,
+            OutputFormat::JsonByClient => output::write_json(accounts, vec![], true)?,
+        };
+        let mut chaos = options.chaos.clone().map(Chaos::new);
+        let mut write = |out: &mut dyn Write| -> std::io::Result<()> {
+            let Some(chaos) = &mut chaos else {
+                return out.write_all(&inner);
+            };
+            chaos.io_fault()?;
+            let (head, tail) = inner.split_at(inner.len() / 2);
+            out.write_all(head)?;
+            out.flush()?;
+            chaos.checkpoint(Stage::Output);
+            out.write_all(tail)
+        };
+        match destination {
+            Some(path) => output::write_atomic(path, |file| write(file))?,
+            None => write(&mut std::io::stdout().lock())?,
+        }
+    }
+
+    if let Some(signal) = signals::shutdown() {
+        warn!(
+            signal = signal,
+            "Interrupted, wrote the state of the input read before the signal"
+        );
+        std::io::stdout().flush()?;
+        std::process::exit(INTERRUPTED + signal);
+    }
+    Ok(())
+}
+
+// Takes a configuration file into the options, which mustn't set what it configures as well
+fn configure(options: &mut Options, config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    if options.settings.is_some()
+        || options.strict
+        || options.format != OutputFormat::Csv
+        || options.history.is_some()
+        || options.max_history_mem.is_some()
+    {
+        return Err(
+            "--config replaces --settings, --strict, --format, --history and --max-history-mem"
+                .into(),
+        );
+    }
+    // rejected transactions never stop a run of the CLI, only malformed records do
+    options.strict = config.error_policy == ErrorPolicy::Strict;
+    options.format = config.format;
+    options.history = config.history;
+    options.max_history_mem = config.max_history_mem;
+    options.channel_capacity = Some(config.channel_capacity);
+    options.limits = Some(config.limits).filter(|limits| *limits != Limits::default());
+    Ok(())
+}
+
+// Applies the inputs with balances kept per client and currency, and writes a row for each
+fn process_currencies(
+    options: &Options,
+    redactor: &Redactor,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // everything else works on the single engine of a run
+    let supported = Options {
+        input: options.input.clone(),
+        inputs: options.inputs.clone(),
+        input_format: options.input_format,
+        output: options.output.clone(),
+        format: options.format,
+        settings: options.settings.clone(),
+        config: options.config.clone(),
+        limits: options.limits.clone(),
+        channel_capacity: options.channel_capacity,
+        strict: options.strict,
+        log_sensitive: options.log_sensitive,
+        echo_columns: options.echo_columns,
+        log_format: options.log_format,
+        log_level: options.log_level,
+        multi_currency: true,
+        ..Default::default()
+    };
+    if *options != supported
+        || options.input.is_dir()
+        || !matches!(options.format, OutputFormat::Csv | OutputFormat::Json)
+    {
+        return Err(
+            "--multi-currency only takes input files, --input-format, --settings, --config, \
+             --strict, --output, --format csv or json and the log options"
+                .into(),
+        );
+    }
+    let started = Instant::now();
+    let limits = match (&options.settings, &options.limits) {
+        (Some(path), _) => settings::load(path)?,
+        (None, limits) => limits.clone().unwrap_or_default(),
+    };
+    let mut engine = MultiCurrencyEngine::with_limits(limits);
+    let (mut rows, mut malformed, mut rejected) = (0u64, 0u64, 0u64);
+    for path in std::iter::once(&options.input).chain(options.inputs.iter()) {
+        let format = options
+            .input_format
+            .unwrap_or_else(|| InputFormat::detect(path));
+        let mut reader = Records::new(input::open(path)?, format)?;
+        for record in reader.by_ref() {
+            rows += 1;
+            let record = match record {
+                Ok(record) => record,
+                Err(InputError::Malformed { line, error }) if !options.strict => {
+                    malformed += 1;
+                    match redactor.is_sensitive() {
+                        true => {
+                            error!(line = line, error = %error, "Failed to deserialize record")
+                        }
+                        false => error!(line = line, "Failed to deserialize record"),
+                    }
+                    continue;
+                }
+                Err(e @ InputError::Malformed { .. }) if !redactor.is_sensitive() => {
+                    return Err(e.to_string().into())
+                }
+                Err(e) => return Err(e.into()),
             };
+            let (client, tx) = (record.client, record.tx);
+            let extra = redactor.columns(&record);
+            if let Err(e) = engine.process(record) {
+                rejected += 1;
+                redactor.log_rejection(tx, client, &e, &extra);
+            }
+        }
+    }
+    info!(
+        rows = rows,
+        malformed = malformed,
+        rejected = rejected,
+        duration = ?started.elapsed(),
+        "Processed input"
+    );
+
+    let accounts = engine.accounts();
+    let sorted: Vec<&Account> = accounts.iter().collect();
+    let inner = match options.format {
+        OutputFormat::Json => output::write_json_rows(&sorted, vec![])?,
+        _ => output::write_csv_rows(&sorted, vec![])?,
+    };
+    match &options.output {
+        Some(path) => output::write_atomic(path, |file| file.write_all(&inner))?,
+        None => std::io::stdout().lock().write_all(&inner)?,
+    }
+    Ok(())
+}
+
+// Compares the final state of a serial run against a parallel run of the same input
+fn verify_determinism(
+    serial: &Engine,
+    parallel: &Engine,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let same_output = output::write_csv(serial.accounts(), vec![])?
+        == output::write_csv(parallel.accounts(), vec![])?;
+    let same_state = Snapshot::new(serial.history(), serial.accounts())
+        == Snapshot::new(parallel.history(), parallel.accounts());
+    if !(same_output && same_state) {
+        return Err("Parallel processing diverged from serial processing".into());
+    }
+    info!("Parallel processing matches serial processing");
+    Ok(())
+}
+
+// Returns the engine and how many input records it read, when they were read from files
+fn process_stream(
+    options: &Options,
+    redactor: &Redactor,
+) -> Result<(Engine, Option<u64>), Box<dyn std::error::Error>> {
+    let started = Instant::now();
+    let restored = match options.restore.as_ref().or(options.resume.as_ref()) {
+        Some(path) => Some(MappedCheckpoint::open(path)?),
+        None => None,
+    };
+    // a resumed run skips the records its checkpoint already covers
+    let skip = match restored.as_ref().filter(|_| options.resume.is_some()) {
+        Some(checkpoint) => checkpoint.position().ok_or(
+            "The checkpoint doesn't record an input position, continue from it with --restore",
+        )?,
+        None => 0,
+    };
+    let mut engine = match (&options.merge_into, &restored) {
+        (Some(path), _) => Engine::with_accounts(output::read_csv(File::open(path)?)?),
+        (None, Some(checkpoint)) => Engine::restore(checkpoint),
+        (None, None) => Engine::new(),
+    }
+    .keep_undo(options.rollback.unwrap_or(0));
+    if options.log_sensitive {
+        engine = engine.with_logged_clients();
+    }
+    match (&options.history, options.max_history_mem) {
+        (Some(path), Some(capacity)) => {
+            engine = engine.with_history(History::tiered(path, capacity)?)
+        }
+        (Some(path), None) => engine = engine.with_history(History::mapped(path)?),
+        (None, _) => {}
+    }
+    if let Some(path) = &options.sled_history {
+        engine = engine.with_history(History::sled(path, options.max_history_mem)?);
+    }
+    if let Some(fee) = options.chargeback_fee {
+        engine = engine.with_chargeback_fee(fee);
+    }
+    if !options.fees.is_empty() {
+        engine = engine.with_fees(options.fees.clone());
+    }
+    if !options.balance_caps.is_empty() {
+        let mut caps = options.balance_caps.clone();
+        if let Some(path) = &options.client_tiers {
+            settings::assign_tiers(&mut caps, path)?;
+        }
+        engine = engine.with_balance_caps(caps);
+    }
+    if let Some(limit) = options.dispute_limit {
+        engine = engine.with_dispute_limit(limit);
+    }
+    if let Some(window) = options.dispute_window {
+        engine = engine.with_dispute_window(window);
+    }
+    if let Some(order) = options.tx_order {
+        engine = engine.with_tx_order(order);
+    }
+    if let Some(order) = options.timestamp_order {
+        engine = engine.with_timestamp_order(order);
+    }
+    if let Some(policy) = options.lock_policy {
+        engine = engine.with_lock_policy(policy);
+    }
+    if let Some(policy) = options.dispute_policy {
+        engine = engine.with_dispute_policy(policy);
+    }
+    if let Some(precision) = options.amount_precision {
+        engine = engine.with_amount_precision(precision);
+    }
+    if !options.credit_limits.is_empty() {
+        engine = engine.with_credit_limits(options.credit_limits.clone());
+    }
+    if options.global_tx_ids {
+        engine = engine.with_global_tx_ids();
+    }
+    if options.check_invariants {
+        engine = engine.with_invariant_checks();
+    }
+    if let Some(path) = &options.settings {
+        engine.set_limits(settings::load(path)?);
+    }
+    if let Some(limits) = &options.limits {
+        engine.set_limits(limits.clone());
+    }
+    if let Some(path) = options.journal.as_ref().filter(|_| options.recover) {
+        // fees in the journal were charged when it was written, replaying the transactions
+        // that incurred them mustn't charge them again
+        let limits = engine.limits().clone();
+        engine.set_limits(Limits {
+            chargeback_fee: None,
+            fees: FeeModel::new(),
+            ..limits.clone()
+        });
+        let report = engine.process_all(journal::recover(path)?);
+        engine.set_limits(limits);
+        info!(report = ?report, "Recovered from journal");
+    }
+
+    let (tx, rx) = sync_channel(options.channel_capacity.unwrap_or(CHANNEL_CAPACITY));
+    let tx_files: Vec<PathBuf> = std::iter::once(&options.input)
+        .chain(options.inputs.iter())
+        .cloned()
+        .collect();
+    let input_format = options.input_format;
+    let log_sensitive = redactor.is_sensitive();
+    let mut throttle = options.max_tps.map(Throttle::new);
+    let mut chaos = options.chaos.clone().map(Chaos::new);
+    let mut reader_chaos = chaos.as_ref().map(|chaos| chaos.fork(1));
+    // a kafka:// address consumes them from a topic, committing what the loop below applied
+    let consumer = match options.input.to_str() {
+        Some(input) if input.starts_with("kafka://") => Some(KafkaInput::subscribe(KafkaConfig {
+            group: options
+                .kafka_group
+                .clone()
+                .unwrap_or_else(|| "bank".to_string()),
+            format: input_format.unwrap_or(InputFormat::JsonLines),
+            follow: options.kafka_follow,
+            ..input.parse::<KafkaConfig>()?
+        })?),
+        _ => None,
+    };
+    let offsets = consumer
+        .as_ref()
+        .map(|consumer| Arc::new(consumer.offsets()));
+    // an address instead of a file receives transactions from `bank send` processes
+    let endpoint = match options.input.to_str() {
+        Some(input) if consumer.is_none() && input.contains("://") => {
+            Some(input.parse::<Endpoint>()?)
         }
-    });
+        _ => None,
+    };
+    // an engine listening on an address runs until its readers finish, which can take days
+    let reloadable = endpoint.is_some() && options.settings.is_some();
+    if reloadable {
+        signals::watch_reload();
+    }
+    // `Options::check` leaves a schedule or a cutoff, and a directory for either
+    let mut reporter = match (&options.report_at, &options.calendar, &options.report_dir) {
+        (Some(schedule), _, Some(dir)) => {
+            Some(Reporter::new(schedule.clone(), dir).echo_columns(options.echo_columns))
+        }
+        (None, Some(calendar), Some(dir)) => {
+            Some(Reporter::at_close(calendar.clone(), dir).echo_columns(options.echo_columns))
+        }
+        _ => None,
+    };
+    let readers = options.readers.unwrap_or(1);
+    let standby = options.standby;
+    // records received over connections or from Kafka have no position
+    let from_files = endpoint.is_none() && consumer.is_none();
+    if endpoint.is_none() {
+        signals::watch_shutdown();
+    }
+    let permissions = options.permissions.clone();
+    let handle = match (endpoint, consumer) {
+        // connections don't count the bytes they receive
+        (Some(endpoint), _) => thread::spawn(move || {
+            // one bucket for all connections, the limit is on what reaches the engine
+            let throttle = throttle.map(Mutex::new);
+            let throttle = throttle.as_ref();
+            receive(&endpoint, readers, standby, &permissions, throttle, tx)
+                .map(|()| 0)
+                .map_err(std::io::Error::other)
+        }),
+        // neither do messages
+        (None, Some(mut consumer)) => {
+            let offsets = offsets.clone().expect("Offsets of the consumer");
+            thread::spawn(move || {
+                consume(&mut consumer, &offsets, &permissions, throttle, tx)
+                    .map(|()| 0)
+                    .map_err(std::io::Error::other)
+            })
+        }
+        // several input files are read one after the other, as if they were concatenated
+        (None, None) => thread::spawn(move || -> std::io::Result<u64> {
+            let mut bytes = 0;
+            let mut skip = skip;
+            for tx_file in tx_files {
+                let file = signals::Interruptible::new(input::open(&tx_file)?);
+                let format = input_format.unwrap_or_else(|| InputFormat::detect(&tx_file));
+                let mut reader = Records::new(file, format).map_err(std::io::Error::other)?;
+                while let Some(record) = reader.next() {
+                    // an interrupted run applies and writes what was read before the signal
+                    if signals::shutdown().is_some() {
+                        return Ok(bytes + reader.bytes());
+                    }
+                    let line = reader.line();
+                    if skip > 0 && !matches!(record, Err(InputError::Read(_) | InputError::Io(_))) {
+                        skip -= 1;
+                        continue;
+                    }
+                    if let Some(throttle) = &mut throttle {
+                        throttle.acquire();
+                    }
+                    if let Some(chaos) = &mut reader_chaos {
+                        chaos.io_fault()?;
+                        chaos.checkpoint(Stage::Read);
+                        chaos.delay();
+                    }
+                    let record = match record {
+                        Err(InputError::Read(e)) => return Err(e.into()),
+                        Err(InputError::Io(e)) => return Err(e),
+                        Ok(record) => permissions.check(FILE_SOURCE, &record).map(|()| record),
+                        record => record,
+                    };
+                    // the engine stopped early, e.g. on a malformed record with --strict
+                    if tx.send((Some(line), record)).is_err() {
+                        return Ok(bytes + reader.bytes());
+                    }
+                }
+                bytes += reader.bytes();
+            }
+            Ok(bytes)
+        }),
+    };
+
+    let anonymizer = options
+        .anonymize
+        .as_ref()
+        .map(|key| Anonymizer::new(key).perturb_amounts(options.perturb_amounts));
+    let mut emitter = match &options.emit_transactions {
+        Some(path) => Some(csv::Writer::from_path(path)?),
+        None => None,
+    };
+    let mut journal = match &options.journal {
+        Some(path) => Some(match &options.calendar {
+            Some(calendar) => {
+                Journal::create_daily(path, options.journal_durability, calendar.clone())?
+            }
+            None if options.recover => Journal::reopen(path, options.journal_durability)?,
+            None => Journal::create(path, options.journal_durability)?,
+        }),
+        None => None,
+    };
+    let mut rejects = match &options.rejects {
+        Some(path) => Some(Rejects::create(path)?),
+        None => None,
+    };
+    let mut publisher = match &options.publish_to {
+        Some(address) => Some(Publisher::connect(
+            address.clone(),
+            options.publish.clone(),
+        )?),
+        None => None,
+    };
+    let mut ledger = options.ledger.as_ref().map(|_| Ledger::new());
+    let mut replica = match &options.replicate_to {
+        Some(endpoint) => Some(endpoint.connect_as(Stream::Replication)?),
+        None => None,
+    };
 
-    while let Ok(record) = rx.recv() {
-        let mut task = Task::new(&mut history, &mut accounts, record);
-        let res = &mut task.run();
+    // when verifying, the serial run happens inline and the parallel run replays the input after
+    let mut scheduler = match options.verify_determinism {
+        true => None,
+        false => options
+            .workers
+            .map(|workers| Scheduler::new(workers, redactor)),
+    };
+    let mut replay = Vec::new();
+    let tracer = Tracer::new(options.trace_clients.iter().copied());
+    let mut filter = OperationFilter::new(options.unknown_ops.clone())?;
+    let (mut malformed, mut skipped, mut denied) = (0, 0, 0);
+    let mut position = skip;
+
+    loop {
+        let received = match &mut reporter {
+            None => match rx.recv() {
+                Ok(received) => received,
+                Err(_) => break,
+            },
+            Some(reporter) => match rx.recv_timeout(reporter.until_due()) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => {
+                    let summary = reporter.write(&engine)?;
+                    info!(summary = ?summary, "Wrote scheduled report");
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+        };
+        let (line, received) = received;
+        // the state covers the records before this one
+        if let (Some(every), Some(path)) = (options.checkpoint_every, &options.checkpoint) {
+            if position > skip && (position - skip) % every == 0 {
+                engine.checkpoint().at(position).save(path)?;
+                info!(position = position, "Wrote checkpoint");
+            }
+        }
+        position += 1;
+        if let Some(offsets) = &offsets {
+            offsets.applied(position - skip - 1)?;
+        }
+        if reloadable && signals::take_reload() {
+            let path = options.settings.as_ref().expect("Reloads need settings");
+            match settings::load(path) {
+                Ok(limits) => {
+                    engine.set_limits(limits);
+                    info!("Reloaded settings");
+                }
+                Err(e) => error!(error = %e, "Invalid settings, keeping the current ones"),
+            }
+        }
+        let mut record = match received {
+            Ok(record) => record,
+            Err(InputError::Malformed { line, error }) if !options.strict => {
+                malformed += 1;
+                // deserialization errors can echo raw field values, only the position is safe to log
+                match log_sensitive {
+                    true => error!(line = line, error = %error, "Failed to deserialize record"),
+                    false => error!(line = line, "Failed to deserialize record"),
+                }
+                continue;
+            }
+            // the message of a malformed record only has its line
+            Err(e @ InputError::Malformed { .. }) if !log_sensitive => {
+                return Err(e.to_string().into())
+            }
+            Err(e @ InputError::Denied { .. }) => {
+                denied += 1;
+                error!(error = %e, "Rejecting transaction");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if !filter.admit(&record)? {
+            skipped += 1;
+            continue;
+        }
+        if let Some(anonymizer) = &anonymizer {
+            record = anonymizer.transaction(record);
+        }
+        if let Some(writer) = &mut emitter {
+            writer.serialize(&record)?;
+        }
+        if let Some(journal) = &mut journal {
+            journal.append(&record)?;
+        }
+        if options.verify_determinism {
+            replay.push(record.clone());
+        }
+        if let Some(scheduler) = &mut scheduler {
+            scheduler.submit(record);
+            continue;
+        }
+        let (client, tx_id, counterparty) = (record.client, record.tx, record.counterparty);
+        let extra = redactor.columns(&record);
+        // rejected transactions leave the state untouched, the standby only needs the rest
+        let replicated = replica.as_ref().map(|_| record.clone());
+        let rejected = (rejects.is_some() || publisher.is_some()).then(|| record.clone());
+        let pending = ledger.as_ref().map(|_| Pending::new(&engine, &record));
+        let (res, trace) = tracer.process(&mut engine, record);
+        if let Some(trace) = trace {
+            eprintln!("{trace}");
+        }
         match res {
-            Ok(_) => (),
-            Err(e) => error!("{}", e),
+            Ok(()) => {
+                if engine.out_of_order() {
+                    warn!(tx = tx_id, client = %redactor.client(client), "Transaction out of order");
+                }
+                if let (Some(sender), Some(record)) = (&mut replica, replicated) {
+                    sender.send(&record)?;
+                }
+                if let (Some(ledger), Some(pending)) = (&mut ledger, pending) {
+                    ledger.post(pending, &engine);
+                }
+                if let Some(fee) = engine.assessed_fee() {
+                    if let Some(journal) = &mut journal {
+                        journal.append(fee)?;
+                    }
+                    if let Some(sender) = &mut replica {
+                        sender.send(fee)?;
+                    }
+                }
+                // after any fee, which is charged to the same client
+                if let Some(publisher) = &mut publisher {
+                    for client in std::iter::once(client).chain(counterparty) {
+                        if let Some(account) = engine.accounts().get(&client) {
+                            publisher.account(tx_id, account)?;
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                // the state can't be trusted anymore, so no output is written
+                if let Some(violation) = engine.invariant_violation() {
+                    return Err(match redactor.is_sensitive() {
+                        true => violation.clone().into(),
+                        false => violation.to_string().into(),
+                    });
+                }
+                if let (Some(rejects), Some(record)) = (&mut rejects, &rejected) {
+                    rejects.write(line, record, &e)?;
+                }
+                if let (Some(publisher), Some(record)) = (&mut publisher, &rejected) {
+                    publisher.rejection(line, record, &e)?;
+                }
+                redactor.log_rejection(tx_id, client, &e, &extra)
+            }
+        }
+        if let Some(chaos) = &mut chaos {
+            chaos.checkpoint(Stage::Apply);
+        }
+    }
+
+    let bytes = handle
+        .join()
+        .map_err(|_| "Reading the input panicked, the run is incomplete")??;
+    if let Some(offsets) = &offsets {
+        offsets.applied(position - skip)?;
+        offsets.commit()?;
+    }
+    let counts = filter.finish()?;
+    if let Some(mut writer) = emitter {
+        writer.flush()?;
+    }
+    if let Some(mut journal) = journal {
+        journal.sync()?;
+    }
+    if let Some(sender) = replica {
+        sender.finish()?;
+    }
+    if let Some(rejects) = rejects {
+        rejects.finish()?;
+    }
+    if let Some(publisher) = publisher {
+        let stats = publisher.finish()?;
+        info!(stats = ?stats, "Published results");
+    }
+    if let (Some(ledger), Some(path)) = (&ledger, &options.ledger) {
+        let postings = ledger.write_csv(vec![])?;
+        output::write_atomic(path, |file| file.write_all(&postings))?;
+        info!(postings = ledger.postings().len(), "Wrote ledger");
+    }
+
+    if options.verify_determinism {
+        let workers = options.workers.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(DEFAULT_WORKERS)
+        });
+        let mut scheduler = Scheduler::new(workers, redactor);
+        for record in replay {
+            scheduler.submit(record);
+        }
+        verify_determinism(&engine, &scheduler.finish())?;
+    }
+
+    let engine = match scheduler {
+        Some(scheduler) => scheduler.finish(),
+        None => engine,
+    };
+    let mut report = engine.report();
+    report.rows += malformed + skipped + denied;
+    report.malformed = malformed;
+    report.skipped = skipped;
+    report.denied = denied;
+    report.bytes = bytes;
+    report.duration = started.elapsed();
+    info!(report = ?report, counts = ?counts, "Processed input");
+    Ok((engine, Some(position).filter(|_| from_files)))
+}
+
+fn snapshot_diff(before: &Path, after: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let before = Snapshot::load(before)?;
+    let after = Snapshot::load(after)?;
+
+    let mut stdout = std::io::stdout().lock();
+    for change in before.diff(&after) {
+        writeln!(stdout, "{change}")?;
+    }
+
+    Ok(())
+}
+
+// Runs the engine as a gRPC service, a REST API or both until SIGINT or SIGTERM, then writes the
+// final accounts to stdout like a run
+fn serve(
+    listen: Option<SocketAddr>,
+    http: Option<SocketAddr>,
+    workers: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let shards = workers.unwrap_or_else(|| thread::available_parallelism().map_or(1, usize::from));
+    let engine = SharedEngine::new(shards);
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let (stop, stopped) = watch::channel(());
+        let stopped = move || {
+            let mut stopped = stopped.clone();
+            async move {
+                stopped.changed().await.ok();
+            }
+        };
+        let mut servers: JoinSet<Result<(), Box<dyn std::error::Error + Send + Sync>>> =
+            JoinSet::new();
+        if let Some(listen) = listen {
+            let incoming = TcpIncoming::bind(listen)?;
+            info!(address = %incoming.local_addr()?, shards, "Serving gRPC");
+            let (engine, stopped) = (engine.clone(), stopped());
+            servers.spawn(async move { Ok(grpc::serve(incoming, engine, stopped).await?) });
+        }
+        if let Some(http) = http {
+            let listener = tokio::net::TcpListener::bind(http).await?;
+            info!(address = %listener.local_addr()?, shards, "Serving HTTP");
+            let (engine, stopped) = (engine.clone(), stopped());
+            servers.spawn(async move { Ok(bank::http::serve(listener, engine, stopped).await?) });
+        }
+        // a server that stops before it's asked to has failed, which stops the others too
+        let first = tokio::select! {
+            _ = stop_serving() => None,
+            Some(result) = servers.join_next() => Some(result),
+        };
+        stop.send_replace(());
+        let failed =
+            |e: Box<dyn std::error::Error + Send + Sync>| -> Box<dyn std::error::Error> { e };
+        if let Some(result) = first {
+            result?.map_err(failed)?;
+        }
+        while let Some(result) = servers.join_next().await {
+            result?.map_err(failed)?;
+        }
+        Ok::<_, Box<dyn std::error::Error>>(())
+    })?;
+    // dropping the runtime drops the connections still holding handles to the engine
+    drop(runtime);
+    let engine = engine
+        .into_engine()
+        .expect("No handles left after the runtime");
+    output::write_csv(engine.accounts(), std::io::stdout().lock())?.flush()?;
+    Ok(())
+}
+
+async fn stop_serving() {
+    let mut terminate =
+        unix::signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    info!("Shutting down");
+}
+
+fn query(state: &Path, client: ClientId) -> Result<(), Box<dyn std::error::Error>> {
+    // journals are input-format CSV and have to be replayed, checkpoints are read in place and
+    // anything else is a snapshot
+    let (act, disputes) = match state.extension().and_then(|ext| ext.to_str()) {
+        Some("rkyv") => {
+            let checkpoint = MappedCheckpoint::open(state)?;
+            let disputes: Vec<HistoryRecord> = checkpoint.open_disputes(client).collect();
+            (checkpoint.account(client), disputes)
+        }
+        ext => {
+            let snapshot = match ext {
+                Some("csv") => {
+                    let engine = shard::process_file(state, &Redactor::default())?;
+                    Snapshot::new(engine.history(), engine.accounts())
+                }
+                _ => Snapshot::load(state)?,
+            };
+            let disputes = snapshot.open_disputes(client).cloned().collect();
+            (snapshot.account(client).cloned(), disputes)
+        }
+    };
+    let act = act.ok_or_else(|| format!("Client {client} not found"))?;
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "client {client}")?;
+    writeln!(stdout, "  available {:.4}", act.available)?;
+    writeln!(stdout, "  held      {:.4}", act.held)?;
+    writeln!(stdout, "  total     {:.4}", act.total)?;
+    writeln!(stdout, "  locked    {}", act.locked)?;
+    for rec in disputes {
+        let amount = rec.amount.unwrap_or_default().abs();
+        writeln!(stdout, "  disputed  tx {} ({amount:.4})", rec.tx)?;
+    }
+
+    Ok(())
+}
+
+// Accepts `readers` connections and forwards their transactions until every reader has closed
+// its connection. Each reader's transactions keep their order, transactions of different readers
+// interleave, so a client's transactions should all go through the same reader.
+//
+// A standby first mirrors its primary's replication stream. If the primary finishes, so does the
+// standby; if it goes away, the standby waits for `bank promote` and then takes readers itself.
+// Reads the transactions of Kafka messages until every partition of the topic was read to its
+// end, or until a signal when following it. Each message is recorded in `offsets` after its
+// transactions were sent, so it's committed once the engine applied all of them.
+fn consume(
+    consumer: &mut KafkaInput,
+    offsets: &Offsets,
+    permissions: &Permissions,
+    mut throttle: Option<Throttle>,
+    tx: SyncSender<Received>,
+) -> Result<(), KafkaError> {
+    let mut sent = 0;
+    while signals::shutdown().is_none() {
+        let message = match consumer.poll(Duration::from_millis(100))? {
+            Poll::Message(message) => message,
+            Poll::Idle => continue,
+            Poll::End => break,
+        };
+        for record in message.records {
+            if let Some(throttle) = &mut throttle {
+                throttle.acquire();
+            }
+            let record = match record {
+                Ok(record) => permissions.check(KAFKA_SOURCE, &record).map(|()| record),
+                record => record,
+            };
+            // the engine stopped early, the message is delivered again to the next run
+            if tx.send((None, record)).is_err() {
+                return Ok(());
+            }
+            sent += 1;
+        }
+        offsets.read(sent, message.position);
+    }
+    Ok(())
+}
+
+fn receive(
+    endpoint: &Endpoint,
+    readers: usize,
+    standby: bool,
+    permissions: &Permissions,
+    throttle: Option<&Mutex<Throttle>>,
+    tx: SyncSender<Received>,
+) -> Result<(), WireError> {
+    let listener = endpoint.listen()?;
+    if standby && !mirror(&listener, &tx)? {
+        if let Endpoint::Unix(path) = endpoint {
+            std::fs::remove_file(path).ok();
+        }
+        return Ok(());
+    }
+    thread::scope(|scope| {
+        let mut connections = Vec::new();
+        while connections.len() < readers {
+            let receiver = listener.accept()?;
+            if receiver.kind() != Stream::Transactions {
+                error!(stream = ?receiver.kind(), "Expected a reader, dropping connection");
+                continue;
+            }
+            let tx = tx.clone();
+            connections.push(scope.spawn(move || forward(receiver, permissions, throttle, &tx)));
+        }
+        for connection in connections {
+            if !connection.join().expect("Failed to join reader connection") {
+                error!("A reader disconnected without finishing its stream");
+            }
+        }
+        Ok::<_, WireError>(())
+    })?;
+    if let Endpoint::Unix(path) = endpoint {
+        std::fs::remove_file(path).ok();
+    }
+    Ok(())
+}
+
+// Forwards a connection's transactions, returns whether the sender finished its stream
+fn forward(
+    mut receiver: wire::Receiver,
+    permissions: &Permissions,
+    throttle: Option<&Mutex<Throttle>>,
+    tx: &SyncSender<Received>,
+) -> bool {
+    let source = receiver.source().unwrap_or(ANONYMOUS_SOURCE).to_string();
+    for record in receiver.by_ref() {
+        match record {
+            Ok(out) => {
+                if let Some(throttle) = throttle {
+                    throttle.lock().expect("Throttle lock poisoned").acquire();
+                }
+                let out = permissions.check(&source, &out).map(|()| out);
+                if tx.send((None, out)).is_err() {
+                    return false;
+                }
+            }
+            Err(e) => {
+                error!(error = %e, "Dropping connection");
+                return false;
+            }
+        }
+    }
+    receiver.finished()
+}
+
+// Applies the primary's replication stream. Returns whether the standby has been promoted, as
+// opposed to the primary having finished.
+fn mirror(listener: &wire::Listener, tx: &SyncSender<Received>) -> Result<bool, WireError> {
+    let receiver = loop {
+        let receiver = listener.accept()?;
+        match receiver.kind() {
+            Stream::Replication => break receiver,
+            kind => error!(stream = ?kind, "Waiting for the primary, dropping connection"),
+        }
+    };
+    // the primary already checked what it applied, and the standby has to keep up with it
+    if forward(receiver, &Permissions::default(), None, tx) {
+        info!("Primary finished, stopping standby");
+        return Ok(false);
+    }
+    info!("Lost the primary, waiting for promotion");
+    loop {
+        let receiver = listener.accept()?;
+        match receiver.kind() {
+            Stream::Promote => break,
+            kind => error!(stream = ?kind, "Not promoted yet, dropping connection"),
+        }
+    }
+    info!("Promoted, accepting readers");
+    Ok(true)
+}
+
+// Transactions of an input file for the subcommands with the line each starts on, read like a
+// run's input: JSON lines by extension, `-` for stdin, and malformed records logged and skipped
+fn records(
+    path: &Path,
+) -> Result<impl Iterator<Item = Result<(u64, Transaction), InputError>>, InputError> {
+    let file = input::open(path).map_err(InputError::Io)?;
+    let mut reader = Records::new(file, InputFormat::detect(path))?;
+    Ok(std::iter::from_fn(move || loop {
+        let record = reader.next()?;
+        match record {
+            Ok(record) => return Some(Ok((reader.line(), record))),
+            Err(InputError::Malformed { line, .. }) => {
+                error!(line = line, "Failed to deserialize record")
+            }
+            Err(e) => return Some(Err(e)),
+        }
+    }))
+}
+
+// Parses an input file and streams its transactions to an engine process started with the
+// address as its input
+fn send(
+    input: &Path,
+    to: &Endpoint,
+    source: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let records = records(input)?;
+    let mut sender = match source {
+        Some(source) => to.connect_from(source)?,
+        None => to.connect()?,
+    };
+    // unknown operations can't be encoded, the receiving instance wouldn't apply them anyway
+    let mut filter = OperationFilter::new(UnknownPolicy::Skip)?;
+    for record in records {
+        let (_, record) = record?;
+        if filter.admit(&record)? {
+            sender.send(&record)?;
+        }
+    }
+    sender.finish()?;
+    Ok(())
+}
+
+// Splits an input file over the instances of a cluster by client
+fn route(
+    input: &Path,
+    shards: &[Shard],
+    nodes: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let records = records(input)?;
+    let mut router = if nodes.is_empty() {
+        Router::connect(shards)?
+    } else {
+        Router::connect_ring(nodes)?
+    };
+    let mut filter = OperationFilter::new(UnknownPolicy::Skip)?;
+    for record in records {
+        let (_, record) = record?;
+        if !filter.admit(&record)? {
+            continue;
+        }
+        match router.route(&record) {
+            Err(ClusterError::Unowned(client)) => {
+                error!(
+                    client = client,
+                    tx = record.tx,
+                    "No shard owns the client, dropping transaction"
+                )
+            }
+            res => res?,
+        }
+    }
+    router.finish()?;
+    Ok(())
+}
+
+// Combines account outputs of partitioned runs, snapshots are recognised by their extension
+fn merge(
+    parts: &[PathBuf],
+    output: Option<&Path>,
+    sum: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut accounts = vec![];
+    for path in parts {
+        let part = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Snapshot::load(path)?
+                .accounts
+                .into_iter()
+                .map(|rec| {
+                    let mut act = Account::new(rec.client);
+                    act.available = rec.available;
+                    act.held = rec.held;
+                    act.total = rec.total;
+                    act.locked = rec.locked;
+                    (rec.client, act)
+                })
+                .collect(),
+            _ => output::read_csv(File::open(path)?)?,
         };
+        accounts.push(part);
+    }
+    let accounts = cluster::merge_outputs(accounts, sum)?;
+    let out = output::write_csv(&accounts, vec![])?;
+    match output {
+        Some(path) => output::write_atomic(path, |file| file.write_all(&out))?,
+        None => std::io::stdout().lock().write_all(&out)?,
     }
+    Ok(())
+}
 
-    handle.join().expect("Failed to join thread handle");
+// Renders customer statements from journal files, read in the order given
+fn statement(
+    journals: &[PathBuf],
+    client: Option<ClientId>,
+    format: StatementFormat,
+    output: Option<&Path>,
+    dir: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut transactions = vec![];
+    for path in journals {
+        for record in records(path)? {
+            let (_, record) = record?;
+            if client.is_none_or(|client| record.client == client) {
+                transactions.push(record);
+            }
+        }
+    }
+    let statements = statement::statements(transactions);
 
-    let mut writer = csv::Writer::from_writer(vec![]);
+    if let Some(dir) = dir {
+        std::fs::create_dir_all(dir)?;
+        for (client, statement) in statements.iter() {
+            let path = dir.join(format!("{client}.{}", format.extension()));
+            let rendered = statement.render(format);
+            output::write_atomic(&path, |file| file.write_all(rendered.as_bytes()))?;
+        }
+        info!(statements = statements.len(), dir = ?dir, "Wrote statements");
+        return Ok(());
+    }
 
-    for act in accounts.values() {
-        writer.serialize(act)?
+    let client = client.ok_or(CliError::MissingArgument("--client"))?;
+    let statement = statements
+        .get(&client)
+        .ok_or_else(|| format!("Client {client} not found"))?;
+    let rendered = statement.render(format);
+    match output {
+        Some(path) => output::write_atomic(path, |file| file.write_all(rendered.as_bytes()))?,
+        None => std::io::stdout().lock().write_all(rendered.as_bytes())?,
     }
+    Ok(())
+}
+
+fn explain(input: &Path, tx: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let tracer = Tracer::transaction(tx);
+    let mut engine = Engine::new();
+    let records = records(input)?;
 
-    // let output = String::from_utf8(writer.into_inner()?)?;
-    let inner = writer.into_inner()?;
-    std::io::stdout().write_all(&inner)?;
+    let mut stdout = std::io::stdout().lock();
+    let mut events = 0;
+    for record in records {
+        let (line, record) = record?;
+        if let (_, Some(trace)) = tracer.process(&mut engine, record) {
+            writeln!(stdout, "line {line}: {trace}")?;
+            events += 1;
+        }
+    }
+    if events == 0 {
+        writeln!(stdout, "tx {tx} doesn't appear in the input")?;
+    }
 
     Ok(())
 }
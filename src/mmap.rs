@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+// Shared mapping of a whole file
+#[derive(Debug)]
+pub(crate) struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+    // kept open for the lifetime of the mapping
+    _file: File,
+}
+
+// The mapping is only reachable through its owner, like any other owned buffer
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    pub(crate) fn read_write(file: File) -> io::Result<Self> {
+        Self::new(file, libc::PROT_READ | libc::PROT_WRITE)
+    }
+
+    // Writing through `bytes_mut` of a read-only mapping faults
+    #[cfg(feature = "archive")]
+    pub(crate) fn read_only(file: File) -> io::Result<Self> {
+        Self::new(file, libc::PROT_READ)
+    }
+
+    fn new(file: File, prot: libc::c_int) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        // SAFETY: maps the whole file shared; the returned pointer is checked before use and
+        // the file stays open until the mapping is dropped.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                prot,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+            _file: file,
+        })
+    }
+
+    pub(crate) fn bytes(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` mapped bytes that live as long as `self`
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    pub(crate) fn bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `bytes`, and `&mut self` makes this the only live view
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        // SAFETY: syncs exactly the range that was mapped
+        match unsafe { libc::msync(self.ptr.cast(), self.len, libc::MS_SYNC) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: unmaps the range mapped in `new`, no views outlive `self`
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
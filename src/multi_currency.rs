@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::domain::{
+    errors::TransactionError, transaction::Operation, Account, ClientId, Transaction,
+};
+use crate::engine::{Engine, Limits};
+
+// Keeps independent balances per currency for every client, with an engine per currency.
+// Deposits, withdrawals, transfers and authorizations name their currency and go to its engine;
+// disputes, resolves, chargebacks and captures go to the currency of the transaction they refer
+// to, naming another one is rejected with `CurrencyMismatch`. Fees from chargebacks are charged in the currency of
+// the chargeback.
+#[derive(Debug, Default)]
+pub struct MultiCurrencyEngine {
+    engines: BTreeMap<String, Engine>,
+    // currency of every applied deposit, withdrawal, transfer and authorization, both sides of a
+    // transfer
+    currencies: HashMap<(ClientId, u32), String>,
+    // every currency's engine gets these
+    limits: Limits,
+}
+
+impl MultiCurrencyEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: Limits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn process(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let key = (transaction.client, transaction.tx);
+        let named = transaction.currency.as_deref().map(str::to_uppercase);
+        let known = self.currencies.get(&key);
+        let currency = match transaction.op {
+            Operation::Deposit
+            | Operation::Withdrawal
+            | Operation::Transfer
+            | Operation::Fee
+            | Operation::Authorize => {
+                let currency = named.ok_or(TransactionError::MissingCurrency)?;
+                // an id is only ever used in one currency
+                if known.is_some_and(|known| *known != currency) {
+                    return Err(TransactionError::CurrencyMismatch);
+                }
+                currency
+            }
+            Operation::Dispute
+            | Operation::Resolve
+            | Operation::Chargeback
+            | Operation::Capture => {
+                let known = known.ok_or(TransactionError::TransactionNotFound)?;
+                if named.is_some_and(|named| named != *known) {
+                    return Err(TransactionError::CurrencyMismatch);
+                }
+                known.clone()
+            }
+            Operation::Unknown(_) => return Err(TransactionError::UnspecifiedBehavior),
+        };
+        let recipient = transaction.recipient();
+        let owned = matches!(
+            transaction.op,
+            Operation::Deposit | Operation::Withdrawal | Operation::Transfer | Operation::Authorize
+        );
+        let engine = self.engines.entry(currency.clone()).or_insert_with(|| {
+            let mut engine = Engine::new();
+            engine.set_limits(self.limits.clone());
+            engine
+        });
+        engine.process(transaction)?;
+        if owned {
+            if let Some(to) = recipient {
+                self.currencies.insert((to, key.1), currency.clone());
+            }
+            self.currencies.insert(key, currency);
+        }
+        Ok(())
+    }
+
+    // Engine keeping the balances in `currency`, if it has seen a transaction
+    pub fn engine(&self, currency: &str) -> Option<&Engine> {
+        self.engines.get(&currency.to_uppercase())
+    }
+
+    // One account per client and currency, ordered by client and then currency
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts: Vec<Account> = self
+            .engines
+            .iter()
+            .flat_map(|(currency, engine)| {
+                engine.accounts().values().map(|act| Account {
+                    currency: Some(currency.clone()),
+                    ..act.clone()
+                })
+            })
+            .collect();
+        accounts.sort_by(|a, b| (a.client, &a.currency).cmp(&(b.client, &b.currency)));
+        accounts
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn transaction(
+        op: Operation,
+        client: ClientId,
+        tx: u32,
+        currency: Option<&str>,
+    ) -> Transaction {
+        Transaction {
+            op,
+            client,
+            tx,
+            amount: Some(dec!(10)),
+            currency: currency.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_balances_per_client_and_currency() {
+        let mut engine = MultiCurrencyEngine::new();
+        engine
+            .process(transaction(Operation::Deposit, 1, 1, Some("EUR")))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Deposit, 1, 2, Some("usd")))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Deposit, 2, 3, Some("USD")))
+            .unwrap();
+        assert_eq!(
+            engine.process(transaction(Operation::Withdrawal, 1, 4, None)),
+            Err(TransactionError::MissingCurrency)
+        );
+        assert_eq!(
+            engine.process(transaction(Operation::Deposit, 1, 1, Some("USD"))),
+            Err(TransactionError::CurrencyMismatch)
+        );
+
+        // disputes follow the disputed transaction, naming another currency is rejected
+        assert_eq!(
+            engine.process(transaction(Operation::Dispute, 1, 1, Some("USD"))),
+            Err(TransactionError::CurrencyMismatch)
+        );
+        engine
+            .process(transaction(Operation::Dispute, 1, 1, None))
+            .unwrap();
+        engine
+            .process(transaction(Operation::Resolve, 1, 1, Some("EUR")))
+            .unwrap();
+
+        let accounts = engine.accounts();
+        let rows: Vec<(ClientId, Option<&str>)> = accounts
+            .iter()
+            .map(|act| (act.client, act.currency.as_deref()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(1, Some("EUR")), (1, Some("USD")), (2, Some("USD"))]
+        );
+        let eur = &engine.engine("eur").unwrap().accounts()[&1];
+        assert_eq!(eur.total, dec!(0));
+        assert_eq!(accounts[1].total, dec!(10));
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::domain::{Account, AccountStore, ClientId};
+
+// Writes accounts as CSV ordered by client id, so identical state always renders to identical
+// bytes regardless of how it was computed.
+pub fn write_csv<W: Write>(accounts: &AccountStore, out: W) -> Result<W, csv::Error> {
+    let mut sorted: Vec<&Account> = accounts.values().collect();
+    sorted.sort_by_key(|act| act.client);
+    write_csv_rows(&sorted, out)
+}
+
+// Writes accounts as CSV in the order given, e.g. the per currency rows of a multi-currency run
+pub fn write_csv_rows<W: Write>(sorted: &[&Account], out: W) -> Result<W, csv::Error> {
+    // once any account has a credit limit, fees or dated activity every row gets the column, 0
+    // for those without
+    let credit = sorted.iter().any(|act| act.credit_limit.is_some());
+    let fees = sorted.iter().any(|act| act.fees.is_some());
+    let dated = sorted.iter().any(|act| act.last_activity.is_some());
+    let mut writer = csv::Writer::from_writer(out);
+    for &act in sorted {
+        if (credit && act.credit_limit.is_none())
+            || (fees && act.fees.is_none())
+            || (dated && act.last_activity.is_none())
+        {
+            writer.serialize(Account {
+                credit_limit: act.credit_limit.or(credit.then_some(Decimal::ZERO)),
+                fees: act.fees.or(fees.then_some(Decimal::ZERO)),
+                first_activity: act.first_activity.or(dated.then_some(0)),
+                last_activity: act.last_activity.or(dated.then_some(0)),
+                ..act.clone()
+            })?;
+        } else {
+            writer.serialize(act)?;
+        }
+    }
+    writer
+        .into_inner()
+        .map_err(|e| io::Error::new(e.error().kind(), e.to_string()).into())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    // aligned columns for reading in a terminal
+    Table,
+    // an array of accounts
+    Json,
+    // an object of accounts keyed by client id
+    JsonByClient,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "json-by-client" => Ok(OutputFormat::JsonByClient),
+            _ => Err(format!(
+                "unknown output format {s}, expected csv, table, json or json-by-client"
+            )),
+        }
+    }
+}
+
+// Writes accounts as pretty-printed JSON ordered by client id, either as an array or keyed by
+// client id. Amounts are strings rounded to four decimals like in the CSV output.
+pub fn write_json<W: Write>(
+    accounts: &AccountStore,
+    mut out: W,
+    by_client: bool,
+) -> serde_json::Result<W> {
+    if by_client {
+        let keyed: BTreeMap<ClientId, &Account> =
+            accounts.iter().map(|(c, act)| (*c, act)).collect();
+        serde_json::to_writer_pretty(&mut out, &keyed)?;
+        out.write_all(b"\n").map_err(serde_json::Error::io)?;
+        Ok(out)
+    } else {
+        let mut sorted: Vec<&Account> = accounts.values().collect();
+        sorted.sort_by_key(|act| act.client);
+        write_json_rows(&sorted, out)
+    }
+}
+
+// Writes accounts as a pretty-printed JSON array in the order given
+pub fn write_json_rows<W: Write>(sorted: &[&Account], mut out: W) -> serde_json::Result<W> {
+    serde_json::to_writer_pretty(&mut out, sorted)?;
+    out.write_all(b"\n").map_err(serde_json::Error::io)?;
+    Ok(out)
+}
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+// Renders accounts as an aligned table ordered by client id, with credit limit, fees and activity
+// columns if any account has them. With color, locked accounts are shown in red and accounts with held
+// funds in yellow.
+pub fn write_table<W: Write>(accounts: &AccountStore, mut out: W, color: bool) -> io::Result<W> {
+    let mut sorted: Vec<&Account> = accounts.values().collect();
+    sorted.sort_by_key(|act| act.client);
+
+    let credit = sorted.iter().any(|act| act.credit_limit.is_some());
+    let mut header = ["client", "available", "held", "total", "locked"]
+        .map(String::from)
+        .to_vec();
+    let fees = sorted.iter().any(|act| act.fees.is_some());
+    if credit {
+        header.push("credit_limit".into());
+    }
+    if fees {
+        header.push("fees".into());
+    }
+    let dated = sorted.iter().any(|act| act.last_activity.is_some());
+    if dated {
+        header.push("first_activity".into());
+        header.push("last_activity".into());
+    }
+    let rows: Vec<Vec<String>> = sorted
+        .iter()
+        .map(|act| {
+            let mut row = vec![
+                act.client.to_string(),
+                act.available.round_dp(4).to_string(),
+                act.held.round_dp(4).to_string(),
+                act.total.round_dp(4).to_string(),
+                act.locked.to_string(),
+            ];
+            if credit {
+                row.push(act.credit_limit.unwrap_or_default().round_dp(4).to_string());
+            }
+            if fees {
+                row.push(act.fees.unwrap_or_default().round_dp(4).to_string());
+            }
+            if dated {
+                row.push(act.first_activity.unwrap_or_default().to_string());
+                row.push(act.last_activity.unwrap_or_default().to_string());
+            }
+            row
+        })
+        .collect();
+    let mut widths: Vec<usize> = header.iter().map(|title| title.len()).collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let line = |cells: &[String]| -> String {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| format!("{cell:>width$}"))
+            .collect();
+        cells.join("  ")
+    };
+    writeln!(out, "{}", line(&header))?;
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    writeln!(out, "{}", rule.join("  "))?;
+    for (act, row) in sorted.iter().zip(rows.iter()) {
+        let highlight = match (act.locked, act.held.is_zero()) {
+            (true, _) => Some(RED),
+            (false, false) => Some(YELLOW),
+            (false, true) => None,
+        };
+        match highlight.filter(|_| color) {
+            Some(code) => writeln!(out, "{code}{}{RESET}", line(row))?,
+            None => writeln!(out, "{}", line(row))?,
+        }
+    }
+    Ok(out)
+}
+
+// Reads accounts back from the CSV produced by `write_csv`
+pub fn read_csv<R: Read>(input: R) -> Result<AccountStore, csv::Error> {
+    let mut reader = csv::Reader::from_reader(input);
+    reader
+        .deserialize::<Account>()
+        .map(|act| act.map(|act| (act.client, act)))
+        .collect()
+}
+
+// Writes to a temp file next to `path` and renames it into place once the contents are on disk,
+// so readers only ever see the previous file or the complete new one.
+pub fn write_atomic<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Output path has no file name")
+    })?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    // one temp file per process, so two runs writing the same path don't share it
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(tmp_name);
+
+    let mut file = File::create(&tmp)?;
+    let written = write(&mut file).and_then(|_| file.sync_all());
+    drop(file);
+    if let Err(e) = written.and_then(|_| fs::rename(&tmp, path)) {
+        fs::remove_file(&tmp).ok();
+        return Err(e);
+    }
+    // persist the rename itself, directories can't be opened for syncing on every platform
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            dir.sync_all().ok();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn writes_accounts_in_client_order() {
+        let mut accounts = AccountStore::new();
+        for client in [7, 3, 5] {
+            accounts.insert(client, Account::new(client));
+        }
+
+        let out = write_csv(&accounts, vec![]).expect("Failed to write accounts");
+        let clients: Vec<&str> = std::str::from_utf8(&out)
+            .expect("Output is utf8")
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+
+        assert_eq!(clients, vec!["3", "5", "7"]);
+    }
+
+    #[test]
+    fn writes_credit_limits_for_every_account_once_one_has_them() {
+        let mut accounts = AccountStore::new();
+        accounts.insert(1, Account::new(1));
+        let mut act = Account::new(2);
+        act.credit_limit = Some(rust_decimal_macros::dec!(50));
+        accounts.insert(2, act);
+
+        let out = write_csv(&accounts, vec![]).expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,credit_limit\n\
+             1,0.0,0.0,0.0,false,0\n\
+             2,0.0,0.0,0.0,false,50\n"
+        );
+    }
+
+    #[test]
+    fn renders_aligned_table() {
+        let mut accounts = AccountStore::new();
+        let mut act = Account::new(12);
+        act.deposit(Some(rust_decimal_macros::dec!(1500.5)))
+            .unwrap();
+        accounts.insert(12, act);
+        accounts.insert(3, Account::new(3));
+
+        let out = write_table(&accounts, vec![], false).expect("Failed to write table");
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            [
+                "client  available  held   total  locked",
+                "------  ---------  ----  ------  ------",
+                "     3        0.0   0.0     0.0   false",
+                "    12     1500.5   0.0  1500.5   false",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn writes_json_arrays_and_objects() {
+        let mut accounts = AccountStore::new();
+        let mut act = Account::new(8);
+        act.deposit(Some(rust_decimal_macros::dec!(2.123456)))
+            .unwrap();
+        accounts.insert(8, act);
+        accounts.insert(2, Account::new(2));
+
+        let array: serde_json::Value =
+            serde_json::from_slice(&write_json(&accounts, vec![], false).unwrap()).unwrap();
+        let object: serde_json::Value =
+            serde_json::from_slice(&write_json(&accounts, vec![], true).unwrap()).unwrap();
+
+        assert_eq!(array[0]["client"], 2);
+        assert_eq!(array[1]["available"], "2.1235");
+        assert_eq!(object["8"]["total"], "2.1235");
+    }
+
+    #[test]
+    fn reads_back_written_accounts() {
+        let mut accounts = AccountStore::new();
+        let mut act = Account::new(4);
+        act.deposit(Some(rust_decimal_macros::dec!(12.25))).unwrap();
+        act.locked = true;
+        accounts.insert(4, act);
+        accounts.insert(9, Account::new(9));
+
+        let out = write_csv(&accounts, vec![]).expect("Failed to write accounts");
+
+        assert_eq!(
+            read_csv(out.as_slice()).expect("Failed to read accounts"),
+            accounts
+        );
+    }
+
+    #[test]
+    fn failed_atomic_write_keeps_previous_file() {
+        let name = format!("bank-output-{}.csv", std::process::id());
+        let path = std::env::temp_dir().join(&name);
+        write_atomic(&path, |file| file.write_all(b"old")).expect("First write succeeds");
+
+        let res = write_atomic(&path, |file| {
+            file.write_all(b"partial")?;
+            Err(io::Error::other("crashed"))
+        });
+
+        assert!(res.is_err());
+        assert_eq!(fs::read(&path).expect("Output exists"), b"old");
+        assert!(!path
+            .with_file_name(format!(".{name}.{}.tmp", std::process::id()))
+            .exists());
+        fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,285 @@
+use std::io::{self, BufReader, Read};
+use std::str::FromStr;
+
+use prost::Message;
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::domain::{transaction::Operation, Account, Amount, ClientId, Transaction};
+use crate::input::InputError;
+
+// Messages generated from proto/bank.proto
+pub mod pb {
+    include!(concat!(env!("OUT_DIR"), "/bank.rs"));
+}
+
+// Messages carry raw client ids and amounts, so errors don't repeat them
+#[derive(Error, Debug, PartialEq)]
+pub enum ProtoError {
+    #[error("Client id out of range")]
+    Client,
+    #[error("Invalid amount")]
+    Amount,
+}
+
+impl<A: Amount> TryFrom<pb::Transaction> for Transaction<A> {
+    type Error = ProtoError;
+
+    fn try_from(message: pb::Transaction) -> Result<Self, Self::Error> {
+        Ok(Transaction {
+            op: Operation::from_name(&message.r#type),
+            client: client_id(message.client)?,
+            tx: message.tx,
+            amount: message.amount.as_deref().map(amount).transpose()?,
+            counterparty: message.counterparty.map(client_id).transpose()?,
+            currency: message.currency,
+            timestamp: message.timestamp,
+            extra: message.extra,
+        })
+    }
+}
+
+impl<A: Amount> TryFrom<&Transaction<A>> for pb::Transaction {
+    type Error = ProtoError;
+
+    fn try_from(transaction: &Transaction<A>) -> Result<Self, Self::Error> {
+        Ok(pb::Transaction {
+            r#type: transaction.op.name().to_string(),
+            client: message_id(transaction.client)?,
+            tx: transaction.tx,
+            amount: transaction.amount.map(|a| a.to_decimal().to_string()),
+            counterparty: transaction.counterparty.map(message_id).transpose()?,
+            currency: transaction.currency.clone(),
+            timestamp: transaction.timestamp,
+            extra: transaction.extra.clone(),
+        })
+    }
+}
+
+impl<A: Amount> TryFrom<pb::Account> for Account<A> {
+    type Error = ProtoError;
+
+    fn try_from(message: pb::Account) -> Result<Self, Self::Error> {
+        Ok(Account {
+            client: client_id(message.client)?,
+            currency: message.currency,
+            available: amount(&message.available)?,
+            held: amount(&message.held)?,
+            total: amount(&message.total)?,
+            locked: message.locked,
+            ..Account::new(0)
+        })
+    }
+}
+
+// Balances are rounded to four decimals like in the CSV output
+impl<A: Amount> TryFrom<&Account<A>> for pb::Account {
+    type Error = ProtoError;
+
+    fn try_from(account: &Account<A>) -> Result<Self, Self::Error> {
+        Ok(pb::Account {
+            client: message_id(account.client)?,
+            currency: account.currency.clone(),
+            available: account.available.to_output(),
+            held: account.held.to_output(),
+            total: account.total.to_output(),
+            locked: account.locked,
+        })
+    }
+}
+
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+pub(crate) fn client_id(id: u64) -> Result<ClientId, ProtoError> {
+    ClientId::try_from(u128::from(id)).map_err(|_| ProtoError::Client)
+}
+
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+pub(crate) fn message_id(client: ClientId) -> Result<u64, ProtoError> {
+    u64::try_from(u128::from(client)).map_err(|_| ProtoError::Client)
+}
+
+fn amount<A: Amount>(value: &str) -> Result<A, ProtoError> {
+    Decimal::from_str(value.trim())
+        .ok()
+        .and_then(A::from_decimal)
+        .ok_or(ProtoError::Amount)
+}
+
+// Appends the transaction as a length-delimited message, the framing `Messages` reads
+pub fn encode<A: Amount>(
+    transaction: &Transaction<A>,
+    out: &mut Vec<u8>,
+) -> Result<(), ProtoError> {
+    let message = pb::Transaction::try_from(transaction)?;
+    message
+        .encode_length_delimited(out)
+        .expect("Vec grows to fit the message");
+    Ok(())
+}
+
+// Transactions read from a stream of length-delimited `bank.Transaction` messages, each a varint
+// length followed by the encoded message. `line` counts messages, so errors point at one the
+// same way CSV and JSON lines errors point at a line.
+pub struct Messages<R> {
+    reader: BufReader<R>,
+    line: u64,
+    bytes: u64,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> Messages<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            reader: BufReader::new(input),
+            line: 0,
+            bytes: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    // None at the end of the stream, which may only fall between messages
+    fn read_len(&mut self) -> io::Result<Option<usize>> {
+        let mut len = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return match shift {
+                    0 => Ok(None),
+                    _ => Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+            }
+            self.bytes += 1;
+            len |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(len as usize));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Length varint too long",
+        ))
+    }
+
+    fn read_message(&mut self) -> io::Result<Option<()>> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        self.buf.resize(len, 0);
+        self.reader.read_exact(&mut self.buf)?;
+        self.bytes += len as u64;
+        Ok(Some(()))
+    }
+}
+
+impl<R: Read> Iterator for Messages<R> {
+    type Item = Result<Transaction, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_message() {
+            Ok(None) => return None,
+            Ok(Some(())) => self.line += 1,
+            Err(e) => return Some(Err(InputError::Io(e))),
+        }
+        let malformed = |error: String| InputError::Malformed {
+            line: self.line,
+            error,
+        };
+        Some(
+            pb::Transaction::decode(self.buf.as_slice())
+                .map_err(|e| malformed(e.to_string()))
+                .and_then(|message| {
+                    Transaction::try_from(message).map_err(|e| malformed(e.to_string()))
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::input::{InputFormat, Records};
+
+    #[test]
+    fn transactions_round_trip_through_length_delimited_messages() {
+        let transactions: Vec<Transaction> = vec![
+            Transaction {
+                op: Operation::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(dec!(2.5)),
+                timestamp: Some(1_700_000_000),
+                extra: [("merchant".to_string(), "m-7".to_string())].into(),
+                ..Default::default()
+            },
+            Transaction {
+                op: Operation::Transfer,
+                client: 1,
+                tx: 2,
+                amount: Some(dec!(1)),
+                counterparty: Some(2),
+                ..Default::default()
+            },
+            Transaction {
+                op: Operation::Dispute,
+                client: 1,
+                tx: 1,
+                ..Default::default()
+            },
+        ];
+        let mut input = Vec::new();
+        for transaction in &transactions {
+            encode(transaction, &mut input).unwrap();
+        }
+        // a message that isn't a transaction
+        let garbage = [0x02, 0xff, 0xff];
+        input.extend_from_slice(&garbage);
+
+        let mut records = Records::new(input.as_slice(), InputFormat::Protobuf).unwrap();
+        for transaction in &transactions {
+            assert_eq!(&records.next().unwrap().unwrap(), transaction);
+        }
+        assert!(matches!(
+            records.next(),
+            Some(Err(InputError::Malformed { line: 4, .. }))
+        ));
+        assert!(records.next().is_none());
+        assert_eq!(records.bytes(), input.len() as u64);
+    }
+
+    #[test]
+    fn accounts_convert_with_output_precision() {
+        let account: Account = Account {
+            available: dec!(1.50004),
+            total: dec!(1.5),
+            ..Account::new(3)
+        };
+        let message = pb::Account::try_from(&account).unwrap();
+        assert_eq!(message.available, "1.5000");
+        assert_eq!(
+            Account::try_from(message),
+            Ok(Account {
+                available: dec!(1.5),
+                ..account
+            })
+        );
+
+        let invalid = pb::Account {
+            total: "lots".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Account::<Decimal>::try_from(invalid),
+            Err(ProtoError::Amount)
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tokio::runtime::{self, Runtime};
+use tonic::transport::{Channel, Endpoint, Uri};
+use tonic::Code;
+use tracing::warn;
+
+use crate::domain::{errors::TransactionError, Account, Amount, Transaction};
+use crate::proto::{pb, ProtoError};
+
+pub use pb::downstream_client::DownstreamClient;
+pub use pb::downstream_server::{Downstream, DownstreamServer};
+
+#[derive(Error, Debug)]
+pub enum PublishError {
+    #[error("Failed to start the publisher: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to encode event: {0}")]
+    Proto(#[from] ProtoError),
+    #[error("Failed to publish {events} events after {attempts} attempts, {code:?}: {message}")]
+    Failed {
+        events: usize,
+        attempts: u32,
+        code: Code,
+        message: String,
+    },
+    #[error("The publisher stopped unexpectedly")]
+    Stopped,
+}
+
+// How a `Publisher` batches and retries
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishConfig {
+    // most events sent in one call
+    pub batch: usize,
+    // longest an event waits for its batch to fill, so results keep flowing from a stream
+    pub linger: Duration,
+    // calls repeated after a failure the downstream may recover from, before the run fails
+    pub retries: u32,
+    // wait before the first retry, doubled before each next one
+    pub backoff: Duration,
+}
+
+impl Default for PublishConfig {
+    fn default() -> Self {
+        Self {
+            batch: 100,
+            linger: Duration::from_secs(1),
+            retries: 5,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PublishStats {
+    pub events: u64,
+    pub batches: u64,
+    pub retries: u64,
+}
+
+// Pushes account updates and rejections to the `bank.Downstream` service of
+// proto/downstream.proto as the engine produces them. Events are sent in order, in batches, from
+// a thread of their own, so a slow downstream only holds up the engine once two batches of
+// events are waiting. A batch the downstream fails to take is sent again after a backoff; one
+// that still fails stops the publisher, and the next event or `finish` returns why.
+pub struct Publisher {
+    events: Option<SyncSender<pb::Event>>,
+    handle: Option<JoinHandle<Result<PublishStats, PublishError>>>,
+}
+
+impl Publisher {
+    // Connects on the first batch, so a downstream that isn't up yet gets the retries too
+    pub fn connect(address: Uri, config: PublishConfig) -> Result<Self, PublishError> {
+        let config = PublishConfig {
+            batch: config.batch.max(1),
+            ..config
+        };
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let channel = {
+            let _entered = runtime.enter();
+            Endpoint::from(address).connect_lazy()
+        };
+        let (events, received) = mpsc::sync_channel(config.batch * 2);
+        let handle = thread::Builder::new()
+            .name("publisher".to_string())
+            .spawn(move || run(runtime, DownstreamClient::new(channel), config, received))?;
+        Ok(Self {
+            events: Some(events),
+            handle: Some(handle),
+        })
+    }
+
+    // The account after transaction `tx` changed it
+    pub fn account<A: Amount>(
+        &mut self,
+        tx: u32,
+        account: &Account<A>,
+    ) -> Result<(), PublishError> {
+        let update = pb::AccountUpdate {
+            account: Some(account.try_into()?),
+            tx,
+        };
+        self.send(pb::event::Event::Account(update))
+    }
+
+    // A transaction the engine rejected, with the input line it was read from if any
+    pub fn rejection<A: Amount>(
+        &mut self,
+        line: Option<u64>,
+        transaction: &Transaction<A>,
+        error: &TransactionError,
+    ) -> Result<(), PublishError> {
+        let rejection = pb::Rejection {
+            transaction: Some(transaction.try_into()?),
+            code: error.code().to_string(),
+            line,
+        };
+        self.send(pb::event::Event::Rejection(rejection))
+    }
+
+    // Sends the events still waiting and waits for the downstream to take them
+    pub fn finish(mut self) -> Result<PublishStats, PublishError> {
+        self.events.take();
+        self.join()
+    }
+
+    fn send(&mut self, event: pb::event::Event) -> Result<(), PublishError> {
+        let sent = match &self.events {
+            Some(events) => events.send(pb::Event { event: Some(event) }).is_ok(),
+            None => false,
+        };
+        match sent {
+            true => Ok(()),
+            // the thread only hangs up when it stopped, and it says why when joined
+            false => {
+                self.events.take();
+                Err(self.join().err().unwrap_or(PublishError::Stopped))
+            }
+        }
+    }
+
+    fn join(&mut self) -> Result<PublishStats, PublishError> {
+        self.handle
+            .take()
+            .ok_or(PublishError::Stopped)?
+            .join()
+            .map_err(|_| PublishError::Stopped)?
+    }
+}
+
+fn run(
+    runtime: Runtime,
+    mut client: DownstreamClient<Channel>,
+    config: PublishConfig,
+    received: Receiver<pb::Event>,
+) -> Result<PublishStats, PublishError> {
+    let mut stats = PublishStats::default();
+    let mut events = Vec::with_capacity(config.batch);
+    let mut deadline: Option<Instant> = None;
+    let mut open = true;
+    while open {
+        let next = match deadline {
+            None => received.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            Some(deadline) => {
+                received.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+        };
+        match next {
+            Ok(event) => {
+                deadline.get_or_insert_with(|| Instant::now() + config.linger);
+                events.push(event);
+                if events.len() < config.batch {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => open = false,
+        }
+        deadline = None;
+        if events.is_empty() {
+            continue;
+        }
+        let batch = pb::EventBatch {
+            events: std::mem::take(&mut events),
+        };
+        stats.retries += u64::from(publish(&runtime, &mut client, &config, &batch)?);
+        stats.events += batch.events.len() as u64;
+        stats.batches += 1;
+    }
+    Ok(stats)
+}
+
+// Returns the retries it took
+fn publish(
+    runtime: &Runtime,
+    client: &mut DownstreamClient<Channel>,
+    config: &PublishConfig,
+    batch: &pb::EventBatch,
+) -> Result<u32, PublishError> {
+    let mut backoff = config.backoff;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let status = match runtime.block_on(client.publish(batch.clone())) {
+            Ok(_) => return Ok(attempts - 1),
+            Err(status) => status,
+        };
+        if attempts > config.retries || !transient(status.code()) {
+            return Err(PublishError::Failed {
+                events: batch.events.len(),
+                attempts,
+                code: status.code(),
+                message: status.message().to_string(),
+            });
+        }
+        warn!(attempt = attempts, error = %status, "Failed to publish events, retrying");
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+}
+
+// Failures the same call may get past later, as opposed to the downstream refusing the events
+fn transient(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted | Code::Aborted
+    )
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::sync::{Arc, Mutex};
+
+    use rust_decimal_macros::dec;
+    use tokio::sync::oneshot;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+
+    use super::*;
+    use crate::domain::transaction::Operation;
+
+    // Keeps the batches it's sent, turning away the first `failures` calls
+    #[derive(Clone, Default)]
+    struct Collector {
+        batches: Arc<Mutex<Vec<pb::EventBatch>>>,
+        failures: Arc<Mutex<u32>>,
+    }
+
+    #[tonic::async_trait]
+    impl Downstream for Collector {
+        async fn publish(
+            &self,
+            request: Request<pb::EventBatch>,
+        ) -> Result<Response<pb::PublishAck>, Status> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Status::unavailable("busy"));
+            }
+            self.batches.lock().unwrap().push(request.into_inner());
+            Ok(Response::new(pb::PublishAck {}))
+        }
+    }
+
+    #[test]
+    fn publishes_batches_and_retries_failed_ones() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let incoming = {
+            let _entered = runtime.enter();
+            TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap()
+        };
+        let address = incoming.local_addr().unwrap();
+        let collector = Collector {
+            failures: Arc::new(Mutex::new(2)),
+            ..Default::default()
+        };
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = runtime.spawn(
+            Server::builder()
+                .add_service(DownstreamServer::new(collector.clone()))
+                .serve_with_incoming_shutdown(incoming, async {
+                    stopped.await.ok();
+                }),
+        );
+
+        let config = PublishConfig {
+            batch: 2,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let address = format!("http://{address}").parse().unwrap();
+        let mut publisher = Publisher::connect(address, config).unwrap();
+        for tx in 1..=2 {
+            let account: Account = Account {
+                available: dec!(5),
+                total: dec!(5),
+                ..Account::new(1)
+            };
+            publisher.account(tx, &account).unwrap();
+        }
+        let rejected: Transaction = Transaction {
+            op: Operation::Withdrawal,
+            client: 1,
+            tx: 3,
+            amount: Some(dec!(25)),
+            ..Default::default()
+        };
+        publisher
+            .rejection(Some(4), &rejected, &TransactionError::InsufficientFunds)
+            .unwrap();
+        let stats = publisher.finish().unwrap();
+        stop.send(()).unwrap();
+        runtime.block_on(server).unwrap().unwrap();
+
+        assert_eq!(
+            stats,
+            PublishStats {
+                events: 3,
+                batches: 2,
+                retries: 2,
+            }
+        );
+        let batches = collector.batches.lock().unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].events.len(), 2);
+        match &batches[1].events[0].event {
+            Some(pb::event::Event::Rejection(rejection)) => {
+                assert_eq!(rejection.code, "insufficient_funds");
+                assert_eq!(rejection.line, Some(4));
+            }
+            other => panic!("Expected a rejection, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn stops_when_the_downstream_refuses_events() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let incoming = {
+            let _entered = runtime.enter();
+            TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap()
+        };
+        let address = incoming.local_addr().unwrap();
+        // more failures than retries
+        let collector = Collector {
+            failures: Arc::new(Mutex::new(10)),
+            ..Default::default()
+        };
+        runtime.spawn(
+            Server::builder()
+                .add_service(DownstreamServer::new(collector))
+                .serve_with_incoming(incoming),
+        );
+
+        let config = PublishConfig {
+            batch: 1,
+            retries: 1,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let address = format!("http://{address}").parse().unwrap();
+        let mut publisher = Publisher::connect(address, config).unwrap();
+        publisher
+            .account(1, &Account::<rust_decimal::Decimal>::new(1))
+            .unwrap();
+        assert!(matches!(
+            publisher.finish(),
+            Err(PublishError::Failed {
+                attempts: 2,
+                code: Code::Unavailable,
+                ..
+            })
+        ));
+    }
+}
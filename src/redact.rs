@@ -0,0 +1,127 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+
+use rust_decimal::Decimal;
+use tracing::error;
+
+use crate::domain::transaction::Extra;
+use crate::domain::{errors::TransactionError, ClientId, Transaction};
+
+// Keeps client ids and amounts out of logs and error messages. Clients are referenced by an
+// opaque token that is stable within a run but can't be correlated across runs.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    sensitive: bool,
+    // adds the input's extra columns to rejections, matching the `--echo-columns` flag
+    echo_columns: bool,
+    state: RandomState,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl Redactor {
+    // `sensitive` disables redaction entirely, matching the `--log-sensitive` flag
+    pub fn new(sensitive: bool) -> Self {
+        Self {
+            sensitive,
+            echo_columns: false,
+            state: RandomState::new(),
+        }
+    }
+
+    pub fn echo_columns(mut self, echo: bool) -> Self {
+        self.echo_columns = echo;
+        self
+    }
+
+    // Extra columns of a transaction to log if it's rejected, taken before the engine consumes it
+    pub fn columns(&self, transaction: &Transaction) -> Extra {
+        match self.echo_columns {
+            true => transaction.extra.clone(),
+            false => Extra::new(),
+        }
+    }
+
+    pub fn client(&self, client: ClientId) -> Redacted {
+        if self.sensitive {
+            Redacted::Plain(client.to_string())
+        } else {
+            Redacted::Token(self.state.hash_one(client) & 0xffff_ffff_ffff)
+        }
+    }
+
+    pub fn amount(&self, amount: Option<Decimal>) -> Redacted {
+        match amount {
+            Some(amt) if self.sensitive => Redacted::Plain(amt.to_string()),
+            Some(_) => Redacted::Hidden,
+            None => Redacted::Plain("none".to_string()),
+        }
+    }
+
+    // Logs a transaction the engine refused without exposing the client id
+    pub fn log_rejection(&self, tx: u32, client: ClientId, e: &TransactionError, extra: &Extra) {
+        if extra.is_empty() {
+            error!(
+                tx = tx, client = %self.client(client), error = %e,
+                "Failed to apply transaction"
+            );
+        } else {
+            error!(
+                tx = tx, client = %self.client(client), error = %e, columns = ?extra,
+                "Failed to apply transaction"
+            );
+        }
+    }
+
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redacted {
+    Plain(String),
+    Token(u64),
+    Hidden,
+}
+
+impl fmt::Display for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Redacted::Plain(value) => f.write_str(value),
+            Redacted::Token(token) => write!(f, "client-{token:012x}"),
+            Redacted::Hidden => f.write_str("<redacted>"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn redacts_by_default() {
+        let redactor = Redactor::default();
+
+        let token = redactor.client(42).to_string();
+        assert!(token.starts_with("client-"));
+        assert_eq!(redactor.client(42), redactor.client(42));
+        assert_ne!(redactor.client(42), redactor.client(43));
+        assert_eq!(redactor.amount(Some(dec!(10.5))).to_string(), "<redacted>");
+    }
+
+    #[test]
+    fn sensitive_mode_passes_values_through() {
+        let redactor = Redactor::new(true);
+
+        assert_eq!(redactor.client(42).to_string(), "42");
+        assert_eq!(redactor.amount(Some(dec!(10.5))).to_string(), "10.5");
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::domain::{errors::TransactionError, Transaction};
+
+#[derive(Error, Debug)]
+pub enum RejectsError {
+    #[error("Failed to write rejected transactions: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to encode rejected transaction: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Failed to encode rejected transaction: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+const HEADER: [&str; 7] = [
+    "line",
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "counterparty",
+    "error",
+];
+
+// Dead letter file of the transactions the engine rejected, so failures can be investigated
+// without going through the logs. Rejections are written as CSV with the columns in `HEADER`,
+// or as JSON lines that also keep the transaction's extra columns when the path ends in
+// `.jsonl`. The error is its `TransactionError::code`, the line the input line the transaction
+// was read from, empty for transactions received over a connection.
+pub struct Rejects {
+    writer: Writer,
+}
+
+enum Writer {
+    Csv(Box<csv::Writer<File>>),
+    Json(BufWriter<File>),
+}
+
+impl Rejects {
+    pub fn create(path: &Path) -> Result<Self, RejectsError> {
+        let writer = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") => Writer::Json(BufWriter::new(File::create(path)?)),
+            _ => {
+                let mut writer = csv::Writer::from_path(path)?;
+                writer.write_record(HEADER)?;
+                Writer::Csv(Box::new(writer))
+            }
+        };
+        Ok(Self { writer })
+    }
+
+    pub fn write(
+        &mut self,
+        line: Option<u64>,
+        transaction: &Transaction,
+        error: &TransactionError,
+    ) -> Result<(), RejectsError> {
+        match &mut self.writer {
+            Writer::Csv(writer) => {
+                let field = |value: Option<String>| value.unwrap_or_default();
+                writer.write_record([
+                    field(line.map(|line| line.to_string())),
+                    transaction.op.name().to_string(),
+                    transaction.client.to_string(),
+                    transaction.tx.to_string(),
+                    field(transaction.amount.map(|amount| amount.to_string())),
+                    field(transaction.counterparty.map(|client| client.to_string())),
+                    error.code().to_string(),
+                ])?;
+            }
+            Writer::Json(writer) => {
+                let mut record = serde_json::to_value(transaction)?;
+                let fields = record.as_object_mut().expect("Transactions are objects");
+                for (name, value) in transaction.extra.iter() {
+                    fields.insert(name.clone(), Value::from(value.as_str()));
+                }
+                fields.insert("line".into(), line.into());
+                fields.insert("error".into(), error.code().into());
+                serde_json::to_writer(&mut *writer, &record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), RejectsError> {
+        match self.writer {
+            Writer::Csv(mut writer) => writer.flush()?,
+            Writer::Json(mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::Operation;
+
+    #[test]
+    fn writes_rejections_as_csv_and_json_lines() {
+        let mut transaction = Transaction {
+            op: Operation::Withdrawal,
+            client: 3,
+            tx: 9,
+            amount: Some(dec!(2.5)),
+            ..Default::default()
+        };
+        transaction.extra.insert("ref".into(), "r-9".into());
+        let dir = std::env::temp_dir();
+        let csv = dir.join(format!("bank-rejects-{}.csv", std::process::id()));
+        let json = csv.with_extension("jsonl");
+
+        for path in [&csv, &json] {
+            let mut rejects = Rejects::create(path).unwrap();
+            let error = TransactionError::InsufficientFunds;
+            rejects.write(Some(4), &transaction, &error).unwrap();
+            rejects.write(None, &transaction, &error).unwrap();
+            rejects.finish().unwrap();
+        }
+
+        assert_eq!(
+            std::fs::read_to_string(&csv).unwrap(),
+            "line,type,client,tx,amount,counterparty,error\n\
+             4,withdrawal,3,9,2.5,,insufficient_funds\n\
+             ,withdrawal,3,9,2.5,,insufficient_funds\n"
+        );
+        let lines: Vec<Value> = std::fs::read_to_string(&json)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["line"], 4);
+        assert_eq!(lines[0]["ref"], "r-9");
+        assert_eq!(lines[1]["error"], "insufficient_funds");
+        assert!(lines[1]["line"].is_null());
+        std::fs::remove_file(csv).ok();
+        std::fs::remove_file(json).ok();
+    }
+}
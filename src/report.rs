@@ -0,0 +1,291 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::calendar::{self, Calendar, CalendarError, DAY};
+use crate::domain::transaction::Operation;
+use crate::engine::Engine;
+use crate::output;
+use crate::snapshot::{Snapshot, SnapshotError};
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error(transparent)]
+    Calendar(#[from] CalendarError),
+    #[error("Failed to write report: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to write report: {0}")]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+}
+
+// Times of day, in UTC, at which reports are written, parsed from a comma separated list such
+// as `06:00,17:30`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    // seconds after midnight, sorted
+    times: Vec<u64>,
+}
+
+impl FromStr for Schedule {
+    type Err = ReportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut times = s
+            .split(',')
+            .map(calendar::parse_time)
+            .collect::<Result<Vec<_>, _>>()?;
+        times.sort_unstable();
+        times.dedup();
+        Ok(Self { times })
+    }
+}
+
+impl Schedule {
+    // First scheduled time strictly after `now`
+    pub fn next_after(&self, now: SystemTime) -> SystemTime {
+        let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let midnight = secs - secs % DAY;
+        let next = self
+            .times
+            .iter()
+            .map(|time| midnight + time)
+            .find(|&time| time > secs)
+            .unwrap_or(midnight + DAY + self.times[0]);
+        UNIX_EPOCH + Duration::from_secs(next)
+    }
+}
+
+// When reports fall due and how they're dated
+#[derive(Debug)]
+enum Timing {
+    // at fixed UTC times, dated with the UTC day
+    Schedule(Schedule),
+    // at the close of every business day, dated with the day that closed
+    Close(Calendar),
+}
+
+// Writes the daily reports of a long running engine into a directory when they are due. Each
+// report is a set of files named after the date it covers:
+//   <date>-snapshot.json   accounts and history, as written by --snapshot
+//   <date>-disputes.csv    transactions under dispute, with their extra input columns if echoed
+//   <date>-summary.txt     account, lock and dispute counts and the funds held
+#[derive(Debug)]
+pub struct Reporter {
+    timing: Timing,
+    dir: PathBuf,
+    due: SystemTime,
+    echo_columns: bool,
+}
+
+impl Reporter {
+    pub fn new(schedule: Schedule, dir: &Path) -> Self {
+        Self {
+            due: schedule.next_after(SystemTime::now()),
+            timing: Timing::Schedule(schedule),
+            dir: dir.to_path_buf(),
+            echo_columns: false,
+        }
+    }
+
+    pub fn at_close(calendar: Calendar, dir: &Path) -> Self {
+        Self {
+            due: calendar.next_close(SystemTime::now()),
+            timing: Timing::Close(calendar),
+            dir: dir.to_path_buf(),
+            echo_columns: false,
+        }
+    }
+
+    pub fn echo_columns(mut self, echo: bool) -> Self {
+        self.echo_columns = echo;
+        self
+    }
+
+    // How long until the next report, zero if it is already due
+    pub fn until_due(&self) -> Duration {
+        self.due
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+    }
+
+    // Writes the report that is due and schedules the next one
+    pub fn write(&mut self, engine: &Engine) -> Result<PathBuf, ReportError> {
+        let date = match &self.timing {
+            Timing::Schedule(_) => calendar::date(self.due),
+            // the close itself already belongs to the next day
+            Timing::Close(calendar) => calendar.business_day(self.due - Duration::from_secs(1)),
+        };
+        write_report(engine, &self.dir, &date, self.echo_columns)?;
+        self.due = match &self.timing {
+            Timing::Schedule(schedule) => schedule.next_after(self.due),
+            Timing::Close(calendar) => calendar.next_close(self.due),
+        };
+        Ok(self.dir.join(format!("{date}-summary.txt")))
+    }
+}
+
+pub fn write_report(
+    engine: &Engine,
+    dir: &Path,
+    date: &str,
+    echo_columns: bool,
+) -> Result<(), ReportError> {
+    let snapshot = Snapshot::new(engine.history(), engine.accounts());
+    snapshot.save(&dir.join(format!("{date}-snapshot.json")))?;
+
+    let disputes: Vec<_> = snapshot
+        .history
+        .iter()
+        .filter(|rec| rec.op == Operation::Dispute)
+        .collect();
+    // one column for every extra column any of the disputed transactions has
+    let columns: BTreeSet<&String> = match echo_columns {
+        true => disputes.iter().flat_map(|rec| rec.extra.keys()).collect(),
+        false => BTreeSet::new(),
+    };
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let header = ["client", "tx", "op", "amount", "disputes"].map(String::from);
+    writer.write_record(header.iter().chain(columns.iter().copied()))?;
+    for rec in disputes.iter() {
+        let amount = rec.amount.map(|amount| amount.to_string());
+        let fields = [
+            rec.client.to_string(),
+            rec.tx.to_string(),
+            rec.op.name().to_string(),
+            amount.unwrap_or_default(),
+            rec.disputes.to_string(),
+        ];
+        let extra = columns
+            .iter()
+            .map(|&name| rec.extra.get(name).cloned().unwrap_or_default());
+        writer.write_record(fields.into_iter().chain(extra))?;
+    }
+    let contents = writer.into_inner().map_err(|e| e.into_error())?;
+    output::write_atomic(&dir.join(format!("{date}-disputes.csv")), |file| {
+        file.write_all(&contents)
+    })?;
+
+    let accounts = &snapshot.accounts;
+    let mut summary = String::new();
+    let sum = |field: fn(&_) -> Decimal| accounts.iter().map(field).sum::<Decimal>();
+    writeln!(summary, "date          {date}").ok();
+    writeln!(summary, "accounts      {}", accounts.len()).ok();
+    let locked = accounts.iter().filter(|act| act.locked).count();
+    writeln!(summary, "locked        {locked}").ok();
+    writeln!(summary, "disputes      {}", disputes.len()).ok();
+    writeln!(summary, "available     {:.4}", sum(|act| act.available)).ok();
+    writeln!(summary, "held          {:.4}", sum(|act| act.held)).ok();
+    writeln!(summary, "total         {:.4}", sum(|act| act.total)).ok();
+    output::write_atomic(&dir.join(format!("{date}-summary.txt")), |file| {
+        file.write_all(summary.as_bytes())
+    })?;
+    Ok(())
+}
+
+// Writes the accounts `Engine::dormant` finds to a CSV file and returns how many there were
+pub fn write_dormant(engine: &Engine, path: &Path, window: u64) -> Result<usize, ReportError> {
+    let dormant = engine.dormant(window);
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record([
+        "client",
+        "available",
+        "held",
+        "total",
+        "locked",
+        "last_seen",
+        "idle",
+    ])?;
+    for dormant in dormant.iter() {
+        let act = &dormant.account;
+        writer.write_record([
+            act.client.to_string(),
+            act.available.to_string(),
+            act.held.to_string(),
+            act.total.to_string(),
+            act.locked.to_string(),
+            dormant.last_seen.to_string(),
+            dormant.idle.to_string(),
+        ])?;
+    }
+    let contents = writer.into_inner().map_err(|e| e.into_error())?;
+    output::write_atomic(path, |file| file.write_all(&contents))?;
+    Ok(dormant.len())
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{env, fs};
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::Transaction;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn schedules_the_next_report() {
+        let schedule: Schedule = "17:30, 06:00".parse().unwrap();
+        // 2024-02-29 12:00 UTC
+        let noon = 1_709_208_000;
+
+        assert_eq!(schedule.next_after(at(noon)), at(noon + 5 * 3600 + 1800));
+        assert_eq!(
+            schedule.next_after(at(noon + 6 * 3600)),
+            at(noon + 18 * 3600)
+        );
+        assert!("24:00".parse::<Schedule>().is_err());
+        assert!("noon".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn writes_dated_reports() {
+        let mut engine = Engine::new();
+        for (op, client, tx, amount) in [
+            (Operation::Deposit, 1, 1, Some(dec!(10))),
+            (Operation::Deposit, 2, 2, Some(dec!(4))),
+            (Operation::Dispute, 2, 2, None),
+        ] {
+            let deposit = op == Operation::Deposit;
+            let mut transaction = Transaction {
+                op,
+                client,
+                tx,
+                amount,
+                ..Default::default()
+            };
+            if deposit {
+                let merchant = ("merchant".to_string(), format!("m-{tx}"));
+                transaction.extra.extend([merchant]);
+            }
+            engine.process(transaction).unwrap();
+        }
+        let dir = env::temp_dir().join(format!("bank-report-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        write_report(&engine, &dir, "2024-02-29", true).expect("Failed to write report");
+
+        let summary = fs::read_to_string(dir.join("2024-02-29-summary.txt")).unwrap();
+        assert!(summary.contains("disputes      1"));
+        assert!(summary.contains("held          4.0000"));
+        let disputes = fs::read_to_string(dir.join("2024-02-29-disputes.csv")).unwrap();
+        // the dispute keeps the columns of the deposit it disputes
+        assert_eq!(
+            disputes,
+            "client,tx,op,amount,disputes,merchant\n2,2,dispute,-4,1,m-2\n"
+        );
+        let snapshot = Snapshot::load(&dir.join("2024-02-29-snapshot.json")).unwrap();
+        assert_eq!(snapshot.accounts.len(), 2);
+        fs::remove_dir_all(dir).ok();
+    }
+}
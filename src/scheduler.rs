@@ -0,0 +1,326 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use crate::domain::transaction::Operation;
+use crate::domain::{ClientId, Transaction};
+use crate::engine::Engine;
+use crate::redact::Redactor;
+
+// Upper bound on queued transactions across all shards before `submit` blocks
+const MAX_PENDING: usize = 64 * 1024;
+
+// Spreads transactions over a fixed pool of workers. Clients are hashed to shards, one per
+// worker, and each shard has a single queue and engine, so a client's transactions are applied
+// in submission order by the one worker that owns it. A transaction between two shards, i.e. a
+// transfer or a dispute of one, waits for both shards to apply what was submitted before it and
+// is then applied by the submitting thread, with the other client's state moved over for it.
+pub struct Scheduler {
+    shards: Vec<Arc<Shard>>,
+    workers: Vec<JoinHandle<()>>,
+    // both sides of every transfer applied across shards, so its disputes take the same route
+    links: HashMap<(ClientId, u32), ClientId>,
+    redactor: Redactor,
+}
+
+struct Shard {
+    state: Mutex<ShardState>,
+    // signalled when transactions are queued or the scheduler closes
+    work: Condvar,
+    // signalled when the worker has applied everything queued
+    idle: Condvar,
+}
+
+#[derive(Default)]
+struct ShardState {
+    pending: VecDeque<Transaction>,
+    // None while the worker or the submitting thread is applying transactions
+    engine: Option<Engine>,
+    closed: bool,
+}
+
+impl Scheduler {
+    pub fn new(workers: usize, redactor: &Redactor) -> Self {
+        let shards: Vec<Arc<Shard>> = (0..workers.max(1))
+            .map(|_| {
+                Arc::new(Shard {
+                    state: Mutex::new(ShardState {
+                        engine: Some(match redactor.is_sensitive() {
+                            true => Engine::new().with_logged_clients(),
+                            false => Engine::new(),
+                        }),
+                        ..Default::default()
+                    }),
+                    work: Condvar::new(),
+                    idle: Condvar::new(),
+                })
+            })
+            .collect();
+        let workers = shards
+            .iter()
+            .map(|shard| {
+                let (shard, redactor) = (shard.clone(), redactor.clone());
+                thread::spawn(move || shard.work_loop(&redactor))
+            })
+            .collect();
+        Self {
+            shards,
+            workers,
+            links: HashMap::new(),
+            redactor: redactor.clone(),
+        }
+    }
+
+    pub fn submit(&mut self, transaction: Transaction) {
+        let home = self.index(transaction.client);
+        let counterparty = match transaction.op {
+            Operation::Transfer => transaction.recipient(),
+            Operation::Dispute | Operation::Resolve | Operation::Chargeback => self
+                .links
+                .get(&(transaction.client, transaction.tx))
+                .copied(),
+            _ => None,
+        };
+        match counterparty.map(|other| (other, self.index(other))) {
+            Some((other, away)) if away != home => {
+                self.apply_across(home, away, other, transaction)
+            }
+            _ => self.shards[home].push(transaction, MAX_PENDING / self.shards.len()),
+        }
+    }
+
+    // Waits for every submitted transaction to be applied and merges the shards' state
+    pub fn finish(mut self) -> Engine {
+        for shard in self.shards.iter() {
+            shard.lock().closed = true;
+            shard.work.notify_all();
+        }
+        for worker in self.workers.drain(..) {
+            worker.join().expect("Scheduler worker panicked");
+        }
+
+        let mut merged = Engine::new();
+        for shard in self.shards.iter() {
+            let engine = shard
+                .lock()
+                .engine
+                .take()
+                .expect("Shard engine returned by worker");
+            merged.merge(engine).expect("Shards hold disjoint clients");
+        }
+        merged
+    }
+
+    fn index(&self, client: ClientId) -> usize {
+        let mut hasher = DefaultHasher::new();
+        client.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    fn apply_across(
+        &mut self,
+        home: usize,
+        away: usize,
+        other: ClientId,
+        transaction: Transaction,
+    ) {
+        let (mut engine, mut away_engine) = (self.shards[home].drain(), self.shards[away].drain());
+        let (client, tx, op) = (transaction.client, transaction.tx, transaction.op.clone());
+        let extra = self.redactor.columns(&transaction);
+        match engine.process_with(&mut away_engine, other, transaction) {
+            Ok(()) if op == Operation::Transfer => {
+                self.links.insert((client, tx), other);
+                self.links.insert((other, tx), client);
+            }
+            Ok(()) => {}
+            Err(e) => self.redactor.log_rejection(tx, client, &e, &extra),
+        }
+        self.shards[home].lock().engine = Some(engine);
+        self.shards[away].lock().engine = Some(away_engine);
+    }
+}
+
+impl Shard {
+    fn lock(&self) -> MutexGuard<'_, ShardState> {
+        self.state.lock().expect("Scheduler lock poisoned")
+    }
+
+    fn push(&self, transaction: Transaction, capacity: usize) {
+        let mut state = self.lock();
+        while state.pending.len() >= capacity.max(1) {
+            state = self.idle.wait(state).expect("Scheduler lock poisoned");
+        }
+        state.pending.push_back(transaction);
+        self.work.notify_one();
+    }
+
+    // Waits for the worker to apply everything queued and takes the engine from it
+    fn drain(&self) -> Engine {
+        let mut state = self.lock();
+        while !state.pending.is_empty() || state.engine.is_none() {
+            state = self.idle.wait(state).expect("Scheduler lock poisoned");
+        }
+        state.engine.take().expect("Idle shard holds its engine")
+    }
+
+    fn work_loop(&self, redactor: &Redactor) {
+        loop {
+            let (mut engine, batch) = {
+                let mut state = self.lock();
+                while state.pending.is_empty() || state.engine.is_none() {
+                    if state.closed && state.pending.is_empty() {
+                        return;
+                    }
+                    state = self.work.wait(state).expect("Scheduler lock poisoned");
+                }
+                let batch: Vec<Transaction> = state.pending.drain(..).collect();
+                let engine = state.engine.take().expect("Queued shard holds its engine");
+                // the submitting thread may be waiting for room in the queue
+                self.idle.notify_all();
+                (engine, batch)
+            };
+
+            for transaction in batch {
+                let (tx_id, client) = (transaction.tx, transaction.client);
+                let extra = redactor.columns(&transaction);
+                if let Err(e) = engine.process(transaction) {
+                    redactor.log_rejection(tx_id, client, &e, &extra);
+                }
+            }
+
+            self.lock().engine = Some(engine);
+            self.idle.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::domain::transaction::Operation;
+    use crate::output;
+    use crate::snapshot::Snapshot;
+
+    fn transactions() -> Vec<Transaction> {
+        let mut out = Vec::new();
+        for tx in 0..2000u32 {
+            // client 0 is a whale with half of all traffic
+            let client = if tx % 2 == 0 {
+                0
+            } else {
+                (tx % 7) as ClientId + 1
+            };
+            let op = match tx % 5 {
+                0 | 1 => Operation::Deposit,
+                2 => Operation::Withdrawal,
+                3 => Operation::Dispute,
+                _ => Operation::Resolve,
+            };
+            let target = if matches!(op, Operation::Dispute | Operation::Resolve) {
+                tx - 2
+            } else {
+                tx
+            };
+            out.push(Transaction {
+                op,
+                client,
+                tx: target,
+                amount: Some(Decimal::from(tx % 13 + 1)),
+                ..Default::default()
+            });
+        }
+        out
+    }
+
+    #[test]
+    fn matches_serial_processing() {
+        let mut serial = Engine::new();
+        for transaction in transactions() {
+            let _ = serial.process(transaction);
+        }
+
+        let mut scheduler = Scheduler::new(4, &Redactor::default());
+        for transaction in transactions() {
+            scheduler.submit(transaction);
+        }
+        let parallel = scheduler.finish();
+
+        assert_eq!(parallel.accounts(), serial.accounts());
+    }
+
+    #[test]
+    fn transfers_between_shards_match_serial_processing() {
+        let transaction = |op, client, tx, counterparty| Transaction {
+            op,
+            client,
+            tx,
+            amount: Some(Decimal::from(3)),
+            counterparty,
+            ..Default::default()
+        };
+        let mut input = Vec::new();
+        for n in 0..16u32 {
+            input.push(transaction(Operation::Deposit, n as ClientId, n, None));
+        }
+        // every client pays the next one, and every other transfer is disputed by its recipient
+        for n in 0..16u32 {
+            let (client, tx, to) = (n as ClientId, 100 + n, ((n + 1) % 16) as ClientId);
+            input.push(transaction(Operation::Transfer, client, tx, Some(to)));
+            if n % 2 == 0 {
+                input.push(transaction(Operation::Dispute, to, tx, None));
+            }
+            if n % 4 == 0 {
+                input.push(transaction(Operation::Chargeback, client, tx, None));
+            }
+        }
+        let mut serial = Engine::new();
+        for transaction in input.iter().cloned() {
+            let _ = serial.process(transaction);
+        }
+
+        for workers in [1, 3, 8] {
+            let mut scheduler = Scheduler::new(workers, &Redactor::default());
+            for transaction in input.iter().cloned() {
+                scheduler.submit(transaction);
+            }
+            let parallel = scheduler.finish();
+
+            assert_eq!(parallel.accounts(), serial.accounts());
+            assert_eq!(
+                Snapshot::new(parallel.history(), parallel.accounts()),
+                Snapshot::new(serial.history(), serial.accounts())
+            );
+        }
+        assert!(serial.accounts().values().any(|act| act.locked));
+    }
+
+    #[test]
+    fn output_is_identical_for_any_worker_count() {
+        let mut serial = Engine::new();
+        for transaction in transactions() {
+            let _ = serial.process(transaction);
+        }
+        let expected = output::write_csv(serial.accounts(), vec![]).unwrap();
+
+        for workers in [1, 2, 8] {
+            let mut scheduler = Scheduler::new(workers, &Redactor::default());
+            for transaction in transactions() {
+                scheduler.submit(transaction);
+            }
+            let parallel = scheduler.finish();
+
+            assert_eq!(
+                output::write_csv(parallel.accounts(), vec![]).unwrap(),
+                expected
+            );
+            assert_eq!(
+                Snapshot::new(parallel.history(), parallel.accounts()),
+                Snapshot::new(serial.history(), serial.accounts())
+            );
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::domain::ClientId;
+use crate::engine::{BalanceCaps, Limits};
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("Failed to read settings {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid setting at line {line}: {reason}")]
+    Invalid { line: usize, reason: String },
+    #[error("Invalid setting in {var}: {reason}")]
+    Env { var: String, reason: String },
+    #[error("Failed to read client tiers {0:?}: {1}")]
+    Tiers(PathBuf, csv::Error),
+}
+
+// Reads the limits and fees of an engine from a settings file. Every line is a `<key> = <value>`
+// pair, blank lines and lines starting with `#` are ignored:
+//   balance-cap = 1000            cap for every client
+//   balance-cap-tier = basic=100  cap for a tier, may be repeated
+//   client-tiers = tiers.csv      `client,tier` rows assigning clients to tiers
+//   dispute-limit = 2             most times a transaction may be disputed
+//   dispute-window = 90           days after a transaction that it may still be disputed
+//   chargeback-fee = 15           charged after every chargeback
+//   deposit-fee = 0.5+1%          charged after every deposit, flat, a percentage or both
+//   withdrawal-fee = 1            charged after every withdrawal
+//   tx-order = reject             reject or flag deposit and withdrawal ids that don't increase
+//   timestamp-order = flag        reject or flag timestamps before the client's last activity
+//   lock-policy = settle          freeze locked accounts, or still settle their disputes
+//   dispute-policy = deposits-only   or deposits-and-withdrawals
+//   amount-precision = reject     round or reject amounts with more than four decimals
+//   credit-limit = 50             overdraft for every client
+//   credit-limit-client = 7=500   overdraft for a single client, may be repeated
+// Anything missing is unlimited or free. The whole file, client tiers included, is checked
+// before any of it is returned, so a bad file never leaves an engine half configured.
+pub fn load(path: &Path) -> Result<Limits, SettingsError> {
+    let contents = fs::read_to_string(path).map_err(|e| SettingsError::Io(path.into(), e))?;
+    parse(&contents)
+}
+
+pub fn parse(contents: &str) -> Result<Limits, SettingsError> {
+    let mut settings = Settings::default();
+    for entry in entries(contents) {
+        let (line, key, value) = entry?;
+        if !settings
+            .set(key, value)
+            .map_err(|reason| SettingsError::Invalid { line, reason })?
+        {
+            return Err(SettingsError::Invalid {
+                line,
+                reason: format!("unknown key {key}"),
+            });
+        }
+    }
+    settings.finish()
+}
+
+// The `key = value` pairs of a settings file with their line numbers, skipping blank lines and
+// comments. Values may be quoted like TOML strings.
+pub(crate) fn entries(
+    contents: &str,
+) -> impl Iterator<Item = Result<(usize, &str, &str), SettingsError>> {
+    contents.lines().enumerate().filter_map(|(idx, line)| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let entry = line
+            .split_once('=')
+            .map(|(key, value)| (idx + 1, key.trim(), unquote(value.trim())))
+            .ok_or_else(|| SettingsError::Invalid {
+                line: idx + 1,
+                reason: "expected <key> = <value>".into(),
+            });
+        Some(entry)
+    })
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+// Limits being read from settings, client tiers are assigned once every key has been read
+#[derive(Debug, Default)]
+pub(crate) struct Settings {
+    limits: Limits,
+    tiers: Option<PathBuf>,
+}
+
+impl Settings {
+    // Sets the limit `key` names, false when it isn't a settings key
+    pub(crate) fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        let limits = &mut self.limits;
+        match key {
+            "balance-cap" => {
+                let cap = amount(value).ok_or_else(|| "expected an amount".to_string())?;
+                limits.balance_caps = mem::take(&mut limits.balance_caps).global(cap);
+            }
+            "balance-cap-tier" => {
+                let (tier, cap) = value
+                    .split_once('=')
+                    .and_then(|(tier, cap)| Some((tier.trim(), amount(cap.trim())?)))
+                    .filter(|(tier, _)| !tier.is_empty())
+                    .ok_or_else(|| "expected <tier>=<amount>".to_string())?;
+                limits.balance_caps = mem::take(&mut limits.balance_caps).tier(tier, cap);
+            }
+            "client-tiers" => self.tiers = Some(PathBuf::from(value)),
+            "dispute-limit" => match value.parse() {
+                Ok(limit) if limit > 0 => limits.dispute_limit = Some(limit),
+                _ => return Err("expected a number from 1 to 255".to_string()),
+            },
+            "dispute-window" => {
+                let window = days(value).ok_or_else(|| "expected a number of days".to_string())?;
+                limits.dispute_window = Some(window);
+            }
+            "chargeback-fee" => {
+                let fee = amount(value).ok_or_else(|| "expected an amount".to_string())?;
+                limits.chargeback_fee = Some(fee);
+            }
+            "deposit-fee" => {
+                let fee = value.parse()?;
+                limits.fees = mem::take(&mut limits.fees).deposit(fee);
+            }
+            "withdrawal-fee" => {
+                let fee = value.parse()?;
+                limits.fees = mem::take(&mut limits.fees).withdrawal(fee);
+            }
+            "tx-order" => limits.tx_order = Some(value.parse()?),
+            "timestamp-order" => limits.timestamp_order = Some(value.parse()?),
+            "lock-policy" => limits.lock_policy = value.parse()?,
+            "dispute-policy" => limits.dispute_policy = value.parse()?,
+            "credit-limit" => {
+                let limit = amount(value).ok_or_else(|| "expected an amount".to_string())?;
+                limits.credit_limits = mem::take(&mut limits.credit_limits).global(limit);
+            }
+            "credit-limit-client" => {
+                let (client, limit) = value
+                    .split_once('=')
+                    .and_then(|(client, limit)| {
+                        Some((client.trim().parse().ok()?, amount(limit.trim())?))
+                    })
+                    .ok_or_else(|| "expected <client>=<amount>".to_string())?;
+                limits.credit_limits = mem::take(&mut limits.credit_limits).client(client, limit);
+            }
+            "amount-precision" => limits.amount_precision = value.parse()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn finish(mut self) -> Result<Limits, SettingsError> {
+        if let Some(path) = self.tiers {
+            assign_tiers(&mut self.limits.balance_caps, &path)
+                .map_err(|e| SettingsError::Tiers(path, e))?;
+        }
+        Ok(self.limits)
+    }
+}
+
+// Assigns clients to tiers from a CSV file of `client,tier` rows
+pub fn assign_tiers(caps: &mut BalanceCaps, path: &Path) -> Result<(), csv::Error> {
+    for row in csv::Reader::from_path(path)?.deserialize() {
+        let (client, tier): (ClientId, String) = row?;
+        caps.assign(client, tier);
+    }
+    Ok(())
+}
+
+// A number of whole days, as the dispute window is given
+pub fn days(value: &str) -> Option<Duration> {
+    value
+        .parse::<u64>()
+        .ok()
+        .and_then(|days| days.checked_mul(24 * 60 * 60))
+        .map(Duration::from_secs)
+}
+
+// A non-negative amount
+fn amount(value: &str) -> Option<Decimal> {
+    value
+        .parse::<Decimal>()
+        .ok()
+        .filter(|amount| !amount.is_sign_negative())
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::LockPolicy;
+    use crate::engine::{AmountPrecision, DisputePolicy, Fee, FeeModel, TxOrder};
+
+    #[test]
+    fn parses_limits_and_rejects_bad_files() {
+        let limits = parse(
+            "# limits for the e-money license\n\
+             balance-cap = 1000\n\
+             balance-cap-tier = basic = 100\n\
+             \n\
+             dispute-limit = 2\n\
+             dispute-window = 90\n\
+             chargeback-fee = 15\n\
+             deposit-fee = 0.5 + 1%\n\
+             tx-order = flag\n\
+             timestamp-order = reject\n\
+             lock-policy = settle\n\
+             dispute-policy = deposits-only\n\
+             amount-precision = reject\n\
+             credit-limit = 50\n\
+             credit-limit-client = 7 = 500\n",
+        )
+        .unwrap();
+
+        assert_eq!(limits.balance_caps.cap(1), Some(dec!(1000)));
+        assert_eq!(limits.dispute_limit, Some(2));
+        assert_eq!(
+            limits.dispute_window,
+            Some(Duration::from_secs(90 * 24 * 60 * 60))
+        );
+        assert_eq!(limits.chargeback_fee, Some(dec!(15)));
+        assert_eq!(
+            limits.fees,
+            FeeModel::new().deposit(Fee {
+                flat: dec!(0.5),
+                percent: dec!(1)
+            })
+        );
+        assert_eq!(limits.tx_order, Some(TxOrder::Flag));
+        assert_eq!(limits.timestamp_order, Some(TxOrder::Reject));
+        assert_eq!(limits.lock_policy, LockPolicy::Settle);
+        assert_eq!(limits.dispute_policy, DisputePolicy::DepositsOnly);
+        assert_eq!(limits.amount_precision, AmountPrecision::Reject);
+        assert_eq!(limits.credit_limits.limit(1), Some(dec!(50)));
+        assert_eq!(limits.credit_limits.limit(7), Some(dec!(500)));
+        assert_eq!(parse("").unwrap(), Limits::default());
+        assert!(matches!(
+            parse("dispute-limit = 2\nbalance-cap = -5\n"),
+            Err(SettingsError::Invalid { line: 2, .. })
+        ));
+        assert!(parse("overdraft = 10\n").is_err());
+        assert!(parse("client-tiers = /nonexistent/tiers.csv\n").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+
+use thiserror::Error;
+use tracing::error;
+
+use crate::domain::ClientId;
+use crate::engine::{Engine, ProcessingReport};
+use crate::input::{OperationFilter, Transactions, UnknownPolicy};
+use crate::redact::Redactor;
+
+#[derive(Error, Debug)]
+pub enum ShardError {
+    #[error("Failed to read shard directory: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to read shard {0:?}: {1}")]
+    Read(PathBuf, csv::Error),
+    #[error("Client {0} appears in more than one shard")]
+    OverlappingClient(ClientId),
+    #[error("Shard worker for {0:?} panicked")]
+    Worker(PathBuf),
+}
+
+// Lists the CSV files of a shard directory in a stable order
+pub fn shard_files(dir: &Path) -> Result<Vec<PathBuf>, ShardError> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "csv") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+// Runs a single CSV file through its own engine. Rejected records are logged and skipped.
+pub fn process_file(path: &Path, redactor: &Redactor) -> Result<Engine, csv::Error> {
+    let mut engine = Engine::new();
+    apply_file(&mut engine, path, redactor)?;
+    Ok(engine)
+}
+
+// Applies every record of a CSV file to an existing engine
+pub fn apply_file(
+    engine: &mut Engine,
+    path: &Path,
+    redactor: &Redactor,
+) -> Result<ProcessingReport, csv::Error> {
+    let start = engine.report();
+    let started = Instant::now();
+    let mut reader = Transactions::new(csv::Reader::from_reader(File::open(path)?))?;
+    let mut filter = OperationFilter::new(UnknownPolicy::Skip).expect("Skipping opens no files");
+    let (mut malformed, mut skipped) = (0, 0);
+    for record in reader.by_ref() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => match e.position() {
+                Some(pos) => {
+                    error!(line = pos.line(), "Failed to deserialize record");
+                    malformed += 1;
+                    continue;
+                }
+                None => return Err(e),
+            },
+        };
+        if !matches!(filter.admit(&record), Ok(true)) {
+            skipped += 1;
+            continue;
+        }
+        let (client, tx_id) = (record.client, record.tx);
+        let extra = redactor.columns(&record);
+        if let Err(e) = engine.process(record) {
+            redactor.log_rejection(tx_id, client, &e, &extra);
+        }
+    }
+    let mut report = engine.report_since(&start);
+    report.rows += malformed + skipped;
+    report.malformed = malformed;
+    report.skipped = skipped;
+    report.bytes = reader.bytes();
+    report.duration = started.elapsed();
+    Ok(report)
+}
+
+// Processes every shard on its own thread with an independent engine, then merges the results.
+// Shards must hold disjoint clients since no state is shared while they run.
+pub fn process_shards(paths: &[PathBuf], redactor: &Redactor) -> Result<Engine, ShardError> {
+    let handles: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            let (path, redactor) = (path.clone(), redactor.clone());
+            thread::spawn(move || process_file(&path, &redactor))
+        })
+        .collect();
+
+    let mut merged = Engine::new();
+    for (path, handle) in paths.iter().zip(handles) {
+        let engine = handle
+            .join()
+            .map_err(|_| ShardError::Worker(path.clone()))?
+            .map_err(|e| ShardError::Read(path.clone(), e))?;
+        merged
+            .merge(engine)
+            .map_err(ShardError::OverlappingClient)?;
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::env;
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::output;
+
+    fn shard_dir(name: &str, shards: &[&str]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bank-shards-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("Failed to create shard dir");
+        for (idx, rows) in shards.iter().enumerate() {
+            let body = format!("type,client,tx,amount\n{rows}");
+            fs::write(dir.join(format!("{idx}.csv")), body).expect("Failed to write shard");
+        }
+        dir
+    }
+
+    #[test]
+    fn merges_disjoint_shards() {
+        let dir = shard_dir(
+            "disjoint",
+            &[
+                "deposit,1,1,10\nwithdrawal,1,2,4\n",
+                "deposit,2,3,7\ndispute,2,3,\n",
+            ],
+        );
+        let paths = shard_files(&dir).expect("Failed to list shards");
+        let engine =
+            process_shards(&paths, &Redactor::default()).expect("Failed to process shards");
+
+        assert_eq!(engine.accounts()[&1].total, dec!(6));
+        assert_eq!(engine.accounts()[&2].held, dec!(7));
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn matches_serial_processing() {
+        let dir = shard_dir(
+            "serial",
+            &[
+                "deposit,3,1,10\nwithdrawal,3,2,4\ndispute,3,1,\n",
+                "deposit,1,3,7\ndeposit,2,4,2\nchargeback,2,4,\n",
+                "deposit,5,5,1.5\nresolve,5,5,\n",
+            ],
+        );
+        let paths = shard_files(&dir).expect("Failed to list shards");
+        let parallel =
+            process_shards(&paths, &Redactor::default()).expect("Failed to process shards");
+        let mut serial = Engine::new();
+        for path in paths.iter() {
+            apply_file(&mut serial, path, &Redactor::default()).expect("Failed to read shard");
+        }
+
+        assert_eq!(
+            output::write_csv(parallel.accounts(), vec![]).unwrap(),
+            output::write_csv(serial.accounts(), vec![]).unwrap()
+        );
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn rejects_overlapping_shards() {
+        let dir = shard_dir("overlap", &["deposit,1,1,10\n", "deposit,1,2,5\n"]);
+        let paths = shard_files(&dir).expect("Failed to list shards");
+        let res = process_shards(&paths, &Redactor::default());
+
+        assert!(matches!(res, Err(ShardError::OverlappingClient(1))));
+        fs::remove_dir_all(dir).ok();
+    }
+}
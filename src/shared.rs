@@ -0,0 +1,237 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "tower")]
+use std::task::{Context, Poll};
+
+use crate::domain::{
+    errors::TransactionError, tx_history::Node, Account, AccountStore, ClientId, Transaction,
+};
+use crate::engine::Engine;
+
+// Engine handle for servers applying transactions from many threads at once. Clients are spread
+// over a fixed set of engines, each behind its own lock, so transactions for different clients
+// mostly proceed in parallel while every client's transactions are applied one at a time, in the
+// order their `process` calls acquire the lock. Clones share the same state. A transfer between
+// two shards, or a dispute of one, locks both of them, in shard order so crossing transfers
+// can't deadlock, and moves the other client's state over while it's applied.
+#[derive(Debug, Clone)]
+pub struct SharedEngine {
+    shards: Arc<Vec<Mutex<Engine>>>,
+}
+
+impl SharedEngine {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: Arc::new(
+                (0..shards.max(1))
+                    .map(|_| Mutex::new(Engine::new()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn process(&self, transaction: Transaction) -> Result<(), TransactionError> {
+        let home = self.index(transaction.client);
+        let mut engine = self.lock(home);
+        let (other, away) = match engine.counterparty(&transaction) {
+            Some(other) if self.index(other) != home => (other, self.index(other)),
+            _ => return engine.process(transaction),
+        };
+        drop(engine);
+        let (mut first, mut second) = (self.lock(home.min(away)), self.lock(home.max(away)));
+        match home < away {
+            true => first.process_with(&mut second, other, transaction),
+            false => second.process_with(&mut first, other, transaction),
+        }
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<Account> {
+        self.shard(client).accounts().get(&client).cloned()
+    }
+
+    // The client's transaction as the history keeps it
+    pub fn transaction(&self, client: ClientId, tx: u32) -> Option<Node> {
+        self.shard(client).history().get(&(client, tx))
+    }
+
+    // Copies every account, locking one shard at a time
+    pub fn accounts(&self) -> AccountStore {
+        let mut accounts = AccountStore::new();
+        for shard in self.shards.iter() {
+            let engine = shard.lock().expect("Engine lock poisoned");
+            accounts.extend(engine.accounts().iter().map(|(c, act)| (*c, act.clone())));
+        }
+        accounts
+    }
+
+    // Merges the shards back into a single engine, None while other handles are still alive
+    pub fn into_engine(self) -> Option<Engine> {
+        let shards = Arc::into_inner(self.shards)?;
+        let mut merged = Engine::new();
+        for shard in shards {
+            let engine = shard.into_inner().expect("Engine lock poisoned");
+            merged
+                .merge(engine)
+                .expect("Clients are only ever held by one shard");
+        }
+        Some(merged)
+    }
+
+    fn shard(&self, client: ClientId) -> MutexGuard<'_, Engine> {
+        self.lock(self.index(client))
+    }
+
+    fn index(&self, client: ClientId) -> usize {
+        let mut hasher = DefaultHasher::new();
+        client.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+
+    fn lock(&self, idx: usize) -> MutexGuard<'_, Engine> {
+        self.shards[idx].lock().expect("Engine lock poisoned")
+    }
+}
+
+// Lets tower middleware such as timeouts, rate limits, load shedding and retries wrap the engine.
+// Transactions are applied on the calling task, `process` only waits for its shards' locks, so the
+// service is always ready and its futures are already complete.
+#[cfg(feature = "tower")]
+impl tower::Service<Transaction> for SharedEngine {
+    type Response = ();
+    type Error = TransactionError;
+    type Future = std::future::Ready<Result<(), TransactionError>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, transaction: Transaction) -> Self::Future {
+        std::future::ready(self.process(transaction))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::thread;
+
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::Operation;
+
+    fn transaction(op: Operation, client: ClientId, tx: u32) -> Transaction {
+        Transaction {
+            op,
+            client,
+            tx,
+            amount: Some(dec!(1.5)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn handles_are_shareable_across_threads() {
+        fn assert_shareable<T: Send + Sync + Clone>() {}
+        assert_shareable::<SharedEngine>();
+    }
+
+    #[test]
+    fn concurrent_submissions_match_serial_processing() {
+        let shared = SharedEngine::new(4);
+        let mut serial = Engine::new();
+        let batches: Vec<Vec<Transaction>> = (0..8)
+            .map(|client| {
+                (0..100)
+                    .map(|tx| match tx % 10 {
+                        9 => transaction(Operation::Withdrawal, client, tx),
+                        _ => transaction(Operation::Deposit, client, tx),
+                    })
+                    .collect()
+            })
+            .collect();
+        for batch in batches.iter() {
+            for tx in batch {
+                serial.process(tx.clone()).ok();
+            }
+        }
+
+        let handles: Vec<_> = batches
+            .into_iter()
+            .map(|batch| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for tx in batch {
+                        shared.process(tx).ok();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(shared.account(3), serial.accounts().get(&3).cloned());
+        assert_eq!(&shared.accounts(), serial.accounts());
+        let merged = shared.into_engine().expect("No other handles");
+        assert_eq!(merged.accounts(), serial.accounts());
+    }
+
+    #[test]
+    fn transfers_between_shards() {
+        let shared = SharedEngine::new(4);
+        for client in 0..8 {
+            shared
+                .process(transaction(Operation::Deposit, client, 1))
+                .unwrap();
+        }
+        let handles: Vec<_> = (0..8u32)
+            .map(|n| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let transfer = Transaction {
+                        counterparty: Some(((n + 1) % 8) as ClientId),
+                        ..transaction(Operation::Transfer, n as ClientId, 10 + n)
+                    };
+                    shared.process(transfer).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        shared
+            .process(transaction(Operation::Dispute, 3, 12))
+            .unwrap();
+
+        // everyone paid and got paid, and the dispute held the funds client 2 sent to 3
+        assert_eq!(shared.account(0).unwrap().available, dec!(1.5));
+        assert_eq!(shared.account(3).unwrap().held, dec!(1.5));
+        assert_eq!(shared.account(3).unwrap().available, dec!(0));
+        let merged = shared.into_engine().expect("No other handles");
+        assert_eq!(merged.history().iter().count(), 24);
+    }
+
+    #[cfg(feature = "tower")]
+    #[test]
+    fn serves_transactions_through_tower() {
+        use futures::{executor, future};
+        use tower::Service;
+
+        let mut service = SharedEngine::new(2);
+        let mut call = |transaction| {
+            executor::block_on(async {
+                future::poll_fn(|cx| service.poll_ready(cx)).await?;
+                service.call(transaction).await
+            })
+        };
+
+        call(transaction(Operation::Deposit, 1, 1)).unwrap();
+        call(transaction(Operation::Withdrawal, 1, 2)).unwrap();
+        assert_eq!(
+            call(transaction(Operation::Withdrawal, 1, 3)),
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(service.account(1).unwrap().total, dec!(0));
+    }
+}
@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_hangup(_: libc::c_int) {
+    RELOAD.store(true, Ordering::SeqCst);
+}
+
+// Makes SIGHUP ask for a reload instead of terminating the process
+pub fn watch_reload() {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_hangup;
+        libc::signal(libc::SIGHUP, handler as libc::sighandler_t);
+    }
+}
+
+// Whether a reload was asked for since the last call
+pub fn take_reload() -> bool {
+    RELOAD.swap(false, Ordering::SeqCst)
+}
+
+// Signal that asked the run to stop, 0 while none has
+static SHUTDOWN: AtomicI32 = AtomicI32::new(0);
+// Write end of a pipe the shutdown handler writes to, so readers blocked waiting for input wake
+// up whichever thread the signal was delivered to. -1 until `watch_shutdown` creates it.
+static WAKE_WRITE: AtomicI32 = AtomicI32::new(-1);
+static WAKE_READ: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_shutdown(signal: libc::c_int) {
+    SHUTDOWN.store(signal, Ordering::SeqCst);
+    // SAFETY: write is async-signal-safe, and the write end is non-blocking so a full pipe
+    // can't hang the handler
+    unsafe {
+        libc::write(WAKE_WRITE.load(Ordering::SeqCst), [1u8].as_ptr().cast(), 1);
+    }
+}
+
+// Makes SIGINT and SIGTERM ask the run to stop reading instead of terminating the process. The
+// handler is installed without SA_RESTART, so a blocked read returns, and with SA_RESETHAND, so a
+// second signal terminates right away in case stopping gets stuck.
+pub fn watch_shutdown() {
+    let mut fds = [-1; 2];
+    // SAFETY: `fds` has room for both ends of the pipe, and fcntl only changes their flags
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) == 0 {
+            for fd in fds {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+            libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK);
+            WAKE_READ.store(fds[0], Ordering::SeqCst);
+            WAKE_WRITE.store(fds[1], Ordering::SeqCst);
+        }
+    }
+    // SAFETY: the handler only stores to an atomic and writes to a pipe, both of which are
+    // async-signal-safe; the sigaction struct is fully initialised before it's installed
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_shutdown;
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+    }
+}
+
+// The signal that asked the run to stop, if one did
+pub fn shutdown() -> Option<i32> {
+    match SHUTDOWN.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+// Input that ends as soon as the run is asked to stop, even while it waits for more data, e.g.
+// on a stdin nothing is written to. Reads wait for the input or the shutdown pipe to be ready.
+pub struct Interruptible {
+    file: File,
+}
+
+impl Interruptible {
+    pub fn new(file: File) -> Self {
+        Self { file }
+    }
+}
+
+impl Read for Interruptible {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if shutdown().is_some() {
+                return Ok(0);
+            }
+            let mut fds = [
+                libc::pollfd {
+                    fd: self.file.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                // a negative fd is skipped, when shutdown isn't watched
+                libc::pollfd {
+                    fd: WAKE_READ.load(Ordering::SeqCst),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            // SAFETY: `fds` is a valid array of the length passed
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => return Err(e),
+                }
+            }
+            if fds[0].revents == 0 {
+                continue;
+            }
+            match self.file.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::os::fd::FromRawFd;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use bank::input::{InputFormat, Records};
+
+    use super::*;
+
+    #[test]
+    fn shutdown_ends_a_blocked_reader() {
+        watch_shutdown();
+        let mut fds = [-1; 2];
+        // SAFETY: `fds` has room for both ends of the pipe
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: both ends were just opened and are owned by these files alone
+        let (input, mut writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        io::Write::write_all(&mut writer, b"type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+
+        let (done, finished) = mpsc::channel();
+        thread::spawn(move || {
+            let records = Records::new(Interruptible::new(input), InputFormat::Csv).unwrap();
+            done.send(records.count()).unwrap();
+        });
+        // the reader has the record and waits for more, the write end is still open
+        thread::sleep(Duration::from_millis(100));
+        assert!(finished.try_recv().is_err());
+
+        // SAFETY: raises a signal the handler above is installed for
+        unsafe {
+            libc::kill(libc::getpid(), libc::SIGTERM);
+        }
+        let read = finished
+            .recv_timeout(Duration::from_secs(5))
+            .expect("Reader still blocked after SIGTERM");
+        assert_eq!(read, 1);
+        assert_eq!(shutdown(), Some(libc::SIGTERM));
+        drop(writer);
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::domain::transaction::{Extra, Operation};
+use crate::domain::{Account, AccountStore, Amount, ClientId, History};
+use crate::output;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Failed to access snapshot: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed snapshot: {0}")]
+    Format(#[from] serde_json::Error),
+}
+
+// Point in time copy of the engine state. Balances are kept at full precision so that
+// comparing two snapshots doesn't hide sub-cent drift.
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Snapshot {
+    pub accounts: Vec<AccountRecord>,
+    pub history: Vec<HistoryRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct AccountRecord {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct HistoryRecord {
+    pub client: ClientId,
+    pub tx: u32,
+    pub op: Operation,
+    pub amount: Option<Decimal>,
+    // times the transaction has been disputed, including an open dispute
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub disputes: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    // the other client of a transfer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<ClientId>,
+    #[serde(default, skip_serializing_if = "Extra::is_empty")]
+    pub extra: Extra,
+}
+
+impl<A: Amount> From<&Account<A>> for AccountRecord {
+    fn from(value: &Account<A>) -> Self {
+        Self {
+            client: value.client,
+            available: value.available.to_decimal(),
+            held: value.held.to_decimal(),
+            total: value.total.to_decimal(),
+            locked: value.locked,
+        }
+    }
+}
+
+fn is_zero(n: &u8) -> bool {
+    *n == 0
+}
+
+impl Snapshot {
+    // Amounts of any type are kept as decimals, which hold every amount exactly
+    pub fn new<A: Amount>(history: &History<A>, accounts: &AccountStore<A>) -> Self {
+        let mut accounts: Vec<AccountRecord> = accounts.values().map(AccountRecord::from).collect();
+        accounts.sort_by_key(|act| act.client);
+
+        let mut history: Vec<HistoryRecord> = history
+            .iter()
+            .map(|((client, tx), node)| HistoryRecord {
+                client,
+                tx,
+                op: node.op,
+                amount: node.amount.map(|amount| amount.to_decimal()),
+                disputes: node.disputes,
+                timestamp: node.timestamp,
+                counterparty: node.counterparty,
+                extra: node.extra,
+            })
+            .collect();
+        history.sort_by_key(|rec| (rec.client, rec.tx));
+
+        Self { accounts, history }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let contents = serde_json::to_vec(self)?;
+        Ok(output::write_atomic(path, |file| {
+            file.write_all(&contents)
+        })?)
+    }
+
+    pub fn account(&self, client: ClientId) -> Option<&AccountRecord> {
+        self.accounts.iter().find(|act| act.client == client)
+    }
+
+    // Transactions of a client currently under dispute
+    pub fn open_disputes(&self, client: ClientId) -> impl Iterator<Item = &HistoryRecord> {
+        self.history
+            .iter()
+            .filter(move |rec| rec.client == client && rec.op == Operation::Dispute)
+    }
+
+    // Lists what changed between this snapshot and a later one, ordered by client id.
+    pub fn diff(&self, after: &Snapshot) -> Vec<Change> {
+        let before_accounts: HashMap<ClientId, &AccountRecord> =
+            self.accounts.iter().map(|act| (act.client, act)).collect();
+        let after_clients: HashSet<ClientId> =
+            after.accounts.iter().map(|act| act.client).collect();
+        let before_disputes: HashSet<(ClientId, u32)> = self
+            .history
+            .iter()
+            .filter(|rec| rec.op == Operation::Dispute)
+            .map(|rec| (rec.client, rec.tx))
+            .collect();
+
+        let mut changes = Vec::new();
+        for act in after.accounts.iter() {
+            match before_accounts.get(&act.client) {
+                None => changes.push(Change::Created(act.clone())),
+                Some(prev) => {
+                    let (available, held, total) = (
+                        act.available - prev.available,
+                        act.held - prev.held,
+                        act.total - prev.total,
+                    );
+                    if !(available.is_zero() && held.is_zero() && total.is_zero()) {
+                        changes.push(Change::Balance {
+                            client: act.client,
+                            available,
+                            held,
+                            total,
+                        });
+                    }
+                    match (prev.locked, act.locked) {
+                        (false, true) => changes.push(Change::Locked(act.client)),
+                        (true, false) => changes.push(Change::Unlocked(act.client)),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        for act in self.accounts.iter() {
+            if !after_clients.contains(&act.client) {
+                changes.push(Change::Removed(act.client));
+            }
+        }
+        for rec in after.history.iter() {
+            if rec.op == Operation::Dispute && !before_disputes.contains(&(rec.client, rec.tx)) {
+                changes.push(Change::Disputed {
+                    client: rec.client,
+                    tx: rec.tx,
+                });
+            }
+        }
+
+        changes.sort_by_key(|change| change.client());
+        changes
+    }
+}
+
+// Engine state grouped by client for reading by hand, e.g. attached to a bug report. Every
+// transaction is listed under its client with its latest state (deposit, withdrawal, dispute,
+// resolve or chargeback).
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct StateDump {
+    pub clients: Vec<ClientDump>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct ClientDump {
+    #[serde(flatten)]
+    pub account: AccountRecord,
+    pub transactions: Vec<TransactionDump>,
+}
+
+#[derive(Debug, PartialEq, serde::Serialize)]
+pub struct TransactionDump {
+    pub tx: u32,
+    pub state: Operation,
+    pub amount: Option<Decimal>,
+    #[serde(skip_serializing_if = "is_zero")]
+    pub disputes: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<ClientId>,
+    #[serde(skip_serializing_if = "Extra::is_empty")]
+    pub extra: Extra,
+}
+
+impl From<Snapshot> for StateDump {
+    fn from(value: Snapshot) -> Self {
+        let mut history = value.history.into_iter().peekable();
+        let clients = value
+            .accounts
+            .into_iter()
+            .map(|account| {
+                // both lists are sorted by client, so each client's history is the next run
+                while history.next_if(|rec| rec.client < account.client).is_some() {}
+                let mut transactions = Vec::new();
+                while let Some(rec) = history.next_if(|rec| rec.client == account.client) {
+                    transactions.push(TransactionDump {
+                        tx: rec.tx,
+                        state: rec.op,
+                        amount: rec.amount,
+                        disputes: rec.disputes,
+                        timestamp: rec.timestamp,
+                        counterparty: rec.counterparty,
+                        extra: rec.extra,
+                    });
+                }
+                ClientDump {
+                    account,
+                    transactions,
+                }
+            })
+            .collect();
+        Self { clients }
+    }
+}
+
+impl StateDump {
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let contents = serde_json::to_vec_pretty(self)?;
+        Ok(output::write_atomic(path, |file| {
+            file.write_all(&contents)
+        })?)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Created(AccountRecord),
+    Removed(ClientId),
+    Balance {
+        client: ClientId,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+    },
+    Locked(ClientId),
+    Unlocked(ClientId),
+    Disputed {
+        client: ClientId,
+        tx: u32,
+    },
+}
+
+impl Change {
+    pub fn client(&self) -> ClientId {
+        match self {
+            Change::Created(act) => act.client,
+            Change::Removed(client) | Change::Locked(client) | Change::Unlocked(client) => *client,
+            Change::Balance { client, .. } | Change::Disputed { client, .. } => *client,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Created(act) => write!(
+                f,
+                "created client {}: available {:.4}, held {:.4}, total {:.4}, locked {}",
+                act.client, act.available, act.held, act.total, act.locked
+            ),
+            Change::Removed(client) => write!(f, "removed client {client}"),
+            Change::Balance {
+                client,
+                available,
+                held,
+                total,
+            } => write!(
+                f,
+                "balance client {client}: available {available:+.4}, held {held:+.4}, total {total:+.4}"
+            ),
+            Change::Locked(client) => write!(f, "locked client {client}"),
+            Change::Unlocked(client) => write!(f, "unlocked client {client}"),
+            Change::Disputed { client, tx } => write!(f, "disputed client {client} tx {tx}"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn record(client: ClientId, available: Decimal, held: Decimal, locked: bool) -> AccountRecord {
+        AccountRecord {
+            client,
+            available,
+            held,
+            total: available + held,
+            locked,
+        }
+    }
+
+    #[test]
+    fn diff_reports_changes() {
+        let before = Snapshot {
+            accounts: vec![
+                record(1, dec!(100), dec!(0), false),
+                record(2, dec!(5), dec!(0), false),
+            ],
+            history: vec![HistoryRecord {
+                client: 1,
+                tx: 1,
+                op: Operation::Deposit,
+                amount: Some(dec!(100)),
+                disputes: 0,
+                timestamp: None,
+                counterparty: None,
+                extra: Extra::new(),
+            }],
+        };
+        let after = Snapshot {
+            accounts: vec![
+                record(1, dec!(0), dec!(100), false),
+                record(2, dec!(5), dec!(0), true),
+                record(3, dec!(7), dec!(0), false),
+            ],
+            history: vec![HistoryRecord {
+                client: 1,
+                tx: 1,
+                op: Operation::Dispute,
+                amount: Some(dec!(-100)),
+                disputes: 0,
+                timestamp: None,
+                counterparty: None,
+                extra: Extra::new(),
+            }],
+        };
+
+        let changes = before.diff(&after);
+
+        assert_eq!(
+            changes,
+            vec![
+                Change::Balance {
+                    client: 1,
+                    available: dec!(-100),
+                    held: dec!(100),
+                    total: dec!(0),
+                },
+                Change::Disputed { client: 1, tx: 1 },
+                Change::Locked(2),
+                Change::Created(record(3, dec!(7), dec!(0), false)),
+            ]
+        );
+        assert_eq!(
+            changes[0].to_string(),
+            "balance client 1: available -100.0000, held +100.0000, total +0.0000"
+        );
+    }
+
+    #[test]
+    fn finds_open_disputes() {
+        let snapshot = Snapshot {
+            accounts: vec![record(1, dec!(0), dec!(3), false)],
+            history: vec![
+                HistoryRecord {
+                    client: 1,
+                    tx: 1,
+                    op: Operation::Dispute,
+                    amount: Some(dec!(-3)),
+                    disputes: 0,
+                    timestamp: None,
+                    counterparty: None,
+                    extra: Extra::new(),
+                },
+                HistoryRecord {
+                    client: 1,
+                    tx: 2,
+                    op: Operation::Resolve,
+                    amount: Some(dec!(4)),
+                    disputes: 0,
+                    timestamp: None,
+                    counterparty: None,
+                    extra: Extra::new(),
+                },
+                HistoryRecord {
+                    client: 2,
+                    tx: 3,
+                    op: Operation::Dispute,
+                    amount: Some(dec!(-1)),
+                    disputes: 0,
+                    timestamp: None,
+                    counterparty: None,
+                    extra: Extra::new(),
+                },
+            ],
+        };
+
+        let disputed: Vec<u32> = snapshot.open_disputes(1).map(|rec| rec.tx).collect();
+
+        assert_eq!(disputed, vec![1]);
+        assert_eq!(snapshot.account(1).map(|act| act.held), Some(dec!(3)));
+        assert!(snapshot.account(2).is_none());
+    }
+
+    #[test]
+    fn dump_groups_history_by_client() {
+        let snapshot = Snapshot {
+            accounts: vec![
+                record(1, dec!(0), dec!(3), false),
+                record(2, dec!(1), dec!(0), false),
+            ],
+            history: vec![
+                HistoryRecord {
+                    client: 1,
+                    tx: 1,
+                    op: Operation::Dispute,
+                    amount: Some(dec!(-3)),
+                    disputes: 0,
+                    timestamp: None,
+                    counterparty: None,
+                    extra: Extra::new(),
+                },
+                HistoryRecord {
+                    client: 2,
+                    tx: 2,
+                    op: Operation::Deposit,
+                    amount: Some(dec!(1)),
+                    disputes: 0,
+                    timestamp: None,
+                    counterparty: None,
+                    extra: Extra::new(),
+                },
+            ],
+        };
+
+        let dump = StateDump::from(snapshot);
+        let json = serde_json::to_value(&dump).expect("Dump serializes");
+
+        assert_eq!(dump.clients.len(), 2);
+        assert_eq!(json["clients"][0]["client"], 1);
+        assert_eq!(json["clients"][0]["transactions"][0]["state"], "dispute");
+        assert_eq!(json["clients"][1]["transactions"][0]["tx"], 2);
+    }
+
+    #[test]
+    fn identical_snapshots_have_no_changes() {
+        let snapshot = Snapshot {
+            accounts: vec![record(1, dec!(1.5), dec!(0), false)],
+            history: vec![],
+        };
+        let copy = Snapshot {
+            accounts: snapshot.accounts.clone(),
+            history: vec![],
+        };
+
+        assert!(snapshot.diff(&copy).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+use thiserror::Error;
+
+use crate::domain::{Account, Amount, ClientId};
+use crate::snapshot::HistoryRecord;
+
+#[derive(Error, Debug)]
+pub enum SqliteError {
+    #[error("Failed to write database: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Failed to encode extra columns: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Client id {0} doesn't fit a SQLite integer")]
+    Client(ClientId),
+}
+
+// Amounts are text so they keep every decimal, SQLite's REAL would round them. Both tables are
+// replaced on every write, other tables in the database are left alone.
+const SCHEMA: &str = "
+    DROP TABLE IF EXISTS accounts;
+    DROP TABLE IF EXISTS transactions;
+    CREATE TABLE accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE transactions (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount TEXT,
+        disputes INTEGER NOT NULL,
+        counterparty INTEGER,
+        timestamp INTEGER,
+        extra TEXT,
+        PRIMARY KEY (client, tx)
+    );
+";
+
+// Writes the accounts, with balances rounded like in the CSV output, and the history as the
+// snapshot keeps it to the database at `path`, creating it if needed. Everything is written in a
+// single transaction, so readers see either the previous tables or all of the new ones.
+pub fn write<'a, A: Amount + 'a>(
+    path: &Path,
+    accounts: impl IntoIterator<Item = &'a Account<A>>,
+    history: &[HistoryRecord],
+) -> Result<(), SqliteError> {
+    let mut connection = Connection::open(path)?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(SCHEMA)?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO accounts (client, available, held, total, locked)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for account in accounts {
+            insert.execute(params![
+                integer(account.client)?,
+                account.available.to_output(),
+                account.held.to_output(),
+                account.total.to_output(),
+                account.locked,
+            ])?;
+        }
+        let mut insert = transaction.prepare(
+            "INSERT INTO transactions
+                 (client, tx, type, amount, disputes, counterparty, timestamp, extra)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for record in history {
+            let extra = match record.extra.is_empty() {
+                true => None,
+                false => Some(serde_json::to_string(&record.extra)?),
+            };
+            insert.execute(params![
+                integer(record.client)?,
+                record.tx,
+                record.op.name(),
+                record.amount.map(|amount| amount.to_string()),
+                record.disputes,
+                record.counterparty.map(integer).transpose()?,
+                record.timestamp,
+                extra,
+            ])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+fn integer(client: ClientId) -> Result<i64, SqliteError> {
+    i64::try_from(u128::from(client)).map_err(|_| SqliteError::Client(client))
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::domain::transaction::Operation;
+    use crate::domain::Transaction;
+    use crate::engine::Engine;
+    use crate::snapshot::Snapshot;
+
+    #[test]
+    fn writes_accounts_and_history_in_one_transaction() {
+        let mut engine: Engine = Engine::new();
+        let transactions = [
+            (Operation::Deposit, 1, 1, Some(dec!(10.5))),
+            (Operation::Deposit, 2, 2, Some(dec!(3))),
+            (Operation::Dispute, 1, 1, None),
+        ];
+        for (op, client, tx, amount) in transactions {
+            let mut transaction = Transaction {
+                op,
+                client,
+                tx,
+                amount,
+                ..Default::default()
+            };
+            if tx == 2 {
+                transaction
+                    .extra
+                    .insert("merchant".to_string(), "m-7".to_string());
+            }
+            engine.process(transaction).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("bank-sqlite-{}.db", std::process::id()));
+        let snapshot = Snapshot::new(engine.history(), engine.accounts());
+        // a second write replaces the tables of the first
+        for _ in 0..2 {
+            write(&path, engine.accounts().values(), &snapshot.history).unwrap();
+        }
+
+        let connection = Connection::open(&path).unwrap();
+        let accounts: Vec<(i64, String, String, bool)> = connection
+            .prepare("SELECT client, available, held, locked FROM accounts ORDER BY client")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            accounts,
+            vec![
+                (1, "0.0".to_string(), "10.5".to_string(), false),
+                (2, "3".to_string(), "0".to_string(), false),
+            ]
+        );
+        let (count, extra): (i64, Option<String>) = connection
+            .query_row("SELECT count(*), max(extra) FROM transactions", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(extra.as_deref(), Some(r#"{"merchant":"m-7"}"#));
+        let disputes: u8 = connection
+            .query_row(
+                "SELECT disputes FROM transactions WHERE client = 1 AND tx = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(disputes, 1);
+        drop(connection);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+
+use crate::domain::errors::TransactionError;
+use crate::domain::transaction::Operation;
+use crate::domain::{Account, ClientId, Transaction};
+use crate::engine::Engine;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StatementFormat {
+    #[default]
+    Markdown,
+    Html,
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(StatementFormat::Markdown),
+            "html" => Ok(StatementFormat::Html),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+impl StatementFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StatementFormat::Markdown => "md",
+            StatementFormat::Html => "html",
+        }
+    }
+}
+
+// A transaction on a statement with the balances after it
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub transaction: Transaction,
+    pub outcome: Result<(), TransactionError>,
+    pub balance: Account,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeStatus {
+    Open,
+    Resolved,
+    ChargedBack,
+}
+
+impl fmt::Display for DisputeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisputeStatus::Open => "open",
+            DisputeStatus::Resolved => "resolved",
+            DisputeStatus::ChargedBack => "charged back",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dispute {
+    pub tx: u32,
+    // amount of the disputed transaction
+    pub amount: Option<Decimal>,
+    pub status: DisputeStatus,
+}
+
+// Everything that happened to one client's account, in the order it was journaled
+#[derive(Debug, PartialEq)]
+pub struct Statement {
+    pub client: ClientId,
+    pub entries: Vec<Entry>,
+    pub disputes: Vec<Dispute>,
+}
+
+// Replays journaled transactions and builds a statement for every client in them. Rejected
+// transactions are kept on the statement with the reason, leaving the balances unchanged.
+pub fn statements(journal: impl IntoIterator<Item = Transaction>) -> BTreeMap<ClientId, Statement> {
+    let mut engine = Engine::new();
+    let mut statements = BTreeMap::new();
+    for transaction in journal {
+        // a transfer, and a dispute of one, also show up on the other client's statement
+        let counterparty = engine.counterparty(&transaction);
+        let clients: Vec<ClientId> = std::iter::once(transaction.client)
+            .chain(counterparty)
+            .collect();
+        let before: Vec<Option<Account>> = clients
+            .iter()
+            .map(|client| engine.accounts().get(client).cloned())
+            .collect();
+        let outcome = engine.process(transaction.clone());
+        for (client, before) in clients.into_iter().zip(before) {
+            // the other client only sees what was applied
+            if client != transaction.client && outcome.is_err() {
+                continue;
+            }
+            let statement = statements.entry(client).or_insert_with(|| Statement {
+                client,
+                entries: vec![],
+                disputes: vec![],
+            });
+            let mut balance = statement.closing();
+            if outcome.is_ok() {
+                let before = before.unwrap_or_else(|| Account::new(client));
+                let after = &engine.accounts()[&client];
+                balance.available += after.available - before.available;
+                balance.held += after.held - before.held;
+                balance.total += after.total - before.total;
+                balance.locked = after.locked;
+                statement.track_dispute(&transaction);
+            }
+            statement.entries.push(Entry {
+                transaction: transaction.clone(),
+                outcome: outcome.clone(),
+                balance,
+            });
+        }
+    }
+    statements
+}
+
+impl Statement {
+    fn track_dispute(&mut self, transaction: &Transaction) {
+        let status = match transaction.op {
+            Operation::Dispute => {
+                let amount = self
+                    .entries
+                    .iter()
+                    .find(|entry| entry.transaction.tx == transaction.tx && entry.outcome.is_ok())
+                    .and_then(|entry| entry.transaction.amount);
+                self.disputes.push(Dispute {
+                    tx: transaction.tx,
+                    amount,
+                    status: DisputeStatus::Open,
+                });
+                return;
+            }
+            Operation::Resolve => DisputeStatus::Resolved,
+            Operation::Chargeback => DisputeStatus::ChargedBack,
+            _ => return,
+        };
+        // settles the latest dispute of the transaction
+        if let Some(dispute) = self
+            .disputes
+            .iter_mut()
+            .rev()
+            .find(|dispute| dispute.tx == transaction.tx)
+        {
+            dispute.status = status;
+        }
+    }
+
+    // Balances after the last transaction
+    pub fn closing(&self) -> Account {
+        match self.entries.last() {
+            Some(entry) => entry.balance.clone(),
+            None => Account::new(self.client),
+        }
+    }
+
+    pub fn render(&self, format: StatementFormat) -> String {
+        match format {
+            StatementFormat::Markdown => self.markdown(),
+            StatementFormat::Html => self.html(),
+        }
+    }
+
+    fn markdown(&self) -> String {
+        let mut out = String::new();
+        let closing = self.closing();
+        writeln!(out, "# Statement for client {}\n", self.client).ok();
+        writeln!(out, "| Available | Held | Total | Status |").ok();
+        writeln!(out, "|---:|---:|---:|---|").ok();
+        writeln!(
+            out,
+            "| {:.4} | {:.4} | {:.4} | {} |",
+            closing.available,
+            closing.held,
+            closing.total,
+            status(&closing)
+        )
+        .ok();
+
+        writeln!(out, "\n## Transactions\n").ok();
+        if self.entries.is_empty() {
+            writeln!(out, "No transactions.").ok();
+        } else {
+            writeln!(
+                out,
+                "| Type | Tx | Amount | Available | Held | Total | Note |"
+            )
+            .ok();
+            writeln!(out, "|---|---:|---:|---:|---:|---:|---|").ok();
+            for entry in self.entries.iter() {
+                let [op, tx, amount, available, held, total, note] = row(self.client, entry);
+                writeln!(
+                    out,
+                    "| {op} | {tx} | {amount} | {available} | {held} | {total} | {note} |"
+                )
+                .ok();
+            }
+        }
+
+        writeln!(out, "\n## Disputes\n").ok();
+        if self.disputes.is_empty() {
+            writeln!(out, "No disputes.").ok();
+        } else {
+            writeln!(out, "| Tx | Amount | Status |").ok();
+            writeln!(out, "|---:|---:|---|").ok();
+            for dispute in self.disputes.iter() {
+                writeln!(
+                    out,
+                    "| {} | {} | {} |",
+                    dispute.tx,
+                    amount(dispute.amount),
+                    dispute.status
+                )
+                .ok();
+            }
+        }
+        out
+    }
+
+    fn html(&self) -> String {
+        let mut out = String::new();
+        let closing = self.closing();
+        let title = format!("Statement for client {}", self.client);
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+        )
+        .ok();
+        writeln!(out, "<title>{title}</title>").ok();
+        writeln!(
+            out,
+            "<style>table {{ border-collapse: collapse; }} th, td {{ padding: 0.2em 0.8em; border-bottom: 1px solid #ccc; }} td.num {{ text-align: right; }}</style>"
+        )
+        .ok();
+        writeln!(out, "</head>\n<body>\n<h1>{title}</h1>").ok();
+        table(
+            &mut out,
+            &["Available", "Held", "Total", "Status"],
+            [[
+                format!("{:.4}", closing.available),
+                format!("{:.4}", closing.held),
+                format!("{:.4}", closing.total),
+                status(&closing).to_string(),
+            ]],
+        );
+
+        writeln!(out, "<h2>Transactions</h2>").ok();
+        if self.entries.is_empty() {
+            writeln!(out, "<p>No transactions.</p>").ok();
+        } else {
+            let header = ["Type", "Tx", "Amount", "Available", "Held", "Total", "Note"];
+            table(
+                &mut out,
+                &header,
+                self.entries.iter().map(|entry| row(self.client, entry)),
+            );
+        }
+
+        writeln!(out, "<h2>Disputes</h2>").ok();
+        if self.disputes.is_empty() {
+            writeln!(out, "<p>No disputes.</p>").ok();
+        } else {
+            let rows = self.disputes.iter().map(|dispute| {
+                [
+                    dispute.tx.to_string(),
+                    amount(dispute.amount),
+                    dispute.status.to_string(),
+                ]
+            });
+            table(&mut out, &["Tx", "Amount", "Status"], rows);
+        }
+        writeln!(out, "</body>\n</html>").ok();
+        out
+    }
+}
+
+fn status(account: &Account) -> &'static str {
+    match account.locked {
+        true => "locked",
+        false => "active",
+    }
+}
+
+fn amount(amount: Option<Decimal>) -> String {
+    amount
+        .map(|amount| format!("{amount:.4}"))
+        .unwrap_or_default()
+}
+
+fn row(client: ClientId, entry: &Entry) -> [String; 7] {
+    let transaction = &entry.transaction;
+    let note = match (&entry.outcome, transaction.recipient()) {
+        (Err(e), _) => format!("rejected: {e}"),
+        (Ok(()), Some(to)) if to == client => format!("from client {}", transaction.client),
+        (Ok(()), Some(to)) => format!("to client {to}"),
+        // a dispute of a transfer the other client opened
+        (Ok(()), None) if transaction.client != client => {
+            format!("by client {}", transaction.client)
+        }
+        (Ok(()), None) => String::new(),
+    };
+    [
+        transaction.op.name().to_string(),
+        transaction.tx.to_string(),
+        amount(transaction.amount),
+        format!("{:.4}", entry.balance.available),
+        format!("{:.4}", entry.balance.held),
+        format!("{:.4}", entry.balance.total),
+        note,
+    ]
+}
+
+// Writes an HTML table, right aligning every column that only holds numbers
+fn table<const N: usize>(
+    out: &mut String,
+    header: &[&str; N],
+    rows: impl IntoIterator<Item = [String; N]>,
+) {
+    writeln!(out, "<table>\n<tr>").ok();
+    for name in header {
+        write!(out, "<th>{}</th>", escape(name)).ok();
+    }
+    writeln!(out, "</tr>").ok();
+    for row in rows {
+        write!(out, "<tr>").ok();
+        for cell in row {
+            let numeric = !cell.is_empty() && cell.parse::<Decimal>().is_ok();
+            let class = if numeric { " class=\"num\"" } else { "" };
+            write!(out, "<td{class}>{}</td>", escape(&cell)).ok();
+        }
+        writeln!(out, "</tr>").ok();
+    }
+    writeln!(out, "</table>").ok();
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn transaction(
+        op: Operation,
+        client: ClientId,
+        tx: u32,
+        amount: Option<Decimal>,
+    ) -> Transaction {
+        Transaction {
+            op,
+            client,
+            tx,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_running_balances_and_disputes() {
+        let journal = vec![
+            transaction(Operation::Deposit, 1, 1, Some(dec!(10))),
+            transaction(Operation::Deposit, 2, 2, Some(dec!(3))),
+            transaction(Operation::Withdrawal, 1, 3, Some(dec!(20))),
+            transaction(Operation::Deposit, 1, 4, Some(dec!(5))),
+            transaction(Operation::Dispute, 1, 4, None),
+            transaction(Operation::Dispute, 1, 1, None),
+            transaction(Operation::Resolve, 1, 1, None),
+        ];
+
+        let statements = statements(journal);
+        assert_eq!(statements.len(), 2);
+        let statement = &statements[&1];
+        assert_eq!(statement.entries.len(), 6);
+        assert_eq!(
+            statement.entries[1].outcome,
+            Err(TransactionError::InsufficientFunds)
+        );
+        assert_eq!(statement.entries[1].balance.total, dec!(10));
+        assert_eq!(statement.entries[3].balance.held, dec!(5));
+        assert_eq!(
+            statement.disputes,
+            vec![
+                Dispute {
+                    tx: 4,
+                    amount: Some(dec!(5)),
+                    status: DisputeStatus::Open,
+                },
+                Dispute {
+                    tx: 1,
+                    amount: Some(dec!(10)),
+                    status: DisputeStatus::Resolved,
+                },
+            ]
+        );
+        assert_eq!(statement.closing(), statement.entries[5].balance);
+
+        let markdown = statement.render(StatementFormat::Markdown);
+        assert!(markdown.starts_with("# Statement for client 1\n"));
+        assert!(markdown.contains(
+            "| withdrawal | 3 | 20.0000 | 10.0000 | 0.0000 | 10.0000 | rejected: Insufficient funds in account |"
+        ));
+        assert!(markdown.contains("| 1 | 10.0000 | resolved |"));
+
+        let html = statements[&2].render(StatementFormat::Html);
+        assert!(html.contains("<td class=\"num\">3.0000</td>"));
+        assert!(html.contains("<p>No disputes.</p>"));
+        assert_eq!(escape("<a & 'b'>"), "&lt;a &amp; &#39;b&#39;&gt;");
+    }
+}
@@ -0,0 +1,100 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use rust_decimal::Decimal;
+
+use crate::core::{errors::TransactionError, Amount, Transaction};
+use crate::engine::{AccountDelta, Engine};
+
+// Applies transactions as an async source yields them, see `Engine::into_result_stream`. Each item
+// is the transaction with its effect on the client's account or why it was rejected, the same
+// pairs `Engine::outcomes` gives blocking callers.
+pub struct ResultStream<S, A = Decimal> {
+    engine: Engine<A>,
+    input: Pin<Box<S>>,
+}
+
+impl<A: Amount> Engine<A> {
+    // Hands the engine to a stream applying `input`; `ResultStream::into_engine` gives it back
+    // with the resulting accounts and history once the caller is done
+    pub fn into_result_stream<S>(self, input: S) -> ResultStream<S, A>
+    where
+        S: Stream<Item = Transaction<A>>,
+    {
+        ResultStream {
+            engine: self,
+            input: Box::pin(input),
+        }
+    }
+}
+
+impl<S, A: Amount> ResultStream<S, A> {
+    pub fn engine(&self) -> &Engine<A> {
+        &self.engine
+    }
+
+    pub fn into_engine(self) -> Engine<A> {
+        self.engine
+    }
+}
+
+// the input is boxed and the engine is never pinned, so the stream can move freely
+impl<S, A> Unpin for ResultStream<S, A> {}
+
+impl<S, A> Stream for ResultStream<S, A>
+where
+    S: Stream<Item = Transaction<A>>,
+    A: Amount,
+{
+    type Item = (Transaction<A>, Result<AccountDelta<A>, TransactionError>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match this.input.as_mut().poll_next(cx) {
+            Poll::Ready(Some(transaction)) => Poll::Ready(Some(this.engine.outcome(transaction))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.input.size_hint()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use futures::{executor, stream, StreamExt};
+    use rust_decimal_macros::dec;
+
+    use crate::core::transaction::Operation;
+
+    use super::*;
+
+    #[test]
+    fn streams_outcomes_of_an_async_source() {
+        let input = stream::iter([
+            (Operation::Deposit, dec!(10)),
+            (Operation::Withdrawal, dec!(25)),
+            (Operation::Withdrawal, dec!(4)),
+        ])
+        .enumerate()
+        .map(|(tx, (op, amount))| Transaction {
+            op,
+            client: 1,
+            tx: tx as u32,
+            amount: Some(amount),
+            ..Default::default()
+        });
+
+        let mut results = Engine::new().into_result_stream(input);
+        let outcomes: Vec<_> = executor::block_on((&mut results).collect());
+
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes[0].1.as_ref().unwrap().total, dec!(10));
+        assert_eq!(outcomes[1].1, Err(TransactionError::InsufficientFunds));
+        assert_eq!(outcomes[2].0.tx, 2);
+        assert_eq!(results.into_engine().accounts()[&1].total, dec!(6));
+    }
+}
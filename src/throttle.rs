@@ -0,0 +1,76 @@
+use std::num::NonZeroU32;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Token bucket limiting how many transactions per second a source may hand to the engine.
+// `acquire` blocks the calling source until a token is available, which is how backpressure
+// reaches the producer.
+#[derive(Debug)]
+pub struct Throttle {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    pub fn new(max_per_second: NonZeroU32) -> Self {
+        let rate = f64::from(max_per_second.get());
+        Self {
+            rate,
+            burst: rate,
+            tokens: rate,
+            last: Instant::now(),
+        }
+    }
+
+    // Caps how many tokens can accumulate while the source is idle
+    pub fn with_burst(mut self, burst: NonZeroU32) -> Self {
+        self.burst = f64::from(burst.get());
+        self.tokens = self.burst;
+        self
+    }
+
+    pub fn acquire(&mut self) {
+        self.refill();
+        if self.tokens < 1.0 {
+            let wait = (1.0 - self.tokens) / self.rate;
+            thread::sleep(Duration::from_secs_f64(wait));
+            self.refill();
+        }
+        self.tokens -= 1.0;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn paces_after_burst() {
+        let mut throttle = Throttle::new(NonZeroU32::new(100).unwrap()).with_burst(NonZeroU32::MIN);
+        let start = Instant::now();
+        for _ in 0..6 {
+            throttle.acquire();
+        }
+        // first token is free, the remaining five wait 10ms each
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[test]
+    fn burst_is_immediate() {
+        let mut throttle = Throttle::new(NonZeroU32::MIN).with_burst(NonZeroU32::new(5).unwrap());
+        let start = Instant::now();
+        for _ in 0..5 {
+            throttle.acquire();
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+}
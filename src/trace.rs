@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use rust_decimal::Decimal;
+
+use crate::domain::{errors::TransactionError, transaction::Operation, ClientId, Transaction};
+use crate::engine::Engine;
+use crate::snapshot::AccountRecord;
+
+// Reports the balance changes caused by each transaction of a chosen set of clients, for
+// working out by hand how an account reached its final state.
+#[derive(Debug, Clone, Default)]
+pub struct Tracer {
+    clients: HashSet<ClientId>,
+    txs: HashSet<u32>,
+    // also describe the rule applied and the history lookup of dispute operations
+    explain: bool,
+}
+
+impl Tracer {
+    pub fn new(clients: impl IntoIterator<Item = ClientId>) -> Self {
+        Self {
+            clients: clients.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    // Follows a single transaction id through its lifecycle: the original deposit or withdrawal
+    // and every dispute, resolve and chargeback referencing it.
+    pub fn transaction(tx: u32) -> Self {
+        Self {
+            txs: HashSet::from([tx]),
+            explain: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn traces(&self, transaction: &Transaction) -> bool {
+        self.clients.contains(&transaction.client) || self.txs.contains(&transaction.tx)
+    }
+
+    // Applies a transaction, returning a description of its effect when it's traced
+    pub fn process(
+        &self,
+        engine: &mut Engine,
+        transaction: Transaction,
+    ) -> (Result<(), TransactionError>, Option<String>) {
+        if !self.traces(&transaction) {
+            return (engine.process(transaction), None);
+        }
+        let record = |engine: &Engine| {
+            engine
+                .accounts()
+                .get(&transaction.client)
+                .map(AccountRecord::from)
+        };
+        let lookup = match transaction.op {
+            Operation::Dispute
+            | Operation::Resolve
+            | Operation::Chargeback
+            | Operation::Capture
+                if self.explain =>
+            {
+                Some(lookup(engine, &transaction))
+            }
+            _ => None,
+        };
+        let before = record(engine);
+        let res = engine.process(transaction.clone());
+        let after = record(engine);
+
+        let mut trace = describe(&transaction, before.as_ref(), after.as_ref(), &res);
+        if self.explain {
+            let _ = write!(trace, "\n  rule      {}", rule(&transaction.op));
+        }
+        if let Some(lookup) = lookup {
+            let _ = write!(trace, "\n  lookup    {lookup}");
+        }
+        (res, Some(trace))
+    }
+}
+
+// The rule the engine applies for an operation when it's accepted
+fn rule(op: &Operation) -> &'static str {
+    match op {
+        Operation::Deposit => "deposit credits available and total",
+        Operation::Withdrawal => "withdrawal debits available and total if funds are sufficient",
+        Operation::Dispute => "dispute moves the disputed amount from available to held",
+        Operation::Resolve => "resolve releases the held amount back to available",
+        Operation::Chargeback => "chargeback removes the held amount and locks the account",
+        Operation::Fee => "fee debits available and total, even on a locked account",
+        Operation::Transfer => {
+            "transfer debits available and total if funds are sufficient and credits the \
+             counterparty"
+        }
+        Operation::Authorize => {
+            "authorize moves funds from available to held if they're sufficient"
+        }
+        Operation::Capture => {
+            "capture removes the captured amount from held and releases the rest of the \
+             authorization to available"
+        }
+        Operation::Unknown(_) => "unknown operations are never applied",
+    }
+}
+
+// What the history holds for the transaction a dispute operation refers to
+fn lookup(engine: &Engine, transaction: &Transaction) -> String {
+    let tx = transaction.tx;
+    match engine.history().get(&(transaction.client, tx)) {
+        Some(node) => {
+            let op = node.op.name();
+            match node.amount {
+                Some(amount) => format!("tx {tx} is recorded as {op} {amount:.4}"),
+                None => format!("tx {tx} is recorded as {op}"),
+            }
+        }
+        None => format!("tx {tx} is not in the client's history"),
+    }
+}
+
+fn describe(
+    transaction: &Transaction,
+    before: Option<&AccountRecord>,
+    after: Option<&AccountRecord>,
+    res: &Result<(), TransactionError>,
+) -> String {
+    let Transaction { client, tx, op, .. } = transaction;
+    let op = format!("{op:?}").to_lowercase();
+    let mut out = match transaction.amount {
+        Some(amount) => format!("client {client} tx {tx} {op} {amount:.4}"),
+        None => format!("client {client} tx {tx} {op}"),
+    };
+    let after = match (after, res) {
+        (_, Err(e)) => {
+            let _ = write!(out, ": rejected ({e})");
+            return out;
+        }
+        (Some(after), Ok(())) => after,
+        (None, Ok(())) => return out,
+    };
+    match before {
+        None => out.push_str(": opened account"),
+        Some(_) => out.push_str(": applied"),
+    }
+    let zero = Decimal::ZERO;
+    let fields = [
+        (
+            "available",
+            before.map_or(zero, |act| act.available),
+            after.available,
+        ),
+        ("held", before.map_or(zero, |act| act.held), after.held),
+        ("total", before.map_or(zero, |act| act.total), after.total),
+    ];
+    for (name, old, new) in fields {
+        if old == new {
+            let _ = write!(out, "\n  {name:<9} {new:.4}");
+        } else {
+            let _ = write!(
+                out,
+                "\n  {name:<9} {old:.4} -> {new:.4} ({:+.4})",
+                new - old
+            );
+        }
+    }
+    let locked = before.is_some_and(|act| act.locked);
+    if locked != after.locked {
+        let _ = write!(out, "\n  locked    {locked} -> {}", after.locked);
+    }
+    out
+}
+
+#[cfg(test)]
+pub mod test {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn transaction(op: Operation, tx: u32, amount: Option<Decimal>) -> Transaction {
+        Transaction {
+            op,
+            client: 7,
+            tx,
+            amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn traces_balance_changes() {
+        let tracer = Tracer::new([7]);
+        let mut engine = Engine::new();
+
+        let (_, trace) = tracer.process(
+            &mut engine,
+            transaction(Operation::Deposit, 1, Some(dec!(10))),
+        );
+        assert_eq!(
+            trace.unwrap(),
+            "client 7 tx 1 deposit 10.0000: opened account\n  \
+             available 0.0000 -> 10.0000 (+10.0000)\n  \
+             held      0.0000\n  \
+             total     0.0000 -> 10.0000 (+10.0000)"
+        );
+
+        let (_, trace) = tracer.process(
+            &mut engine,
+            transaction(Operation::Withdrawal, 2, Some(dec!(50))),
+        );
+        assert_eq!(
+            trace.unwrap(),
+            "client 7 tx 2 withdrawal 50.0000: rejected (Insufficient funds in account)"
+        );
+
+        let (res, trace) = tracer.process(
+            &mut engine,
+            Transaction {
+                client: 8,
+                ..transaction(Operation::Deposit, 2, Some(dec!(1)))
+            },
+        );
+        assert!(res.is_ok() && trace.is_none());
+    }
+
+    #[test]
+    fn explains_a_transaction() {
+        let tracer = Tracer::transaction(1);
+        let mut engine = Engine::new();
+
+        let (_, trace) = tracer.process(
+            &mut engine,
+            transaction(Operation::Deposit, 2, Some(dec!(4))),
+        );
+        assert!(trace.is_none());
+        let _ = tracer.process(
+            &mut engine,
+            transaction(Operation::Deposit, 1, Some(dec!(10))),
+        );
+        let (_, trace) = tracer.process(&mut engine, transaction(Operation::Dispute, 1, None));
+
+        assert_eq!(
+            trace.unwrap(),
+            "client 7 tx 1 dispute: applied\n  \
+             available 14.0000 -> 4.0000 (-10.0000)\n  \
+             held      0.0000 -> 10.0000 (+10.0000)\n  \
+             total     14.0000\n  \
+             rule      dispute moves the disputed amount from available to held\n  \
+             lookup    tx 1 is recorded as deposit 10.0000"
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::domain::{transaction::Operation, Amount, ClientId, Transaction};
+
+#[derive(Error, Debug)]
+pub enum WireError {
+    #[error("Connection error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Malformed frame: {0}")]
+    Malformed(&'static str),
+    #[error("Invalid address {0}, expected tcp://host:port or unix:///path")]
+    Address(String),
+}
+
+// Where reader processes send transactions to and the engine process listens on
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = WireError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            return Ok(Endpoint::Tcp(addr.to_string()));
+        }
+        match s.strip_prefix("unix://") {
+            Some(path) if !path.is_empty() => Ok(Endpoint::Unix(path.into())),
+            _ => Err(WireError::Address(s.to_string())),
+        }
+    }
+}
+
+// What a connection carries, sent as its first byte
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    // transactions from a reader, to be processed
+    Transactions = 1,
+    // transactions a primary has applied, to be mirrored by a standby
+    Replication = 2,
+    // asks a standby to take over from its primary, carries no frames
+    Promote = 3,
+}
+
+// Kind byte of a reader that names its source, followed by the name's length and bytes. The
+// connection is a `Stream::Transactions` otherwise.
+const NAMED: u8 = 4;
+
+impl Stream {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Stream::Transactions),
+            2 => Some(Stream::Replication),
+            3 => Some(Stream::Promote),
+            _ => None,
+        }
+    }
+}
+
+impl Endpoint {
+    pub fn connect(&self) -> io::Result<Sender> {
+        self.connect_as(Stream::Transactions)
+    }
+
+    pub fn connect_as(&self, kind: Stream) -> io::Result<Sender> {
+        let stream: Box<dyn Write + Send> = match self {
+            Endpoint::Tcp(addr) => Box::new(TcpStream::connect(addr)?),
+            Endpoint::Unix(path) => Box::new(UnixStream::connect(path)?),
+        };
+        Ok(Sender {
+            out: BufWriter::new(stream),
+            frame: vec![kind as u8],
+        })
+    }
+
+    // Connects as a reader that names the source of its transactions, e.g. for the engine to
+    // check which operations it may issue. Names are at most 255 bytes.
+    pub fn connect_from(&self, source: &str) -> io::Result<Sender> {
+        let mut sender = self.connect()?;
+        let len = u8::try_from(source.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Source name too long"))?;
+        sender.frame = [NAMED, len].into_iter().chain(source.bytes()).collect();
+        Ok(sender)
+    }
+
+    pub fn listen(&self) -> io::Result<Listener> {
+        Ok(match self {
+            Endpoint::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+            Endpoint::Unix(path) => Listener::Unix(UnixListener::bind(path)?),
+        })
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    // Waits for the next connection and reads what it carries
+    pub fn accept(&self) -> Result<Receiver, WireError> {
+        let stream: Box<dyn Read + Send> = match self {
+            Listener::Tcp(listener) => Box::new(listener.accept()?.0),
+            Listener::Unix(listener) => Box::new(listener.accept()?.0),
+        };
+        let mut input = BufReader::new(stream);
+        let mut kind = [0u8];
+        input.read_exact(&mut kind)?;
+        let mut source = None;
+        if kind[0] == NAMED {
+            let mut len = [0u8];
+            input.read_exact(&mut len)?;
+            let mut name = vec![0; len[0] as usize];
+            input.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| WireError::Malformed("source name"))?;
+            source = Some(name);
+            kind[0] = Stream::Transactions as u8;
+        }
+        Ok(Receiver {
+            kind: Stream::from_code(kind[0]).ok_or(WireError::Malformed("unknown stream"))?,
+            source,
+            input,
+            finished: false,
+        })
+    }
+}
+
+// Frames are a length byte followed by the body:
+//   op u8 | fields u8 | client, little endian at the width of `ClientId` | tx u32 LE
+//   | amount [u8; 16] | counterparty, at the width of `ClientId` | timestamp u64 LE
+// `fields` has a bit for each of the optional trailing fields the frame carries, in that order.
+// The amount uses `Amount::to_bits`.
+// A frame of length zero ends the stream, so the receiving end can tell a finished sender from
+// one that went away. Both ends have to be built with the same `client-id-*` feature.
+const CLIENT: usize = std::mem::size_of::<ClientId>();
+const HEAD: usize = 2 + CLIENT + 4;
+
+const AMOUNT: u8 = 1;
+const COUNTERPARTY: u8 = 2;
+const TIMESTAMP: u8 = 4;
+
+pub fn encode<A: Amount>(transaction: &Transaction<A>, out: &mut Vec<u8>) {
+    let fields = transaction.amount.map_or(0, |_| AMOUNT)
+        | transaction.counterparty.map_or(0, |_| COUNTERPARTY)
+        | transaction.timestamp.map_or(0, |_| TIMESTAMP);
+    out.push(frame_len(fields) as u8);
+    out.push(transaction.op.code());
+    out.push(fields);
+    out.extend_from_slice(&transaction.client.to_le_bytes());
+    out.extend_from_slice(&transaction.tx.to_le_bytes());
+    if let Some(amount) = transaction.amount {
+        out.extend_from_slice(&amount.to_bits());
+    }
+    if let Some(counterparty) = transaction.counterparty {
+        out.extend_from_slice(&counterparty.to_le_bytes());
+    }
+    if let Some(timestamp) = transaction.timestamp {
+        out.extend_from_slice(&timestamp.to_le_bytes());
+    }
+}
+
+fn frame_len(fields: u8) -> usize {
+    let width = |field, len| if fields & field != 0 { len } else { 0 };
+    HEAD + width(AMOUNT, 16) + width(COUNTERPARTY, CLIENT) + width(TIMESTAMP, 8)
+}
+
+// Decodes the body of a single frame
+pub fn decode<A: Amount>(body: &[u8]) -> Result<Transaction<A>, WireError> {
+    let fields = match body.get(1) {
+        Some(&fields) if fields & !(AMOUNT | COUNTERPARTY | TIMESTAMP) == 0 => fields,
+        Some(_) => return Err(WireError::Malformed("unknown fields")),
+        None => return Err(WireError::Malformed("unexpected frame length")),
+    };
+    if body.len() != frame_len(fields) {
+        return Err(WireError::Malformed("unexpected frame length"));
+    }
+    let op = Operation::from_code(body[0]).ok_or(WireError::Malformed("unknown operation"))?;
+    let client = ClientId::from_le_bytes(body[2..2 + CLIENT].try_into().expect("Sized above"));
+    let tx = u32::from_le_bytes(body[2 + CLIENT..HEAD].try_into().expect("Sized above"));
+    let mut rest = &body[HEAD..];
+    let mut take = |field, len| {
+        (fields & field != 0).then(|| {
+            let (bytes, tail) = rest.split_at(len);
+            rest = tail;
+            bytes
+        })
+    };
+    let amount = take(AMOUNT, 16).map(|bits| A::from_bits(bits.try_into().expect("Sized above")));
+    let counterparty = take(COUNTERPARTY, CLIENT)
+        .map(|bytes| ClientId::from_le_bytes(bytes.try_into().expect("Sized above")));
+    let timestamp =
+        take(TIMESTAMP, 8).map(|bytes| u64::from_le_bytes(bytes.try_into().expect("Sized above")));
+    Ok(Transaction {
+        op,
+        client,
+        tx,
+        amount,
+        counterparty,
+        timestamp,
+        ..Default::default()
+    })
+}
+
+// Writing half of a connection
+pub struct Sender {
+    out: BufWriter<Box<dyn Write + Send>>,
+    // starts out holding the stream kind, which goes out with the first write
+    frame: Vec<u8>,
+}
+
+impl Sender {
+    pub fn send(&mut self, transaction: &Transaction) -> io::Result<()> {
+        encode(transaction, &mut self.frame);
+        self.out.write_all(&self.frame)?;
+        self.frame.clear();
+        Ok(())
+    }
+
+    // Writes the end frame, flushes and closes the connection
+    pub fn finish(mut self) -> io::Result<()> {
+        self.frame.push(0);
+        self.out.write_all(&self.frame)?;
+        self.out.flush()
+    }
+}
+
+// Reading half of a connection on the engine side
+pub struct Receiver {
+    kind: Stream,
+    // name the reader gave, if any
+    source: Option<String>,
+    input: BufReader<Box<dyn Read + Send>>,
+    finished: bool,
+}
+
+impl Receiver {
+    pub fn kind(&self) -> Stream {
+        self.kind
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    // Whether the sender ended the stream, rather than disconnecting
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Iterator for Receiver {
+    type Item = Result<Transaction, WireError>;
+
+    // Ends with the end frame or when the sender disconnects between frames
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len = [0u8];
+        match self.input.read(&mut len) {
+            Ok(0) => return None,
+            Ok(_) if len[0] == 0 => {
+                self.finished = true;
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => return Some(Err(e.into())),
+        }
+        let mut body = vec![0; len[0] as usize];
+        if let Err(e) = self.input.read_exact(&mut body) {
+            return Some(Err(e.into()));
+        }
+        Some(decode(&body))
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::thread;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let transactions = [
+            Transaction {
+                op: Operation::Withdrawal,
+                client: 513,
+                tx: 70_000,
+                amount: Some(dec!(-12.3456)),
+                ..Default::default()
+            },
+            Transaction {
+                op: Operation::Chargeback,
+                client: 2,
+                tx: 3,
+                amount: None,
+                ..Default::default()
+            },
+            Transaction {
+                op: Operation::Transfer,
+                client: 4,
+                tx: 5,
+                amount: Some(dec!(7.5)),
+                counterparty: Some(9),
+                ..Default::default()
+            },
+            Transaction {
+                op: Operation::Dispute,
+                client: 4,
+                tx: 5,
+                amount: None,
+                timestamp: Some(1_709_164_800),
+                ..Default::default()
+            },
+            Transaction {
+                op: Operation::Transfer,
+                client: 4,
+                tx: 6,
+                amount: None,
+                counterparty: Some(9),
+                timestamp: Some(u64::MAX),
+                ..Default::default()
+            },
+        ];
+        for transaction in transactions {
+            let mut frame = vec![];
+            encode(&transaction, &mut frame);
+            assert_eq!(frame[0] as usize, frame.len() - 1);
+            assert_eq!(decode::<Decimal>(&frame[1..]).unwrap(), transaction);
+        }
+        assert!(decode::<Decimal>(&[9; HEAD]).is_err());
+        assert!(decode::<Decimal>(&[0; 3]).is_err());
+        // a timestamp flagged but missing from the body
+        let mut frame = vec![0; HEAD];
+        frame[1] = TIMESTAMP;
+        assert!(decode::<Decimal>(&frame).is_err());
+        frame.extend_from_slice(&7u64.to_le_bytes());
+        assert_eq!(decode::<Decimal>(&frame).unwrap().timestamp, Some(7));
+    }
+
+    #[test]
+    fn streams_transactions_over_a_socket() {
+        let path = std::env::temp_dir().join(format!("bank-wire-{}.sock", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let endpoint = Endpoint::Unix(path.clone());
+        let listener = endpoint.listen().expect("Failed to listen");
+
+        let reader = thread::spawn(move || {
+            let mut sender = endpoint.connect_from("partner").expect("Failed to connect");
+            for tx in 0..100 {
+                let transaction = Transaction {
+                    op: Operation::Deposit,
+                    client: 1,
+                    tx,
+                    amount: Some(dec!(1.5)),
+                    ..Default::default()
+                };
+                sender.send(&transaction).unwrap();
+            }
+            sender.finish().unwrap();
+        });
+        let mut receiver = listener.accept().expect("Failed to accept");
+        let received: Vec<Transaction> = receiver
+            .by_ref()
+            .collect::<Result<_, _>>()
+            .expect("Frames decode");
+        reader.join().unwrap();
+
+        assert_eq!(receiver.kind(), Stream::Transactions);
+        assert_eq!(receiver.source(), Some("partner"));
+        assert!(receiver.finished());
+        assert_eq!(received.len(), 100);
+        assert_eq!(received[99].tx, 99);
+        std::fs::remove_file(path).ok();
+    }
+}